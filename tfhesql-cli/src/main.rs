@@ -3,7 +3,7 @@ use std::{
 };
 
 use clap::Parser;
-use tfhe::{set_server_key, ClientKey, ConfigBuilder};
+use tfhe::{set_server_key, ClientKey, ConfigBuilder, ServerKey};
 use tfhesql::test_util::broadcast_set_server_key;
 use tfhesql::FheSqlError;
 use tfhesql::*;
@@ -121,9 +121,10 @@ fn run_trivial(
     sql_client: &FheSqlClient,
     server_tables: &OrderedTables,
     start_time: Instant,
+    server_key: &ServerKey,
 ) -> Result<(), FheSqlError> {
     let triv_sql_query = sql_client.trivial_encrypt_sql(sql, SqlResultOptions::best())?;
-    let triv_sql_result = FheSqlServer::run(&triv_sql_query, server_tables)?;
+    let triv_sql_result = FheSqlServer::run_with_key(&triv_sql_query, server_tables, server_key)?;
     let csv_result = triv_sql_result
         .try_decrypt_trivial_csv()
         .unwrap_or_default();
@@ -141,9 +142,10 @@ fn run_enc(
     server_tables: &OrderedTables,
     start_time: Instant,
     client_key: &ClientKey,
+    server_key: &ServerKey,
 ) -> Result<(), FheSqlError> {
     let enc_sql_query = sql_client.encrypt_sql(sql, client_key, SqlResultOptions::best())?;
-    let enc_sql_result = FheSqlServer::run(&enc_sql_query, server_tables)?;
+    let enc_sql_result = FheSqlServer::run_with_key(&enc_sql_query, server_tables, server_key)?;
     let csv_result = enc_sql_result.decrypt_csv(client_key).unwrap_or_default();
 
     print_duration(start_time);
@@ -159,13 +161,14 @@ fn run_check_enc(
     server_tables: &OrderedTables,
     start_time: Instant,
     client_key: &ClientKey,
+    server_key: &ServerKey,
 ) -> Result<(), FheSqlError> {
     let clear_sql_query = sql_client.clear_sql(sql, SqlResultOptions::best())?;
     let clear_sql_result = FheSqlServer::run(&clear_sql_query, server_tables)?;
     let clear_csv_result = clear_sql_result.into_csv().unwrap_or_default();
 
     let enc_sql_query = sql_client.encrypt_sql(sql, client_key, SqlResultOptions::best())?;
-    let enc_sql_result = FheSqlServer::run(&enc_sql_query, server_tables)?;
+    let enc_sql_result = FheSqlServer::run_with_key(&enc_sql_query, server_tables, server_key)?;
     let enc_csv_result = enc_sql_result.decrypt_csv(client_key).unwrap_or_default();
 
     print_duration(start_time);
@@ -201,16 +204,16 @@ fn run<P: AsRef<Path>>(sql: &str, csv_dir: P, run_mode: RunMode) -> Result<(), F
             let ck = ClientKey::generate(config);
             let sk = ck.generate_server_key();
 
-            // Setup server keys
-            broadcast_set_server_key(&sk);
-            set_server_key(sk.clone());
-
+            // The server runs the query with its own key, the client
+            // only needs it to trivially encrypt the query.
             if matches!(run_mode, RunMode::Trivial) {
-                run_trivial(sql, &sql_client, &server_tables, start_time)
+                broadcast_set_server_key(&sk);
+                set_server_key(sk.clone());
+                run_trivial(sql, &sql_client, &server_tables, start_time, &sk)
             } else if matches!(run_mode, RunMode::Encrypt) {
-                run_enc(sql, &sql_client, &server_tables, start_time, &ck)
+                run_enc(sql, &sql_client, &server_tables, start_time, &ck, &sk)
             } else {
-                run_check_enc(sql, &sql_client, &server_tables, start_time, &ck)
+                run_check_enc(sql, &sql_client, &server_tables, start_time, &ck, &sk)
             }
         }
         RunMode::Clear => {
@@ -1,13 +1,85 @@
 ///use criterion::{black_box};
-use criterion::{criterion_group, criterion_main, Criterion};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::sync::Arc;
+
+use arrow_array::{Int32Array, RecordBatch};
+use arrow_schema::{DataType, Field, Schema};
+use tfhesql::{
+    DistinctStrategy, FheRunSqlQuery, FheSqlClient, FheSqlServer, OrderedTables, SqlResultOptions,
+    SqlServerOptions, Table,
+};
 
 fn bench_cast(_: &mut Criterion) {
 }
 
+fn distinct_tables(num_rows: usize) -> OrderedTables {
+    let schema = Schema::new(vec![
+        Field::new("a", DataType::Int32, false),
+        Field::new("b", DataType::Int32, false),
+    ]);
+    let a = Int32Array::from((0..num_rows).map(|i| (i % 7) as i32).collect::<Vec<i32>>());
+    let b = Int32Array::from((0..num_rows).map(|i| (i % 5) as i32).collect::<Vec<i32>>());
+    let batch = RecordBatch::try_new(Arc::new(schema), vec![Arc::new(a), Arc::new(b)]).unwrap();
+    OrderedTables::new(vec![Table::new("t", batch)]).unwrap()
+}
+
+fn bench_distinct(c: &mut Criterion) {
+    let mut group = c.benchmark_group("distinct");
+    for num_rows in [64, 256] {
+        let tables = distinct_tables(num_rows);
+        let sql_client = FheSqlClient::new(tables.ordered_schemas().clone()).unwrap();
+        let query = sql_client
+            .clear_sql("SELECT DISTINCT a FROM t", SqlResultOptions::default())
+            .unwrap();
+        for strategy in [DistinctStrategy::Pairwise, DistinctStrategy::SortNetwork] {
+            let options = SqlServerOptions::default().with_distinct_strategy(strategy);
+            // Clear timings do not reflect the cost of the encrypted gates,
+            // the gate counts of each strategy are reported with the 'stats' feature
+            #[cfg(feature = "stats")]
+            print_distinct_stats(&query, &tables, &options, num_rows);
+            group.bench_with_input(
+                BenchmarkId::new(format!("{:?}", strategy), num_rows),
+                &num_rows,
+                |bench, _| {
+                    bench.iter(|| FheSqlServer::run_with_options(&query, &tables, &options).unwrap())
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+/// Prints the gate counts of a clear run and the admission control estimate
+#[cfg(feature = "stats")]
+fn print_distinct_stats(
+    query: &tfhesql::ClearSqlQuery,
+    tables: &OrderedTables,
+    options: &SqlServerOptions,
+    num_rows: usize,
+) {
+    let estimate = FheSqlServer::estimate_gates(query, tables, options).unwrap();
+    println!(
+        "distinct/{:?}/{}: estimated bool gates={} u8 gates={}",
+        options.distinct_strategy(),
+        num_rows,
+        estimate.bool_gates,
+        estimate.u8_gates
+    );
+    FheSqlServer::run_with_options(query, tables, options)
+        .unwrap()
+        .print_stats();
+}
+
 criterion_group!(
     name=group_bench_cast;
     config = Criterion::default();
     targets=bench_cast
 );
 
-criterion_main!(group_bench_cast);
+criterion_group!(
+    name=group_bench_distinct;
+    config = Criterion::default().sample_size(10);
+    targets=bench_distinct
+);
+
+criterion_main!(group_bench_cast, group_bench_distinct);
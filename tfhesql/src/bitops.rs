@@ -347,6 +347,47 @@ impl RefNot for u8 {
     }
 }
 
+////////////////////////////////////////////////////////////////////////////////
+// RefEqGt
+////////////////////////////////////////////////////////////////////////////////
+
+/// Comparison of two (possibly encrypted) bytes, used when the left
+/// operand of a comparison is encrypted as well.
+pub trait RefEqGt<B> {
+    fn refref_eq(&self, rhs: &Self) -> B;
+    fn refref_gt(&self, rhs: &Self) -> B;
+}
+
+impl RefEqGt<bool> for u8 {
+    #[inline(always)]
+    fn refref_eq(&self, rhs: &u8) -> bool {
+        #[cfg(feature = "stats")]
+        inc_u8_cmp();
+        self == rhs
+    }
+    #[inline(always)]
+    fn refref_gt(&self, rhs: &u8) -> bool {
+        #[cfg(feature = "stats")]
+        inc_u8_cmp();
+        self > rhs
+    }
+}
+
+impl RefEqGt<FheBool> for FheUint8 {
+    #[inline(always)]
+    fn refref_eq(&self, rhs: &FheUint8) -> FheBool {
+        #[cfg(feature = "stats")]
+        inc_u8_cmp();
+        tfhe::prelude::FheEq::eq(self, rhs)
+    }
+    #[inline(always)]
+    fn refref_gt(&self, rhs: &FheUint8) -> FheBool {
+        #[cfg(feature = "stats")]
+        inc_u8_cmp();
+        tfhe::prelude::FheOrd::gt(self, rhs)
+    }
+}
+
 pub fn par_bitor_vec<T>(v: Vec<T>) -> Option<T>
 where
     T: Send + Sync + RefBitOr<Output = T> + Clone,
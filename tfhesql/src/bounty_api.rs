@@ -1,10 +1,10 @@
 use crate::{
-    test_util::broadcast_set_server_key, FheRunSqlQuery,
+    FheRunSqlQuery,
     FheSqlClient, FheSqlQuery, FheSqlResult, FheSqlServer, OrderedSchemas, OrderedTables,
     SqlResultOptions,
 };
 use std::{cell::RefCell, io::BufWriter};
-use tfhe::{set_server_key, shortint::PBSParameters, ClientKey};
+use tfhe::{shortint::PBSParameters, ClientKey};
 
 thread_local! {
     static INTERNAL_BOUNTY_PARAMS: RefCell<Option<OrderedSchemas>> = const { RefCell::new(None) };
@@ -82,10 +82,7 @@ pub fn run_fhe_query(
     input: &EncryptedQuery,
     data: &Tables,
 ) -> EncrypedResult {
-    broadcast_set_server_key(sks);
-    set_server_key(sks.clone());
-
-    let sql_result = FheSqlServer::run(&input.0, &data.0).unwrap();
+    let sql_result = FheSqlServer::run_with_key(&input.0, &data.0, sks).unwrap();
     EncrypedResult(sql_result)
 }
 
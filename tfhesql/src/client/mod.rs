@@ -1,3 +1,6 @@
 mod sql_client;
+mod sql_explain;
 
-pub use sql_client::FheSqlClient;
\ No newline at end of file
+pub use sql_client::FheSqlClient;
+pub use sql_explain::SqlGateEstimate;
+pub use sql_explain::SqlQueryExplain;
@@ -1,10 +1,14 @@
 use crate::ClearSqlQuery;
+use crate::CoercionMode;
 use crate::CompactFheSqlQuery;
 use crate::CompressedFheSqlQuery;
 use crate::FheSqlError;
 use crate::FheSqlQuery;
 use crate::OrderedSchemas;
+use crate::QueryPadding;
+use crate::SqlLeakageReport;
 use crate::SqlResultOptions;
+use super::sql_explain::SqlQueryExplain;
 use tfhe::ClientKey;
 use tfhe::CompactPublicKey;
 
@@ -14,6 +18,9 @@ use tfhe::CompactPublicKey;
 
 pub struct FheSqlClient {
     ordered_schemas: OrderedSchemas,
+    coercion_mode: CoercionMode,
+    padding: Option<QueryPadding>,
+    constant_time_false_where: bool,
 }
 
 impl FheSqlClient {
@@ -21,14 +28,54 @@ impl FheSqlClient {
     pub fn new(schemas: OrderedSchemas) -> Result<Self, FheSqlError> {
         Ok(FheSqlClient {
             ordered_schemas: schemas,
+            coercion_mode: CoercionMode::default(),
+            padding: None,
+            constant_time_false_where: false,
         })
     }
 
+    /// Sets the SQL type-coercion semantics used to compile the WHERE clause
+    /// (default is [CoercionMode::MySql])
+    pub fn with_coercion_mode(mut self, mode: CoercionMode) -> Self {
+        self.coercion_mode = mode;
+        self
+    }
+
+    /// Pads every query WHERE clause to the same AND/OR tree shape
+    /// (see [QueryPadding]), always-false WHERE clauses included
+    pub fn with_query_padding(mut self, padding: QueryPadding) -> Self {
+        self.padding = Some(padding);
+        self
+    }
+
+    /// If enabled, a WHERE clause that can never match is sent as a full-size
+    /// query instead of an empty one, the server cannot tell them apart
+    /// (default is `false`)
+    pub fn with_constant_time_false_where(mut self, enabled: bool) -> Self {
+        self.constant_time_false_where = enabled;
+        self
+    }
+
     /// Returns an immutable reference to the client's [OrderedSchemas]
     pub fn ordered_schemas(&self) -> &OrderedSchemas {
         &self.ordered_schemas
     }
 
+    /// Returns the client's [CoercionMode]
+    pub fn coercion_mode(&self) -> CoercionMode {
+        self.coercion_mode
+    }
+
+    /// Returns the client's [QueryPadding] if any
+    pub fn query_padding(&self) -> Option<QueryPadding> {
+        self.padding
+    }
+
+    /// Returns `true` if always-false WHERE clauses are sent as full-size queries
+    pub fn constant_time_false_where(&self) -> bool {
+        self.constant_time_false_where
+    }
+
     /// Creates a clear SqlQuery from SQL query text
     pub fn clear_sql(
         &self,
@@ -39,6 +86,35 @@ impl FheSqlClient {
         Ok(clear_query)
     }
 
+    /// Compiles the SQL query text without encrypting it and returns
+    /// the simplified WHERE clause, the AND/OR tree shape and a gate count
+    /// estimator (see [SqlQueryExplain])
+    pub fn explain(
+        &self,
+        sql: &str,
+        options: SqlResultOptions,
+    ) -> Result<SqlQueryExplain, FheSqlError> {
+        let (_, explain) = self.compile_query(sql, options)?;
+        Ok(explain)
+    }
+
+    /// Lists every clear metadata item and structural size the server
+    /// will observe when running the SQL query text (see [SqlLeakageReport]).
+    /// With a [QueryPadding], the `query_padding.unpadded` item lists the items
+    /// the padding does not hide (see [SqlLeakageReport::unpadded_items]).
+    pub fn leakage_report(
+        &self,
+        sql: &str,
+        options: SqlResultOptions,
+    ) -> Result<SqlLeakageReport, FheSqlError> {
+        let clear_query = self.build_query(sql, options)?;
+        let mut report = clear_query.leakage_report();
+        if self.padding.is_some() {
+            report.push_unpadded_items();
+        }
+        Ok(report)
+    }
+
     /// Creates a FHE encrypted SqlQuery from SQL query text and a [ClientKey]
     pub fn encrypt_sql(
         &self,
@@ -95,24 +171,78 @@ impl FheSqlClient {
         sql: &str,
         options: SqlResultOptions,
     ) -> Result<ClearSqlQuery, FheSqlError> {
+        let (clear_query, _) = self.compile_query(sql, options)?;
+        Ok(clear_query)
+    }
+
+    fn compile_query(
+        &self,
+        sql: &str,
+        options: SqlResultOptions,
+    ) -> Result<(ClearSqlQuery, SqlQueryExplain), FheSqlError> {
         use crate::bitops::RefNot;
         use crate::query::sql_query::ClearTableBoolMaskHeader;
         use crate::query::sql_query_tree::ClearSqlQueryTree;
-        use crate::sql_ast::and_or_ast::{compute_ast_tree, AstTreeResult};
+        use crate::query::sql_query_window::ClearSqlQueryWindow;
+        use crate::sql_ast::and_or_ast::{
+            compute_ast_tree, compute_constant_ast_tree, AstTreeResult,
+        };
+        use crate::sql_ast::column_arithmetic::*;
+        use crate::sql_ast::in_set::*;
         use crate::sql_ast::parser::*;
+        use crate::sql_ast::scalar_subquery::*;
+        use crate::sql_ast::to_parenthesized_string::ToParenthesizedString;
+        use crate::sql_ast::window::ExtractWindowFunction;
         use crate::sql_ast::*;
         use crate::uint::mask::ClearBoolMask;
         use sqlparser::{dialect::GenericDialect, parser::Parser};
 
+        options.validate()?;
+
         let dialect = GenericDialect {}; // or AnsiDialect
-        let statements = Parser::parse_sql(&dialect, sql).unwrap();
+        let mut statements = Parser::parse_sql(&dialect, sql).unwrap();
+
+        // Remove the window function (if any) from the projection
+        let window = match statements.first_mut() {
+            Some(statement) => statement.extract_window_function(&self.ordered_schemas)?,
+            None => None,
+        };
 
         // First quick synthax validation
         // Eliminate unsupported SQL features
         validate_statements(&statements, sql)?;
 
+        // Replace scalar subqueries by identifiers
+        let subqueries = statements[0].extract_scalar_subqueries(&self.ordered_schemas, self.coercion_mode)?;
+        let aggregates: Vec<AstAggregate> =
+            subqueries.iter().map(|s| s.aggregate().clone()).collect();
+
+        // Replace large and tuple IN lists by identifiers
+        let in_sets = statements[0].extract_in_sets(&self.ordered_schemas, self.coercion_mode)?;
+        let sets: Vec<AstInSet> = in_sets.iter().map(|s| s.set().clone()).collect();
+
+        // Replace column arithmetics by identifiers
+        let column_arithmetics =
+            statements[0].extract_column_arithmetics(&self.ordered_schemas, self.coercion_mode)?;
+        let arithmetics: Vec<AstArithmetic> = column_arithmetics
+            .arithmetics()
+            .iter()
+            .map(|a| a.arithmetic().clone())
+            .collect();
+
         let statement_ref = &statements[0];
 
+        let mut explain = SqlQueryExplain::new(self.ordered_schemas.clone(), options);
+        explain.window = window.is_some();
+        explain.num_aggregates = aggregates.len();
+        explain.num_arithmetics = arithmetics.len();
+        explain.num_sets = sets.len();
+
+        let with_window = |query: ClearSqlQuery| match &window {
+            Some(w) => query.with_window(w.name.clone(), ClearSqlQueryWindow::build(Some(w))),
+            None => query,
+        };
+
         // Retrieve DISTINCT option if any
         let is_distinct = match get_statement_distinct_option(statement_ref)? {
             Some(o) => match o {
@@ -125,6 +255,7 @@ impl FheSqlClient {
             },
             None => false,
         };
+        explain.distinct = is_distinct;
 
         let from = get_statement_from(statement_ref)?;
         let projection = get_statement_projections(statement_ref)?;
@@ -150,42 +281,93 @@ impl FheSqlClient {
             not_field_mask,
         };
 
-        let where_expr = match statement_ref.compile_where(table_schema)? {
-            Some(we) => we,
+        // Subqueries, arithmetics and sets identifiers are typed as extra columns
+        let where_schema = schema_with_in_sets(
+            &schema_with_column_arithmetics(
+                &schema_with_scalar_subqueries(table_schema, &subqueries),
+                column_arithmetics.arithmetics(),
+            ),
+            &in_sets,
+        );
+
+        let where_expr = match statement_ref.compile_where(&where_schema, self.coercion_mode)? {
+            Some(we) => {
+                explain.where_sql = Some(
+                    we.try_to_parenthesized_string()
+                        .unwrap_or_else(|_| we.to_string()),
+                );
+                we
+            }
+            // A padded query always has a WHERE tree
+            None if self.padding.is_some() => {
+                Box::new(sqlparser::ast::Expr::Value(sqlparser::ast::Value::Boolean(true)))
+            }
             None => {
                 // no WHERE clause is equivalent to TRUE
                 let where_tree = ClearSqlQueryTree::build(AstTreeResult::Boolean(true))?;
-                return Ok(ClearSqlQuery::new(
-                    header,
-                    is_distinct,
-                    where_tree,
-                    self.ordered_schemas.clone(),
-                    options,
+                return Ok((
+                    with_window(ClearSqlQuery::new(
+                        header,
+                        is_distinct,
+                        where_tree,
+                        self.ordered_schemas.clone(),
+                        options,
+                    )),
+                    explain,
                 ));
             }
         };
 
-        let ast_tree = compute_ast_tree(
+        let mut ast_tree = compute_ast_tree(
             &where_expr,
-            table_schema,
+            &where_schema,
             self.ordered_schemas.max_num_fields(),
+            &aggregates,
+            &arithmetics,
+            &sets,
+            self.padding.as_ref(),
         )?;
+        explain.where_is_false = ast_tree.is_false();
+        if ast_tree.is_false() && (self.constant_time_false_where || self.padding.is_some()) {
+            // Never matching comparison, the server does the same work.
+            // A padded query keeps the padded shape as well.
+            ast_tree = compute_constant_ast_tree(
+                false,
+                self.ordered_schemas.max_num_fields(),
+                &aggregates,
+                &arithmetics,
+                &sets,
+                self.padding.as_ref(),
+            )?;
+        }
         let ast_tree_is_false = ast_tree.is_false();
+        if let AstTreeResult::Tree(t) = &ast_tree {
+            explain.where_tree = Some(t.to_string());
+            explain.bool_ops_tree_levels = t.bool_ops_tree_levels();
+            explain.num_leaves = t.num_ops().len();
+            explain.num_dummy_leaves = t.num_ops().iter().filter(|op| op.is_dummy).count();
+        }
 
         let where_tree = ClearSqlQueryTree::build(ast_tree)?;
 
         if ast_tree_is_false {
-            Ok(ClearSqlQuery::new_empty(
-                self.ordered_schemas.clone(),
-                options,
+            Ok((
+                ClearSqlQuery::new_empty(self.ordered_schemas.clone(), options),
+                explain,
             ))
         } else {
-            Ok(ClearSqlQuery::new(
-                header,
-                is_distinct,
-                where_tree,
-                self.ordered_schemas.clone(),
-                options,
+            Ok((
+                with_window(
+                    ClearSqlQuery::new(
+                        header,
+                        is_distinct,
+                        where_tree,
+                        self.ordered_schemas.clone(),
+                        options,
+                    )
+                    .with_arithmetic_constants(column_arithmetics.constants().to_vec()),
+                ),
+                explain,
             ))
         }
     }
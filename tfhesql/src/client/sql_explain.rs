@@ -0,0 +1,581 @@
+use crate::query::sql_query::SqlQuery;
+use crate::sql_ast::column_arithmetic::ArithmeticOp;
+use crate::sql_ast::scalar_subquery::AggregateFunc;
+use crate::table::{CELL_ASCII_BYTES, CELL_INTEGER_BYTES};
+use crate::utils::arrow::arrow_shema_data_type_width;
+use crate::DistinctStrategy;
+use crate::FheSqlError;
+use crate::OrderedSchemas;
+use crate::SqlResultFormat;
+use crate::SqlResultOptions;
+use arrow_schema::DataType;
+
+////////////////////////////////////////////////////////////////////////////////
+// SqlGateEstimate
+////////////////////////////////////////////////////////////////////////////////
+
+/// Estimated number of boolean and u8 operations the server will perform
+/// to run a query (see [SqlQueryExplain::estimate_gates_with_strategy])
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SqlGateEstimate {
+    /// Boolean AND/OR/NOT operations (WHERE clause, DISTINCT, window function)
+    pub bool_gates: usize,
+    /// u8 AND/OR operations (result dataset masking)
+    pub u8_gates: usize,
+}
+
+impl SqlGateEstimate {
+    pub fn total(&self) -> usize {
+        self.bool_gates + self.u8_gates
+    }
+}
+
+impl std::fmt::Display for SqlGateEstimate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "bool: {} / u8: {}", self.bool_gates, self.u8_gates)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// SqlQueryExplain
+////////////////////////////////////////////////////////////////////////////////
+
+/// What the [FheSqlClient](crate::FheSqlClient) compiles from a SQL query text,
+/// before any encryption (see [FheSqlClient::explain](crate::FheSqlClient::explain))
+#[derive(Debug, Clone)]
+pub struct SqlQueryExplain {
+    pub(super) where_sql: Option<String>,
+    pub(super) where_tree: Option<String>,
+    pub(super) where_is_false: bool,
+    pub(super) bool_ops_tree_levels: u32,
+    pub(super) num_leaves: usize,
+    pub(super) num_dummy_leaves: usize,
+    pub(super) num_aggregates: usize,
+    pub(super) num_arithmetics: usize,
+    pub(super) num_sets: usize,
+    /// Total number of values of the IN sets, tuples count one value per position
+    pub(super) num_set_values: usize,
+    pub(super) num_arithmetic_constants: usize,
+    pub(super) distinct: bool,
+    pub(super) window: bool,
+    pub(super) ordered_schemas: OrderedSchemas,
+    pub(super) options: SqlResultOptions,
+}
+
+impl SqlQueryExplain {
+    pub(super) fn new(ordered_schemas: OrderedSchemas, options: SqlResultOptions) -> Self {
+        SqlQueryExplain {
+            where_sql: None,
+            where_tree: None,
+            where_is_false: false,
+            bool_ops_tree_levels: 0,
+            num_leaves: 0,
+            num_dummy_leaves: 0,
+            num_aggregates: 0,
+            num_arithmetics: 0,
+            num_sets: 0,
+            num_set_values: 0,
+            num_arithmetic_constants: 0,
+            distinct: false,
+            window: false,
+            ordered_schemas,
+            options,
+        }
+    }
+
+    /// The server side view of a received query: only the clear shape is known.
+    /// The SQL text, the dummy comparisons and the DISTINCT flag are encrypted,
+    /// `distinct` is false and every comparison counts.
+    pub(crate) fn from_query<B>(query: &SqlQuery<B>) -> Self {
+        let mut explain = SqlQueryExplain::new(query.ordered_schemas().clone(), *query.options());
+        explain.where_is_false = query.is_empty();
+        explain.window = !query.window().is_empty();
+        explain.num_aggregates = query.num_aggregates();
+        explain.num_arithmetics = query.arithmetics().len();
+        explain.num_sets = query.sets().len();
+        explain.num_set_values = (0..query.sets().len())
+            .map(|s| {
+                query
+                    .sets()
+                    .get(s)
+                    .elements
+                    .iter()
+                    .map(|e| e.len())
+                    .sum::<usize>()
+            })
+            .sum();
+        explain.num_arithmetic_constants = query.arithmetic_constants().len();
+        if !query.is_where_empty() {
+            let where_tree = query.where_tree();
+            explain.bool_ops_tree_levels = (where_tree.num_nodes() + 1).ilog2();
+            explain.num_leaves = where_tree.dummy_mask().len();
+            explain.num_dummy_leaves = explain.num_leaves - query.num_binary_ops();
+        }
+        explain
+    }
+
+    /// The simplified WHERE clause as a fully parenthesized SQL text.
+    /// `None` if the query has no WHERE clause.
+    pub fn where_sql(&self) -> Option<&String> {
+        self.where_sql.as_ref()
+    }
+
+    /// Node by node dump of the compiled AND/OR tree.
+    /// `None` if the WHERE clause has been simplified to a constant.
+    pub fn where_tree(&self) -> Option<&String> {
+        self.where_tree.as_ref()
+    }
+
+    /// True if the WHERE clause has been simplified to FALSE (empty query)
+    pub fn where_is_false(&self) -> bool {
+        self.where_is_false
+    }
+
+    /// Number of levels of the AND/OR operators tree
+    pub fn bool_ops_tree_levels(&self) -> u32 {
+        self.bool_ops_tree_levels
+    }
+
+    /// Number of comparison leaves of the AND/OR tree, dummies included
+    pub fn num_leaves(&self) -> usize {
+        self.num_leaves
+    }
+
+    /// Number of dummy leaves used to complete the AND/OR tree
+    pub fn num_dummy_leaves(&self) -> usize {
+        self.num_dummy_leaves
+    }
+
+    /// Number of comparisons the server will actually evaluate
+    pub fn num_compare_ops(&self) -> usize {
+        self.num_leaves - self.num_dummy_leaves
+    }
+
+    pub fn num_aggregates(&self) -> usize {
+        self.num_aggregates
+    }
+
+    pub fn num_arithmetics(&self) -> usize {
+        self.num_arithmetics
+    }
+
+    pub fn num_sets(&self) -> usize {
+        self.num_sets
+    }
+
+    pub fn distinct(&self) -> bool {
+        self.distinct
+    }
+
+    pub fn window(&self) -> bool {
+        self.window
+    }
+
+    /// Same as [estimate_gates_with_strategy](SqlQueryExplain::estimate_gates_with_strategy)
+    /// with the default [DistinctStrategy]
+    pub fn estimate_gates(&self, num_rows: &[usize]) -> Result<SqlGateEstimate, FheSqlError> {
+        self.estimate_gates_with_strategy(num_rows, DistinctStrategy::default())
+    }
+
+    /// Estimates the number of boolean and u8 operations the server will perform
+    /// on tables with `num_rows[i]` rows for the i-th schema of the client's
+    /// [OrderedSchemas], when the server runs the SELECT DISTINCT pass with
+    /// `distinct_strategy`.
+    ///
+    /// The estimate is a worst case: every cell value is assumed distinct for
+    /// the WHERE clause and every pair of rows is assumed partially equal for
+    /// the DISTINCT pass (which always runs since the DISTINCT flag is encrypted).
+    /// It covers the WHERE comparisons, the scalar subqueries, the column
+    /// arithmetics, the IN sets, the DISTINCT pass, the window function and the
+    /// result. The per operation costs are rough heuristics: the estimate gives
+    /// an order of magnitude, not an exact count.
+    pub fn estimate_gates_with_strategy(
+        &self,
+        num_rows: &[usize],
+        distinct_strategy: DistinctStrategy,
+    ) -> Result<SqlGateEstimate, FheSqlError> {
+        if num_rows.len() != self.ordered_schemas.len() {
+            return Err(FheSqlError::InvalidQueryError(format!(
+                "Expecting {} table sizes, got {}",
+                self.ordered_schemas.len(),
+                num_rows.len()
+            )));
+        }
+        if self.where_is_false && self.num_leaves == 0 {
+            // Empty query
+            return Ok(SqlGateEstimate::default());
+        }
+
+        let max_rows = num_rows.iter().copied().max().unwrap_or(0);
+        let num_tables = num_rows.len();
+
+        let mut cells_gates = 0;
+        let mut num_cells = 0;
+        let mut num_columns = 0;
+        let mut arithmetic_gates = 0;
+        let mut row_bytes = 0;
+        for (table_index, rows) in num_rows.iter().enumerate() {
+            let schema = self.ordered_schemas.schema(table_index);
+            let mut row_gates = COMPARE_ROW_GATES;
+            let mut row_width = 1; // EOF marker
+            if self.options.compress() {
+                row_width += COMPRESS_HEADER_BYTES;
+            }
+            for field in schema.fields() {
+                row_gates += compare_cell_gates(field.data_type());
+                row_width += arrow_shema_data_type_width(field.data_type())?;
+            }
+            cells_gates += rows * row_gates;
+            num_cells += rows * schema.fields().len();
+            num_columns += schema.fields().len();
+            row_bytes += rows * row_width;
+
+            // Every `column op column` and `column op constant` candidate expression
+            // is selected and compared on each row
+            if self.num_arithmetics > 0 {
+                let num_fields = schema.fields().len();
+                let num_candidates =
+                    num_fields * (num_fields + self.num_arithmetic_constants) * ArithmeticOp::LEN;
+                arithmetic_gates += num_candidates * (4 * self.num_arithmetics + 1)
+                    + rows * num_candidates * (2 + ARITHMETIC_COMPARE_GATES);
+            }
+        }
+        let total_rows = num_rows.iter().sum::<usize>();
+        let num_ops = self.num_compare_ops();
+
+        // WHERE clause
+        let mut bool_gates = num_ops * cells_gates;
+        if num_ops > 1 {
+            bool_gates += max_rows * self.tree_row_gates();
+        }
+
+        // Scalar subqueries: every (table, column, function) candidate aggregate
+        // is selected and compared with every distinct cell, for each comparison
+        if self.num_aggregates > 0 {
+            let num_candidates = num_columns * AggregateFunc::LEN;
+            let max_num_fields = self.ordered_schemas.max_num_fields();
+            bool_gates += num_ops
+                * num_candidates
+                * (4 * self.num_aggregates + num_cells * (3 * max_num_fields + 2));
+        }
+
+        // Column arithmetics, for each comparison
+        bool_gates += num_ops * arithmetic_gates;
+
+        // IN sets: the memberships are computed once (byte, cell and row equalities),
+        // then each comparison selects its set on each row
+        if self.num_sets > 0 {
+            let num_bytes = (SET_VALUE_BYTES * num_cells).min(SET_VALUE_BYTES * 256);
+            bool_gates += self.num_set_values
+                * (8 * num_bytes + (SET_VALUE_BYTES + 1) * num_cells + 2 * total_rows);
+            bool_gates += num_ops * max_rows * num_tables * (2 * self.num_sets + 3);
+        }
+
+        // SELECT DISTINCT
+        if max_rows > 1 {
+            let avg_cols = num_cells.div_ceil(max_rows);
+            bool_gates += match distinct_strategy {
+                // Every row i is compared with every row j < i
+                DistinctStrategy::Pairwise => {
+                    let num_pairs = max_rows * (max_rows - 1) / 2;
+                    num_pairs * (avg_cols + num_tables + 1) + 3 * (max_rows - 1)
+                }
+                // Two bitonic sorts of the row keys, n.log(n).(log(n) + 1) / 4
+                // compare-exchanges each
+                DistinctStrategy::SortNetwork => {
+                    let num_items = max_rows.next_power_of_two();
+                    let log_n = num_items.ilog2() as usize;
+                    let key_width = 1 + (avg_cols + 1) * log_n;
+                    let num_exchanges = num_items / 2 * log_n * (log_n + 1) / 2;
+                    2 * num_exchanges * COMPARE_EXCHANGE_BIT_GATES * key_width
+                        + max_rows * key_width * (2 * num_tables + 2)
+                }
+            };
+        }
+
+        // Window function: every pair of rows is compared, then the rank bits of
+        // each row are incremented
+        let window_bits = ((usize::BITS - max_rows.leading_zeros()) as usize).max(1);
+        if self.window {
+            bool_gates += max_rows * max_rows * (2 * num_tables + 4 * window_bits + 2);
+        }
+
+        // Result: Select(r) AND Table(t) AND Bytes(t, r)
+        let mut u8_gates = row_bytes;
+        if self.window {
+            u8_gates += 2 * max_rows * window_bits.div_ceil(8);
+        }
+        if num_tables > 1 {
+            u8_gates += max_rows * num_tables;
+            if matches!(self.options.format(), SqlResultFormat::RowBytes(_)) {
+                // Row by row OR
+                u8_gates += row_bytes;
+            }
+        }
+
+        // Compaction: Slot(r, j) AND Row(r) for j <= min(r, k), then OR by slot
+        if let Some(k) = self.options.max_num_rows() {
+            let num_slots = (0..max_rows).map(|r| r.min(k) + 1).sum::<usize>();
+            bool_gates += 4 * num_slots;
+            let row_width = row_bytes.div_ceil(max_rows.max(1));
+            u8_gates += 2 * num_slots * row_width;
+        }
+
+        Ok(SqlGateEstimate {
+            bool_gates,
+            u8_gates,
+        })
+    }
+
+    /// Same as [estimate_gates_with_strategy](SqlQueryExplain::estimate_gates_with_strategy)
+    /// for tables encrypted at rest (see [FheOrderedTables](crate::FheOrderedTables)),
+    /// where the SELECT DISTINCT pass is always pairwise.
+    ///
+    /// On top of the clear tables estimate, the cells are compared byte by byte: the
+    /// WHERE clause compares each cell with the value and with the other cells of its
+    /// row, the DISTINCT pass compares the cells of every pair of rows. The encrypted
+    /// byte comparisons are counted as u8 operations.
+    pub fn estimate_gates_on_encrypted_tables(
+        &self,
+        num_rows: &[usize],
+    ) -> Result<SqlGateEstimate, FheSqlError> {
+        let mut estimate =
+            self.estimate_gates_with_strategy(num_rows, DistinctStrategy::Pairwise)?;
+        if self.where_is_false && self.num_leaves == 0 {
+            // Empty query
+            return Ok(estimate);
+        }
+
+        let num_ops = self.num_compare_ops();
+        let mut row_bytes = 0;
+        for (table_index, rows) in num_rows.iter().enumerate() {
+            let cell_bytes = self
+                .ordered_schemas
+                .schema(table_index)
+                .fields()
+                .iter()
+                .map(|field| encrypted_cell_bytes(field.data_type()))
+                .collect::<Vec<usize>>();
+            // Cell vs Value, for each comparison
+            let value_bytes = cell_bytes.iter().sum::<usize>();
+            // Cell vs Cell of the same row, shared by all the comparisons
+            let col_pair_bytes = (0..cell_bytes.len())
+                .flat_map(|i| (i + 1..cell_bytes.len()).map(move |j| (i, j)))
+                .map(|(i, j)| cell_bytes[i].min(cell_bytes[j]))
+                .sum::<usize>();
+            if num_ops > 0 {
+                let compared_bytes = rows * (num_ops * value_bytes + col_pair_bytes);
+                estimate.u8_gates += 2 * compared_bytes;
+                estimate.bool_gates += BYTE_COMPARE_GATES * compared_bytes;
+            }
+            row_bytes += value_bytes;
+        }
+
+        // SELECT DISTINCT: byte equality of every pair of rows
+        let max_rows = num_rows.iter().copied().max().unwrap_or(0);
+        if max_rows > 1 {
+            let num_pairs = max_rows * (max_rows - 1) / 2;
+            estimate.u8_gates += num_pairs * row_bytes;
+            estimate.bool_gates += num_pairs * row_bytes;
+        }
+
+        Ok(estimate)
+    }
+
+    /// Number of boolean operations needed by each row to combine the
+    /// comparison results through the AND/OR tree
+    fn tree_row_gates(&self) -> usize {
+        let num_nodes = (1_usize << self.bool_ops_tree_levels) - 1;
+        let mut gates = num_nodes * TREE_NODE_GATES;
+
+        // Each tree leaf selects its comparison among the
+        // [max(1, i-D), min(n-1, i)] possible ones (see SqlQueryTree::ops_at)
+        let d = self.num_dummy_leaves;
+        let n = self.num_compare_ops();
+        if d > 0 {
+            (1..self.num_leaves).for_each(|i| {
+                let min = if i <= d { 1 } else { i - d };
+                let max = (n - 1).min(i);
+                let len = max + 1 - min;
+                if len > 1 {
+                    // len x AND + (len - 1) x OR
+                    gates += 2 * len - 1;
+                }
+            });
+        }
+        gates
+    }
+}
+
+// Rough per operation heuristics, derived from the structure of the server
+// computations, not measured.
+const COMPARE_ROW_GATES: usize = 16;
+const TREE_NODE_GATES: usize = 4;
+const COMPRESS_HEADER_BYTES: usize = 8;
+const ARITHMETIC_COMPARE_GATES: usize = 21;
+const COMPARE_EXCHANGE_BIT_GATES: usize = 10;
+/// IN set values are 256 bits wide
+const SET_VALUE_BYTES: usize = 32;
+/// Ordered reduction of the eq/gt flags of an encrypted byte comparison
+const BYTE_COMPARE_GATES: usize = 3;
+
+fn compare_cell_gates(data_type: &DataType) -> usize {
+    match data_type {
+        DataType::Boolean => 5,
+        DataType::Int8 | DataType::UInt8 => 12,
+        DataType::Int16 | DataType::UInt16 => 15,
+        DataType::Int32 | DataType::UInt32 => 18,
+        DataType::Int64 | DataType::UInt64 => 21,
+        _ => 13,
+    }
+}
+
+/// Number of encrypted bytes compared for a cell of [FheOrderedTables](crate::FheOrderedTables)
+fn encrypted_cell_bytes(data_type: &DataType) -> usize {
+    match data_type {
+        DataType::Utf8 => CELL_ASCII_BYTES,
+        _ => CELL_INTEGER_BYTES,
+    }
+}
+
+impl std::fmt::Display for SqlQueryExplain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.where_sql {
+            Some(s) => writeln!(f, "WHERE: {}", s)?,
+            None => writeln!(f, "WHERE: <none>")?,
+        }
+        if self.where_is_false {
+            writeln!(f, "WHERE is always FALSE")?;
+        }
+        writeln!(f, "DISTINCT: {}", self.distinct)?;
+        writeln!(f, "Window: {}", self.window)?;
+        writeln!(f, "Tree levels: {}", self.bool_ops_tree_levels)?;
+        writeln!(
+            f,
+            "Leaves: {} ({} dummies)",
+            self.num_leaves, self.num_dummy_leaves
+        )?;
+        writeln!(
+            f,
+            "Aggregates: {} / Arithmetics: {} / Sets: {}",
+            self.num_aggregates, self.num_arithmetics, self.num_sets
+        )?;
+        if let Some(tree) = &self.where_tree {
+            f.write_str(tree)?;
+        }
+        Ok(())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::SqlQueryExplain;
+    use crate::test::simple_batch::{simple_batch_1, simple_batch_3};
+    use crate::{FheSqlClient, OrderedTables, QueryPadding, SqlResultOptions, Table};
+
+    #[test]
+    fn test_explain() {
+        let t1 = Table::new("table1", simple_batch_1());
+        let t3 = Table::new("table3", simple_batch_3());
+        let tables = OrderedTables::new(vec![t1, t3]).unwrap();
+        let sql_client = FheSqlClient::new(tables.ordered_schemas().clone()).unwrap();
+        let options = SqlResultOptions::default();
+
+        let explain = sql_client
+            .explain("SELECT ProductID FROM table3", options)
+            .unwrap();
+        assert!(explain.where_sql().is_none());
+        assert_eq!(explain.num_leaves(), 0);
+
+        let explain = sql_client
+            .explain(
+                "SELECT ProductID FROM table3 WHERE Type > 70 AND Type <= 550",
+                options,
+            )
+            .unwrap();
+        assert_eq!(
+            explain.where_sql().unwrap(),
+            "((Type > 70) AND (Type <= 550))"
+        );
+        assert_eq!(explain.bool_ops_tree_levels(), 1);
+        assert_eq!(explain.num_leaves(), 2);
+        assert_eq!(explain.num_dummy_leaves(), 0);
+        assert!(explain.where_tree().is_some());
+
+        let explain_3 = sql_client
+            .explain(
+                "SELECT DISTINCT ProductID FROM table3 WHERE Type > 70 AND Type <= 550 OR ProductID = 1",
+                options,
+            )
+            .unwrap();
+        assert!(explain_3.distinct());
+        assert_eq!(explain_3.bool_ops_tree_levels(), 2);
+        assert_eq!(explain_3.num_leaves(), 4);
+        assert_eq!(explain_3.num_dummy_leaves(), 1);
+        assert_eq!(explain_3.num_compare_ops(), 3);
+
+        let small = explain.estimate_gates(&[10, 10]).unwrap();
+        let large = explain.estimate_gates(&[100, 100]).unwrap();
+        assert!(small.bool_gates > 0 && small.u8_gates > 0);
+        assert!(large.bool_gates > small.bool_gates);
+        assert!(large.u8_gates > small.u8_gates);
+        assert!(explain_3.estimate_gates(&[10, 10]).unwrap().bool_gates > small.bool_gates);
+        assert!(explain.estimate_gates(&[10]).is_err());
+
+        let explain = sql_client
+            .explain("SELECT ProductID FROM table3 WHERE 1 = 2", options)
+            .unwrap();
+        assert!(explain.where_is_false());
+        assert_eq!(explain.estimate_gates(&[10, 10]).unwrap().total(), 0);
+    }
+
+    #[test]
+    fn test_explain_from_query() {
+        let t1 = Table::new("table1", simple_batch_1());
+        let t3 = Table::new("table3", simple_batch_3());
+        let tables = OrderedTables::new(vec![t1, t3]).unwrap();
+        let sql_client = FheSqlClient::new(tables.ordered_schemas().clone()).unwrap();
+        let padded_client = FheSqlClient::new(tables.ordered_schemas().clone())
+            .unwrap()
+            .with_query_padding(QueryPadding::new(3, 6).unwrap());
+        let options = SqlResultOptions::default();
+
+        let sqls = [
+            "SELECT ProductID FROM table3",
+            "SELECT ProductID FROM table3 WHERE Type > 70",
+            "SELECT ProductID FROM table3 WHERE Type > 70 AND Type <= 550",
+            "SELECT ProductID FROM table3 WHERE Type > 70 AND Type <= 550 OR ProductID = 1",
+            "SELECT ProductID FROM table3 WHERE 1 = 2",
+        ];
+        for client in [&sql_client, &padded_client] {
+            for sql in sqls {
+                let explain = client.explain(sql, options).unwrap();
+                let query = client.clear_sql(sql, options).unwrap();
+                let server_explain = SqlQueryExplain::from_query(&query);
+                assert_eq!(
+                    server_explain.bool_ops_tree_levels(),
+                    explain.bool_ops_tree_levels(),
+                    "{}",
+                    sql
+                );
+                assert_eq!(server_explain.num_leaves(), explain.num_leaves(), "{}", sql);
+                assert_eq!(
+                    server_explain.num_dummy_leaves(),
+                    explain.num_dummy_leaves(),
+                    "{}",
+                    sql
+                );
+                assert_eq!(
+                    server_explain.estimate_gates(&[10, 10]).unwrap(),
+                    explain.estimate_gates(&[10, 10]).unwrap(),
+                    "{}",
+                    sql
+                );
+            }
+        }
+    }
+}
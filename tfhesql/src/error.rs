@@ -14,6 +14,12 @@ pub enum FheSqlError {
     InternalError(String),
     DecryptError(String),
     InvalidQueryError(String),
+    SchemaMismatch(String),
+    Cancelled,
+    CostLimitExceeded {
+        estimated_gates: usize,
+        max_gates: usize,
+    },
 }
 
 impl Error for FheSqlError {}
@@ -65,6 +71,15 @@ impl Display for FheSqlError {
             FheSqlError::InternalError(desc) => write!(f, "Internal error: {desc}"),
             FheSqlError::DecryptError(desc) => write!(f, "Decrypt error: {desc}"),
             FheSqlError::InvalidQueryError(desc) => write!(f, "Invalid query error: {desc}"),
+            FheSqlError::SchemaMismatch(desc) => write!(f, "Schema mismatch: {desc}"),
+            FheSqlError::Cancelled => write!(f, "Query cancelled"),
+            FheSqlError::CostLimitExceeded {
+                estimated_gates,
+                max_gates,
+            } => write!(
+                f,
+                "Query rejected: {estimated_gates} estimated gates, the server limit is {max_gates}"
+            ),
         }
     }
 }
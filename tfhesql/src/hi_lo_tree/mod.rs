@@ -8,6 +8,7 @@ pub use u64_hi_lo_logic_op_tree::U64EquTree;
 pub mod traits {
     pub use super::u64_hi_lo_logic_op_tree::CompareToUnsignedInteger;
     pub use super::u64_hi_lo_logic_op_tree::CompareToSignedInteger;
+    pub use super::hi_lo_logic_op::HiLoLogicOp;
 }
 
 mod eq_gt_lt;
@@ -15,6 +16,7 @@ mod eq_gt_lt;
 pub use eq_gt_lt::EqGtLt;
 
 mod eq_gt;
+pub use eq_gt::EqGt;
 pub use eq_gt::Bytes64EqGt;
 pub use eq_gt::ClearBytes64EqGt;
 
@@ -27,6 +27,9 @@ pub use error::FheSqlError;
 pub use table::Table;
 pub use table::OrderedTables;
 pub use table::OrderedSchemas;
+pub use table::EncryptedOrderedTables;
+pub use table::ClearEncryptedOrderedTables;
+pub use table::FheOrderedTables;
 
 pub use query::ClearSqlQuery;
 pub use query::ClearSqlResult;
@@ -34,13 +37,36 @@ pub use query::FheSqlQuery;
 pub use query::CompressedFheSqlQuery;
 pub use query::CompactFheSqlQuery;
 pub use query::FheSqlResult;
+pub use query::ClearSqlResultHeader;
+pub use query::ClearSqlResultChunk;
+pub use query::FheSqlResultHeader;
+pub use query::FheSqlResultChunk;
 pub use query::SqlResultFormat;
 pub use query::SqlResultOptions;
+pub use query::SqlLeakageItem;
+pub use query::SqlLeakageReport;
 
 pub use client::FheSqlClient;
+pub use client::SqlGateEstimate;
+pub use client::SqlQueryExplain;
+pub use sql_ast::coercion::CoercionMode;
+pub use sql_ast::and_or_ast::QueryPadding;
 
 pub use server::FheSqlServer;
 pub use server::FheRunSqlQuery;
+pub use server::FheRunSqlQueryOnEncryptedTables;
+pub use server::SqlResultStreamIter;
+pub use server::DistinctStrategy;
+pub use server::SqlServerOptions;
+pub use server::RunControl;
+pub use server::SqlCheckpoint;
+pub use server::RunProgress;
+pub use server::RunStage;
+pub use server::ServerKeyPool;
+pub use server::FheSqlService;
+pub use server::SqlServiceOptions;
+pub use server::SqlServiceQuery;
+pub use server::SqlServiceResult;
 
 pub mod bounty_api;
 
@@ -24,6 +24,13 @@ impl<K, V> Default for IndexedMap<K, V> {
     }
 }
 
+impl<K, V> IndexedMap<K, V> {
+    /// Returns the keys in insertion order
+    pub fn into_keys(self) -> Vec<K> {
+        self.keys
+    }
+}
+
 impl<K, V> IndexedMap<K, V>
 where
     K: std::hash::Hash + std::cmp::Eq + Clone,
@@ -3,15 +3,28 @@ pub mod optional_bool_tree;
 pub mod sql_query;
 pub mod sql_query_tree;
 pub mod sql_query_binops;
+pub mod sql_query_aggregate;
+pub mod sql_query_arithmetic;
+pub mod sql_query_set;
+pub mod sql_query_window;
 pub mod sql_query_value;
+pub mod sql_leakage;
 pub mod sql_result;
+pub mod sql_result_chunk;
 pub mod sql_result_options;
 
 pub use sql_result_options::SqlResultFormat;
 pub use sql_result_options::SqlResultOptions;
 
+pub use sql_leakage::SqlLeakageItem;
+pub use sql_leakage::SqlLeakageReport;
+
 pub use sql_result::ClearSqlResult;
 pub use sql_result::FheSqlResult;
+pub use sql_result_chunk::ClearSqlResultChunk;
+pub use sql_result_chunk::ClearSqlResultHeader;
+pub use sql_result_chunk::FheSqlResultChunk;
+pub use sql_result_chunk::FheSqlResultHeader;
 
 pub use sql_query::ClearSqlQuery;
 pub use sql_query::FheSqlQuery;
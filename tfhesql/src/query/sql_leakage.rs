@@ -0,0 +1,334 @@
+use super::sql_query::SqlQuery;
+use crate::SqlResultFormat;
+
+////////////////////////////////////////////////////////////////////////////////
+// SqlLeakageItem
+////////////////////////////////////////////////////////////////////////////////
+
+/// A single clear metadata item or structural size observed by the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SqlLeakageItem {
+    pub name: &'static str,
+    pub value: String,
+    pub description: &'static str,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// SqlLeakageReport
+////////////////////////////////////////////////////////////////////////////////
+
+/// Everything the server learns from a query without decrypting it:
+/// the clear part of the query and the length of every encrypted array.
+/// Items that are fully determined by the public [OrderedSchemas](crate::OrderedSchemas)
+/// are listed as well, for completeness.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SqlLeakageReport {
+    items: Vec<SqlLeakageItem>,
+}
+
+impl SqlLeakageReport {
+    pub fn items(&self) -> &Vec<SqlLeakageItem> {
+        &self.items
+    }
+
+    /// Returns the item named `name` (if any)
+    pub fn get(&self, name: &str) -> Option<&SqlLeakageItem> {
+        self.items.iter().find(|item| item.name == name)
+    }
+
+    /// The items that [QueryPadding](crate::QueryPadding) does not hide and whose
+    /// value differs from the one of a plain query: padded queries can still be
+    /// told apart by these items
+    pub fn unpadded_items(&self) -> Vec<&SqlLeakageItem> {
+        self.items
+            .iter()
+            .filter(|item| {
+                UNPADDED_ITEMS
+                    .iter()
+                    .any(|(name, plain)| item.name == *name && item.value != *plain)
+            })
+            .collect()
+    }
+
+    /// Adds the names of the [unpadded_items](Self::unpadded_items) to the report
+    pub(crate) fn push_unpadded_items(&mut self) {
+        let names = self
+            .unpadded_items()
+            .iter()
+            .map(|item| item.name)
+            .collect::<Vec<&str>>();
+        self.push(
+            "query_padding.unpadded",
+            if names.is_empty() {
+                "<none>".to_string()
+            } else {
+                names.join(",")
+            },
+            "items not hidden by the query padding",
+        );
+    }
+
+    fn push<T: ToString>(&mut self, name: &'static str, value: T, description: &'static str) {
+        self.items.push(SqlLeakageItem {
+            name,
+            value: value.to_string(),
+            description,
+        })
+    }
+}
+
+/// The items not hidden by [QueryPadding](crate::QueryPadding), with their
+/// value for a query without scalar subquery, arithmetic, IN set or window function
+const UNPADDED_ITEMS: [(&str, &str); 7] = [
+    ("window_name", "<none>"),
+    ("arithmetic_constants", "[]"),
+    ("num_aggregates", "0"),
+    ("num_arithmetics", "0"),
+    ("num_sets", "0"),
+    ("sets.shape", "[]"),
+    ("window", "false"),
+];
+
+impl std::fmt::Display for SqlLeakageReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.items.iter().try_for_each(|item| {
+            writeln!(f, "{}: {} ({})", item.name, item.value, item.description)
+        })
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+impl<B> SqlQuery<B> {
+    /// Lists every clear metadata item and structural size the server
+    /// observes when running this query.
+    pub fn leakage_report(&self) -> SqlLeakageReport {
+        let mut r = SqlLeakageReport::default();
+
+        // Clear part of the query
+        r.push(
+            "options.compress",
+            self.options().compress(),
+            "clear, result compression",
+        );
+        r.push(
+            "options.format",
+            match self.options().format() {
+                SqlResultFormat::RowBytes(padding) => format!("RowBytes({})", padding),
+                SqlResultFormat::TableBytesInRowOrder => "TableBytesInRowOrder".to_string(),
+                SqlResultFormat::TableBytesInColumnOrder => "TableBytesInColumnOrder".to_string(),
+            },
+            "clear, result format",
+        );
+        r.push(
+            "options.max_num_rows",
+            self.options()
+                .max_num_rows()
+                .map_or("<none>".to_string(), |k| k.to_string()),
+            "clear, number of result rows after compaction",
+        );
+        let schemas = self.ordered_schemas();
+        r.push(
+            "ordered_schemas",
+            (0..schemas.len())
+                .map(|i| {
+                    let fields: Vec<&str> = schemas
+                        .schema(i)
+                        .fields()
+                        .iter()
+                        .map(|f| f.name().as_str())
+                        .collect();
+                    format!("{}({})", schemas.name(i), fields.join(","))
+                })
+                .collect::<Vec<String>>()
+                .join(" "),
+            "clear, shared with the client",
+        );
+        r.push(
+            "window_name",
+            self.window_name().map_or("<none>", |n| n.as_str()),
+            "clear, window function output column name",
+        );
+        r.push(
+            "arithmetic_constants",
+            format!("{:?}", self.arithmetic_constants()),
+            "clear, column arithmetic right operands",
+        );
+
+        // Shortcuts
+        r.push(
+            "is_empty",
+            self.is_empty(),
+            "WHERE clause folded to FALSE, the server returns an empty result",
+        );
+        if self.is_empty() {
+            return r;
+        }
+        r.push(
+            "is_where_empty",
+            self.is_where_empty(),
+            "no WHERE clause (or folded to TRUE), no comparison is computed",
+        );
+
+        // Encrypted arrays lengths
+        let header = self.header();
+        r.push(
+            "table_mask.len",
+            header.table_mask.len(),
+            "number of tables, from ordered_schemas",
+        );
+        r.push(
+            "field_mask.len",
+            header.field_mask.len(),
+            "max number of columns, from ordered_schemas",
+        );
+        let where_tree = self.where_tree();
+        r.push(
+            "where_tree.num_nodes",
+            where_tree.num_nodes(),
+            "AND/OR operators, reveals the WHERE tree depth",
+        );
+        r.push(
+            "where_tree.num_leaves",
+            where_tree.dummy_mask().len(),
+            "comparison leaves, dummies included",
+        );
+        r.push(
+            "where_tree.num_compare_ops",
+            self.num_binary_ops(),
+            "encrypted comparisons",
+        );
+        r.push(
+            "num_aggregates",
+            self.num_aggregates(),
+            "scalar subqueries",
+        );
+        r.push(
+            "num_arithmetics",
+            self.arithmetics().len(),
+            "column arithmetic expressions",
+        );
+        r.push("num_sets", self.sets().len(), "IN sets");
+        r.push(
+            "sets.shape",
+            format!(
+                "{:?}",
+                (0..self.sets().len())
+                    .map(|i| {
+                        let set = self.sets().get(i);
+                        (set.ident_masks.len(), set.elements.len())
+                    })
+                    .collect::<Vec<(usize, usize)>>()
+            ),
+            "(tuple arity, number of elements) of each IN set",
+        );
+        r.push(
+            "window",
+            !self.window().is_empty(),
+            "a window function is requested",
+        );
+
+        r
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use crate::test::simple_batch::{simple_batch_2, simple_batch_3};
+    use crate::{FheSqlClient, OrderedTables, QueryPadding, SqlResultOptions, Table};
+
+    #[test]
+    fn test_leakage_report() {
+        let t2 = Table::new("table2", simple_batch_2());
+        let t3 = Table::new("table3", simple_batch_3());
+        let tables = OrderedTables::new(vec![t2, t3]).unwrap();
+        let sql_client = FheSqlClient::new(tables.ordered_schemas().clone()).unwrap();
+        let options = SqlResultOptions::default();
+
+        let value = |sql: &str, client: &FheSqlClient, name: &str| {
+            client
+                .leakage_report(sql, options)
+                .unwrap()
+                .get(name)
+                .map(|item| item.value.clone())
+        };
+
+        let sql = "SELECT ProductID FROM table3 WHERE Type > 70 AND Type <= 550 OR ProductID = 1";
+        let report = sql_client.leakage_report(sql, options).unwrap();
+        let lines = report.to_string();
+        assert_eq!(lines.lines().count(), report.items().len());
+        assert!(lines.contains("where_tree.num_compare_ops: 3 (encrypted comparisons)\n"));
+        assert!(lines.contains("window_name: <none> (clear, window function output column name)\n"));
+        assert_eq!(
+            report.get("ordered_schemas").unwrap().value,
+            "table2(ProductID,Type,Style,Category,Name) table3(ProductID,Type)"
+        );
+        assert_eq!(report.get("is_empty").unwrap().value, "false");
+        assert_eq!(report.get("where_tree.num_compare_ops").unwrap().value, "3");
+        assert_eq!(report.get("where_tree.num_leaves").unwrap().value, "4");
+        assert_eq!(report.get("where_tree.num_nodes").unwrap().value, "3");
+
+        let sql = "SELECT ProductID FROM table3 WHERE Type % 3 > 1";
+        assert_eq!(
+            value(sql, &sql_client, "arithmetic_constants").unwrap(),
+            "[3]"
+        );
+        // Folded into the encrypted compared value
+        let sql = "SELECT ProductID FROM table3 WHERE Type + 3 > 70";
+        assert_eq!(
+            value(sql, &sql_client, "arithmetic_constants").unwrap(),
+            "[]"
+        );
+
+        // Folded to FALSE
+        let sql = "SELECT ProductID FROM table3 WHERE 1 = 2";
+        assert_eq!(value(sql, &sql_client, "is_empty").unwrap(), "true");
+        assert!(value(sql, &sql_client, "where_tree.num_compare_ops").is_none());
+
+        // Padded queries have the same shape
+        let padded_client = FheSqlClient::new(tables.ordered_schemas().clone())
+            .unwrap()
+            .with_query_padding(QueryPadding::new(2, 3).unwrap());
+        let a = padded_client
+            .leakage_report("SELECT ProductID FROM table3", options)
+            .unwrap();
+        let b = padded_client
+            .leakage_report("SELECT * FROM table2 WHERE Type < 7 AND Style > 9", options)
+            .unwrap();
+        assert_eq!(a, b);
+        let f = padded_client
+            .leakage_report("SELECT ProductID FROM table3 WHERE 1 = 2", options)
+            .unwrap();
+        assert_eq!(a, f);
+        assert_eq!(a.get("query_padding.unpadded").unwrap().value, "<none>");
+        assert!(sql_client
+            .leakage_report("SELECT ProductID FROM table3", options)
+            .unwrap()
+            .get("query_padding.unpadded")
+            .is_none());
+
+        // Subqueries, arithmetics, sets and window functions are not padded
+        let c = padded_client
+            .leakage_report(
+                "SELECT ProductID FROM table3 WHERE Type IN (1, 2, 3, 4) AND Type % 3 > 1",
+                options,
+            )
+            .unwrap();
+        assert_ne!(a, c);
+        assert_eq!(
+            c.get("query_padding.unpadded").unwrap().value,
+            "arithmetic_constants,num_arithmetics,num_sets,sets.shape"
+        );
+        assert_eq!(
+            c.unpadded_items()
+                .iter()
+                .map(|item| item.name)
+                .collect::<Vec<&str>>(),
+            ["arithmetic_constants", "num_arithmetics", "num_sets", "sets.shape"]
+        );
+    }
+}
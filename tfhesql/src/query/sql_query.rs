@@ -1,6 +1,11 @@
+use super::sql_query_aggregate::SqlQueryAggregate;
+use super::sql_query_arithmetic::SqlQueryArithmetics;
 use super::sql_query_binops::SqlQueryBinaryOp;
+use super::sql_query_set::SqlQuerySets;
 use super::sql_query_tree::ClearSqlQueryTree;
 use super::sql_query_tree::SqlQueryTree;
+use super::sql_query_window::ClearSqlQueryWindow;
+use super::sql_query_window::SqlQueryWindow;
 use crate::default_into::*;
 use crate::encrypt::*;
 use crate::encrypt::traits::*;
@@ -62,6 +67,12 @@ pub struct SqlQuery<B> {
     // the client and the server. It is included in the query, so that the server
     // can check if the tables it is manipulating are in sync with the client schemas
     ordered_schemas: OrderedSchemas,
+
+    // name of the window function output column (if any)
+    window_name: Option<String>,
+
+    // right operand constants of the column arithmetics (if any)
+    arithmetic_constants: Vec<i64>,
 }
 
 pub type SqlQueryRef<B> = Arc<SqlQuery<B>>;
@@ -75,9 +86,10 @@ struct EncryptedSqlQuery<B> {
     header: TableBoolMaskHeader<B>,
     is_distinct: B,
     where_tree: SqlQueryTree<B>,
+    window: SqlQueryWindow<B>,
 }
 
-derive3_encrypt_decrypt! { EncryptedSqlQuery<B> {header: TableBoolMaskHeader<B>, is_distinct: B, where_tree: SqlQueryTree<B>} }
+derive4_encrypt_decrypt! { EncryptedSqlQuery<B> {header: TableBoolMaskHeader<B>, is_distinct: B, where_tree: SqlQueryTree<B>, window: SqlQueryWindow<B>} }
 
 type ClearEncryptedSqlQuery = EncryptedSqlQuery<bool>;
 
@@ -93,6 +105,8 @@ impl tfhe::prelude::FheDecrypt<ClearSqlQuery> for FheSqlQuery {
             enc: self.enc.decrypt(key),
             options: self.options,
             ordered_schemas: self.ordered_schemas.clone(),
+            window_name: self.window_name.clone(),
+            arithmetic_constants: self.arithmetic_constants.clone(),
         }
     }
 }
@@ -146,6 +160,8 @@ impl FheSqlQuery {
             enc: self.enc.try_decrypt_trivial()?,
             options: self.options,
             ordered_schemas: self.ordered_schemas.clone(),
+            window_name: self.window_name.clone(),
+            arithmetic_constants: self.arithmetic_constants.clone(),
         })
     }
 }
@@ -157,6 +173,8 @@ impl CompressedFheSqlQuery {
         SqlQuery {
             options: self.options,
             ordered_schemas: self.ordered_schemas.clone(),
+            window_name: self.window_name.clone(),
+            arithmetic_constants: self.arithmetic_constants.clone(),
             enc: self.enc.decompress(),
         }
     }
@@ -169,6 +187,8 @@ impl CompactFheSqlQuery {
         SqlQuery {
             options: self.options,
             ordered_schemas: self.ordered_schemas.clone(),
+            window_name: self.window_name.clone(),
+            arithmetic_constants: self.arithmetic_constants.clone(),
             enc: self.enc.expand(),
         }
     }
@@ -184,10 +204,13 @@ where
         SqlQuery {
             options,
             ordered_schemas,
+            window_name: None,
+            arithmetic_constants: vec![],
             enc: EncryptedSqlQuery::<B> {
                 header: TableBoolMaskHeader::<B>::new_empty(),
                 is_distinct: B::get_false(),
                 where_tree: SqlQueryTree::<B>::new_empty(),
+                window: SqlQueryWindow::<B>::new_empty(),
             },
         }
     }
@@ -229,6 +252,31 @@ impl<B> SqlQuery<B> {
         self.where_tree().compare_ops.get(index)
     }
 
+    #[inline]
+    pub(crate) fn num_aggregates(&self) -> usize {
+        self.where_tree().compare_ops.num_aggregates()
+    }
+
+    #[inline]
+    pub(crate) fn aggregate_at(&self, index: usize) -> &SqlQueryAggregate<B> {
+        self.where_tree().compare_ops.aggregate(index)
+    }
+
+    #[inline]
+    pub(crate) fn aggregate_mask_at(&self, binary_op_index: usize) -> &BoolMask<B> {
+        self.where_tree().compare_ops.aggregate_mask(binary_op_index)
+    }
+
+    #[inline]
+    pub(crate) fn arithmetics(&self) -> &SqlQueryArithmetics<B> {
+        self.where_tree().compare_ops.arithmetics()
+    }
+
+    #[inline]
+    pub(crate) fn sets(&self) -> &SqlQuerySets<B> {
+        self.where_tree().compare_ops.sets()
+    }
+
     #[inline]
     pub(crate) fn is_where_empty(&self) -> bool {
         self.where_tree().is_empty()
@@ -238,6 +286,21 @@ impl<B> SqlQuery<B> {
     pub(crate) fn where_tree(&self) -> &SqlQueryTree<B> {
         &self.enc.where_tree
     }
+
+    #[inline]
+    pub(crate) fn window(&self) -> &SqlQueryWindow<B> {
+        &self.enc.window
+    }
+
+    #[inline]
+    pub(crate) fn window_name(&self) -> Option<&String> {
+        self.window_name.as_ref()
+    }
+
+    #[inline]
+    pub(crate) fn arithmetic_constants(&self) -> &[i64] {
+        &self.arithmetic_constants
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -256,8 +319,25 @@ impl ClearSqlQuery {
             enc: ClearEncryptedSqlQuery::new(header, is_distinct, where_tree),
             options,
             ordered_schemas,
+            window_name: None,
+            arithmetic_constants: vec![],
         }
     }
+
+    pub(crate) fn with_window(
+        mut self,
+        window_name: String,
+        window: ClearSqlQueryWindow,
+    ) -> Self {
+        self.window_name = Some(window_name);
+        self.enc.window = window;
+        self
+    }
+
+    pub(crate) fn with_arithmetic_constants(mut self, arithmetic_constants: Vec<i64>) -> Self {
+        self.arithmetic_constants = arithmetic_constants;
+        self
+    }
 }
 
 impl ClearEncryptedSqlQuery {
@@ -270,6 +350,7 @@ impl ClearEncryptedSqlQuery {
             header,
             is_distinct,
             where_tree,
+            window: ClearSqlQueryWindow::new_empty(),
         }
     }
 }
@@ -290,6 +371,8 @@ where
             enc: value.enc.encrypt_into(key),
             options: value.options,
             ordered_schemas: value.ordered_schemas.clone(),
+            window_name: value.window_name.clone(),
+            arithmetic_constants: value.arithmetic_constants.clone(),
         }
     }
 }
@@ -305,6 +388,8 @@ where
             enc: value.enc.encrypt_trivial_into(),
             options: value.options,
             ordered_schemas: value.ordered_schemas.clone(),
+            window_name: value.window_name.clone(),
+            arithmetic_constants: value.arithmetic_constants.clone(),
         }
     }
 }
@@ -319,6 +404,8 @@ where
             enc: EncryptedSqlQuery::<C>::default_into(),
             options: SqlResultOptions::default(),
             ordered_schemas: OrderedSchemas::new_empty(),
+            window_name: None,
+            arithmetic_constants: vec![],
         }
     }
 }
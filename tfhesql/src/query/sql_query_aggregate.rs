@@ -0,0 +1,88 @@
+use crate::default_into::*;
+use crate::encrypt::*;
+use crate::encrypt::traits::*;
+use crate::sql_ast::scalar_subquery::AstAggregate;
+use crate::uint::mask::BoolMask;
+
+////////////////////////////////////////////////////////////////////////////////
+// SqlQueryAggregate
+////////////////////////////////////////////////////////////////////////////////
+
+/// Encrypted `AGG(column) FROM table` scalar subquery.
+/// The aggregate value itself is computed by the server.
+#[derive(Clone, PartialEq, Eq, Debug, serde::Deserialize, serde::Serialize)]
+pub struct SqlQueryAggregate<B> {
+    pub table_mask: BoolMask<B>,
+    pub ident_mask: BoolMask<B>,
+    /// See [`crate::sql_ast::scalar_subquery::AggregateFunc`]
+    pub func_mask: BoolMask<B>,
+}
+
+derive3_encrypt_decrypt! { SqlQueryAggregate<B> {table_mask: BoolMask<B>, ident_mask: BoolMask<B>, func_mask: BoolMask<B>} }
+
+pub type ClearSqlQueryAggregate = SqlQueryAggregate<bool>;
+
+impl ClearSqlQueryAggregate {
+    pub(super) fn build(value: &AstAggregate) -> Self {
+        ClearSqlQueryAggregate {
+            table_mask: value.table_mask.clone(),
+            ident_mask: value.ident_mask.clone(),
+            func_mask: value.func_mask.clone(),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// SqlQueryAggregates
+////////////////////////////////////////////////////////////////////////////////
+
+/// Scalar subquery leaves: for each binary op, a mask over `aggregates`.
+/// A binary op compares its left operand against the server-computed
+/// value of the selected aggregate (if any).
+/// Empty when the query does not contain any scalar subquery.
+#[derive(Clone, PartialEq, Eq, Debug, serde::Deserialize, serde::Serialize)]
+pub struct SqlQueryAggregates<B> {
+    pub(super) masks: Vec<BoolMask<B>>,
+    pub(super) aggregates: Vec<SqlQueryAggregate<B>>,
+}
+
+derive2_encrypt_decrypt! { SqlQueryAggregates<B> {masks: Vec<BoolMask<B>>, aggregates: Vec<SqlQueryAggregate<B>>} }
+
+pub type ClearSqlQueryAggregates = SqlQueryAggregates<bool>;
+
+impl ClearSqlQueryAggregates {
+    pub(super) fn build(masks: Vec<BoolMask<bool>>, aggregates: &[AstAggregate]) -> Self {
+        if aggregates.is_empty() {
+            return SqlQueryAggregates::new_empty();
+        }
+        ClearSqlQueryAggregates {
+            masks,
+            aggregates: aggregates
+                .iter()
+                .map(ClearSqlQueryAggregate::build)
+                .collect(),
+        }
+    }
+}
+
+impl<B> SqlQueryAggregates<B> {
+    #[inline]
+    pub fn new_empty() -> Self {
+        SqlQueryAggregates::<B> {
+            masks: vec![],
+            aggregates: vec![],
+        }
+    }
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.aggregates.len()
+    }
+    #[inline]
+    pub fn get(&self, index: usize) -> &SqlQueryAggregate<B> {
+        &self.aggregates[index]
+    }
+    #[inline]
+    pub fn mask(&self, binary_op_index: usize) -> &BoolMask<B> {
+        &self.masks[binary_op_index]
+    }
+}
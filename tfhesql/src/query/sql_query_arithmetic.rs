@@ -0,0 +1,93 @@
+use crate::default_into::*;
+use crate::encrypt::*;
+use crate::encrypt::traits::*;
+use crate::sql_ast::column_arithmetic::AstArithmetic;
+use crate::uint::mask::BoolMask;
+
+////////////////////////////////////////////////////////////////////////////////
+// SqlQueryArithmetic
+////////////////////////////////////////////////////////////////////////////////
+
+/// Encrypted `column op column` or `column op constant` expression.
+/// The value itself is computed by the server on each row.
+#[derive(Clone, PartialEq, Eq, Debug, serde::Deserialize, serde::Serialize)]
+pub struct SqlQueryArithmetic<B> {
+    pub left_ident_mask: BoolMask<B>,
+    /// Columns followed by the clear query constants
+    pub right_ident_mask: BoolMask<B>,
+    /// See [`crate::sql_ast::column_arithmetic::ArithmeticOp`]
+    pub op_mask: BoolMask<B>,
+}
+
+derive3_encrypt_decrypt! { SqlQueryArithmetic<B> {left_ident_mask: BoolMask<B>, right_ident_mask: BoolMask<B>, op_mask: BoolMask<B>} }
+
+pub type ClearSqlQueryArithmetic = SqlQueryArithmetic<bool>;
+
+impl ClearSqlQueryArithmetic {
+    fn build(value: &AstArithmetic) -> Self {
+        ClearSqlQueryArithmetic {
+            left_ident_mask: value.left_ident_mask.clone(),
+            right_ident_mask: value.right_ident_mask.clone(),
+            op_mask: value.op_mask.clone(),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// SqlQueryArithmetics
+////////////////////////////////////////////////////////////////////////////////
+
+/// Column arithmetic leaves: for each binary op, a mask over `arithmetics`.
+/// A binary op compares the server-computed value of the selected arithmetic
+/// expression (if any) against its right operand value.
+/// Empty when the query does not contain any column arithmetic.
+#[derive(Clone, PartialEq, Eq, Debug, serde::Deserialize, serde::Serialize)]
+pub struct SqlQueryArithmetics<B> {
+    pub(super) masks: Vec<BoolMask<B>>,
+    pub(super) arithmetics: Vec<SqlQueryArithmetic<B>>,
+}
+
+derive2_encrypt_decrypt! { SqlQueryArithmetics<B> {masks: Vec<BoolMask<B>>, arithmetics: Vec<SqlQueryArithmetic<B>>} }
+
+pub type ClearSqlQueryArithmetics = SqlQueryArithmetics<bool>;
+
+impl ClearSqlQueryArithmetics {
+    pub(super) fn build(masks: Vec<BoolMask<bool>>, arithmetics: &[AstArithmetic]) -> Self {
+        if arithmetics.is_empty() {
+            return SqlQueryArithmetics::new_empty();
+        }
+        ClearSqlQueryArithmetics {
+            masks,
+            arithmetics: arithmetics
+                .iter()
+                .map(ClearSqlQueryArithmetic::build)
+                .collect(),
+        }
+    }
+}
+
+impl<B> SqlQueryArithmetics<B> {
+    #[inline]
+    pub fn new_empty() -> Self {
+        SqlQueryArithmetics::<B> {
+            masks: vec![],
+            arithmetics: vec![],
+        }
+    }
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.arithmetics.len()
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.arithmetics.is_empty()
+    }
+    #[inline]
+    pub fn get(&self, index: usize) -> &SqlQueryArithmetic<B> {
+        &self.arithmetics[index]
+    }
+    #[inline]
+    pub fn mask(&self, binary_op_index: usize) -> &BoolMask<B> {
+        &self.masks[binary_op_index]
+    }
+}
@@ -1,10 +1,17 @@
 use crate::sql_ast::and_or_ast::AstNumBinaryOp;
-use crate::{sql_ast::ComparatorMask, uint::mask::BoolMask};
+use crate::sql_ast::column_arithmetic::AstArithmetic;
+use crate::sql_ast::in_set::AstInSet;
+use crate::sql_ast::scalar_subquery::AstAggregate;
+use crate::sql_ast::ComparatorMask;
+use crate::uint::mask::{BoolMask, ClearBoolMask};
 
 use crate::default_into::*;
 use crate::encrypt::*;
 use crate::encrypt::traits::*;
 
+use super::sql_query_aggregate::{ClearSqlQueryAggregates, SqlQueryAggregate, SqlQueryAggregates};
+use super::sql_query_arithmetic::{ClearSqlQueryArithmetics, SqlQueryArithmetics};
+use super::sql_query_set::{ClearSqlQuerySets, SqlQuerySets};
 use super::sql_query_value::{ClearSqlQueryValue, SqlQueryRightOperand};
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -14,30 +21,88 @@ use super::sql_query_value::{ClearSqlQueryValue, SqlQueryRightOperand};
 #[derive(Clone, PartialEq, Eq, Debug, serde::Deserialize, serde::Serialize)]
 pub struct SqlQueryBinOpArray<B> {
     pub(super) array: Vec<SqlQueryBinaryOp<B>>,
+    pub(super) aggregates: SqlQueryAggregates<B>,
+    pub(super) arithmetics: SqlQueryArithmetics<B>,
+    pub(super) sets: SqlQuerySets<B>,
 }
 
-derive1_encrypt_decrypt! { SqlQueryBinOpArray<B> {array: Vec<SqlQueryBinaryOp<B>>} }
+derive4_encrypt_decrypt! { SqlQueryBinOpArray<B> {array: Vec<SqlQueryBinaryOp<B>>, aggregates: SqlQueryAggregates<B>, arithmetics: SqlQueryArithmetics<B>, sets: SqlQuerySets<B>} }
 
 pub type ClearSqlQueryBinOpArray = SqlQueryBinOpArray<bool>;
 
 impl ClearSqlQueryBinOpArray {
-    pub fn build(num_bin_ops: &[AstNumBinaryOp]) -> Self {
+    pub fn build(
+        num_bin_ops: &[AstNumBinaryOp],
+        aggregates: &[AstAggregate],
+        arithmetics: &[AstArithmetic],
+        sets: &[AstInSet],
+    ) -> Self {
         if num_bin_ops.is_empty() {
-            return SqlQueryBinOpArray { array: vec![] };
+            return SqlQueryBinOpArray::new_empty();
         }
-        let array = num_bin_ops
-            .iter()
-            .filter(|x| !x.is_dummy)
+        let non_dummy_ops = num_bin_ops.iter().filter(|x| !x.is_dummy);
+        let array = non_dummy_ops
+            .clone()
             .map(|x| ClearSqlQueryBinaryOp::build(x).unwrap())
             .collect::<Vec<ClearSqlQueryBinaryOp>>();
-        ClearSqlQueryBinOpArray { array }
+        let aggregates = ClearSqlQueryAggregates::build(
+            non_dummy_ops
+                .clone()
+                .map(|x| x.right_aggregate_mask.clone())
+                .collect::<Vec<ClearBoolMask>>(),
+            aggregates,
+        );
+        let arithmetics = ClearSqlQueryArithmetics::build(
+            non_dummy_ops
+                .clone()
+                .map(|x| x.left_arithmetic_mask.clone())
+                .collect::<Vec<ClearBoolMask>>(),
+            arithmetics,
+        );
+        let sets = ClearSqlQuerySets::build(
+            non_dummy_ops
+                .map(|x| x.left_set_mask.clone())
+                .collect::<Vec<ClearBoolMask>>(),
+            sets,
+        );
+        ClearSqlQueryBinOpArray {
+            array,
+            aggregates,
+            arithmetics,
+            sets,
+        }
     }
 }
 
 impl<B> SqlQueryBinOpArray<B> {
     #[inline]
     pub fn new_empty() -> Self {
-        SqlQueryBinOpArray::<B> { array: vec![] }
+        SqlQueryBinOpArray::<B> {
+            array: vec![],
+            aggregates: SqlQueryAggregates::new_empty(),
+            arithmetics: SqlQueryArithmetics::new_empty(),
+            sets: SqlQuerySets::new_empty(),
+        }
+    }
+    #[inline]
+    pub fn arithmetics(&self) -> &SqlQueryArithmetics<B> {
+        &self.arithmetics
+    }
+    #[inline]
+    pub fn sets(&self) -> &SqlQuerySets<B> {
+        &self.sets
+    }
+    #[inline]
+    pub fn num_aggregates(&self) -> usize {
+        self.aggregates.len()
+    }
+    #[inline]
+    pub fn aggregate(&self, index: usize) -> &SqlQueryAggregate<B> {
+        self.aggregates.get(index)
+    }
+    #[inline]
+    pub fn aggregate_mask(&self, index: usize) -> &BoolMask<B> {
+        self.aggregates.mask(index)
     }
     #[inline]
     pub fn is_empty(&self) -> bool {
@@ -72,11 +137,20 @@ impl ClearSqlQueryBinaryOp {
         if value.is_dummy {
             return None;
         }
+        let mut right = ClearSqlQueryValue::build(&value.right_ident_mask, &value.right_value, value.right_minus_sign);
+        // A scalar subquery is neither a column nor a client-side value,
+        // a column arithmetic or an IN set is evaluated by its own operator
+        if value.right_aggregate_mask.count_set() > 0
+            || value.left_arithmetic_mask.count_set() > 0
+            || value.left_set_mask.count_set() > 0
+        {
+            right.is_value = false;
+        }
         Some(ClearSqlQueryBinaryOp {
             position_mask: value.pos_mask.clone(),
             comparator_mask: value.op_mask.clone(),
             left_ident_mask: value.left_ident_mask.clone(),
-            right: ClearSqlQueryValue::build(&value.right_ident_mask, &value.right_value, value.right_minus_sign),
+            right,
         })
     }
 }
@@ -0,0 +1,119 @@
+use crate::default_into::*;
+use crate::encrypt::*;
+use crate::encrypt::traits::*;
+use crate::sql_ast::in_set::{AstInSet, InSetValue};
+use crate::uint::mask::BoolMask;
+
+////////////////////////////////////////////////////////////////////////////////
+// SqlQuerySetValue
+////////////////////////////////////////////////////////////////////////////////
+
+/// Encrypted set element component: 256 bits + sign
+#[derive(Clone, PartialEq, Eq, Debug, serde::Deserialize, serde::Serialize)]
+pub struct SqlQuerySetValue<B> {
+    /// Little endian bits, see [`InSetValue`]
+    pub le_bits: BoolMask<B>,
+    pub is_strictly_negative: B,
+}
+
+derive2_encrypt_decrypt! { SqlQuerySetValue<B> {le_bits: BoolMask<B>, is_strictly_negative: B} }
+
+pub type ClearSqlQuerySetValue = SqlQuerySetValue<bool>;
+
+impl From<&InSetValue> for ClearSqlQuerySetValue {
+    fn from(value: &InSetValue) -> Self {
+        ClearSqlQuerySetValue {
+            le_bits: BoolMask {
+                mask: (0..InSetValue::BITS).map(|i| value.bit(i)).collect(),
+            },
+            is_strictly_negative: value.negative,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// SqlQuerySet
+////////////////////////////////////////////////////////////////////////////////
+
+/// Encrypted `(c1, c2, ...) IN ((v11, v12, ...), ...)` set.
+/// Membership is computed by the server on each distinct row.
+#[derive(Clone, PartialEq, Eq, Debug, serde::Deserialize, serde::Serialize)]
+pub struct SqlQuerySet<B> {
+    /// One column mask per tuple position
+    pub ident_masks: Vec<BoolMask<B>>,
+    /// One value per tuple position
+    pub elements: Vec<Vec<SqlQuerySetValue<B>>>,
+}
+
+derive2_encrypt_decrypt! { SqlQuerySet<B> {ident_masks: Vec<BoolMask<B>>, elements: Vec<Vec<SqlQuerySetValue<B>>>} }
+
+pub type ClearSqlQuerySet = SqlQuerySet<bool>;
+
+impl ClearSqlQuerySet {
+    fn build(value: &AstInSet) -> Self {
+        ClearSqlQuerySet {
+            ident_masks: value.ident_masks.clone(),
+            elements: value
+                .elements
+                .iter()
+                .map(|e| e.iter().map(ClearSqlQuerySetValue::from).collect())
+                .collect(),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// SqlQuerySets
+////////////////////////////////////////////////////////////////////////////////
+
+/// IN set leaves: for each binary op, a mask over `sets`.
+/// A binary op selects the rows that belong (EQ) or do not belong (NOTEQ)
+/// to the selected set (if any).
+/// Empty when the query does not contain any IN set.
+#[derive(Clone, PartialEq, Eq, Debug, serde::Deserialize, serde::Serialize)]
+pub struct SqlQuerySets<B> {
+    pub(super) masks: Vec<BoolMask<B>>,
+    pub(super) sets: Vec<SqlQuerySet<B>>,
+}
+
+derive2_encrypt_decrypt! { SqlQuerySets<B> {masks: Vec<BoolMask<B>>, sets: Vec<SqlQuerySet<B>>} }
+
+pub type ClearSqlQuerySets = SqlQuerySets<bool>;
+
+impl ClearSqlQuerySets {
+    pub(super) fn build(masks: Vec<BoolMask<bool>>, sets: &[AstInSet]) -> Self {
+        if sets.is_empty() {
+            return SqlQuerySets::new_empty();
+        }
+        ClearSqlQuerySets {
+            masks,
+            sets: sets.iter().map(ClearSqlQuerySet::build).collect(),
+        }
+    }
+}
+
+impl<B> SqlQuerySets<B> {
+    #[inline]
+    pub fn new_empty() -> Self {
+        SqlQuerySets::<B> {
+            masks: vec![],
+            sets: vec![],
+        }
+    }
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.sets.len()
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.sets.is_empty()
+    }
+    #[inline]
+    pub fn get(&self, index: usize) -> &SqlQuerySet<B> {
+        &self.sets[index]
+    }
+    #[inline]
+    pub fn mask(&self, binary_op_index: usize) -> &BoolMask<B> {
+        &self.masks[binary_op_index]
+    }
+}
@@ -55,7 +55,12 @@ impl ClearSqlQueryTree {
                         || and_or_tree.num_leaves() == 0
                 );
 
-                let compare_ops = ClearSqlQueryBinOpArray::build(ast_tree.num_ops());
+                let compare_ops = ClearSqlQueryBinOpArray::build(
+                    ast_tree.num_ops(),
+                    ast_tree.aggregates(),
+                    ast_tree.arithmetics(),
+                    ast_tree.sets(),
+                );
                 assert!(dummy_mask.len() >= compare_ops.len());
 
                 Ok(ClearSqlQueryTree {
@@ -103,6 +108,11 @@ impl<B> SqlQueryTree<B> {
     //     &self.dummy_mask[tree_index].ne
     // }
 
+    #[inline]
+    pub fn num_nodes(&self) -> usize {
+        self.tree.num_nodes()
+    }
+
     #[inline]
     pub fn dummy_mask(&self) -> &Vec<EqNe<B>> {
         &self.dummy_mask
@@ -0,0 +1,61 @@
+use crate::default_into::*;
+use crate::encrypt::*;
+use crate::encrypt::traits::*;
+use crate::sql_ast::window::AstWindow;
+use crate::types::BooleanType;
+use crate::uint::mask::BoolMask;
+
+////////////////////////////////////////////////////////////////////////////////
+// SqlQueryWindow
+////////////////////////////////////////////////////////////////////////////////
+
+/// Encrypted `ROW_NUMBER() | RANK() OVER ([PARTITION BY column] ORDER BY column)`.
+/// All the masks are empty when the query does not contain any window function.
+#[derive(Clone, PartialEq, Eq, Debug, serde::Deserialize, serde::Serialize)]
+pub struct SqlQueryWindow<B> {
+    /// See [`crate::sql_ast::window::WindowFunc`]
+    pub func_mask: BoolMask<B>,
+    pub order_ident_mask: BoolMask<B>,
+    pub order_desc: B,
+    /// All false if there is no PARTITION BY clause
+    pub partition_ident_mask: BoolMask<B>,
+}
+
+derive4_encrypt_decrypt! { SqlQueryWindow<B> {func_mask: BoolMask<B>, order_ident_mask: BoolMask<B>, order_desc: B, partition_ident_mask: BoolMask<B>} }
+
+pub type ClearSqlQueryWindow = SqlQueryWindow<bool>;
+
+impl<B> SqlQueryWindow<B>
+where
+    B: BooleanType,
+{
+    pub fn new_empty() -> Self {
+        SqlQueryWindow {
+            func_mask: BoolMask::<B>::new_empty(),
+            order_ident_mask: BoolMask::<B>::new_empty(),
+            order_desc: B::get_false(),
+            partition_ident_mask: BoolMask::<B>::new_empty(),
+        }
+    }
+}
+
+impl<B> SqlQueryWindow<B> {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.func_mask.len() == 0
+    }
+}
+
+impl ClearSqlQueryWindow {
+    pub(crate) fn build(window: Option<&AstWindow>) -> Self {
+        match window {
+            None => ClearSqlQueryWindow::new_empty(),
+            Some(w) => ClearSqlQueryWindow {
+                func_mask: w.func_mask.clone(),
+                order_ident_mask: w.order_ident_mask.clone(),
+                order_desc: w.order_desc,
+                partition_ident_mask: w.partition_ident_mask.clone(),
+            },
+        }
+    }
+}
@@ -6,13 +6,14 @@ use crate::uint::mask::BoolMask;
 use crate::uint::{ByteArray, ClearByteArray};
 use crate::OrderedSchemas;
 use crate::SqlResultOptions;
-use arrow_array::RecordBatch;
-use arrow_schema::{Field, Schema};
+use arrow_array::{ArrayRef, RecordBatch, UInt64Array};
+use arrow_schema::{DataType, Field, Schema};
 use std::mem::swap;
 use std::sync::Arc;
 use tfhe::{ClientKey, FheBool, FheUint8};
 
 use super::sql_query::SqlQueryRef;
+use super::sql_result_chunk::{SqlResultChunk, SqlResultHeader};
 
 #[cfg(feature = "stats")]
 use crate::server::SqlStats;
@@ -28,10 +29,15 @@ pub(crate) struct SqlResult<U8, B> {
     field_mask: BoolMask<B>,
     select_mask: BoolMask<B>,
     byte_arrays: Vec<ByteArray<U8>>,
+    /// Window function value of each row (little endian), empty if none
+    window_bytes: Vec<ByteArray<U8>>,
+    /// Compacted result only: true if some selected rows have been dropped, empty otherwise
+    truncated: BoolMask<B>,
 
     /// Clear part
     pub(crate) options: SqlResultOptions,
     pub(crate) ordered_schemas: OrderedSchemas,
+    pub(crate) window_name: Option<String>,
 
     #[cfg(feature = "stats")]
     #[serde(skip_serializing, skip_deserializing)]
@@ -53,9 +59,12 @@ impl<U8, B> SqlResult<U8, B> {
             field_mask: BoolMask::<B>::new_empty(),
             select_mask: BoolMask::<B>::new_empty(),
             byte_arrays: vec![],
+            window_bytes: vec![],
+            truncated: BoolMask::<B>::new_empty(),
 
             options: SqlResultOptions::default(),
             ordered_schemas: OrderedSchemas::new_empty(),
+            window_name: None,
 
             #[cfg(feature = "stats")]
             stats: SqlStats::new_empty(),
@@ -84,17 +93,59 @@ where
         query_ref: &SqlQueryRef<B>,
         select_mask: BoolMask<B>,
         byte_arrays: Vec<ByteArray<U8>>,
+        window_bytes: Vec<ByteArray<U8>>,
     ) -> Self {
         SqlResult::<U8, B> {
             table_mask: query_ref.header().table_mask.clone(),
             field_mask: query_ref.header().field_mask.clone(),
             select_mask,
             byte_arrays,
+            window_bytes,
+            truncated: BoolMask::<B>::new_empty(),
 
             #[cfg(feature = "stats")]
             stats: SqlStats::new_empty(),
             options: *query_ref.options(),
-            ordered_schemas: query_ref.ordered_schemas().clone()
+            ordered_schemas: query_ref.ordered_schemas().clone(),
+            window_name: query_ref.window_name().cloned(),
+        }
+    }
+
+    pub(crate) fn with_truncated(mut self, truncated: B) -> Self {
+        self.truncated = BoolMask::<B> {
+            mask: vec![truncated],
+        };
+        self
+    }
+}
+
+impl<U8, B> SqlResult<U8, B> {
+    /// Drops the header part, shared by all the chunks of a chunked result
+    pub(crate) fn into_chunk(self) -> SqlResultChunk<U8, B> {
+        SqlResultChunk::<U8, B> {
+            select_mask: self.select_mask,
+            byte_arrays: self.byte_arrays,
+            window_bytes: self.window_bytes,
+        }
+    }
+
+    pub(super) fn from_header_and_chunk(
+        header: SqlResultHeader<B>,
+        chunk: SqlResultChunk<U8, B>,
+    ) -> Self {
+        SqlResult::<U8, B> {
+            table_mask: header.table_mask,
+            field_mask: header.field_mask,
+            select_mask: chunk.select_mask,
+            byte_arrays: chunk.byte_arrays,
+            window_bytes: chunk.window_bytes,
+            truncated: BoolMask::<B>::new_empty(),
+
+            #[cfg(feature = "stats")]
+            stats: SqlStats::new_empty(),
+            options: header.options,
+            ordered_schemas: header.ordered_schemas,
+            window_name: header.window_name,
         }
     }
 }
@@ -109,15 +160,20 @@ impl FheSqlResult {
         let field_mask = self.0.field_mask.decrypt(key);
         let select_mask = self.0.select_mask.decrypt(key);
         let byte_arrays = self.0.byte_arrays.decrypt(key);
+        let window_bytes = self.0.window_bytes.decrypt(key);
+        let truncated = self.0.truncated.decrypt(key);
         ClearSqlResult(SqlResult::<u8, bool> {
             table_mask,
             field_mask,
             select_mask,
             byte_arrays,
+            window_bytes,
+            truncated,
             #[cfg(feature = "stats")]
             stats: self.0.stats.clone(),
             options: self.0.options,
-            ordered_schemas: self.0.ordered_schemas.clone()
+            ordered_schemas: self.0.ordered_schemas.clone(),
+            window_name: self.0.window_name.clone(),
         })
     }
 
@@ -128,15 +184,20 @@ impl FheSqlResult {
         let field_mask = self.0.field_mask.try_decrypt_trivial()?;
         let select_mask = self.0.select_mask.try_decrypt_trivial()?;
         let byte_arrays = self.0.byte_arrays.try_decrypt_trivial()?;
+        let window_bytes = self.0.window_bytes.try_decrypt_trivial()?;
+        let truncated = self.0.truncated.try_decrypt_trivial()?;
         Ok(ClearSqlResult(SqlResult::<u8, bool> {
             table_mask,
             field_mask,
             select_mask,
             byte_arrays,
+            window_bytes,
+            truncated,
             #[cfg(feature = "stats")]
             stats: self.0.stats.clone(),
             options: self.0.options,
             ordered_schemas: self.0.ordered_schemas.clone(),
+            window_name: self.0.window_name.clone(),
         }))
     }
 
@@ -178,6 +239,13 @@ impl FheSqlResult {
         record_batch_to_csv_string(&rb)
     }
 
+    /// Decrypts the flag indicating that the result has been truncated, that is
+    /// more rows were selected than the requested maximum number of rows
+    /// (see [SqlResultOptions::with_max_num_rows]).
+    pub fn decrypt_is_truncated(&self, key: &ClientKey) -> bool {
+        self.0.truncated.decrypt(key).mask.first().copied().unwrap_or(false)
+    }
+
     /// Helper: Ouputs the encrypted sql result in json format
     #[inline]
     pub fn to_json<W>(&self, writer: W) -> serde_json::Result<()>
@@ -222,6 +290,12 @@ impl ClearSqlResult {
         record_batch_to_csv_string(&rb)
     }
 
+    /// True if more rows were selected than the requested maximum number of rows
+    /// (see [SqlResultOptions::with_max_num_rows]).
+    pub fn is_truncated(&self) -> bool {
+        self.0.truncated.mask.first().copied().unwrap_or(false)
+    }
+
     /// Helper: Ouputs the clear sql result in json format
     #[inline]
     pub fn to_json<W>(&self, writer: W) -> serde_json::Result<()>
@@ -260,7 +334,12 @@ impl SqlResult<u8, bool> {
             )));
         }
 
-        match self.options.format() {
+        if self.window_name.is_some() && self.field_mask.count_set() == 0 {
+            // Only the window function column is selected
+            return self.append_window_column(None);
+        }
+
+        let rb = match self.options.format() {
             crate::SqlResultFormat::RowBytes(_) => {
                 let mut my_byte_array: Vec<ClearByteArray> = vec![];
                 swap(&mut my_byte_array, &mut self.byte_arrays);
@@ -285,6 +364,47 @@ impl SqlResult<u8, bool> {
                     self.options.compress(),
                 )
             }
+        }?;
+
+        if self.window_name.is_some() {
+            self.append_window_column(Some(rb))
+        } else {
+            Ok(rb)
         }
     }
+
+    /// Appends the window function values of the selected rows as a UInt64 column
+    fn append_window_column(
+        &mut self,
+        rb: Option<RecordBatch>,
+    ) -> Result<RecordBatch, FheSqlError> {
+        assert_eq!(self.window_bytes.len(), self.select_mask.len());
+        let values = self
+            .window_bytes
+            .iter()
+            .zip(self.select_mask.mask.iter())
+            .filter(|(_, selected)| **selected)
+            .map(|(byte_array, _)| {
+                byte_array
+                    .bytes
+                    .iter()
+                    .rev()
+                    .fold(0_u64, |acc, b| (acc << 8) | (*b as u64))
+            })
+            .collect::<Vec<u64>>();
+
+        let name = self.window_name.clone().unwrap();
+        let (mut fields, mut columns) = match rb {
+            Some(rb) => (
+                rb.schema().fields().iter().map(|f| f.as_ref().clone()).collect(),
+                rb.columns().to_vec(),
+            ),
+            None => (vec![], vec![]),
+        };
+        fields.push(Field::new(name, DataType::UInt64, false));
+        columns.push(Arc::new(UInt64Array::from(values)) as ArrayRef);
+
+        RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)
+            .map_err(|err| FheSqlError::DecryptError(err.to_string()))
+    }
 }
@@ -0,0 +1,246 @@
+use crate::encrypt::traits::{Decrypt, TryTrivialDecrypt};
+use crate::error::FheSqlError;
+use crate::uint::mask::BoolMask;
+use crate::uint::ByteArray;
+use crate::OrderedSchemas;
+use crate::SqlResultOptions;
+use arrow_array::RecordBatch;
+use tfhe::{ClientKey, FheBool, FheUint8};
+
+use super::sql_query::SqlQueryRef;
+use super::sql_result::SqlResult;
+
+////////////////////////////////////////////////////////////////////////////////
+// SqlResultHeader
+////////////////////////////////////////////////////////////////////////////////
+
+/// The part of a sql result shared by all the chunks, known before
+/// the server starts computing.
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub(crate) struct SqlResultHeader<B> {
+    /// Encrypted part
+    pub(super) table_mask: BoolMask<B>,
+    pub(super) field_mask: BoolMask<B>,
+
+    /// Clear part
+    pub(super) options: SqlResultOptions,
+    pub(super) ordered_schemas: OrderedSchemas,
+    pub(super) window_name: Option<String>,
+}
+
+/// A chunked result header (see [FheRunSqlQuery::run_chunked](crate::FheRunSqlQuery::run_chunked))
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct ClearSqlResultHeader(pub(crate) SqlResultHeader<bool>);
+
+/// An encrypted chunked result header (see [FheRunSqlQuery::run_chunked](crate::FheRunSqlQuery::run_chunked))
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct FheSqlResultHeader(pub(crate) SqlResultHeader<FheBool>);
+
+////////////////////////////////////////////////////////////////////////////////
+// SqlResultChunk
+////////////////////////////////////////////////////////////////////////////////
+
+/// The rows of a sql result computed on a range of table rows
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub(crate) struct SqlResultChunk<U8, B> {
+    /// Encrypted part
+    pub(super) select_mask: BoolMask<B>,
+    pub(super) byte_arrays: Vec<ByteArray<U8>>,
+    pub(super) window_bytes: Vec<ByteArray<U8>>,
+}
+
+/// A chunk of a clear chunked result
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct ClearSqlResultChunk(pub(crate) SqlResultChunk<u8, bool>);
+
+/// A chunk of an encrypted chunked result
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct FheSqlResultChunk(pub(crate) SqlResultChunk<FheUint8, FheBool>);
+
+////////////////////////////////////////////////////////////////////////////////
+
+impl<B> SqlResultHeader<B>
+where
+    B: Clone,
+{
+    pub(crate) fn new_empty() -> Self {
+        SqlResultHeader::<B> {
+            table_mask: BoolMask::<B>::new_empty(),
+            field_mask: BoolMask::<B>::new_empty(),
+            options: SqlResultOptions::default(),
+            ordered_schemas: OrderedSchemas::new_empty(),
+            window_name: None,
+        }
+    }
+
+    pub(crate) fn from_query_ref(query_ref: &SqlQueryRef<B>) -> Self {
+        if query_ref.is_empty() {
+            return Self::new_empty();
+        }
+        SqlResultHeader::<B> {
+            table_mask: query_ref.header().table_mask.clone(),
+            field_mask: query_ref.header().field_mask.clone(),
+            options: *query_ref.options(),
+            ordered_schemas: query_ref.ordered_schemas().clone(),
+            window_name: query_ref.window_name().cloned(),
+        }
+    }
+
+    #[inline]
+    pub fn to_json<W>(&self, writer: W) -> serde_json::Result<()>
+    where
+        W: std::io::Write,
+        B: serde::Serialize,
+    {
+        serde_json::to_writer(writer, self)
+    }
+}
+
+impl<U8, B> SqlResultChunk<U8, B> {
+    #[inline]
+    pub fn to_json<W>(&self, writer: W) -> serde_json::Result<()>
+    where
+        W: std::io::Write,
+        U8: serde::Serialize,
+        B: serde::Serialize,
+    {
+        serde_json::to_writer(writer, self)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// FheSqlResultHeader
+////////////////////////////////////////////////////////////////////////////////
+
+impl FheSqlResultHeader {
+    /// Decrypts the header once, the returned clear header decrypts
+    /// the chunks one by one.
+    pub fn decrypt(&self, key: &ClientKey) -> ClearSqlResultHeader {
+        ClearSqlResultHeader(SqlResultHeader::<bool> {
+            table_mask: self.0.table_mask.decrypt(key),
+            field_mask: self.0.field_mask.decrypt(key),
+            options: self.0.options,
+            ordered_schemas: self.0.ordered_schemas.clone(),
+            window_name: self.0.window_name.clone(),
+        })
+    }
+
+    /// Decrypts the trivialy encrypted header
+    pub fn try_decrypt_trivial(&self) -> Result<ClearSqlResultHeader, FheSqlError> {
+        let map_err = |err: tfhe::shortint::ciphertext::NotTrivialCiphertextError| {
+            FheSqlError::DecryptError(err.to_string())
+        };
+        Ok(ClearSqlResultHeader(SqlResultHeader::<bool> {
+            table_mask: self.0.table_mask.try_decrypt_trivial().map_err(map_err)?,
+            field_mask: self.0.field_mask.try_decrypt_trivial().map_err(map_err)?,
+            options: self.0.options,
+            ordered_schemas: self.0.ordered_schemas.clone(),
+            window_name: self.0.window_name.clone(),
+        }))
+    }
+
+    /// Helper: Ouputs the encrypted header in json format
+    #[inline]
+    pub fn to_json<W>(&self, writer: W) -> serde_json::Result<()>
+    where
+        W: std::io::Write,
+    {
+        self.0.to_json(writer)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// FheSqlResultChunk
+////////////////////////////////////////////////////////////////////////////////
+
+impl FheSqlResultChunk {
+    fn decrypt(&self, key: &ClientKey) -> ClearSqlResultChunk {
+        ClearSqlResultChunk(SqlResultChunk::<u8, bool> {
+            select_mask: self.0.select_mask.decrypt(key),
+            byte_arrays: self.0.byte_arrays.decrypt(key),
+            window_bytes: self.0.window_bytes.decrypt(key),
+        })
+    }
+
+    fn try_decrypt_trivial(
+        &self,
+    ) -> Result<ClearSqlResultChunk, tfhe::shortint::ciphertext::NotTrivialCiphertextError> {
+        Ok(ClearSqlResultChunk(SqlResultChunk::<u8, bool> {
+            select_mask: self.0.select_mask.try_decrypt_trivial()?,
+            byte_arrays: self.0.byte_arrays.try_decrypt_trivial()?,
+            window_bytes: self.0.window_bytes.try_decrypt_trivial()?,
+        }))
+    }
+
+    /// Helper: Ouputs the encrypted chunk in json format
+    #[inline]
+    pub fn to_json<W>(&self, writer: W) -> serde_json::Result<()>
+    where
+        W: std::io::Write,
+    {
+        self.0.to_json(writer)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// ClearSqlResultHeader
+////////////////////////////////////////////////////////////////////////////////
+
+impl ClearSqlResultHeader {
+    /// Consumes a clear chunk and returns its rows as
+    /// an [arrow `RecordBatch`](arrow_array::record_batch::RecordBatch).
+    pub fn chunk_into_record_batch(
+        &self,
+        chunk: ClearSqlResultChunk,
+    ) -> Result<RecordBatch, FheSqlError> {
+        let mut sql_result = SqlResult::<u8, bool>::from_header_and_chunk(self.0.clone(), chunk.0);
+        sql_result.extract_record_batch()
+    }
+
+    /// Decrypts an encrypted chunk and returns its rows as
+    /// an [arrow `RecordBatch`](arrow_array::record_batch::RecordBatch).
+    pub fn decrypt_chunk_record_batch(
+        &self,
+        chunk: &FheSqlResultChunk,
+        key: &ClientKey,
+    ) -> Result<RecordBatch, FheSqlError> {
+        self.chunk_into_record_batch(chunk.decrypt(key))
+    }
+
+    /// Decrypts a trivialy encrypted chunk and returns its rows as
+    /// an [arrow `RecordBatch`](arrow_array::record_batch::RecordBatch).
+    pub fn try_decrypt_trivial_chunk_record_batch(
+        &self,
+        chunk: &FheSqlResultChunk,
+    ) -> Result<RecordBatch, FheSqlError> {
+        let clear_chunk = match chunk.try_decrypt_trivial() {
+            Ok(c) => c,
+            Err(err) => return Err(FheSqlError::DecryptError(err.to_string())),
+        };
+        self.chunk_into_record_batch(clear_chunk)
+    }
+
+    /// Helper: Ouputs the clear header in json format
+    #[inline]
+    pub fn to_json<W>(&self, writer: W) -> serde_json::Result<()>
+    where
+        W: std::io::Write,
+    {
+        self.0.to_json(writer)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// ClearSqlResultChunk
+////////////////////////////////////////////////////////////////////////////////
+
+impl ClearSqlResultChunk {
+    /// Helper: Ouputs the clear chunk in json format
+    #[inline]
+    pub fn to_json<W>(&self, writer: W) -> serde_json::Result<()>
+    where
+        W: std::io::Write,
+    {
+        self.0.to_json(writer)
+    }
+}
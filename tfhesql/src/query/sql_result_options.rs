@@ -1,3 +1,5 @@
+use crate::FheSqlError;
+
 ////////////////////////////////////////////////////////////////////////////////
 // SqlResultFormat
 ////////////////////////////////////////////////////////////////////////////////
@@ -31,6 +33,9 @@ pub struct SqlResultOptions {
     compress: bool,
     /// dataset byte format
     format: SqlResultFormat,
+    /// Maximum number of rows of the result (RowBytes format only),
+    /// the selected rows are obliviously compacted by the server
+    max_num_rows: Option<usize>,
 }
 
 impl Default for SqlResultOptions {
@@ -38,6 +43,7 @@ impl Default for SqlResultOptions {
         Self {
             compress: true,
             format: Default::default(),
+            max_num_rows: None,
         }
     }
 }
@@ -48,6 +54,7 @@ impl SqlResultOptions {
         SqlResultOptions {
             compress:true,
             format: SqlResultFormat::TableBytesInColumnOrder,
+            max_num_rows: None,
         }
     }
     /// Enable/disable server-side dataset compression (default=`true`)
@@ -62,6 +69,16 @@ impl SqlResultOptions {
         self.format = format;
         self
     }
+
+    /// Requests a result made of exactly `max_num_rows` encrypted rows (default=`None`,
+    /// one encrypted row per table row). The server obliviously moves the selected rows,
+    /// in order, to the first slots of the result. Selected rows beyond `max_num_rows`
+    /// are dropped (see [FheSqlResult::decrypt_is_truncated](crate::FheSqlResult::decrypt_is_truncated)).
+    /// Requires the `SqlResultFormat::RowBytes` format.
+    pub fn with_max_num_rows(mut self, max_num_rows: usize) -> Self {
+        self.max_num_rows = Some(max_num_rows);
+        self
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -78,6 +95,24 @@ impl SqlResultOptions {
     pub(crate) fn in_row_order(&self) -> bool {
         matches!(self.format, SqlResultFormat::TableBytesInRowOrder)
     }
+
+    pub(crate) fn max_num_rows(&self) -> Option<usize> {
+        self.max_num_rows
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), FheSqlError> {
+        match self.max_num_rows {
+            Some(0) => Err(FheSqlError::InvalidQueryError(
+                "The maximum number of result rows must be greater than zero".to_string(),
+            )),
+            Some(_) if !matches!(self.format, SqlResultFormat::RowBytes(_)) => {
+                Err(FheSqlError::InvalidQueryError(
+                    "A maximum number of result rows requires the RowBytes format".to_string(),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -0,0 +1,144 @@
+use crate::FheSqlError;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+////////////////////////////////////////////////////////////////////////////////
+// SqlCheckpoint
+////////////////////////////////////////////////////////////////////////////////
+
+const FINGERPRINT_FILE: &str = "fingerprint";
+const STATE_FILE_EXT: &str = "bin";
+
+/// A directory where the server saves the intermediate state of a query
+/// (see [RunControl::with_checkpoint](crate::RunControl::with_checkpoint)).
+///
+/// The saved state is:
+/// - the select mask of each WHERE binary operation
+/// - the WHERE clause mask, chunk by chunk
+/// - the SELECT DISTINCT flags, chunk by chunk (pairwise strategy)
+/// - the final select mask
+///
+/// When the same query is run again on the same tables with the same directory,
+/// the completed steps are loaded instead of being computed. A checkpoint written
+/// by a different query is discarded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SqlCheckpoint {
+    dir: PathBuf,
+    chunk_num_rows: usize,
+}
+
+impl SqlCheckpoint {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        SqlCheckpoint {
+            dir: dir.as_ref().to_path_buf(),
+            chunk_num_rows: 64,
+        }
+    }
+
+    /// Number of rows computed between two saves of the WHERE clause mask
+    /// and of the SELECT DISTINCT mask (default=64, at least 1)
+    pub fn with_chunk_num_rows(mut self, chunk_num_rows: usize) -> Self {
+        self.chunk_num_rows = chunk_num_rows.max(1);
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn chunk_num_rows(&self) -> usize {
+        self.chunk_num_rows
+    }
+
+    /// Removes the saved state, the next run starts from scratch.
+    pub fn clear(&self) -> Result<(), FheSqlError> {
+        if !self.dir.is_dir() {
+            return Ok(());
+        }
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let is_state = path.extension().is_some_and(|ext| ext == STATE_FILE_EXT);
+            let is_fingerprint = path.file_name().is_some_and(|name| name == FINGERPRINT_FILE);
+            if path.is_file() && (is_state || is_fingerprint) {
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Prepares the directory for the query identified by `fingerprint`,
+    /// the state saved by any other query is removed.
+    pub(crate) fn open(&self, fingerprint: u64) -> Result<(), FheSqlError> {
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(FINGERPRINT_FILE);
+        let fingerprint = fingerprint.to_string();
+        if std::fs::read_to_string(&path).ok().as_deref() == Some(fingerprint.as_str()) {
+            return Ok(());
+        }
+        self.clear()?;
+        std::fs::write(path, fingerprint)?;
+        Ok(())
+    }
+
+    /// Returns `None` if `name` has not been saved yet
+    pub(crate) fn load<T: DeserializeOwned>(&self, name: &str) -> Result<Option<T>, FheSqlError> {
+        let path = self.state_path(name);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let bytes = std::fs::read(path)?;
+        bincode::deserialize(&bytes)
+            .map(Some)
+            .map_err(|err| FheSqlError::IoError(err.to_string()))
+    }
+
+    /// Writes to a temporary file first: an interrupted save never leaves
+    /// a truncated state behind.
+    pub(crate) fn save<T: Serialize>(&self, name: &str, value: &T) -> Result<(), FheSqlError> {
+        let bytes =
+            bincode::serialize(value).map_err(|err| FheSqlError::IoError(err.to_string()))?;
+        let path = self.state_path(name);
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, bytes)?;
+        std::fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    fn state_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", name, STATE_FILE_EXT))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_save_load() {
+        let dir = std::env::temp_dir().join(format!("tfhesql_checkpoint_{}", std::process::id()));
+        let checkpoint = SqlCheckpoint::new(&dir);
+
+        checkpoint.open(1).unwrap();
+        assert_eq!(checkpoint.load::<Vec<bool>>("mask").unwrap(), None);
+        checkpoint.save("mask", &vec![true, false]).unwrap();
+        assert_eq!(
+            checkpoint.load::<Vec<bool>>("mask").unwrap(),
+            Some(vec![true, false])
+        );
+
+        // Same query: the state is kept
+        checkpoint.open(1).unwrap();
+        assert!(checkpoint.load::<Vec<bool>>("mask").unwrap().is_some());
+
+        // Other query: the state is discarded
+        checkpoint.open(2).unwrap();
+        assert_eq!(checkpoint.load::<Vec<bool>>("mask").unwrap(), None);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,197 @@
+use crate::bitops::*;
+use crate::default_into::{DefaultInto, ValueFrom};
+use crate::error::FheSqlError;
+use crate::types::*;
+use crate::uint::mask::BoolMask;
+use crate::uint::ByteArray;
+#[cfg(feature = "parallel")]
+use rayon::iter::*;
+
+////////////////////////////////////////////////////////////////////////////////
+// Compaction
+////////////////////////////////////////////////////////////////////////////////
+
+/// Oblivious and stable compaction of the selected rows into `k` slots.
+///
+/// - Count(r, j) = { SUM s < r; Select(s) } == j, for 0 <= j < k
+/// - Count(r, k) = { SUM s < r; Select(s) } >= k
+/// - Slot(r, j) = Select(r) AND Count(r, j)
+///
+/// Row r is moved to slot j if Slot(r, j) is set, Slot(r, k) means row r is dropped.
+pub(super) struct Compaction<B> {
+    k: usize,
+    /// Slot(r, j) for 0 <= j <= min(r, k), since Count(r, j) = false for j > r
+    slots: Vec<Vec<B>>,
+}
+
+impl<B> Compaction<B>
+where
+    B: ThreadSafeBool,
+{
+    pub(super) fn new(select_mask: &BoolMask<B>, k: usize) -> Result<Self, FheSqlError> {
+        if k == 0 {
+            return Err(FheSqlError::InvalidQueryError(
+                "The maximum number of result rows must be greater than zero".to_string(),
+            ));
+        }
+        let mut count = vec![B::get_true()];
+        let slots = select_mask
+            .mask
+            .iter()
+            .map(|select_r| {
+                let slots_r = Self::slots_at(&count, select_r);
+                count = Self::next_count(&count, &slots_r, select_r, k);
+                slots_r
+            })
+            .collect();
+        Ok(Compaction { k, slots })
+    }
+
+    #[cfg(feature = "parallel")]
+    fn slots_at(count: &[B], select_r: &B) -> Vec<B> {
+        count.par_iter().map(|c| c.refref_bitand(select_r)).collect()
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    fn slots_at(count: &[B], select_r: &B) -> Vec<B> {
+        count.iter().map(|c| c.refref_bitand(select_r)).collect()
+    }
+
+    /// - Count(r+1, j) = (Count(r, j) AND NOT Select(r)) OR Slot(r, j-1)
+    /// - Count(r+1, k) = Count(r, k) OR Slot(r, k-1)
+    #[cfg(feature = "parallel")]
+    fn next_count(count: &[B], slots_r: &[B], select_r: &B, k: usize) -> Vec<B> {
+        let not_select_r = select_r.ref_not();
+        let len = (count.len() + 1).min(k + 1);
+        (0..len)
+            .into_par_iter()
+            .map(|j| Self::next_count_at(count, slots_r, &not_select_r, k, j))
+            .collect()
+    }
+
+    /// - Count(r+1, j) = (Count(r, j) AND NOT Select(r)) OR Slot(r, j-1)
+    /// - Count(r+1, k) = Count(r, k) OR Slot(r, k-1)
+    #[cfg(not(feature = "parallel"))]
+    fn next_count(count: &[B], slots_r: &[B], select_r: &B, k: usize) -> Vec<B> {
+        let not_select_r = select_r.ref_not();
+        let len = (count.len() + 1).min(k + 1);
+        (0..len)
+            .map(|j| Self::next_count_at(count, slots_r, &not_select_r, k, j))
+            .collect()
+    }
+
+    fn next_count_at(count: &[B], slots_r: &[B], not_select_r: &B, k: usize, j: usize) -> B {
+        if j == k {
+            return match count.get(k) {
+                Some(c) => c.refref_bitor(&slots_r[k - 1]),
+                None => slots_r[k - 1].clone(),
+            };
+        }
+        let stay = count.get(j).map(|c| c.refref_bitand(not_select_r));
+        match (stay, j) {
+            (Some(stay), 0) => stay,
+            (Some(stay), _) => stay.refref_bitor(&slots_r[j - 1]),
+            (None, _) => slots_r[j - 1].clone(),
+        }
+    }
+
+    /// Select(j) = OR { r; Slot(r, j) }, for 0 <= j < k
+    pub(super) fn select_mask(&self) -> BoolMask<B> {
+        let mask = (0..self.k)
+            .map(|j| par_bitor_vec_ref(self.slots_at_column(j)).unwrap_or(B::get_false()))
+            .collect::<Vec<B>>();
+        BoolMask::<B> { mask }
+    }
+
+    /// True if at least one selected row has been dropped
+    pub(super) fn truncated(&self) -> B {
+        par_bitor_vec_ref(self.slots_at_column(self.k)).unwrap_or(B::get_false())
+    }
+
+    fn slots_at_column(&self, j: usize) -> Vec<&B> {
+        self.slots.iter().filter_map(|s| s.get(j)).collect()
+    }
+
+    /// Compacted(j) = OR { r; Slot(r, j) AND Row(r) }, for 0 <= j < k.
+    /// Every compacted row has the same width.
+    pub(super) fn compact<U8>(&self, rows: &[ByteArray<U8>]) -> Vec<ByteArray<U8>>
+    where
+        for<'a> U8: ThreadSafeUInt + ValueFrom<&'a B> + ValueFrom<u8> + ValueFrom<U8> + DefaultInto<U8>,
+    {
+        assert_eq!(rows.len(), self.slots.len());
+        let width = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+        let compact_at = |j: usize| {
+            let masked_rows = rows
+                .iter()
+                .zip(self.slots.iter())
+                .filter_map(|(row, slots_r)| {
+                    slots_r.get(j).map(|slot| {
+                        let m = U8::value_from(slot);
+                        ByteArray::<U8>::from_bytes(
+                            row.bytes.iter().map(|b| b.refref_bitand(&m)).collect(),
+                        )
+                    })
+                })
+                .collect::<Vec<ByteArray<U8>>>();
+            let mut row = par_bitor_vec(masked_rows)
+                .unwrap_or(ByteArray::<U8>::alloc(0, U8::value_from(0)));
+            while row.len() < width {
+                row.bytes.push(U8::value_from(0));
+            }
+            row
+        };
+
+        #[cfg(feature = "parallel")]
+        let compacted = (0..self.k).into_par_iter().map(compact_at).collect();
+
+        #[cfg(not(feature = "parallel"))]
+        let compacted = (0..self.k).map(compact_at).collect();
+
+        compacted
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn compact(select: &[bool], k: usize) -> (Vec<bool>, Vec<u8>, bool) {
+        let select_mask = BoolMask::<bool> {
+            mask: select.to_vec(),
+        };
+        let rows = (0..select.len())
+            .map(|r| ByteArray::<u8>::from_bytes(vec![r as u8 + 1]))
+            .collect::<Vec<ByteArray<u8>>>();
+        let c = Compaction::<bool>::new(&select_mask, k).unwrap();
+        let compacted = c.compact(&rows);
+        assert_eq!(compacted.len(), k);
+        (
+            c.select_mask().mask,
+            compacted.iter().map(|r| r.bytes[0]).collect(),
+            c.truncated(),
+        )
+    }
+
+    #[test]
+    fn test_compaction() {
+        let s = [false, true, false, true, true, false];
+        assert_eq!(
+            compact(&s, 3),
+            (vec![true, true, true], vec![2, 4, 5], false)
+        );
+        assert_eq!(
+            compact(&s, 4),
+            (vec![true, true, true, false], vec![2, 4, 5, 0], false)
+        );
+        assert_eq!(compact(&s, 2), (vec![true, true], vec![2, 4], true));
+        assert_eq!(compact(&s, 8).1, vec![2, 4, 5, 0, 0, 0, 0, 0]);
+        assert_eq!(compact(&[false; 4], 2), (vec![false, false], vec![0, 0], false));
+        assert_eq!(compact(&[true; 4], 4), (vec![true; 4], vec![1, 2, 3, 4], false));
+        let select_mask = BoolMask::<bool> { mask: s.to_vec() };
+        assert!(Compaction::<bool>::new(&select_mask, 0).is_err());
+    }
+}
@@ -1,26 +1,54 @@
 use crate::uint::mask::BoolMask;
 use crate::bitops::*;
 use crate::types::*;
+use crate::FheSqlError;
 use crate::OrderedTables;
 use crate::Table;
 use rayon::iter::*;
 
+use super::run_control::{RunControl, RunStage};
+
+/// Checkpoint state: the flags of a chunk of rows computed by the DISTINCT pass
+/// (suffixed by the first row of the chunk)
+const DISTINCT_STATE: &str = "distinct";
+
 pub(super) fn compute_select_distinct<B>(
     select_mask: &mut BoolMask<B>,
     distinct: &B,
     tables: &OrderedTables,
     table_mask: &BoolMask<B>,
     not_field_mask: &BoolMask<B>,
-) where
-    B: ThreadSafeUInt + ThreadSafeBool,
+    control: &RunControl,
+) -> Result<(), FheSqlError>
+where
+    B: ThreadSafeUInt + ThreadSafeBool + serde::Serialize + serde::de::DeserializeOwned,
 {
     if select_mask.mask.len() <= 1 {
-        return;
+        return Ok(());
     }
 
     // Start at 1 since line 0 is invariant
+    let mut first_row = 1;
+    let checkpoint = control.checkpoint();
+    if let Some(checkpoint) = checkpoint {
+        // Only the flags of the rows before `first_row` have been modified
+        while let Some(flags) =
+            checkpoint.load::<BoolMask<B>>(&format!("{}_{}", DISTINCT_STATE, first_row))?
+        {
+            let end = first_row + flags.len();
+            if flags.is_empty() || end > select_mask.len() {
+                break;
+            }
+            select_mask.mask[first_row..end].clone_from_slice(&flags.mask);
+            first_row = end;
+        }
+    }
+    let mut chunk_start = first_row;
+
     // Iterative
-    (1..select_mask.mask.len()).for_each(|row_index_i| {
+    let num_steps = select_mask.mask.len() - 1;
+    for row_index_i in first_row..select_mask.mask.len() {
+        control.step(RunStage::SelectDistinct, row_index_i - 1, num_steps)?;
         // Parallel
         par_compute_select_distinct_row_i(
             select_mask,
@@ -29,38 +57,118 @@ pub(super) fn compute_select_distinct<B>(
             tables,
             table_mask,
             not_field_mask,
-        )
-    });
+        );
+        if let Some(checkpoint) = checkpoint {
+            if row_index_i % checkpoint.chunk_num_rows() == 0 {
+                let flags = BoolMask::<B> {
+                    mask: select_mask.mask[chunk_start..=row_index_i].to_vec(),
+                };
+                checkpoint.save(&format!("{}_{}", DISTINCT_STATE, chunk_start), &flags)?;
+                chunk_start = row_index_i + 1;
+            }
+        }
+    }
 
     par_unselect_out_of_bounds(select_mask, tables, table_mask);
+
+    control.progress(RunStage::SelectDistinct, num_steps, num_steps);
+    Ok(())
+}
+
+/// Streaming version: computes the SELECT DISTINCT flag of the rows in `rows`.
+/// `select_mask` holds the final flags of all the previous rows followed by
+/// the flags of the rows in `rows`.
+pub(super) fn compute_select_distinct_rows<B>(
+    select_mask: &mut BoolMask<B>,
+    rows: std::ops::Range<usize>,
+    distinct: &B,
+    tables: &OrderedTables,
+    table_mask: &BoolMask<B>,
+    not_field_mask: &BoolMask<B>,
+    control: &RunControl,
+) -> Result<(), FheSqlError>
+where
+    B: ThreadSafeUInt + ThreadSafeBool,
+{
+    assert_eq!(select_mask.len(), rows.end);
+
+    // Row 0 is invariant
+    // Iterative
+    let num_steps = rows.len();
+    for (step, row_index_i) in rows.enumerate() {
+        control.step(RunStage::SelectDistinct, step, num_steps)?;
+        if row_index_i == 0 {
+            continue;
+        }
+        // Parallel
+        par_compute_select_distinct_row_i(
+            select_mask,
+            row_index_i,
+            distinct,
+            tables,
+            table_mask,
+            not_field_mask,
+        )
+    }
+
+    control.progress(RunStage::SelectDistinct, num_steps, num_steps);
+    Ok(())
 }
 
 /// Unselect every out of bounds lines
 /// Try to minimize the number of boolean ops
-/// Maximum number of tables = 64
-fn par_unselect_out_of_bounds<B>(
+pub(super) fn par_unselect_out_of_bounds<B>(
     select_mask: &mut BoolMask<B>,
     tables: &OrderedTables,
     table_mask: &BoolMask<B>,
 ) where
     B: ThreadSafeUInt + ThreadSafeBool + DebugToString,
+{
+    let num_rows = tables
+        .iter_tables()
+        .map(|table| table.num_rows())
+        .collect::<Vec<usize>>();
+    par_unselect_out_of_bounds_num_rows(select_mask, &num_rows, table_mask);
+}
+
+/// Same as [par_unselect_out_of_bounds], the tables are only described by
+/// their number of rows
+pub(super) fn par_unselect_out_of_bounds_num_rows<B>(
+    select_mask: &mut BoolMask<B>,
+    tables_num_rows: &[usize],
+    table_mask: &BoolMask<B>,
+) where
+    B: ThreadSafeUInt + ThreadSafeBool + DebugToString,
 {
     assert!(!table_mask.is_empty());
-    assert!(table_mask.len() <= 64);
-    let min_rows = tables.min_num_rows();
-    let max_rows = tables.max_num_rows();
+    assert_eq!(table_mask.len(), tables_num_rows.len());
+    let min_rows = tables_num_rows.iter().copied().min().unwrap_or(usize::MAX);
+    let max_rows = tables_num_rows.iter().copied().max().unwrap_or(0);
+
+    // The list of predicates NumRows(t) >= num_rows, one for each table
+    let encode_predicate_rows_gteq = |num_rows: usize| {
+        tables_num_rows
+            .iter()
+            .map(|n| *n >= num_rows)
+            .collect::<Vec<bool>>()
+    };
 
     let mut prev_rows = min_rows;
-    let mut prev = tables.encode_predicate_rows_gteq(min_rows);
-    assert_eq!(prev, table_mask.max_u64());
+    let mut prev = encode_predicate_rows_gteq(min_rows);
+    assert!(prev.iter().all(|gteq| *gteq));
 
     let mut vanished = B::get_false();
     let mut not_vanished = B::get_true();
 
     ((min_rows + 1)..=max_rows).for_each(|num_rows| {
-        let cur = tables.encode_predicate_rows_gteq(num_rows);
+        let cur = encode_predicate_rows_gteq(num_rows);
         if prev != cur {
-            let indices_of_new_vanished_tables = tables.rows_between(prev_rows, num_rows);
+            let indices_of_new_vanished_tables = tables_num_rows
+                .iter()
+                .enumerate()
+                .filter(|(_, n)| prev_rows <= **n && **n < num_rows)
+                .map(|(i, _)| i)
+                .collect::<Vec<usize>>();
             assert!(!indices_of_new_vanished_tables.is_empty());
             let mut new_vanished_mask = table_mask.extract(&indices_of_new_vanished_tables);
             assert_eq!(
@@ -88,7 +196,7 @@ fn par_compute_select_distinct_row_i<B>(
 ) where
     B: ThreadSafeUInt + ThreadSafeBool,
 {
-    assert!(select_mask.len() == tables.max_num_rows());
+    assert!(select_mask.len() <= tables.max_num_rows());
 
     // [j < i; LineVisible(j) AND EqualLine(i, j)]
     let select_j_and_eq_line_i_j: Vec<B> = select_mask
@@ -0,0 +1,410 @@
+use crate::bitops::*;
+use crate::types::*;
+use crate::uint::mask::BoolMask;
+use crate::utils::arrow::array_column_cell_cmp;
+use crate::FheSqlError;
+use crate::OrderedTables;
+use crate::Table;
+#[cfg(feature = "parallel")]
+use rayon::{iter::*, slice::ParallelSliceMut};
+
+use super::distinct::par_unselect_out_of_bounds;
+use super::run_control::{RunControl, RunStage};
+
+/// SELECT DISTINCT based on a sort network:
+///
+/// 1. Key(r) = [NOT Select(r), Rank(r, c) of each visible column c, r]
+/// 2. Bitonic sort of the keys, selected rows first, equal rows ordered by row index
+/// 3. Dup(p) = Select(p) AND Key(p) == Key(p - 1) (row index excluded)
+/// 4. Bitonic sort of [r, Dup] by row index, back to the original row order
+/// 5. Select(r) = Select(r) AND NOT { Dup(r) AND distinct }
+///
+/// Cost: O(n.log²(n)) compare-exchanges instead of O(n²) row comparisons
+pub(super) fn compute_select_distinct_sort<B>(
+    select_mask: &mut BoolMask<B>,
+    distinct: &B,
+    tables: &OrderedTables,
+    table_mask: &BoolMask<B>,
+    not_field_mask: &BoolMask<B>,
+    control: &RunControl,
+) -> Result<(), FheSqlError>
+where
+    B: ThreadSafeUInt + ThreadSafeBool,
+{
+    const NUM_STEPS: usize = 4;
+
+    let num_rows = select_mask.mask.len();
+    if num_rows <= 1 {
+        return Ok(());
+    }
+
+    // Out of bounds rows must not be merged with the rows of the selected table
+    par_unselect_out_of_bounds(select_mask, tables, table_mask);
+
+    let num_items = num_rows.next_power_of_two();
+    let index_width = num_bits(num_items - 1);
+
+    // 1. Key(r)
+    control.step(RunStage::SelectDistinct, 0, NUM_STEPS)?;
+    let rank_keys = RankKeys::new(tables, table_mask, not_field_mask);
+    let key_width = 1 + rank_keys.width() + index_width;
+    let mut items = (0..num_items)
+        .map(|r| {
+            let mut item = Vec::<B>::with_capacity(key_width);
+            if r < num_rows {
+                item.push(select_mask.mask[r].ref_not());
+                item.extend(rank_keys.key(r));
+            } else {
+                // Padding: after every row
+                item.extend((0..1 + rank_keys.width()).map(|_| B::get_true()));
+            }
+            item.extend(clear_bits::<B>(r, index_width));
+            item
+        })
+        .collect::<Vec<Vec<B>>>();
+
+    // 2. Sort
+    control.step(RunStage::SelectDistinct, 1, NUM_STEPS)?;
+    bitonic_sort(&mut items, key_width);
+
+    // 3. Dup(p) AND distinct, with the row index of p
+    control.step(RunStage::SelectDistinct, 2, NUM_STEPS)?;
+    let dup_width = 1 + rank_keys.width();
+    let dup_at = |p: usize| {
+        let item = &items[p];
+        let dup = if p == 0 {
+            B::get_false()
+        } else {
+            let (_, eq) = gt_eq(&items[p - 1][0..dup_width], &item[0..dup_width]);
+            eq.refref_bitand(&item[0].ref_not()).refref_bitand(distinct)
+        };
+        let mut dup_item = item[dup_width..].to_vec();
+        dup_item.push(dup);
+        dup_item
+    };
+
+    #[cfg(feature = "parallel")]
+    let mut dup_items = (0..num_items)
+        .into_par_iter()
+        .map(dup_at)
+        .collect::<Vec<Vec<B>>>();
+
+    #[cfg(not(feature = "parallel"))]
+    let mut dup_items = (0..num_items).map(dup_at).collect::<Vec<Vec<B>>>();
+
+    // 4. Back to the original row order
+    control.step(RunStage::SelectDistinct, 3, NUM_STEPS)?;
+    bitonic_sort(&mut dup_items, index_width);
+
+    // 5. Select(r) AND NOT Dup(r)
+    select_mask
+        .mask
+        .iter_mut()
+        .zip(dup_items.iter())
+        .for_each(|(select_r, item)| {
+            *select_r = select_r.refref_bitand(&item[index_width].ref_not());
+        });
+
+    control.progress(RunStage::SelectDistinct, NUM_STEPS, NUM_STEPS);
+    Ok(())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// RankKeys
+////////////////////////////////////////////////////////////////////////////////
+
+/// Projected row keys. Each cell value is replaced by its dense rank
+/// in its column: the rank bits preserve cell equality and order with
+/// much fewer bits than the cell value itself.
+///
+/// - Bit(r, c, b) = OR { t; Table(t) AND Visible(c) AND Bit(b, Rank(t, r, c)) }
+struct RankKeys<'a, B> {
+    tables: &'a OrderedTables,
+    /// ranks[t][c][r] = Rank(t, r, c)
+    ranks: Vec<Vec<Vec<usize>>>,
+    /// Rank width of each column index (max over all tables)
+    widths: Vec<usize>,
+    /// table_visible[t][c] = Table(t) AND Visible(c)
+    table_visible: Vec<Vec<B>>,
+}
+
+impl<'a, B> RankKeys<'a, B>
+where
+    B: ThreadSafeBool,
+{
+    fn new(
+        tables: &'a OrderedTables,
+        table_mask: &BoolMask<B>,
+        not_field_mask: &BoolMask<B>,
+    ) -> Self {
+        let ranks = tables
+            .tables()
+            .iter()
+            .map(table_dense_ranks)
+            .collect::<Vec<Vec<Vec<usize>>>>();
+
+        let mut widths = vec![0; not_field_mask.len()];
+        ranks.iter().for_each(|table_ranks| {
+            table_ranks.iter().enumerate().for_each(|(c, column_ranks)| {
+                let max_rank = column_ranks.iter().copied().max().unwrap_or(0);
+                widths[c] = widths[c].max(num_bits(max_rank));
+            })
+        });
+
+        let visible = not_field_mask
+            .mask
+            .iter()
+            .map(|not_field| not_field.ref_not())
+            .collect::<Vec<B>>();
+        let table_visible = tables
+            .tables()
+            .iter()
+            .zip(table_mask.mask.iter())
+            .map(|(table, is_table)| {
+                visible
+                    .iter()
+                    .take(table.num_columns())
+                    .map(|v| v.refref_bitand(is_table))
+                    .collect()
+            })
+            .collect();
+
+        RankKeys {
+            tables,
+            ranks,
+            widths,
+            table_visible,
+        }
+    }
+
+    fn width(&self) -> usize {
+        self.widths.iter().sum()
+    }
+
+    /// Key bits of row r, most significant bit first
+    fn key(&self, r: usize) -> Vec<B> {
+        let mut key = Vec::<B>::with_capacity(self.width());
+        self.widths.iter().enumerate().for_each(|(c, width)| {
+            (0..*width).rev().for_each(|b| {
+                let v = self
+                    .tables
+                    .tables()
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(t, table)| {
+                        if r >= table.num_rows() || c >= table.num_columns() {
+                            return None;
+                        }
+                        if (self.ranks[t][c][r] >> b) & 1 == 0 {
+                            return None;
+                        }
+                        Some(&self.table_visible[t][c])
+                    })
+                    .collect::<Vec<&B>>();
+                key.push(par_bitor_vec_ref(v).unwrap_or(B::get_false()));
+            })
+        });
+        key
+    }
+}
+
+/// Dense rank of each cell within its column: ranks[c][r]
+fn table_dense_ranks(table: &Table) -> Vec<Vec<usize>> {
+    table
+        .batch()
+        .columns()
+        .iter()
+        .map(|column| {
+            let mut rows = (0..table.num_rows()).collect::<Vec<usize>>();
+            rows.sort_by(|a, b| cell_ordering(array_column_cell_cmp(column, *a, *b)));
+            let mut ranks = vec![0; table.num_rows()];
+            let mut rank = 0;
+            (1..rows.len()).for_each(|i| {
+                if !array_column_cell_cmp(column, rows[i - 1], rows[i])[0] {
+                    rank += 1;
+                }
+                ranks[rows[i]] = rank;
+            });
+            ranks
+        })
+        .collect()
+}
+
+fn cell_ordering(eq_gt_lt: [bool; 3]) -> std::cmp::Ordering {
+    if eq_gt_lt[0] {
+        std::cmp::Ordering::Equal
+    } else if eq_gt_lt[1] {
+        std::cmp::Ordering::Greater
+    } else {
+        std::cmp::Ordering::Less
+    }
+}
+
+fn num_bits(value: usize) -> usize {
+    (usize::BITS - value.leading_zeros()) as usize
+}
+
+/// Trivial bits of value, most significant bit first
+fn clear_bits<B>(value: usize, width: usize) -> Vec<B>
+where
+    B: BooleanType,
+{
+    (0..width)
+        .rev()
+        .map(|b| {
+            if (value >> b) & 1 == 1 {
+                B::get_true()
+            } else {
+                B::get_false()
+            }
+        })
+        .collect()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Bitonic sort
+////////////////////////////////////////////////////////////////////////////////
+
+/// Returns (A > B, A == B), bits are ordered most significant bit first
+///
+/// - Gt(hi:lo) = Gt(hi) OR { Eq(hi) AND Gt(lo) }
+/// - Eq(hi:lo) = Eq(hi) AND Eq(lo)
+fn gt_eq<B>(a: &[B], b: &[B]) -> (B, B)
+where
+    B: ThreadSafeBool,
+{
+    assert_eq!(a.len(), b.len());
+    assert!(!a.is_empty());
+    if a.len() == 1 {
+        let gt = a[0].refref_bitand(&b[0].ref_not());
+        let lt = b[0].refref_bitand(&a[0].ref_not());
+        let eq = gt.refref_bitor(&lt).ref_not();
+        return (gt, eq);
+    }
+    let mid = a.len() / 2;
+    let (gt_hi, eq_hi) = gt_eq(&a[0..mid], &b[0..mid]);
+    let (gt_lo, eq_lo) = gt_eq(&a[mid..], &b[mid..]);
+    (
+        gt_hi.refref_bitor(&eq_hi.refref_bitand(&gt_lo)),
+        eq_hi.refref_bitand(&eq_lo),
+    )
+}
+
+/// Swaps A and B if Key(A) > Key(B)
+fn compare_exchange<B>(a: &mut [B], b: &mut [B], key_width: usize)
+where
+    B: ThreadSafeBool,
+{
+    let (swap, _) = gt_eq(&a[0..key_width], &b[0..key_width]);
+    let keep = swap.ref_not();
+    a.iter_mut().zip(b.iter_mut()).for_each(|(x, y)| {
+        let new_x = swap.refref_bitand(y).refref_bitor(&keep.refref_bitand(x));
+        let new_y = swap.refref_bitand(x).refref_bitor(&keep.refref_bitand(y));
+        *x = new_x;
+        *y = new_y;
+    });
+}
+
+/// Ascending sort of the items by their first `key_width` bits,
+/// the number of items must be a power of two.
+fn bitonic_sort<B>(items: &mut [Vec<B>], key_width: usize)
+where
+    B: ThreadSafeBool,
+{
+    let n = items.len();
+    assert!(n.is_power_of_two());
+    let mut k = 2;
+    while k <= n {
+        let mut j = k / 2;
+        while j > 0 {
+            bitonic_stage(items, key_width, k, j);
+            j /= 2;
+        }
+        k *= 2;
+    }
+}
+
+/// Pairs (i, i ^ j) are disjoint: chunks of 2j items hold j independent pairs
+#[cfg(feature = "parallel")]
+fn bitonic_stage<B>(items: &mut [Vec<B>], key_width: usize, k: usize, j: usize)
+where
+    B: ThreadSafeBool,
+{
+    items
+        .par_chunks_mut(2 * j)
+        .enumerate()
+        .for_each(|(chunk_index, chunk)| {
+            let ascending = (chunk_index * 2 * j) & k == 0;
+            let (lo, hi) = chunk.split_at_mut(j);
+            lo.par_iter_mut().zip(hi.par_iter_mut()).for_each(|(a, b)| {
+                if ascending {
+                    compare_exchange(a, b, key_width)
+                } else {
+                    compare_exchange(b, a, key_width)
+                }
+            });
+        });
+}
+
+/// Pairs (i, i ^ j) are disjoint: chunks of 2j items hold j independent pairs
+#[cfg(not(feature = "parallel"))]
+fn bitonic_stage<B>(items: &mut [Vec<B>], key_width: usize, k: usize, j: usize)
+where
+    B: ThreadSafeBool,
+{
+    items
+        .chunks_mut(2 * j)
+        .enumerate()
+        .for_each(|(chunk_index, chunk)| {
+            let ascending = (chunk_index * 2 * j) & k == 0;
+            let (lo, hi) = chunk.split_at_mut(j);
+            lo.iter_mut().zip(hi.iter_mut()).for_each(|(a, b)| {
+                if ascending {
+                    compare_exchange(a, b, key_width)
+                } else {
+                    compare_exchange(b, a, key_width)
+                }
+            });
+        });
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bitonic_sort() {
+        let values = [5_usize, 0, 7, 3, 3, 6, 1, 2];
+        // Payload: original position
+        let mut items = values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                let mut item = clear_bits::<bool>(*v, 3);
+                item.extend(clear_bits::<bool>(i, 3));
+                item
+            })
+            .collect::<Vec<Vec<bool>>>();
+        bitonic_sort(&mut items, 3);
+        let to_usize = |bits: &[bool]| bits.iter().fold(0, |acc, b| (acc << 1) | *b as usize);
+        let sorted = items
+            .iter()
+            .map(|item| (to_usize(&item[0..3]), to_usize(&item[3..])))
+            .collect::<Vec<(usize, usize)>>();
+        assert_eq!(
+            sorted.iter().map(|(v, _)| *v).collect::<Vec<usize>>(),
+            vec![0, 1, 2, 3, 3, 5, 6, 7]
+        );
+        sorted
+            .iter()
+            .for_each(|(v, i)| assert_eq!(values[*i], *v));
+
+        assert_eq!(gt_eq(&clear_bits::<bool>(6, 3), &clear_bits(5, 3)), (true, false));
+        assert_eq!(gt_eq(&clear_bits::<bool>(5, 3), &clear_bits(5, 3)), (false, true));
+        assert_eq!(gt_eq(&clear_bits::<bool>(1, 3), &clear_bits(4, 3)), (false, false));
+    }
+}
@@ -0,0 +1,957 @@
+use crate::bitops::*;
+use crate::default_into::{DefaultInto, ValueFrom};
+use crate::hi_lo_tree::traits::HiLoLogicOp;
+use crate::hi_lo_tree::{EqGt, EqGtLt, EqNe};
+use crate::maps::U8Map;
+use crate::query::sql_query::{SqlQuery, SqlQueryRef};
+use crate::query::sql_result::SqlResult;
+use crate::server::ident_compare_with::IdentCompareWithArray;
+use crate::sql_ast::ComparatorMask;
+use crate::table::byte_rows::{ByteRows, ByteRowsList};
+use crate::query::sql_query_value::SqlQueryRightBytes256;
+use crate::table::{CellKind, EncryptedCell, EncryptedOrderedTables, EncryptedTable};
+use crate::table::{CELL_ASCII_BYTES, CELL_INTEGER_BYTES};
+use crate::types::*;
+use crate::uint::mask::{BoolMask, Mask};
+use crate::uint::triangular_matrix::TriangularMatrix;
+use crate::uint::ByteArray;
+use crate::ClearEncryptedOrderedTables;
+use crate::ClearSqlQuery;
+use crate::ClearSqlResult;
+use crate::FheOrderedTables;
+use crate::FheSqlError;
+use crate::FheSqlQuery;
+use crate::FheSqlResult;
+use crate::SqlGateEstimate;
+use crate::SqlQueryExplain;
+use crate::SqlResultFormat;
+use rayon::iter::*;
+use rayon::slice::ParallelSlice;
+use std::{marker::PhantomData, sync::Arc};
+use tfhe::{FheBool, FheUint8};
+
+use super::compact::Compaction;
+use super::distinct::par_unselect_out_of_bounds_num_rows;
+use super::run_control::{RunControl, RunStage};
+use super::sql_server_options::SqlServerOptions;
+use super::FheSqlServer;
+
+////////////////////////////////////////////////////////////////////////////////
+// FheRunSqlQueryOnEncryptedTables
+////////////////////////////////////////////////////////////////////////////////
+
+/// Runs a query on tables encrypted at rest (see [FheOrderedTables]). The query
+/// must be encrypted with the client key of the tables owner.
+///
+/// The WHERE clause compares encrypted cells with the encrypted query operands,
+/// every comparison is homomorphic. Scalar subqueries, column arithmetics, IN sets,
+/// window functions and compressed results are not supported. The SELECT DISTINCT
+/// pass always uses the pairwise strategy.
+pub trait FheRunSqlQueryOnEncryptedTables<Q> {
+    type Tables;
+    type Result;
+    fn run_on_encrypted_tables(query: &Q, tables: &Self::Tables) -> Result<Self::Result, FheSqlError> {
+        Self::run_on_encrypted_tables_with_options(query, tables, &SqlServerOptions::default())
+    }
+    fn run_on_encrypted_tables_with_options(
+        query: &Q,
+        tables: &Self::Tables,
+        options: &SqlServerOptions,
+    ) -> Result<Self::Result, FheSqlError> {
+        Self::run_on_encrypted_tables_with_control(query, tables, options, &RunControl::default())
+    }
+    /// Same as [run_on_encrypted_tables_with_options](FheRunSqlQueryOnEncryptedTables::run_on_encrypted_tables_with_options),
+    /// the progress of each stage is reported to `control`. Checkpoints are not supported.
+    fn run_on_encrypted_tables_with_control(
+        query: &Q,
+        tables: &Self::Tables,
+        options: &SqlServerOptions,
+        control: &RunControl,
+    ) -> Result<Self::Result, FheSqlError>;
+    /// Worst case estimate of the number of operations needed to run the query on
+    /// `tables`, the estimate used by the admission control (see
+    /// [SqlServerOptions::with_max_gates] and
+    /// [SqlQueryExplain::estimate_gates_on_encrypted_tables]).
+    fn estimate_gates_on_encrypted_tables(
+        query: &Q,
+        tables: &Self::Tables,
+    ) -> Result<SqlGateEstimate, FheSqlError>;
+}
+
+impl FheRunSqlQueryOnEncryptedTables<ClearSqlQuery> for FheSqlServer {
+    type Tables = ClearEncryptedOrderedTables;
+    type Result = ClearSqlResult;
+
+    fn run_on_encrypted_tables_with_control(
+        query: &ClearSqlQuery,
+        tables: &ClearEncryptedOrderedTables,
+        options: &SqlServerOptions,
+        control: &RunControl,
+    ) -> Result<Self::Result, FheSqlError> {
+        Ok(ClearSqlResult(EncSqlServer::<u8, bool>::run(
+            Arc::new(query.clone()),
+            tables,
+            options,
+            control,
+        )?))
+    }
+
+    fn estimate_gates_on_encrypted_tables(
+        query: &ClearSqlQuery,
+        tables: &ClearEncryptedOrderedTables,
+    ) -> Result<SqlGateEstimate, FheSqlError> {
+        estimate_encrypted_query_gates(query, tables)
+    }
+}
+
+impl FheRunSqlQueryOnEncryptedTables<FheSqlQuery> for FheSqlServer {
+    type Tables = FheOrderedTables;
+    type Result = FheSqlResult;
+
+    fn run_on_encrypted_tables_with_control(
+        query: &FheSqlQuery,
+        tables: &FheOrderedTables,
+        options: &SqlServerOptions,
+        control: &RunControl,
+    ) -> Result<Self::Result, FheSqlError> {
+        Ok(FheSqlResult(EncSqlServer::<FheUint8, FheBool>::run(
+            Arc::new(query.clone()),
+            tables,
+            options,
+            control,
+        )?))
+    }
+
+    fn estimate_gates_on_encrypted_tables(
+        query: &FheSqlQuery,
+        tables: &FheOrderedTables,
+    ) -> Result<SqlGateEstimate, FheSqlError> {
+        estimate_encrypted_query_gates(query, tables)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// EncSqlServer
+////////////////////////////////////////////////////////////////////////////////
+
+struct EncSqlServer<'a, U8, B> {
+    phantom_b: PhantomData<B>,
+    tables: &'a EncryptedOrderedTables<U8, B>,
+    control: RunControl,
+}
+
+/// Checks that the query only uses the features supported on encrypted tables
+fn validate_query<B>(query: &SqlQuery<B>, control: &RunControl) -> Result<(), FheSqlError> {
+    let unsupported = if query.num_aggregates() > 0 {
+        Some("Scalar subqueries")
+    } else if !query.arithmetics().is_empty() {
+        Some("Column arithmetics")
+    } else if !query.sets().is_empty() {
+        Some("IN sets")
+    } else if !query.window().is_empty() {
+        Some("Window functions")
+    } else if query.options().compress() {
+        Some("Compressed results")
+    } else if !matches!(query.options().format(), SqlResultFormat::RowBytes(_)) {
+        Some("Table byte formats")
+    } else if control.checkpoint().is_some() {
+        Some("Checkpoints")
+    } else {
+        None
+    };
+    match unsupported {
+        Some(feature) => Err(FheSqlError::UnsupportedSqlQuery(format!(
+            "{} are not supported on encrypted tables",
+            feature
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Worst case estimate of the number of operations needed to run the query on the
+/// encrypted `tables`, computed from the clear shape of the query
+fn estimate_encrypted_query_gates<U8, B>(
+    query: &SqlQuery<B>,
+    tables: &EncryptedOrderedTables<U8, B>,
+) -> Result<SqlGateEstimate, FheSqlError> {
+    if tables.ordered_schemas() != query.ordered_schemas() {
+        return Err(FheSqlError::InvalidQueryError(
+            "The requested query schemas and tables schemas are incompatible".to_string(),
+        ));
+    }
+    SqlQueryExplain::from_query(query).estimate_gates_on_encrypted_tables(&tables.tables_num_rows())
+}
+
+/// Admission control: rejects the query if its estimated cost is over the server budget
+fn admit_encrypted_query<U8, B>(
+    query: &SqlQuery<B>,
+    tables: &EncryptedOrderedTables<U8, B>,
+    options: &SqlServerOptions,
+) -> Result<(), FheSqlError> {
+    let Some(max_gates) = options.max_gates() else {
+        return Ok(());
+    };
+    let estimated_gates = estimate_encrypted_query_gates(query, tables)?.total();
+    if estimated_gates > max_gates {
+        return Err(FheSqlError::CostLimitExceeded {
+            estimated_gates,
+            max_gates,
+        });
+    }
+    Ok(())
+}
+
+impl<'a, U8, B> EncSqlServer<'a, U8, B>
+where
+    B: ThreadSafeUInt + ThreadSafeBool + DefaultInto<B> + ValueFrom<B>,
+    for<'b> U8: ThreadSafeUInt
+        + RefEqGt<B>
+        + ValueFrom<&'b B>
+        + ValueFrom<u8>
+        + DefaultInto<U8>
+        + ValueFrom<U8>,
+{
+    fn run(
+        query_ref: SqlQueryRef<B>,
+        tables: &'a EncryptedOrderedTables<U8, B>,
+        options: &SqlServerOptions,
+        control: &RunControl,
+    ) -> Result<SqlResult<U8, B>, FheSqlError> {
+        // The options are set by the client
+        query_ref.options().validate()?;
+        if tables.ordered_schemas() != query_ref.ordered_schemas() {
+            return Err(FheSqlError::InvalidQueryError(
+                "The requested query schemas and tables schemas are incompatible".to_string(),
+            ));
+        }
+        validate_query(&query_ref, control)?;
+        admit_encrypted_query(&query_ref, tables, options)?;
+        if query_ref.is_empty() {
+            return Ok(SqlResult::<U8, B>::new_empty());
+        }
+
+        let srv = EncSqlServer {
+            phantom_b: PhantomData,
+            tables,
+            control: control.clone(),
+        };
+        let select_mask = srv.compute_select_mask(&query_ref)?;
+        srv.compute_result(&query_ref, select_mask)
+    }
+
+    fn compute_select_mask(&self, query_ref: &SqlQueryRef<B>) -> Result<BoolMask<B>, FheSqlError> {
+        let mut select_mask = if query_ref.is_where_empty() {
+            BoolMask::<B>::all(self.tables.max_num_rows())
+        } else {
+            let num_ops = query_ref.num_binary_ops();
+            // Shared by all the binary ops: Col(i) vs Col(j) on each row of each table
+            self.control.step(RunStage::ComputeSelect, 0, num_ops + 1)?;
+            let col_cmp_col = self.par_compute_col_cmp_col();
+            let mut select_masks = vec![];
+            for op_index in 0..num_ops {
+                self.control
+                    .step(RunStage::ComputeSelect, op_index + 1, num_ops + 1)?;
+                select_masks.push(self.compute_binary_op(query_ref, op_index, &col_cmp_col));
+            }
+            let mut ident_cmp_array = IdentCompareWithArray::<B>::new_empty(query_ref);
+            ident_cmp_array.set_select_masks(select_masks);
+            ident_cmp_array.compute_select_rows(query_ref, 0..self.tables.max_num_rows())
+        };
+        self.control.progress(RunStage::ComputeSelect, 1, 1);
+
+        self.compute_select_distinct(&mut select_mask, query_ref)?;
+        Ok(select_mask)
+    }
+
+    ////////////////////////////////////////////////////////////////////////////
+    // WHERE clause
+    ////////////////////////////////////////////////////////////////////////////
+
+    /// For each table, for each row: the triangular matrix of Col(i) vs Col(j)
+    fn par_compute_col_cmp_col(&self) -> Vec<Vec<TriangularMatrix<EqGtLt<B>>>> {
+        self.tables
+            .tables()
+            .par_iter()
+            .map(|table| {
+                (0..table.num_rows())
+                    .into_par_iter()
+                    .map(|row_index| table_row_col_cmp_col(table, row_index))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Select mask of the binary op: `Ident Op Value` or `Ident Op Ident`
+    fn compute_binary_op(
+        &self,
+        query_ref: &SqlQueryRef<B>,
+        op_index: usize,
+        col_cmp_col: &[Vec<TriangularMatrix<EqGtLt<B>>>],
+    ) -> BoolMask<B> {
+        let binary_op = query_ref.binary_op_at(op_index);
+        let table_mask = &query_ref.header().table_mask;
+        let right = RightBytes::<U8>::decode(&binary_op.right.bytes_256);
+
+        // Tr(i,j) = [k; Left(i) & Right(j) & Op(k)] where i <= j < max_num_columns
+        let op_matrix = binary_op.comparator_mask.triangular_matrix_and(
+            &binary_op
+                .left_ident_mask
+                .triangular_matrix(&binary_op.right.ident_mask),
+        );
+
+        let mut select_mask = BoolMask::<B>::all_false(self.tables.max_num_rows());
+        select_mask
+            .mask
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(row_index, dst)| {
+                let buffer = self
+                    .tables
+                    .tables()
+                    .par_iter()
+                    .enumerate()
+                    .filter(|(_, table)| row_index < table.num_rows())
+                    .map(|(table_index, table)| {
+                        let (value, ident) = rayon::join(
+                            || {
+                                table_row_op_value(
+                                    table,
+                                    row_index,
+                                    &binary_op.left_ident_mask,
+                                    &binary_op.comparator_mask,
+                                    &right,
+                                    &binary_op.right.is_strictly_negative.eq,
+                                )
+                                .refref_bitand(&binary_op.right.is_value)
+                            },
+                            || {
+                                table_row_op_ident(
+                                    &op_matrix,
+                                    &col_cmp_col[table_index][row_index],
+                                )
+                            },
+                        );
+                        value
+                            .refref_bitor(&ident)
+                            .refref_bitand(table_mask.get(table_index))
+                    })
+                    .collect::<Vec<B>>();
+                if let Some(b) = par_bitor_vec(buffer) {
+                    *dst = b;
+                }
+            });
+        select_mask
+    }
+
+    ////////////////////////////////////////////////////////////////////////////
+    // SELECT DISTINCT
+    ////////////////////////////////////////////////////////////////////////////
+
+    /// Pairwise strategy, see [compute_select_distinct](super::distinct::compute_select_distinct)
+    fn compute_select_distinct(
+        &self,
+        select_mask: &mut BoolMask<B>,
+        query_ref: &SqlQueryRef<B>,
+    ) -> Result<(), FheSqlError> {
+        let distinct = query_ref.distinct();
+        let table_mask = &query_ref.header().table_mask;
+        let not_field_mask = &query_ref.header().not_field_mask;
+
+        // Start at 1 since line 0 is invariant
+        let num_steps = select_mask.len().saturating_sub(1);
+        for row_index_i in 1..select_mask.len() {
+            self.control
+                .step(RunStage::SelectDistinct, row_index_i - 1, num_steps)?;
+            // [j < i; Select(j) AND EqualLine(i, j)]
+            let select_j_and_eq_line_i_j = select_mask
+                .mask
+                .par_iter()
+                .take(row_index_i)
+                .enumerate()
+                .filter_map(|(row_index_j, select_j)| {
+                    self.tables_row_i_eq_row_j(row_index_i, row_index_j, table_mask, not_field_mask)
+                        .map(|eq_line_i_j| select_j.refref_bitand(&eq_line_i_j))
+                })
+                .collect::<Vec<B>>();
+            if let Some(or_j) = par_bitor_vec(select_j_and_eq_line_i_j) {
+                // Select(i) AND !{ OR [j < i; Select(j) AND EqualLine(i, j)] AND distinct }
+                select_mask.mask[row_index_i] = or_j
+                    .refref_bitand(distinct)
+                    .ref_not()
+                    .refref_bitand(&select_mask.mask[row_index_i]);
+            }
+        }
+
+        par_unselect_out_of_bounds_num_rows(select_mask, &self.tables.tables_num_rows(), table_mask);
+
+        self.control
+            .progress(RunStage::SelectDistinct, num_steps, num_steps);
+        Ok(())
+    }
+
+    /// EqualLine(i, j) = OR { t; IsTable(t) AND AND { k; Cell(t,i,k) == Cell(t,j,k) OR !Visible(k) } }
+    fn tables_row_i_eq_row_j(
+        &self,
+        row_index_i: usize,
+        row_index_j: usize,
+        table_mask: &BoolMask<B>,
+        not_field_mask: &BoolMask<B>,
+    ) -> Option<B> {
+        let a = self
+            .tables
+            .tables()
+            .par_iter()
+            .enumerate()
+            .filter(|(_, table)| row_index_i < table.num_rows())
+            .map(|(table_index, table)| {
+                let mut v = (0..table.num_columns())
+                    .into_par_iter()
+                    .map(|column_index| {
+                        cell_eq(
+                            table.kind(column_index),
+                            table.cell(row_index_i, column_index),
+                            table.cell(row_index_j, column_index),
+                        )
+                        .refref_bitor(&not_field_mask.mask[column_index])
+                    })
+                    .collect::<Vec<B>>();
+                v.push(table_mask.get(table_index).clone());
+                par_bitand_vec(v).unwrap()
+            })
+            .collect::<Vec<B>>();
+        par_bitor_vec(a)
+    }
+
+    ////////////////////////////////////////////////////////////////////////////
+    // Result
+    ////////////////////////////////////////////////////////////////////////////
+
+    fn compute_result(
+        &self,
+        query_ref: &SqlQueryRef<B>,
+        select_mask: BoolMask<B>,
+    ) -> Result<SqlResult<U8, B>, FheSqlError> {
+        assert_eq!(select_mask.len(), self.tables.max_num_rows());
+
+        self.control.step(RunStage::ComputeResult, 0, 1)?;
+
+        let (byte_table_mask, byte_select_mask) = rayon::join(
+            || Mask::<U8>::value_from(&query_ref.header().table_mask),
+            || Mask::<U8>::value_from(&select_mask),
+        );
+
+        // Apply Table(t) AND Select(r) to each row of each table
+        let enc_masked_byte_rows_list = ByteRowsList::<U8>::from_byte_rows(
+            self.tables
+                .tables()
+                .par_iter()
+                .zip(byte_table_mask.mask.par_iter())
+                .map(|(table, table_byte_mask)| {
+                    ByteRows::<U8>::from_byte_array_vec(
+                        table
+                            .byte_rows()
+                            .par_iter()
+                            .zip(byte_select_mask.mask.par_iter())
+                            .map(|(row, select_byte_mask)| {
+                                let m = table_byte_mask.refref_bitand(select_byte_mask);
+                                ByteArray::<U8>::from_bytes(
+                                    row.bytes.par_iter().map(|b| b.refref_bitand(&m)).collect(),
+                                )
+                            })
+                            .collect(),
+                    )
+                })
+                .collect(),
+        );
+
+        let padding = match query_ref.options().format() {
+            SqlResultFormat::RowBytes(padding) => padding,
+            _ => unreachable!(),
+        };
+        let enc_byte_arrays = enc_masked_byte_rows_list
+            .par_flatten_row_by_row(padding)
+            .into_byte_array_vec();
+        assert_eq!(select_mask.len(), enc_byte_arrays.len());
+
+        let result = match query_ref.options().max_num_rows() {
+            Some(k) => {
+                let c = Compaction::<B>::new(&select_mask, k)?;
+                SqlResult::<U8, B>::from_query_ref(
+                    query_ref,
+                    c.select_mask(),
+                    c.compact(&enc_byte_arrays),
+                    vec![],
+                )
+                .with_truncated(c.truncated())
+            }
+            None => SqlResult::<U8, B>::from_query_ref(query_ref, select_mask, enc_byte_arrays, vec![]),
+        };
+
+        self.control.progress(RunStage::ComputeResult, 1, 1);
+        Ok(result)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// RightBytes
+////////////////////////////////////////////////////////////////////////////////
+
+/// The right operand value as encrypted bytes, decoded from its byte maps
+struct RightBytes<U8> {
+    /// The 32 bytes of the value. Numbers: the first 8 bytes are the
+    /// absolute value in little endian order. Strings: the ascii chars.
+    bytes: Vec<U8>,
+}
+
+impl<U8> RightBytes<U8> {
+    fn decode<B>(bytes_256: &SqlQueryRightBytes256<B>) -> Self
+    where
+        B: ThreadSafeBool,
+        for<'b> U8: ThreadSafeUInt + ValueFrom<&'b B> + ValueFrom<u8>,
+    {
+        // One map per byte, in little endian order: word 0 then words 1, 2 and 3
+        let mut eq_maps: Vec<Vec<&B>> = vec![];
+        eq_maps.extend(bytes_256.word_0_eq_gt.le_bytes.iter().map(eq_values(|x: &EqGt<B>| &x.eq)));
+        [
+            &bytes_256.word_1_eq_ne,
+            &bytes_256.word_2_eq_ne,
+            &bytes_256.word_3_eq_ne,
+        ]
+        .iter()
+        .for_each(|word| {
+            eq_maps.extend(word.le_bytes.iter().map(eq_values(|x: &EqNe<B>| &x.eq)))
+        });
+        assert_eq!(eq_maps.len(), CELL_ASCII_BYTES);
+
+        RightBytes {
+            bytes: eq_maps.into_par_iter().map(decode_byte::<U8, B>).collect(),
+        }
+    }
+
+    #[inline]
+    fn le_bytes(&self) -> &[U8] {
+        &self.bytes[..CELL_INTEGER_BYTES]
+    }
+
+    #[inline]
+    fn ascii(&self) -> &[U8] {
+        &self.bytes
+    }
+}
+
+fn eq_values<T, B>(eq: impl Fn(&T) -> &B) -> impl Fn(&U8Map<T>) -> Vec<&B> {
+    move |map| map.values().iter().map(&eq).collect()
+}
+
+/// `eq[v]` is true iff the byte is equal to `v`.
+///
+/// Formula:
+/// --------
+/// - Bit(b) = OR { v; (v >> b) & 1 == 1; eq[v] }
+/// - Byte = OR { b; U8(Bit(b)) AND (1 << b) }
+fn decode_byte<U8, B>(eq: Vec<&B>) -> U8
+where
+    B: ThreadSafeBool,
+    for<'b> U8: ThreadSafeUInt + ValueFrom<&'b B> + ValueFrom<u8>,
+{
+    assert_eq!(eq.len(), 256);
+    let bits = (0..u8::BITS)
+        .into_par_iter()
+        .map(|b| {
+            let bit = par_bitor_vec_ref(
+                eq.iter()
+                    .enumerate()
+                    .filter(|(v, _)| (v >> b) & 1 == 1)
+                    .map(|(_, e)| *e)
+                    .collect(),
+            )
+            .unwrap();
+            U8::value_from(&bit).ref_bitand(U8::value_from(1u8 << b))
+        })
+        .collect::<Vec<U8>>();
+    par_bitor_vec(bits).unwrap()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Encrypted comparisons
+////////////////////////////////////////////////////////////////////////////////
+
+/// Unsigned comparison of two byte strings given in most significant byte
+/// first order: `eq = (a == b)`, `gt = (a > b)`
+fn par_eq_gt_msb_first<'c, U8, B>(pairs: Vec<(&'c U8, &'c U8)>) -> EqGt<B>
+where
+    B: ThreadSafeBool,
+    U8: RefEqGt<B> + Sync + 'c,
+{
+    let mut v = pairs
+        .into_par_iter()
+        .map(|(a, b)| {
+            let (eq, gt) = rayon::join(|| a.refref_eq(b), || a.refref_gt(b));
+            EqGt { eq, gt }
+        })
+        .collect::<Vec<EqGt<B>>>();
+    assert!(!v.is_empty());
+    // Ordered reduction, hi is on the left
+    while v.len() > 1 {
+        v = v
+            .par_chunks(2)
+            .map(|x| {
+                if x.len() == 1 {
+                    x[0].clone()
+                } else {
+                    EqGt::<B>::from_hi_any_lo_any(&x[0], &x[1])
+                }
+            })
+            .collect();
+    }
+    v.pop().unwrap()
+}
+
+/// Comparison of the absolute values `|a|` and `|b|` (little endian bytes)
+fn par_abs_eq_gt<U8, B>(a: &[U8], b: &[U8]) -> EqGt<B>
+where
+    B: ThreadSafeBool,
+    U8: RefEqGt<B> + Sync,
+{
+    assert_eq!(a.len(), b.len());
+    par_eq_gt_msb_first(a.iter().rev().zip(b.iter().rev()).collect())
+}
+
+/// Signed comparison of `a` and `b` from their absolute value comparison and signs
+///
+/// Formula:
+/// --------
+/// - eq = |a| == |b| AND Neg(a) == Neg(b)
+/// - gt = (!Neg(a) AND Neg(b)) OR (!Neg(a) AND !Neg(b) AND |a| > |b|) OR (Neg(a) AND Neg(b) AND |a| < |b|)
+fn signed_eq_gt_lt<B>(abs: &EqGt<B>, a_is_strictly_negative: &B, b_is_strictly_negative: &B) -> EqGtLt<B>
+where
+    B: ThreadSafeBool,
+{
+    let (not_a, not_b) = rayon::join(
+        || a_is_strictly_negative.ref_not(),
+        || b_is_strictly_negative.ref_not(),
+    );
+    let both_pos = not_a.refref_bitand(&not_b);
+    let both_neg = a_is_strictly_negative.refref_bitand(b_is_strictly_negative);
+    let abs_lt = abs.eq.refref_bitor(&abs.gt).ref_not();
+    let eq = abs.eq.refref_bitand(&both_pos.refref_bitor(&both_neg));
+    let gt = par_bitor_vec(vec![
+        not_a.refref_bitand(b_is_strictly_negative),
+        both_pos.refref_bitand(&abs.gt),
+        both_neg.refref_bitand(&abs_lt),
+    ])
+    .unwrap();
+    EqGtLt::from_eq_gt(&eq, &gt)
+}
+
+/// Cell equality, used by the SELECT DISTINCT pass
+fn cell_eq<U8, B>(kind: CellKind, a: &EncryptedCell<U8, B>, b: &EncryptedCell<U8, B>) -> B
+where
+    B: ThreadSafeBool,
+    U8: RefEqGt<B> + Sync,
+{
+    match kind {
+        CellKind::Ascii => par_bitand_vec(
+            a.ascii
+                .par_iter()
+                .zip(b.ascii.par_iter())
+                .map(|(x, y)| x.refref_eq(y))
+                .collect(),
+        )
+        .unwrap(),
+        CellKind::Unsigned | CellKind::Signed => {
+            let mut v = a
+                .le_bytes
+                .par_iter()
+                .zip(b.le_bytes.par_iter())
+                .map(|(x, y)| x.refref_eq(y))
+                .collect::<Vec<B>>();
+            if kind == CellKind::Signed {
+                let (neg, pos) = rayon::join(
+                    || a.is_strictly_negative.refref_bitand(&b.is_strictly_negative),
+                    || {
+                        a.is_strictly_negative
+                            .ref_not()
+                            .refref_bitand(&b.is_strictly_negative.ref_not())
+                    },
+                );
+                v.push(neg.refref_bitor(&pos));
+            }
+            par_bitand_vec(v).unwrap()
+        }
+    }
+}
+
+/// Col(i) vs Col(j) on a row, same rules as [table_row_col_cmp_col](crate::table::cmp::table_row_col_cmp_col):
+/// two strings are compared in lexicographic order, any other pair is compared as integers
+/// (a string is converted to the integer it parses to, zero otherwise).
+fn table_row_col_cmp_col<U8, B>(table: &EncryptedTable<U8, B>, row_index: usize) -> TriangularMatrix<EqGtLt<B>>
+where
+    B: ThreadSafeBool,
+    U8: RefEqGt<B> + Sync,
+{
+    let dim = table.num_columns();
+    let coords = (0..dim)
+        .flat_map(|i| (i..dim).map(move |j| (i, j)))
+        .collect::<Vec<(usize, usize)>>();
+    let elements = coords
+        .into_par_iter()
+        .map(|(i, j)| {
+            if i == j {
+                return EqGtLt {
+                    eq: B::get_true(),
+                    gt: B::get_false(),
+                    lt: B::get_false(),
+                };
+            }
+            let (left, right) = (table.cell(row_index, i), table.cell(row_index, j));
+            if table.kind(i) == CellKind::Ascii && table.kind(j) == CellKind::Ascii {
+                let eq_gt = par_eq_gt_msb_first(left.ascii.iter().zip(right.ascii.iter()).collect());
+                EqGtLt::from_eq_gt(&eq_gt.eq, &eq_gt.gt)
+            } else {
+                let abs = par_abs_eq_gt(&left.le_bytes, &right.le_bytes);
+                signed_eq_gt_lt(&abs, &left.is_strictly_negative, &right.is_strictly_negative)
+            }
+        })
+        .collect();
+    TriangularMatrix::from_vec(elements, dim)
+}
+
+/// `Ident Op Value` on a row:
+/// Op { OR c; Left(c) AND EqGtLt(Value, Cell(c)) }
+fn table_row_op_value<U8, B>(
+    table: &EncryptedTable<U8, B>,
+    row_index: usize,
+    left_ident_mask: &BoolMask<B>,
+    comparator_mask: &ComparatorMask<B>,
+    right: &RightBytes<U8>,
+    right_is_strictly_negative: &B,
+) -> B
+where
+    B: ThreadSafeBool + DefaultInto<B>,
+    U8: RefEqGt<B> + Sync,
+{
+    let v = (0..table.num_columns())
+        .into_par_iter()
+        .map(|column_index| {
+            let cell = table.cell(row_index, column_index);
+            // EqGtLt is expressed from the value point of view (Value > Cell)
+            let eq_gt_lt = match table.kind(column_index) {
+                // String only supports the EQ operator
+                CellKind::Ascii => EqGtLt {
+                    eq: par_bitand_vec(
+                        right
+                            .ascii()
+                            .par_iter()
+                            .zip(cell.ascii.par_iter())
+                            .map(|(x, y)| x.refref_eq(y))
+                            .collect(),
+                    )
+                    .unwrap(),
+                    gt: B::get_false(),
+                    lt: B::get_false(),
+                },
+                // The sign of the value is not taken into account (see IdentOpValue)
+                CellKind::Unsigned => {
+                    let abs = par_abs_eq_gt(right.le_bytes(), &cell.le_bytes);
+                    EqGtLt::from_eq_gt(&abs.eq, &abs.gt)
+                }
+                CellKind::Signed => {
+                    let abs = par_abs_eq_gt(right.le_bytes(), &cell.le_bytes);
+                    signed_eq_gt_lt(&abs, right_is_strictly_negative, &cell.is_strictly_negative)
+                }
+            };
+            eq_gt_lt.refref_bitand(left_ident_mask.get(column_index))
+        })
+        .collect::<Vec<EqGtLt<B>>>();
+    let or_col = par_bitor_vec(v).unwrap_or_default();
+    // Apply comparator mask + Invert the result
+    comparator_mask.or_and_eq_gt_lt(&or_col.eq, &or_col.gt, &or_col.lt)
+}
+
+/// `Ident Op Ident` on a row:
+/// OR { i <= j; Tr(i,j) AND EqGtLt(Cell(i), Cell(j)) }
+fn table_row_op_ident<B>(
+    op_matrix: &TriangularMatrix<ComparatorMask<B>>,
+    col_cmp_col: &TriangularMatrix<EqGtLt<B>>,
+) -> B
+where
+    B: ThreadSafeBool + DefaultInto<B>,
+{
+    let dim = col_cmp_col.dim();
+    assert!(dim <= op_matrix.dim());
+    let v = col_cmp_col
+        .elements()
+        .par_iter()
+        .enumerate()
+        .map(|(index, eq_gt_lt)| {
+            let (i, j) = TriangularMatrix::<EqGtLt<B>>::compute_coords(index, col_cmp_col.len(), dim);
+            let op = op_matrix.get(i, j);
+            if i == j {
+                op.or_and_value3(&[true, false, false]).unwrap()
+            } else {
+                // Col(i) vs Col(j) without inversion: swap GT and LT
+                op.or_and_eq_gt_lt(&eq_gt_lt.eq, &eq_gt_lt.lt, &eq_gt_lt.gt)
+            }
+        })
+        .collect::<Vec<B>>();
+    par_bitor_vec(v).unwrap_or(B::get_false())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::simple_batch::*;
+    use crate::{FheRunSqlQuery, FheSqlClient, OrderedTables, SqlResultOptions, Table};
+
+    fn tables() -> OrderedTables {
+        OrderedTables::new(vec![
+            Table::new("table1", simple_batch_1()),
+            Table::new("table2", simple_batch_2()),
+            Table::new("table3", simple_batch_3()),
+            Table::new("table5", simple_batch_5()),
+        ])
+        .unwrap()
+    }
+
+    fn assert_same_result(sql: &str, options: SqlResultOptions) {
+        let tables = tables();
+        let enc_tables = ClearEncryptedOrderedTables::from(&tables);
+        let sql_client = FheSqlClient::new(tables.ordered_schemas().clone()).unwrap();
+        let clear_sql_query = sql_client.clear_sql(sql, options).unwrap();
+
+        let expected = FheSqlServer::run(&clear_sql_query, &tables)
+            .unwrap()
+            .into_record_batch()
+            .unwrap();
+        let result = FheSqlServer::run_on_encrypted_tables(&clear_sql_query, &enc_tables)
+            .unwrap()
+            .into_record_batch()
+            .unwrap();
+        assert_eq!(result, expected, "{}", sql);
+    }
+
+    #[test]
+    fn test_ident_op_value() {
+        let options = SqlResultOptions::default().with_compress(false);
+        [
+            "SELECT * FROM table1 WHERE PostalCode > -8",
+            "SELECT * FROM table1 WHERE PostalCode <= -6 AND CustomerID <> 24",
+            "SELECT * FROM table1 WHERE CustomerID <= 23 OR Preferences = 37",
+            "SELECT ProductID,Name FROM table2 WHERE Name = 'cd'",
+            "SELECT some_str FROM table5 WHERE some_str <> 'first line'",
+            "SELECT * FROM table5 WHERE some_bool = true AND some_int > 2",
+            "SELECT * FROM table3",
+        ]
+        .iter()
+        .for_each(|sql| assert_same_result(sql, options));
+    }
+
+    #[test]
+    fn test_ident_op_ident() {
+        let options = SqlResultOptions::default().with_compress(false);
+        [
+            "SELECT * FROM table2 WHERE ProductID < Type",
+            "SELECT * FROM table2 WHERE Style >= Category OR ProductID = Type",
+            "SELECT * FROM table3 WHERE ProductID <> Type",
+        ]
+        .iter()
+        .for_each(|sql| assert_same_result(sql, options));
+    }
+
+    #[test]
+    fn test_distinct() {
+        let options = SqlResultOptions::default().with_compress(false);
+        [
+            "SELECT DISTINCT ProductID FROM table3",
+            "SELECT DISTINCT ProductID FROM table3 WHERE ProductID = Type",
+            "SELECT DISTINCT some_str FROM table5",
+            "SELECT DISTINCT some_int,some_str FROM table5 WHERE some_int < 100",
+        ]
+        .iter()
+        .for_each(|sql| assert_same_result(sql, options));
+    }
+
+    #[test]
+    fn test_max_num_rows() {
+        let options = SqlResultOptions::default()
+            .with_compress(false)
+            .with_max_num_rows(2);
+        assert_same_result("SELECT * FROM table1 WHERE CustomerID > 21", options);
+    }
+
+    #[test]
+    fn test_max_gates() {
+        let tables = tables();
+        let enc_tables = ClearEncryptedOrderedTables::from(&tables);
+        let sql_client = FheSqlClient::new(tables.ordered_schemas().clone()).unwrap();
+        let options = SqlResultOptions::default().with_compress(false);
+        let clear_sql_query = sql_client
+            .clear_sql("SELECT DISTINCT * FROM table2 WHERE Style >= Category", options)
+            .unwrap();
+
+        // The encrypted cells cost more than the clear ones
+        let estimate =
+            FheSqlServer::estimate_gates_on_encrypted_tables(&clear_sql_query, &enc_tables)
+                .unwrap();
+        let clear_estimate =
+            FheSqlServer::estimate_gates(&clear_sql_query, &tables, &Default::default()).unwrap();
+        assert!(estimate.bool_gates > clear_estimate.bool_gates);
+        assert!(estimate.u8_gates > clear_estimate.u8_gates);
+
+        let server_options = SqlServerOptions::default().with_max_gates(estimate.total() - 1);
+        assert_eq!(
+            FheSqlServer::run_on_encrypted_tables_with_options(
+                &clear_sql_query,
+                &enc_tables,
+                &server_options
+            )
+            .err(),
+            Some(FheSqlError::CostLimitExceeded {
+                estimated_gates: estimate.total(),
+                max_gates: estimate.total() - 1
+            })
+        );
+        let server_options = SqlServerOptions::default().with_max_gates(estimate.total());
+        assert!(FheSqlServer::run_on_encrypted_tables_with_options(
+            &clear_sql_query,
+            &enc_tables,
+            &server_options
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let tables = tables();
+        let enc_tables = ClearEncryptedOrderedTables::from(&tables);
+        let sql_client = FheSqlClient::new(tables.ordered_schemas().clone()).unwrap();
+
+        // Compressed result
+        let options = SqlResultOptions::default().with_compress(true);
+        let clear_sql_query = sql_client
+            .clear_sql("SELECT * FROM table1", options)
+            .unwrap();
+        assert!(matches!(
+            FheSqlServer::run_on_encrypted_tables(&clear_sql_query, &enc_tables),
+            Err(FheSqlError::UnsupportedSqlQuery(_))
+        ));
+
+        // Schemas mismatch
+        let other_tables =
+            OrderedTables::new(vec![Table::new("table1", simple_batch_1())]).unwrap();
+        let other_enc_tables = ClearEncryptedOrderedTables::from(&other_tables);
+        let options = SqlResultOptions::default().with_compress(false);
+        let clear_sql_query = sql_client
+            .clear_sql("SELECT * FROM table1", options)
+            .unwrap();
+        assert!(matches!(
+            FheSqlServer::run_on_encrypted_tables(&clear_sql_query, &other_enc_tables),
+            Err(FheSqlError::InvalidQueryError(_))
+        ));
+    }
+}
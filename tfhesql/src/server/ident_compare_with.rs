@@ -1,5 +1,9 @@
+use super::ident_op_aggregate::IdentOpAggregate;
+use super::ident_op_arithmetic::IdentOpArithmetic;
 use super::ident_op_ident::IdentOpIdent;
+use super::ident_op_set::{IdentOpSet, InSetMemberships};
 use super::ident_op_value::IdentOpValue;
+use super::run_control::{RunControl, RunStage};
 use crate::default_into::{DefaultInto, ValueFrom};
 use crate::query::optional_bool_tree::OptionalBool;
 use crate::query::sql_query::SqlQueryRef;
@@ -7,8 +11,12 @@ use crate::query::sql_query_tree::SqlQueryTree;
 use crate::uint::mask::BoolMask;
 use crate::bitops::*;
 use crate::types::*;
+use crate::FheSqlError;
 use crate::OrderedTables;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+#[cfg(feature = "parallel")]
+use crate::utils::rayon::rayon_join5;
 #[cfg(feature = "parallel")]
 use rayon::iter::*;
 
@@ -19,6 +27,9 @@ use rayon::iter::*;
 struct IdentCompareWith<B> {
     ident: IdentOpIdent<B>,
     value: IdentOpValue<B>,
+    aggregate: IdentOpAggregate<B>,
+    arithmetic: IdentOpArithmetic<B>,
+    set: IdentOpSet<B>,
     select_mask: BoolMask<B>,
 }
 
@@ -41,6 +52,30 @@ where
             self.ident.select_mask(),
             self.value.select_mask(),
         );
+        // Scalar subqueries
+        if !self.aggregate.select_mask().is_empty() {
+            assert_eq!(self.select_mask.len(), self.aggregate.select_mask().len());
+            self.select_mask = RefBitOr::<BoolMask<B>>::refref_bitor(
+                &self.select_mask,
+                self.aggregate.select_mask(),
+            );
+        }
+        // Column arithmetics
+        if !self.arithmetic.select_mask().is_empty() {
+            assert_eq!(self.select_mask.len(), self.arithmetic.select_mask().len());
+            self.select_mask = RefBitOr::<BoolMask<B>>::refref_bitor(
+                &self.select_mask,
+                self.arithmetic.select_mask(),
+            );
+        }
+        // IN sets
+        if !self.set.select_mask().is_empty() {
+            assert_eq!(self.select_mask.len(), self.set.select_mask().len());
+            self.select_mask = RefBitOr::<BoolMask<B>>::refref_bitor(
+                &self.select_mask,
+                self.set.select_mask(),
+            );
+        }
     }
 }
 
@@ -62,13 +97,14 @@ where
         &self,
         query_ref: &SqlQueryRef<B>,
         select_mask: &mut BoolMask<B>,
+        first_row: usize,
     ) {
         select_mask
             .mask
             .par_iter_mut()
             .enumerate()
-            .for_each(|(row_index, dst)| {
-                let tree_arg = self.compute_select_row_arg(query_ref, row_index);
+            .for_each(|(i, dst)| {
+                let tree_arg = self.compute_select_row_arg(query_ref, first_row + i);
                 *dst = query_ref.where_tree().tree_compute(tree_arg);
             });
     }
@@ -79,13 +115,14 @@ where
         &self,
         query_ref: &SqlQueryRef<B>,
         select_mask: &mut BoolMask<B>,
+        first_row: usize,
     ) {
         select_mask
             .mask
             .iter_mut()
             .enumerate()
-            .for_each(|(row_index, dst)| {
-                let tree_arg = self.compute_select_row_arg(query_ref, row_index);
+            .for_each(|(i, dst)| {
+                let tree_arg = self.compute_select_row_arg(query_ref, first_row + i);
                 *dst = query_ref.where_tree().tree_compute(tree_arg);
             });
     }
@@ -250,6 +287,9 @@ where
             arr.array.push(IdentCompareWith {
                 ident: IdentOpIdent::new_empty(i, query_ref.clone()),
                 value: IdentOpValue::new_empty(i, query_ref.clone()),
+                aggregate: IdentOpAggregate::new_empty(i, query_ref.clone()),
+                arithmetic: IdentOpArithmetic::new_empty(i, query_ref.clone()),
+                set: IdentOpSet::new_empty(i, query_ref.clone()),
                 select_mask: BoolMask::<B>::new_empty(),
             })
         }
@@ -262,44 +302,105 @@ where
     B: ThreadSafeBool + ThreadSafeUInt + DefaultInto<B> + ValueFrom<B>,
 {
     #[cfg(feature = "parallel")]
-    fn pre_compute(&mut self, tables: &OrderedTables, chunck_size: usize) {
-        self.array.par_iter_mut().for_each(|x| {
-            rayon::join(
+    fn pre_compute(
+        &mut self,
+        tables: &OrderedTables,
+        chunck_size: usize,
+        memberships: &InSetMemberships<B>,
+        control: &RunControl,
+        num_steps: usize,
+    ) -> Result<(), FheSqlError> {
+        let num_done = AtomicUsize::new(0);
+        self.array.par_iter_mut().try_for_each(|x| {
+            control.check_cancelled()?;
+            rayon_join5(
                 || x.ident.compute(tables),
                 || x.value.compute(tables, chunck_size),
+                || x.aggregate.compute(tables),
+                || x.arithmetic.compute(tables, chunck_size),
+                || x.set.compute(tables, memberships),
             );
             x.compute_select();
+            let done = num_done.fetch_add(1, Ordering::Relaxed) + 1;
+            control.step(RunStage::ComputeSelect, done, num_steps)
         })
     }
 
     #[cfg(not(feature = "parallel"))]
-    fn pre_compute(&mut self, tables: &OrderedTables, chunck_size: usize) {
-        self.array.iter_mut().for_each(|x| {
+    fn pre_compute(
+        &mut self,
+        tables: &OrderedTables,
+        chunck_size: usize,
+        memberships: &InSetMemberships<B>,
+        control: &RunControl,
+        num_steps: usize,
+    ) -> Result<(), FheSqlError> {
+        let num_done = AtomicUsize::new(0);
+        self.array.iter_mut().try_for_each(|x| {
+            control.check_cancelled()?;
             x.ident.compute(tables);
             x.value.compute(tables, chunck_size);
+            x.aggregate.compute(tables);
+            x.arithmetic.compute(tables, chunck_size);
+            x.set.compute(tables, memberships);
             x.compute_select();
+            let done = num_done.fetch_add(1, Ordering::Relaxed) + 1;
+            control.step(RunStage::ComputeSelect, done, num_steps)
         })
     }
 
-    pub fn compute_select(
+    /// Computes the select mask of each binary op. `control` is checked before each
+    /// binary op, whose completion is reported as one of the `num_steps` steps of
+    /// [RunStage::ComputeSelect].
+    pub fn pre_compute_select(
         &mut self,
         query_ref: &SqlQueryRef<B>,
         tables: &OrderedTables,
         chunck_size: usize,
+        control: &RunControl,
+        num_steps: usize,
+    ) -> Result<(), FheSqlError> {
+        control.check_cancelled()?;
+        // Shared by all the binary ops
+        let memberships = InSetMemberships::compute(tables, query_ref);
+        self.pre_compute(tables, chunck_size, &memberships, control, num_steps)
+    }
+
+    /// Combines the select masks of the binary ops into the WHERE clause mask
+    /// of the rows in `rows`. Requires [pre_compute_select](Self::pre_compute_select)
+    /// or [set_select_masks](Self::set_select_masks).
+    pub fn compute_select_rows(
+        &self,
+        query_ref: &SqlQueryRef<B>,
+        rows: std::ops::Range<usize>,
     ) -> BoolMask<B> {
-        self.pre_compute(tables, chunck_size);
-        
         assert_eq!(self.len(), query_ref.num_binary_ops());
 
         if query_ref.num_binary_ops() == 1 {
             // Without WHERE Tree
-            self.array[0].select_mask().clone()
+            BoolMask::<B> {
+                mask: self.array[0].select_mask().mask[rows].to_vec(),
+            }
         } else {
             // With WHERE Tree
             assert!(query_ref.num_binary_ops() > 1);
-            let mut select_mask = BoolMask::<B>::none(tables.max_num_rows());
-            self.tree_compute_select_in_place(query_ref, &mut select_mask);
-            select_mask            
+            let mut select_mask = BoolMask::<B>::none(rows.len());
+            self.tree_compute_select_in_place(query_ref, &mut select_mask, rows.start);
+            select_mask
         }
     }
+
+    /// The select mask of each binary op
+    pub fn select_masks(&self) -> Vec<BoolMask<B>> {
+        self.array.iter().map(|x| x.select_mask().clone()).collect()
+    }
+
+    /// Restores the select masks previously returned by [select_masks](Self::select_masks)
+    pub fn set_select_masks(&mut self, select_masks: Vec<BoolMask<B>>) {
+        assert_eq!(self.len(), select_masks.len());
+        self.array
+            .iter_mut()
+            .zip(select_masks)
+            .for_each(|(x, select_mask)| x.select_mask = select_mask);
+    }
 }
@@ -0,0 +1,309 @@
+use crate::bitops::*;
+use crate::query::sql_query::SqlQueryRef;
+use crate::sql_ast::scalar_subquery::AggregateFunc;
+use crate::sql_ast::ComparatorMask;
+use crate::table::aggregate::AggregateValue;
+use crate::table::{OrderedTables, Table};
+use crate::types::*;
+use crate::uint::mask::BoolMask;
+#[cfg(feature = "parallel")]
+use rayon::iter::*;
+use std::collections::HashMap;
+
+////////////////////////////////////////////////////////////////////////////////
+// IdentOpAggregate
+////////////////////////////////////////////////////////////////////////////////
+
+/// Computes `Column op (SELECT AGG(column) FROM table)`
+///
+/// The server evaluates every possible aggregate in clear, the encrypted
+/// aggregate masks select the one requested by the client.
+pub struct IdentOpAggregate<B> {
+    binary_op_index: usize,
+    query_ref: SqlQueryRef<B>,
+    select_mask: BoolMask<B>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+impl<B> IdentOpAggregate<B> {
+    pub fn new_empty(binary_op_index: usize, query_ref: SqlQueryRef<B>) -> Self {
+        IdentOpAggregate {
+            binary_op_index,
+            query_ref,
+            select_mask: BoolMask::<B>::new_empty(),
+        }
+    }
+
+    pub fn table_mask(&self) -> &BoolMask<B> {
+        &self.query_ref.header().table_mask
+    }
+
+    /// Empty if the query does not contain any scalar subquery
+    pub fn select_mask(&self) -> &BoolMask<B> {
+        &self.select_mask
+    }
+}
+
+impl<B> IdentOpAggregate<B>
+where
+    B: ThreadSafeBool,
+{
+    pub fn compute(&mut self, tables: &OrderedTables) {
+        if self.query_ref.num_aggregates() == 0 {
+            return;
+        }
+        // Costly
+        self.select_mask = IdentOpAggregateCacheBuilder::<B>::build(tables, self);
+    }
+
+    /// Clear list of all the non-null aggregate values: (table, column, func, value)
+    fn candidates(tables: &OrderedTables) -> Vec<(usize, usize, AggregateFunc, AggregateValue)> {
+        let mut candidates = vec![];
+        tables
+            .tables()
+            .iter()
+            .enumerate()
+            .for_each(|(table_index, table)| {
+                for column_index in 0..table.num_columns() {
+                    for func in AggregateFunc::ALL {
+                        if func != AggregateFunc::Count && table.is_ascii_column(column_index) {
+                            continue;
+                        }
+                        if let Some(value) = table.aggregate(column_index, func) {
+                            candidates.push((table_index, column_index, func, value));
+                        }
+                    }
+                }
+            });
+        candidates
+    }
+
+    /// Candidate(c) = OR { a; AggregateMask(a) AND Table(a, t) AND Column(a, c) AND Func(a, f) }
+    fn candidate_flags(
+        &self,
+        candidates: &[(usize, usize, AggregateFunc, AggregateValue)],
+    ) -> Vec<B> {
+        let aggregate_mask = self.query_ref.aggregate_mask_at(self.binary_op_index);
+        let n = self.query_ref.num_aggregates();
+        assert_eq!(aggregate_mask.len(), n);
+        candidates
+            .iter()
+            .map(|(table_index, column_index, func, _)| {
+                let v = (0..n)
+                    .map(|a| {
+                        let agg = self.query_ref.aggregate_at(a);
+                        aggregate_mask
+                            .get(a)
+                            .refref_bitand(agg.table_mask.get(*table_index))
+                            .refref_bitand(agg.ident_mask.get(*column_index))
+                            .refref_bitand(agg.func_mask.get(func.index()))
+                    })
+                    .collect::<Vec<B>>();
+                par_bitor_vec(v).unwrap()
+            })
+            .collect()
+    }
+
+    /// Left(i) = [k; Op(k) AND LeftIdent(i)]
+    fn left_comparator_masks(&self, tables: &OrderedTables) -> Vec<ComparatorMask<B>> {
+        let sql_binary_op = self.query_ref.binary_op_at(self.binary_op_index);
+        let left_ident = &sql_binary_op.left_ident_mask;
+        let op_mask = &sql_binary_op.comparator_mask;
+        assert_eq!(left_ident.len(), tables.ordered_schemas().max_num_fields());
+        left_ident
+            .mask
+            .iter()
+            .map(|l| op_mask.and_value(l))
+            .collect()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// IdentOpAggregateCacheBuilder
+////////////////////////////////////////////////////////////////////////////////
+
+struct IdentOpAggregateCacheBuilder<B> {
+    candidates: Vec<(usize, usize, AggregateFunc, AggregateValue)>,
+    cache_indices: HashMap<Vec<u8>, usize>,
+    /// For each cached value: cache_eq_gt_lt[column][candidate]
+    cache_eq_gt_lt: Vec<Vec<Vec<[bool; 3]>>>,
+    cache_values: Vec<B>,
+    table_row_index_to_cache_index: Vec<Vec<usize>>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+impl<B> IdentOpAggregateCacheBuilder<B>
+where
+    B: ThreadSafeBool,
+{
+    fn pre_build(tables: &OrderedTables) -> Self {
+        let mut builder = IdentOpAggregateCacheBuilder::<B> {
+            candidates: IdentOpAggregate::<B>::candidates(tables),
+            cache_indices: HashMap::<Vec<u8>, usize>::new(),
+            cache_eq_gt_lt: vec![],
+            cache_values: vec![],
+            table_row_index_to_cache_index: vec![],
+        };
+        // Serial prepare
+        tables
+            .tables()
+            .iter()
+            .enumerate()
+            .for_each(|(table_index, table)| {
+                builder.pre_insert_table(table, table_index);
+            });
+        builder
+    }
+
+    pub fn build(tables: &OrderedTables, ident_op_aggregate: &IdentOpAggregate<B>) -> BoolMask<B> {
+        let mut builder = Self::pre_build(tables);
+        let candidate_flags = ident_op_aggregate.candidate_flags(&builder.candidates);
+        let left_masks = ident_op_aggregate.left_comparator_masks(tables);
+        builder.compute_values(&candidate_flags, &left_masks);
+        builder.compute_select(tables, ident_op_aggregate)
+    }
+
+    // OR { c; Candidate(c) AND OR { i; Left(i) AND [EQ,GT,LT](i, c) } }
+    fn compute_value(
+        eq_gt_lt: &[Vec<[bool; 3]>],
+        candidate_flags: &[B],
+        left_masks: &[ComparatorMask<B>],
+    ) -> B {
+        let v = candidate_flags
+            .iter()
+            .enumerate()
+            .map(|(candidate_index, flag)| {
+                let cmp = eq_gt_lt
+                    .iter()
+                    .enumerate()
+                    .map(|(column_index, eq_gt_lt_c)| {
+                        left_masks[column_index]
+                            .or_and_value3(&eq_gt_lt_c[candidate_index])
+                            .unwrap()
+                    })
+                    .collect::<Vec<B>>();
+                flag.refref_bitand(&par_bitor_vec(cmp).unwrap())
+            })
+            .collect::<Vec<B>>();
+        par_bitor_vec(v).unwrap_or(B::get_false())
+    }
+
+    #[cfg(feature = "parallel")]
+    fn compute_values(&mut self, candidate_flags: &[B], left_masks: &[ComparatorMask<B>]) {
+        self.cache_values
+            .par_iter_mut()
+            .zip(self.cache_eq_gt_lt.par_iter())
+            .for_each(|(dst, eq_gt_lt)| {
+                *dst = Self::compute_value(eq_gt_lt, candidate_flags, left_masks);
+            });
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    fn compute_values(&mut self, candidate_flags: &[B], left_masks: &[ComparatorMask<B>]) {
+        self.cache_values
+            .iter_mut()
+            .zip(self.cache_eq_gt_lt.iter())
+            .for_each(|(dst, eq_gt_lt)| {
+                *dst = Self::compute_value(eq_gt_lt, candidate_flags, left_masks);
+            });
+    }
+
+    #[cfg(feature = "parallel")]
+    // Compute final select, iterate over rows and apply table mask
+    fn compute_select(
+        &self,
+        tables: &OrderedTables,
+        ident_op_aggregate: &IdentOpAggregate<B>,
+    ) -> BoolMask<B> {
+        let table_mask = ident_op_aggregate.table_mask();
+        let mut select_mask = BoolMask::<B>::all_false(tables.max_num_rows());
+        select_mask
+            .mask
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(row_index, dst)| *dst = self.compute_tables_row(table_mask, row_index));
+        select_mask
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    // Compute final select, iterate over rows and apply table mask
+    fn compute_select(
+        &self,
+        tables: &OrderedTables,
+        ident_op_aggregate: &IdentOpAggregate<B>,
+    ) -> BoolMask<B> {
+        let table_mask = ident_op_aggregate.table_mask();
+        let mut select_mask = BoolMask::<B>::all_false(tables.max_num_rows());
+        select_mask
+            .mask
+            .iter_mut()
+            .enumerate()
+            .for_each(|(row_index, dst)| *dst = self.compute_tables_row(table_mask, row_index));
+        select_mask
+    }
+
+    // TableMask AND { OR 0 <= t < num_tables; TableRow(t)}
+    fn compute_tables_row(&self, table_mask: &BoolMask<B>, row_index: usize) -> B {
+        let buffer = self
+            .table_row_index_to_cache_index
+            .iter()
+            .enumerate()
+            .filter(|(_, row_index_to_cache_value_index)| {
+                row_index < row_index_to_cache_value_index.len()
+            })
+            .map(|(table_index, row_index_to_cache_value_index)| {
+                let i = row_index_to_cache_value_index[row_index];
+                self.cache_values[i].refref_bitand(table_mask.get(table_index))
+            })
+            .collect::<Vec<B>>();
+
+        par_bitor_vec(buffer).unwrap_or(B::get_false())
+    }
+
+    // Serial
+    fn pre_insert_table(&mut self, table: &Table, table_index: usize) {
+        assert!(table_index == self.table_row_index_to_cache_index.len());
+        self.table_row_index_to_cache_index.push(vec![]);
+        let n = table.num_rows();
+        for i in 0..n {
+            self.pre_insert_row(table, table_index, i);
+        }
+    }
+
+    // Serial: we are manipulating clear data on a single row
+    fn pre_insert_row(&mut self, table: &Table, table_index: usize, row_index: usize) {
+        assert!(row_index == self.table_row_index_to_cache_index[table_index].len());
+
+        // eq_gt_lt[i][c] = Col(i) cmp Candidate(c)
+        let eq_gt_lt = (0..table.num_columns())
+            .map(|column_index| {
+                let left = table.cell_as_i128(column_index, row_index);
+                self.candidates
+                    .iter()
+                    .map(|(_, _, _, value)| value.cmp_left(left))
+                    .collect::<Vec<[bool; 3]>>()
+            })
+            .collect::<Vec<Vec<[bool; 3]>>>();
+
+        // 0 = EQ, 1 = GT, 2 = LT
+        let key = eq_gt_lt
+            .iter()
+            .flatten()
+            .map(|v| v.iter().position(|b| *b).unwrap() as u8)
+            .collect::<Vec<u8>>();
+
+        let value_index = match self.cache_indices.get(&key) {
+            Some(existing_value_index) => *existing_value_index,
+            None => {
+                let next_value_index = self.cache_values.len();
+                self.cache_eq_gt_lt.push(eq_gt_lt);
+                self.cache_values.push(B::get_false());
+                self.cache_indices.insert(key, next_value_index);
+                next_value_index
+            }
+        };
+        self.table_row_index_to_cache_index[table_index].push(value_index);
+    }
+}
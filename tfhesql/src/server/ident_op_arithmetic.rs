@@ -0,0 +1,500 @@
+use crate::bitops::*;
+use crate::default_into::DefaultInto;
+use crate::hi_lo_tree::traits::CompareToSignedInteger;
+use crate::hi_lo_tree::U64EqGtTree;
+use crate::query::sql_query::SqlQueryRef;
+use crate::sql_ast::column_arithmetic::ArithmeticOp;
+use crate::sql_ast::ComparatorMask;
+use crate::table::{OrderedTables, Table};
+use crate::types::*;
+use crate::uint::mask::BoolMask;
+use crate::uint::traits::*;
+#[cfg(feature = "parallel")]
+use rayon::iter::*;
+use std::collections::HashMap;
+
+////////////////////////////////////////////////////////////////////////////////
+// ArithmeticValue
+////////////////////////////////////////////////////////////////////////////////
+
+/// Clear result of `left op right` on a table row
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ArithmeticValue {
+    /// Division or modulo by zero (NULL), never selected
+    Null,
+    /// |Value| > u64::MAX
+    Overflow { negative: bool },
+    /// Value in [floor, floor + 1[, floor = (-1)^negative * abs
+    Value { abs: u64, negative: bool, is_integer: bool },
+}
+
+impl ArithmeticValue {
+    fn compute(left: i128, right: i128, op: ArithmeticOp) -> Self {
+        let (floor, is_integer) = match op {
+            ArithmeticOp::Plus => (left.checked_add(right), true),
+            ArithmeticOp::Minus => (left.checked_sub(right), true),
+            ArithmeticOp::Multiply => (left.checked_mul(right), true),
+            ArithmeticOp::Divide => {
+                if right == 0 {
+                    return ArithmeticValue::Null;
+                }
+                let (num, den) = if right < 0 { (-left, -right) } else { (left, right) };
+                (Some(num.div_euclid(den)), num.rem_euclid(den) == 0)
+            }
+            ArithmeticOp::Modulo => {
+                if right == 0 {
+                    return ArithmeticValue::Null;
+                }
+                // Sign of the dividend
+                (Some(left % right), true)
+            }
+            // Two's complement
+            ArithmeticOp::BitwiseAnd => (Some(left & right), true),
+            ArithmeticOp::BitwiseOr => (Some(left | right), true),
+            ArithmeticOp::BitwiseXor => (Some(left ^ right), true),
+        };
+        let floor = match floor {
+            Some(f) => f,
+            // Only reachable with u64 * u64
+            None => return ArithmeticValue::Overflow { negative: (left < 0) != (right < 0) },
+        };
+        match u64::try_from(floor.unsigned_abs()) {
+            Ok(abs) => ArithmeticValue::Value {
+                abs,
+                negative: floor < 0,
+                is_integer,
+            },
+            Err(_) => ArithmeticValue::Overflow { negative: floor < 0 },
+        }
+    }
+
+    #[inline]
+    fn abs(&self) -> Option<u64> {
+        match self {
+            ArithmeticValue::Value { abs, .. } => Some(*abs),
+            _ => None,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// IdentOpArithmetic
+////////////////////////////////////////////////////////////////////////////////
+
+/// Computes `(Column op Column) cmp Value` and `(Column op Constant) cmp Value`
+///
+/// The server evaluates every possible `Column op Column` and `Column op Constant`
+/// expression in clear, the encrypted arithmetic masks select the one requested
+/// by the client. The constants are part of the clear query.
+pub(super) struct IdentOpArithmetic<B> {
+    binary_op_index: usize,
+    query_ref: SqlQueryRef<B>,
+    select_mask: BoolMask<B>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+impl<B> IdentOpArithmetic<B> {
+    pub fn new_empty(binary_op_index: usize, query_ref: SqlQueryRef<B>) -> Self {
+        IdentOpArithmetic {
+            binary_op_index,
+            query_ref,
+            select_mask: BoolMask::<B>::new_empty(),
+        }
+    }
+
+    pub fn table_mask(&self) -> &BoolMask<B> {
+        &self.query_ref.header().table_mask
+    }
+
+    /// Empty if the query does not contain any column arithmetic
+    pub fn select_mask(&self) -> &BoolMask<B> {
+        &self.select_mask
+    }
+
+    pub fn comparator_mask(&self) -> &ComparatorMask<B> {
+        &self
+            .query_ref
+            .binary_op_at(self.binary_op_index)
+            .comparator_mask
+    }
+}
+
+impl<B> IdentOpArithmetic<B>
+where
+    B: ThreadSafeBool + DefaultInto<B>,
+{
+    pub fn compute(&mut self, tables: &OrderedTables, chunck_size: usize) {
+        if self.query_ref.arithmetics().is_empty() {
+            return;
+        }
+        // Costly
+        self.select_mask = IdentOpArithmeticCacheBuilder::<B>::build(tables, self, chunck_size);
+    }
+
+    /// Clear list of all the possible expressions of a table: (left, right, op)
+    /// Constant `c` is the right operand at index `max_num_fields + c`
+    fn candidates(
+        table: &Table,
+        max_num_fields: usize,
+        num_constants: usize,
+    ) -> Vec<(usize, usize, ArithmeticOp)> {
+        let mut candidates = vec![];
+        for left in 0..table.num_columns() {
+            if table.is_ascii_column(left) {
+                continue;
+            }
+            let right_columns = (0..table.num_columns()).filter(|r| !table.is_ascii_column(*r));
+            let right_constants = max_num_fields..max_num_fields + num_constants;
+            for right in right_columns.chain(right_constants) {
+                for op in ArithmeticOp::ALL {
+                    candidates.push((left, right, op));
+                }
+            }
+        }
+        candidates
+    }
+
+    /// Candidate(t, c) = Table(t) AND OR { a; ArithmeticMask(a) AND Left(a, l) AND Right(a, r) AND Op(a, o) }
+    fn candidate_flags(&self, table_index: usize, candidates: &[(usize, usize, ArithmeticOp)]) -> Vec<B> {
+        let arithmetics = self.query_ref.arithmetics();
+        let arithmetic_mask = arithmetics.mask(self.binary_op_index);
+        assert_eq!(arithmetic_mask.len(), arithmetics.len());
+        let table_flag = self.table_mask().get(table_index);
+        candidates
+            .iter()
+            .map(|(left, right, op)| {
+                let v = (0..arithmetics.len())
+                    .map(|a| {
+                        let arithmetic = arithmetics.get(a);
+                        arithmetic_mask
+                            .get(a)
+                            .refref_bitand(arithmetic.left_ident_mask.get(*left))
+                            .refref_bitand(arithmetic.right_ident_mask.get(*right))
+                            .refref_bitand(arithmetic.op_mask.get(op.index()))
+                    })
+                    .collect::<Vec<B>>();
+                par_bitor_vec(v).unwrap().refref_bitand(table_flag)
+            })
+            .collect()
+    }
+
+    /// Op(Value)
+    fn compare(&self, value: &ArithmeticValue, eq_gt_cache: &U64EqGtTree<B>) -> B {
+        let right_strictly_negative = &self
+            .query_ref
+            .binary_op_at(self.binary_op_index)
+            .right
+            .is_strictly_negative;
+        let comparator_mask = self.comparator_mask();
+        match value {
+            ArithmeticValue::Null => B::get_false(),
+            ArithmeticValue::Overflow { negative } => comparator_mask
+                .or_and_value3(&[false, !negative, *negative])
+                .unwrap(),
+            ArithmeticValue::Value {
+                abs,
+                negative,
+                is_integer,
+            } => {
+                let eq_gt_lt = eq_gt_cache.eq_gt_lt_from_signed(
+                    (*abs, *negative),
+                    right_strictly_negative.eq(),
+                    right_strictly_negative.ne(),
+                );
+                if *is_integer {
+                    comparator_mask.or_and_eq_gt_lt(&eq_gt_lt.eq, &eq_gt_lt.gt, &eq_gt_lt.lt)
+                } else {
+                    // floor < Value < floor + 1
+                    // EqGtLt compares the right operand X with floor:
+                    // X < Value <=> X <= floor
+                    comparator_mask.or_and_eq_gt_lt(
+                        &B::get_false(),
+                        &eq_gt_lt.gt,
+                        &eq_gt_lt.lt.refref_bitor(&eq_gt_lt.eq),
+                    )
+                }
+            }
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// IdentOpArithmeticCacheBuilder
+////////////////////////////////////////////////////////////////////////////////
+
+struct IdentOpArithmeticCacheBuilder<B> {
+    /// candidates[t] = all the expressions of table t
+    candidates: Vec<Vec<(usize, usize, ArithmeticOp)>>,
+    value_indices: HashMap<ArithmeticValue, usize>,
+    values: Vec<ArithmeticValue>,
+    /// For each cached row: (table, [value index; candidate])
+    cache_indices: HashMap<(usize, Vec<usize>), usize>,
+    cache_keys: Vec<(usize, Vec<usize>)>,
+    cache_values: Vec<B>,
+    table_row_index_to_cache_index: Vec<Vec<usize>>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+impl<B> IdentOpArithmeticCacheBuilder<B>
+where
+    B: ThreadSafeBool + DefaultInto<B>,
+{
+    // Serial: we are manipulating clear data
+    fn pre_build(tables: &OrderedTables, constants: &[i64]) -> Self {
+        let mut builder = IdentOpArithmeticCacheBuilder::<B> {
+            candidates: vec![],
+            value_indices: HashMap::new(),
+            values: vec![],
+            cache_indices: HashMap::new(),
+            cache_keys: vec![],
+            cache_values: vec![],
+            table_row_index_to_cache_index: vec![],
+        };
+        tables
+            .tables()
+            .iter()
+            .enumerate()
+            .for_each(|(table_index, table)| {
+                builder.pre_insert_table(
+                    table,
+                    table_index,
+                    tables.ordered_schemas().max_num_fields(),
+                    constants,
+                );
+            });
+        builder.cache_values = vec![B::get_false(); builder.cache_keys.len()];
+        builder
+    }
+
+    pub fn build(
+        tables: &OrderedTables,
+        ident_op_arithmetic: &IdentOpArithmetic<B>,
+        chunck_size: usize,
+    ) -> BoolMask<B> {
+        let mut builder = Self::pre_build(
+            tables,
+            ident_op_arithmetic.query_ref.arithmetic_constants(),
+        );
+        let flags = builder
+            .candidates
+            .iter()
+            .enumerate()
+            .map(|(table_index, candidates)| {
+                ident_op_arithmetic.candidate_flags(table_index, candidates)
+            })
+            .collect::<Vec<Vec<B>>>();
+        let eq_gt_cache = builder.eq_gt_cache(ident_op_arithmetic, chunck_size);
+        let value_cmp = builder.compute_value_cmp(ident_op_arithmetic, &eq_gt_cache);
+        builder.compute_values(&flags, &value_cmp);
+        builder.compute_select(tables)
+    }
+
+    /// Encrypted EQ, GT for every clear value
+    fn eq_gt_cache(
+        &self,
+        ident_op_arithmetic: &IdentOpArithmetic<B>,
+        chunck_size: usize,
+    ) -> U64EqGtTree<B> {
+        let right = &ident_op_arithmetic
+            .query_ref
+            .binary_op_at(ident_op_arithmetic.binary_op_index)
+            .right;
+        let mut eq_gt_cache = U64EqGtTree::<B>::new(right.bytes_256.word_0_eq_gt.clone());
+        let abs = self.values.iter().filter_map(|v| v.abs());
+        eq_gt_cache
+            .fill_with_iter(
+                abs.clone().flat_map(|v| (0..4).map(move |k| v.le_u16_block(k))),
+                chunck_size,
+            )
+            .fill16();
+        eq_gt_cache
+            .fill_with_iter(
+                abs.clone().flat_map(|v| (0..2).map(move |k| v.le_u32_block(k))),
+                chunck_size,
+            )
+            .fill32();
+        eq_gt_cache
+            .fill_with_iter(abs.map(|v| v.le_u64_block(0)), chunck_size)
+            .fill64();
+        eq_gt_cache
+    }
+
+    #[cfg(feature = "parallel")]
+    fn compute_value_cmp(
+        &self,
+        ident_op_arithmetic: &IdentOpArithmetic<B>,
+        eq_gt_cache: &U64EqGtTree<B>,
+    ) -> Vec<B> {
+        self.values
+            .par_iter()
+            .map(|v| ident_op_arithmetic.compare(v, eq_gt_cache))
+            .collect()
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    fn compute_value_cmp(
+        &self,
+        ident_op_arithmetic: &IdentOpArithmetic<B>,
+        eq_gt_cache: &U64EqGtTree<B>,
+    ) -> Vec<B> {
+        self.values
+            .iter()
+            .map(|v| ident_op_arithmetic.compare(v, eq_gt_cache))
+            .collect()
+    }
+
+    // OR { c; Candidate(t, c) AND Op(Value(c)) }
+    fn compute_value(key: &(usize, Vec<usize>), flags: &[Vec<B>], value_cmp: &[B]) -> B {
+        let (table_index, value_indices) = key;
+        let v = value_indices
+            .iter()
+            .zip(flags[*table_index].iter())
+            .map(|(value_index, flag)| flag.refref_bitand(&value_cmp[*value_index]))
+            .collect::<Vec<B>>();
+        par_bitor_vec(v).unwrap_or(B::get_false())
+    }
+
+    #[cfg(feature = "parallel")]
+    fn compute_values(&mut self, flags: &[Vec<B>], value_cmp: &[B]) {
+        self.cache_values
+            .par_iter_mut()
+            .zip(self.cache_keys.par_iter())
+            .for_each(|(dst, key)| {
+                *dst = Self::compute_value(key, flags, value_cmp);
+            });
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    fn compute_values(&mut self, flags: &[Vec<B>], value_cmp: &[B]) {
+        self.cache_values
+            .iter_mut()
+            .zip(self.cache_keys.iter())
+            .for_each(|(dst, key)| {
+                *dst = Self::compute_value(key, flags, value_cmp);
+            });
+    }
+
+    #[cfg(feature = "parallel")]
+    // Compute final select, iterate over rows
+    fn compute_select(&self, tables: &OrderedTables) -> BoolMask<B> {
+        let mut select_mask = BoolMask::<B>::all_false(tables.max_num_rows());
+        select_mask
+            .mask
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(row_index, dst)| *dst = self.compute_tables_row(row_index));
+        select_mask
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    // Compute final select, iterate over rows
+    fn compute_select(&self, tables: &OrderedTables) -> BoolMask<B> {
+        let mut select_mask = BoolMask::<B>::all_false(tables.max_num_rows());
+        select_mask
+            .mask
+            .iter_mut()
+            .enumerate()
+            .for_each(|(row_index, dst)| *dst = self.compute_tables_row(row_index));
+        select_mask
+    }
+
+    // OR { 0 <= t < num_tables; TableRow(t) }, the table mask is already applied
+    fn compute_tables_row(&self, row_index: usize) -> B {
+        let buffer = self
+            .table_row_index_to_cache_index
+            .iter()
+            .filter_map(|row_index_to_cache_index| {
+                row_index_to_cache_index
+                    .get(row_index)
+                    .map(|i| &self.cache_values[*i])
+            })
+            .collect::<Vec<&B>>();
+
+        par_bitor_vec_ref(buffer).unwrap_or(B::get_false())
+    }
+
+    // Serial
+    fn pre_insert_table(
+        &mut self,
+        table: &Table,
+        table_index: usize,
+        max_num_fields: usize,
+        constants: &[i64],
+    ) {
+        assert!(table_index == self.table_row_index_to_cache_index.len());
+        let candidates =
+            IdentOpArithmetic::<B>::candidates(table, max_num_fields, constants.len());
+        let operand = |index: usize, row_index: usize| {
+            if index < max_num_fields {
+                table.cell_as_i128(index, row_index)
+            } else {
+                constants[index - max_num_fields] as i128
+            }
+        };
+        let mut row_index_to_cache_index = Vec::with_capacity(table.num_rows());
+        for row_index in 0..table.num_rows() {
+            let value_indices = candidates
+                .iter()
+                .map(|(left, right, op)| {
+                    let value = ArithmeticValue::compute(
+                        operand(*left, row_index),
+                        operand(*right, row_index),
+                        *op,
+                    );
+                    let next_index = self.values.len();
+                    let index = *self.value_indices.entry(value).or_insert(next_index);
+                    if index == next_index {
+                        self.values.push(value);
+                    }
+                    index
+                })
+                .collect::<Vec<usize>>();
+
+            let key = (table_index, value_indices);
+            let next_index = self.cache_keys.len();
+            let index = *self.cache_indices.entry(key.clone()).or_insert(next_index);
+            if index == next_index {
+                self.cache_keys.push(key);
+            }
+            row_index_to_cache_index.push(index);
+        }
+        self.candidates.push(candidates);
+        self.table_row_index_to_cache_index.push(row_index_to_cache_index);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_arithmetic_value() {
+        let v = |abs, negative, is_integer| ArithmeticValue::Value {
+            abs,
+            negative,
+            is_integer,
+        };
+        assert_eq!(ArithmeticValue::compute(3, 4, ArithmeticOp::Plus), v(7, false, true));
+        assert_eq!(ArithmeticValue::compute(3, 4, ArithmeticOp::Minus), v(1, true, true));
+        assert_eq!(ArithmeticValue::compute(-3, 4, ArithmeticOp::Multiply), v(12, true, true));
+        assert_eq!(ArithmeticValue::compute(7, 2, ArithmeticOp::Divide), v(3, false, false));
+        assert_eq!(ArithmeticValue::compute(7, -2, ArithmeticOp::Divide), v(4, true, false));
+        assert_eq!(ArithmeticValue::compute(-8, -2, ArithmeticOp::Divide), v(4, false, true));
+        assert_eq!(ArithmeticValue::compute(1, 0, ArithmeticOp::Divide), ArithmeticValue::Null);
+        assert_eq!(
+            ArithmeticValue::compute(u64::MAX as i128, 2, ArithmeticOp::Multiply),
+            ArithmeticValue::Overflow { negative: false }
+        );
+        assert_eq!(ArithmeticValue::compute(-7, 3, ArithmeticOp::Modulo), v(1, true, true));
+        assert_eq!(ArithmeticValue::compute(7, 0, ArithmeticOp::Modulo), ArithmeticValue::Null);
+        assert_eq!(ArithmeticValue::compute(6, 4, ArithmeticOp::BitwiseAnd), v(4, false, true));
+        assert_eq!(ArithmeticValue::compute(6, 1, ArithmeticOp::BitwiseOr), v(7, false, true));
+        assert_eq!(ArithmeticValue::compute(6, -1, ArithmeticOp::BitwiseXor), v(7, true, true));
+    }
+}
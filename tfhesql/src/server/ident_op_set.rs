@@ -0,0 +1,449 @@
+use crate::bitops::*;
+use crate::default_into::DefaultInto;
+use crate::query::sql_query::SqlQueryRef;
+use crate::query::sql_query_set::SqlQuerySet;
+use crate::sql_ast::in_set::InSetValue;
+use crate::sql_ast::ComparatorMask;
+use crate::table::{OrderedTables, Table};
+use crate::types::*;
+use crate::uint::mask::BoolMask;
+#[cfg(feature = "parallel")]
+use rayon::iter::*;
+use std::collections::HashMap;
+
+/// 256 bits = 32 bytes
+const NUM_BYTES: usize = InSetValue::BITS / 8;
+
+/// Clear 256 bits value of a table cell
+fn cell_value(table: &Table, column_index: usize, row_index: usize) -> InSetValue {
+    if table.is_ascii_column(column_index) {
+        InSetValue::from_ascii(table.cell_as_ascii(column_index, row_index))
+    } else {
+        InSetValue::from_i128(table.cell_as_i128(column_index, row_index))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// InSetMemberships
+////////////////////////////////////////////////////////////////////////////////
+
+/// Encrypted membership of every table row to every IN set of the query.
+/// Computed once and shared by all the binary ops.
+pub(super) struct InSetMemberships<B> {
+    /// members[s][t][r] = row r of table t belongs to set s
+    members: Vec<Vec<Vec<B>>>,
+}
+
+impl<B> InSetMemberships<B> {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    #[inline]
+    fn get(&self, set_index: usize, table_index: usize, row_index: usize) -> &B {
+        &self.members[set_index][table_index][row_index]
+    }
+}
+
+impl<B> InSetMemberships<B>
+where
+    B: ThreadSafeBool + DefaultInto<B>,
+{
+    pub fn compute(tables: &OrderedTables, query_ref: &SqlQueryRef<B>) -> Self {
+        let sets = query_ref.sets();
+        if sets.is_empty() {
+            return InSetMemberships { members: vec![] };
+        }
+        InSetMemberships {
+            members: (0..sets.len())
+                .map(|s| IdentOpSetCacheBuilder::<B>::build(tables, sets.get(s)))
+                .collect(),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// IdentOpSet
+////////////////////////////////////////////////////////////////////////////////
+
+/// Computes `(Column, ...) [NOT] IN (...)`
+///
+/// The set memberships are computed once per query (see [`InSetMemberships`]),
+/// the encrypted set masks select the set of each binary op.
+/// The comparator is EQ for IN and NOTEQ for NOT IN.
+pub(super) struct IdentOpSet<B> {
+    binary_op_index: usize,
+    query_ref: SqlQueryRef<B>,
+    select_mask: BoolMask<B>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+impl<B> IdentOpSet<B> {
+    pub fn new_empty(binary_op_index: usize, query_ref: SqlQueryRef<B>) -> Self {
+        IdentOpSet {
+            binary_op_index,
+            query_ref,
+            select_mask: BoolMask::<B>::new_empty(),
+        }
+    }
+
+    pub fn table_mask(&self) -> &BoolMask<B> {
+        &self.query_ref.header().table_mask
+    }
+
+    /// Empty if the query does not contain any IN set
+    pub fn select_mask(&self) -> &BoolMask<B> {
+        &self.select_mask
+    }
+
+    pub fn comparator_mask(&self) -> &ComparatorMask<B> {
+        &self
+            .query_ref
+            .binary_op_at(self.binary_op_index)
+            .comparator_mask
+    }
+
+    pub fn set_mask(&self) -> &BoolMask<B> {
+        self.query_ref.sets().mask(self.binary_op_index)
+    }
+}
+
+impl<B> IdentOpSet<B>
+where
+    B: ThreadSafeBool + DefaultInto<B>,
+{
+    #[cfg(feature = "parallel")]
+    pub fn compute(&mut self, tables: &OrderedTables, memberships: &InSetMemberships<B>) {
+        if memberships.is_empty() {
+            return;
+        }
+        let is_set_op = par_bitor_vec_ref(self.set_mask().mask.iter().collect()).unwrap();
+        let mut select_mask = BoolMask::<B>::all_false(tables.max_num_rows());
+        select_mask
+            .mask
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(row_index, dst)| {
+                *dst = self
+                    .compute_tables_row(tables, memberships, row_index)
+                    .refref_bitand(&is_set_op)
+            });
+        self.select_mask = select_mask;
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    pub fn compute(&mut self, tables: &OrderedTables, memberships: &InSetMemberships<B>) {
+        if memberships.is_empty() {
+            return;
+        }
+        let is_set_op = par_bitor_vec_ref(self.set_mask().mask.iter().collect()).unwrap();
+        let mut select_mask = BoolMask::<B>::all_false(tables.max_num_rows());
+        select_mask
+            .mask
+            .iter_mut()
+            .enumerate()
+            .for_each(|(row_index, dst)| {
+                *dst = self
+                    .compute_tables_row(tables, memberships, row_index)
+                    .refref_bitand(&is_set_op)
+            });
+        self.select_mask = select_mask;
+    }
+
+    /// OR { t; Table(t) AND Op(OR { s; SetMask(s) AND Member(s, t, row) }) }
+    fn compute_tables_row(
+        &self,
+        tables: &OrderedTables,
+        memberships: &InSetMemberships<B>,
+        row_index: usize,
+    ) -> B {
+        let set_mask = self.set_mask();
+        let table_mask = self.table_mask();
+        let comparator_mask = self.comparator_mask();
+        let buffer = tables
+            .tables()
+            .iter()
+            .enumerate()
+            .filter(|(_, table)| row_index < table.num_rows())
+            .map(|(table_index, _)| {
+                let v = (0..set_mask.len())
+                    .map(|s| {
+                        set_mask
+                            .get(s)
+                            .refref_bitand(memberships.get(s, table_index, row_index))
+                    })
+                    .collect::<Vec<B>>();
+                let member = par_bitor_vec(v).unwrap();
+                comparator_mask
+                    .or_and_eq_ne(&member, &member.ref_not())
+                    .refref_bitand(table_mask.get(table_index))
+            })
+            .collect::<Vec<B>>();
+        par_bitor_vec(buffer).unwrap_or(B::get_false())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// IdentOpSetCacheBuilder
+////////////////////////////////////////////////////////////////////////////////
+
+/// Computes the membership of every row of every table to a single set.
+///
+/// Equalities are cached at 3 levels:
+/// - (element, byte position, byte value)
+/// - (element, distinct cell value)
+/// - distinct row (table, [cell value; column])
+struct IdentOpSetCacheBuilder<'a, B> {
+    set: &'a SqlQuerySet<B>,
+    value_indices: HashMap<InSetValue, usize>,
+    values: Vec<InSetValue>,
+    byte_indices: HashMap<(usize, u8), usize>,
+    bytes: Vec<(usize, u8)>,
+    /// For each cached row: (table, [value index; column])
+    cache_indices: HashMap<(usize, Vec<usize>), usize>,
+    cache_keys: Vec<(usize, Vec<usize>)>,
+    table_row_index_to_cache_index: Vec<Vec<usize>>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+impl<'a, B> IdentOpSetCacheBuilder<'a, B>
+where
+    B: ThreadSafeBool + DefaultInto<B>,
+{
+    // Serial: we are manipulating clear data
+    fn pre_build(tables: &OrderedTables, set: &'a SqlQuerySet<B>) -> Self {
+        let mut builder = IdentOpSetCacheBuilder::<B> {
+            set,
+            value_indices: HashMap::new(),
+            values: vec![],
+            byte_indices: HashMap::new(),
+            bytes: vec![],
+            cache_indices: HashMap::new(),
+            cache_keys: vec![],
+            table_row_index_to_cache_index: vec![],
+        };
+        tables
+            .tables()
+            .iter()
+            .enumerate()
+            .for_each(|(table_index, table)| builder.pre_insert_table(table, table_index));
+        builder
+    }
+
+    /// Returns members[t][r]
+    fn build(tables: &OrderedTables, set: &'a SqlQuerySet<B>) -> Vec<Vec<B>> {
+        let builder = Self::pre_build(tables, set);
+        let byte_eq = builder.compute_byte_eq();
+        let value_eq = builder.compute_value_eq(&byte_eq);
+        let cache_values = builder.compute_rows(&value_eq);
+        builder
+            .table_row_index_to_cache_index
+            .iter()
+            .map(|row_index_to_cache_index| {
+                row_index_to_cache_index
+                    .iter()
+                    .map(|i| cache_values[*i].clone())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[inline]
+    fn num_positions(&self) -> usize {
+        self.set.ident_masks.len()
+    }
+
+    /// Components in (element, position) order
+    fn components(&self) -> Vec<(usize, usize)> {
+        let k = self.num_positions();
+        (0..self.set.elements.len())
+            .flat_map(|e| (0..k).map(move |p| (e, p)))
+            .collect()
+    }
+
+    /// ByteEq[(e, p)][b] = AND { 8 bit literals of Element(e, p) at byte position b }
+    fn byte_eq(&self, element: usize, position: usize) -> Vec<B> {
+        let component = &self.set.elements[element][position];
+        let not_bits = component.le_bits.mask.ref_not();
+        self.bytes
+            .iter()
+            .map(|(byte_pos, byte)| {
+                let v = (0..8)
+                    .map(|i| {
+                        let bit_index = 8 * byte_pos + i;
+                        if (byte >> i) & 1 == 1 {
+                            component.le_bits.get(bit_index)
+                        } else {
+                            &not_bits[bit_index]
+                        }
+                    })
+                    .collect::<Vec<&B>>();
+                par_bitand_vec_ref(v).unwrap()
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "parallel")]
+    fn compute_byte_eq(&self) -> Vec<Vec<B>> {
+        self.components()
+            .par_iter()
+            .map(|(e, p)| self.byte_eq(*e, *p))
+            .collect()
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    fn compute_byte_eq(&self) -> Vec<Vec<B>> {
+        self.components()
+            .iter()
+            .map(|(e, p)| self.byte_eq(*e, *p))
+            .collect()
+    }
+
+    /// ValueEq[(e, p)][v] = Sign(e, p) AND { b; ByteEq[(e, p)][b, Value(v, b)] }
+    fn value_eq(&self, element: usize, position: usize, byte_eq: &[B]) -> Vec<B> {
+        let is_strictly_negative = &self.set.elements[element][position].is_strictly_negative;
+        let is_positive = is_strictly_negative.ref_not();
+        self.values
+            .iter()
+            .map(|value| {
+                let mut v = (0..NUM_BYTES)
+                    .map(|byte_pos| &byte_eq[self.byte_indices[&(byte_pos, value.byte(byte_pos))]])
+                    .collect::<Vec<&B>>();
+                v.push(if value.negative {
+                    is_strictly_negative
+                } else {
+                    &is_positive
+                });
+                par_bitand_vec_ref(v).unwrap()
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "parallel")]
+    fn compute_value_eq(&self, byte_eq: &[Vec<B>]) -> Vec<Vec<B>> {
+        self.components()
+            .par_iter()
+            .zip(byte_eq.par_iter())
+            .map(|((e, p), b)| self.value_eq(*e, *p, b))
+            .collect()
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    fn compute_value_eq(&self, byte_eq: &[Vec<B>]) -> Vec<Vec<B>> {
+        self.components()
+            .iter()
+            .zip(byte_eq.iter())
+            .map(|((e, p), b)| self.value_eq(*e, *p, b))
+            .collect()
+    }
+
+    /// Single column: OR { c; Mask(c) AND Member(Value(c)) }
+    /// with Member(v) = OR { e; ValueEq[e][v] } computed once per distinct value
+    ///
+    /// Tuple: OR { e; AND { p; OR { c; Mask(p, c) AND ValueEq[(e, p)][Value(c)] } } }
+    fn compute_row(
+        &self,
+        key: &(usize, Vec<usize>),
+        value_eq: &[Vec<B>],
+        members: &[B],
+    ) -> B {
+        let (_, value_indices) = key;
+        let k = self.num_positions();
+        if k == 1 {
+            let mask = &self.set.ident_masks[0];
+            let v = value_indices
+                .iter()
+                .enumerate()
+                .map(|(c, value_index)| mask.get(c).refref_bitand(&members[*value_index]))
+                .collect::<Vec<B>>();
+            return par_bitor_vec(v).unwrap_or(B::get_false());
+        }
+        let v = (0..self.set.elements.len())
+            .map(|e| {
+                let and = (0..k)
+                    .map(|p| {
+                        let mask = &self.set.ident_masks[p];
+                        let eq = &value_eq[e * k + p];
+                        let or = value_indices
+                            .iter()
+                            .enumerate()
+                            .map(|(c, value_index)| mask.get(c).refref_bitand(&eq[*value_index]))
+                            .collect::<Vec<B>>();
+                        par_bitor_vec(or).unwrap_or(B::get_false())
+                    })
+                    .collect::<Vec<B>>();
+                par_bitand_vec(and).unwrap()
+            })
+            .collect::<Vec<B>>();
+        par_bitor_vec(v).unwrap_or(B::get_false())
+    }
+
+    /// Member(v) = OR { e; ValueEq[e][v] }, single column only
+    fn compute_members(&self, value_eq: &[Vec<B>]) -> Vec<B> {
+        if self.num_positions() != 1 {
+            return vec![];
+        }
+        (0..self.values.len())
+            .map(|v| par_bitor_vec_ref(value_eq.iter().map(|eq| &eq[v]).collect()).unwrap())
+            .collect()
+    }
+
+    #[cfg(feature = "parallel")]
+    fn compute_rows(&self, value_eq: &[Vec<B>]) -> Vec<B> {
+        let members = self.compute_members(value_eq);
+        self.cache_keys
+            .par_iter()
+            .map(|key| self.compute_row(key, value_eq, &members))
+            .collect()
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    fn compute_rows(&self, value_eq: &[Vec<B>]) -> Vec<B> {
+        let members = self.compute_members(value_eq);
+        self.cache_keys
+            .iter()
+            .map(|key| self.compute_row(key, value_eq, &members))
+            .collect()
+    }
+
+    // Serial
+    fn pre_insert_table(&mut self, table: &Table, table_index: usize) {
+        assert!(table_index == self.table_row_index_to_cache_index.len());
+        let mut row_index_to_cache_index = Vec::with_capacity(table.num_rows());
+        for row_index in 0..table.num_rows() {
+            let value_indices = (0..table.num_columns())
+                .map(|column_index| {
+                    let value = cell_value(table, column_index, row_index);
+                    let next_index = self.values.len();
+                    let index = *self.value_indices.entry(value).or_insert(next_index);
+                    if index == next_index {
+                        self.values.push(value);
+                        for byte_pos in 0..NUM_BYTES {
+                            let byte = (byte_pos, value.byte(byte_pos));
+                            let next_byte_index = self.bytes.len();
+                            if *self.byte_indices.entry(byte).or_insert(next_byte_index)
+                                == next_byte_index
+                            {
+                                self.bytes.push(byte);
+                            }
+                        }
+                    }
+                    index
+                })
+                .collect::<Vec<usize>>();
+
+            let key = (table_index, value_indices);
+            let next_index = self.cache_keys.len();
+            let index = *self.cache_indices.entry(key.clone()).or_insert(next_index);
+            if index == next_index {
+                self.cache_keys.push(key);
+            }
+            row_index_to_cache_index.push(index);
+        }
+        self.table_row_index_to_cache_index
+            .push(row_index_to_cache_index);
+    }
+}
@@ -1,14 +1,40 @@
+mod checkpoint;
+mod compact;
 mod distinct;
+mod distinct_sort;
+mod enc_sql_server;
 mod ident_compare_with;
+mod ident_op_aggregate;
+mod ident_op_arithmetic;
 mod ident_op_ident;
+mod ident_op_set;
 mod ident_op_value;
+mod server_key_pool;
+mod run_control;
 mod sql_server;
+mod sql_server_options;
+mod sql_service;
+mod window;
 
 mod ident_op_value_builder;
 use ident_op_value_builder::IdentOpValueCacheBuilder;
 
+pub use checkpoint::SqlCheckpoint;
+pub use enc_sql_server::FheRunSqlQueryOnEncryptedTables;
+pub use run_control::RunControl;
+pub use run_control::RunProgress;
+pub use run_control::RunStage;
+pub use server_key_pool::ServerKeyPool;
+pub(crate) use sql_server::clear_where_mask;
 pub use sql_server::FheSqlServer;
 pub use sql_server::FheRunSqlQuery;
+pub use sql_server::SqlResultStreamIter;
+pub use sql_server_options::DistinctStrategy;
+pub use sql_server_options::SqlServerOptions;
+pub use sql_service::FheSqlService;
+pub use sql_service::SqlServiceOptions;
+pub use sql_service::SqlServiceQuery;
+pub use sql_service::SqlServiceResult;
 
 #[cfg(feature = "stats")]
 mod sql_stats;
@@ -0,0 +1,145 @@
+use crate::FheSqlError;
+use crate::SqlCheckpoint;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+////////////////////////////////////////////////////////////////////////////////
+// RunStage
+////////////////////////////////////////////////////////////////////////////////
+
+/// The successive stages of a query execution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunStage {
+    /// WHERE clause evaluation
+    ComputeSelect,
+    /// SELECT DISTINCT pass
+    SelectDistinct,
+    /// Masking of the table bytes with the select mask
+    ComputeResult,
+}
+
+impl RunStage {
+    /// The stage name, as displayed by the `stats` feature
+    pub fn name(&self) -> &'static str {
+        match self {
+            RunStage::ComputeSelect => "Compute Select",
+            RunStage::SelectDistinct => "Select Distinct",
+            RunStage::ComputeResult => "Compute Result",
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// RunProgress
+////////////////////////////////////////////////////////////////////////////////
+
+/// `done` steps out of `total` have been completed in `stage`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunProgress {
+    stage: RunStage,
+    done: usize,
+    total: usize,
+}
+
+impl RunProgress {
+    pub fn stage(&self) -> RunStage {
+        self.stage
+    }
+
+    pub fn done(&self) -> usize {
+        self.done
+    }
+
+    pub fn total(&self) -> usize {
+        self.total
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// RunControl
+////////////////////////////////////////////////////////////////////////////////
+
+type ProgressCallback = Arc<dyn Fn(&RunProgress) + Send + Sync>;
+
+/// A handle passed to the server to follow the progress of a query and
+/// to cancel it. Cloned handles share the same cancellation flag, the
+/// server stops at the next step boundary and returns [FheSqlError::Cancelled].
+/// With a checkpoint, a cancelled or crashed query resumes from its last saved step.
+#[derive(Clone, Default)]
+pub struct RunControl {
+    cancelled: Arc<AtomicBool>,
+    on_progress: Option<ProgressCallback>,
+    checkpoint: Option<Arc<SqlCheckpoint>>,
+}
+
+impl Debug for RunControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RunControl")
+            .field("cancelled", &self.is_cancelled())
+            .field("on_progress", &self.on_progress.is_some())
+            .field("checkpoint", &self.checkpoint)
+            .finish()
+    }
+}
+
+impl RunControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `on_progress` is called by the server threads at the beginning and at
+    /// the end of each stage, and after each step of the long stages.
+    pub fn with_progress<F>(mut self, on_progress: F) -> Self
+    where
+        F: Fn(&RunProgress) + Send + Sync + 'static,
+    {
+        self.on_progress = Some(Arc::new(on_progress));
+        self
+    }
+
+    /// The intermediate state of the query is saved to `checkpoint` and
+    /// reloaded by the next run of the same query. Streamed and chunked
+    /// runs do not use the checkpoint.
+    pub fn with_checkpoint(mut self, checkpoint: SqlCheckpoint) -> Self {
+        self.checkpoint = Some(Arc::new(checkpoint));
+        self
+    }
+
+    pub fn checkpoint(&self) -> Option<&SqlCheckpoint> {
+        self.checkpoint.as_deref()
+    }
+
+    /// Requests the cancellation of the running query
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn progress(&self, stage: RunStage, done: usize, total: usize) {
+        if let Some(on_progress) = &self.on_progress {
+            on_progress(&RunProgress { stage, done, total });
+        }
+    }
+
+    /// Reports the progress then returns an error if the query has been cancelled
+    pub(crate) fn step(
+        &self,
+        stage: RunStage,
+        done: usize,
+        total: usize,
+    ) -> Result<(), FheSqlError> {
+        self.progress(stage, done, total);
+        self.check_cancelled()
+    }
+
+    pub(crate) fn check_cancelled(&self) -> Result<(), FheSqlError> {
+        if self.is_cancelled() {
+            return Err(FheSqlError::Cancelled);
+        }
+        Ok(())
+    }
+}
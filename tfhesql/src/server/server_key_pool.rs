@@ -0,0 +1,41 @@
+use crate::FheSqlError;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use tfhe::ServerKey;
+
+////////////////////////////////////////////////////////////////////////////////
+// ServerKeyPool
+////////////////////////////////////////////////////////////////////////////////
+
+/// A dedicated rayon thread pool where every thread has the same server key
+/// installed. The global rayon pool and the calling thread are left untouched,
+/// several pools with different keys can run at the same time.
+///
+/// Building a pool spawns its threads and clones the server key on each of them,
+/// a server that runs several queries with the same key should build the pool
+/// once and reuse it (see [FheRunSqlQuery::run_with_pool](crate::FheRunSqlQuery::run_with_pool)).
+pub struct ServerKeyPool {
+    pool: ThreadPool,
+}
+
+impl ServerKeyPool {
+    /// `num_threads` = 0 means the rayon default (number of logical cpus)
+    pub fn new(server_key: &ServerKey, num_threads: usize) -> Result<Self, FheSqlError> {
+        let pool_sk = server_key.clone();
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .start_handler(move |_| tfhe::set_server_key(pool_sk.clone()))
+            .build()
+            .map_err(|err| FheSqlError::InternalError(err.to_string()))?;
+        Ok(ServerKeyPool { pool })
+    }
+
+    /// Runs `op` on one of the pool threads, every rayon call made by `op`
+    /// is executed by the pool.
+    pub fn install<OP, R>(&self, op: OP) -> R
+    where
+        OP: FnOnce() -> R + Send,
+        R: Send,
+    {
+        self.pool.install(op)
+    }
+}
@@ -1,20 +1,39 @@
 use crate::default_into::{DefaultInto, ValueFrom};
-use crate::query::sql_query::SqlQueryRef;
+use crate::query::sql_query::{SqlQuery, SqlQueryRef};
 use crate::query::sql_result::SqlResult;
+use crate::query::sql_result_chunk::SqlResultHeader;
 use crate::server::ident_compare_with::IdentCompareWithArray;
 use crate::types::*;
+use crate::utils::{fnv1a_hash, FNV1A_OFFSET_BASIS};
 use crate::uint::mask::{BoolMask, ByteMaskMatrix, Mask};
+use crate::uint::ByteArrayList;
+use crate::table::byte_rows::ByteRowsList;
 use crate::ClearSqlQuery;
 use crate::ClearSqlResult;
+use crate::ClearSqlResultChunk;
+use crate::ClearSqlResultHeader;
 use crate::FheSqlError;
 use crate::FheSqlQuery;
 use crate::FheSqlResult;
+use crate::FheSqlResultChunk;
+use crate::FheSqlResultHeader;
+use crate::SqlCheckpoint;
+use crate::SqlGateEstimate;
+use crate::SqlQueryExplain;
 use crate::OrderedTables;
 use crate::SqlResultFormat;
 use std::{fmt::Debug, marker::PhantomData, ops::BitOrAssign, sync::Arc};
-use tfhe::{FheBool, FheUint8};
-
-use super::distinct::compute_select_distinct;
+use tfhe::{FheBool, FheUint8, ServerKey};
+
+use super::compact::Compaction;
+use super::distinct::{
+    compute_select_distinct, compute_select_distinct_rows, par_unselect_out_of_bounds,
+};
+use super::distinct_sort::compute_select_distinct_sort;
+use super::run_control::{RunControl, RunStage};
+use super::server_key_pool::ServerKeyPool;
+use super::sql_server_options::{DistinctStrategy, SqlServerOptions};
+use super::window::compute_window_bytes;
 
 #[cfg(feature = "stats")]
 use crate::server::SqlStats;
@@ -25,31 +44,288 @@ use crate::server::SqlStats;
 
 pub struct FheSqlServer {}
 
+/// Iterator over the partial results of
+/// [FheRunSqlQuery::run_streaming](crate::FheRunSqlQuery::run_streaming) or
+/// [FheRunSqlQuery::run_chunked](crate::FheRunSqlQuery::run_chunked)
+pub type SqlResultStreamIter<'a, R> = Box<dyn Iterator<Item = Result<R, FheSqlError>> + 'a>;
+
 pub trait FheRunSqlQuery<Q> {
     type Result;
-    fn run(query: &Q, tables: &OrderedTables) -> Result<Self::Result, FheSqlError>;
+    type ResultHeader;
+    type ResultChunk;
+    fn run(query: &Q, tables: &OrderedTables) -> Result<Self::Result, FheSqlError> {
+        Self::run_with_options(query, tables, &SqlServerOptions::default())
+    }
+    fn run_with_options(
+        query: &Q,
+        tables: &OrderedTables,
+        options: &SqlServerOptions,
+    ) -> Result<Self::Result, FheSqlError> {
+        Self::run_with_control(query, tables, options, &RunControl::default())
+    }
+    /// Worst case estimate of the number of operations needed to run the query on
+    /// `tables` with `options`. Only the clear shape of the query is used: the estimate
+    /// is the one used by the admission control (see [SqlServerOptions::with_max_gates]).
+    fn estimate_gates(
+        query: &Q,
+        tables: &OrderedTables,
+        options: &SqlServerOptions,
+    ) -> Result<SqlGateEstimate, FheSqlError>;
+    /// Same as [run_with_options](FheRunSqlQuery::run_with_options), the progress of
+    /// each stage is reported to `control`. If `control` is cancelled, the query stops
+    /// at the next step and returns [FheSqlError::Cancelled].
+    fn run_with_control(
+        query: &Q,
+        tables: &OrderedTables,
+        options: &SqlServerOptions,
+        control: &RunControl,
+    ) -> Result<Self::Result, FheSqlError>;
+    /// Runs the query on a dedicated thread pool with `server_key` installed on
+    /// every thread, instead of the key set by `tfhe::set_server_key`. Queries
+    /// encrypted with different client keys can run concurrently in the same process.
+    ///
+    /// The pool is built on every call: its threads are spawned and `server_key` is
+    /// cloned on each of them before the query starts. To run several queries with
+    /// the same key, or with other options, build a [ServerKeyPool] once and use
+    /// [run_with_pool_and_control](FheRunSqlQuery::run_with_pool_and_control).
+    fn run_with_key(
+        query: &Q,
+        tables: &OrderedTables,
+        server_key: &ServerKey,
+    ) -> Result<Self::Result, FheSqlError>
+    where
+        Q: Sync,
+        Self::Result: Send,
+    {
+        let pool = ServerKeyPool::new(server_key, 0)?;
+        Self::run_with_pool(query, tables, &pool)
+    }
+    /// Same as [run_with_key](FheRunSqlQuery::run_with_key), on an existing `pool`
+    /// and with the key installed on its threads.
+    fn run_with_pool(
+        query: &Q,
+        tables: &OrderedTables,
+        pool: &ServerKeyPool,
+    ) -> Result<Self::Result, FheSqlError>
+    where
+        Q: Sync,
+        Self::Result: Send,
+    {
+        Self::run_with_pool_and_control(
+            query,
+            tables,
+            pool,
+            &SqlServerOptions::default(),
+            &RunControl::default(),
+        )
+    }
+    /// Same as [run_with_pool](FheRunSqlQuery::run_with_pool), with the `options`
+    /// and the `control` of [run_with_control](FheRunSqlQuery::run_with_control).
+    fn run_with_pool_and_control(
+        query: &Q,
+        tables: &OrderedTables,
+        pool: &ServerKeyPool,
+        options: &SqlServerOptions,
+        control: &RunControl,
+    ) -> Result<Self::Result, FheSqlError>
+    where
+        Q: Sync,
+        Self::Result: Send,
+    {
+        pool.install(|| Self::run_with_control(query, tables, options, control))
+    }
+    /// Runs the query on consecutive ranges of `chunk_num_rows` rows and returns
+    /// an iterator over the partial results, one for each range, in row order.
+    /// Each partial result can be decrypted on its own. Scalar subqueries, window
+    /// functions and compacted results are not supported.
+    ///
+    /// SELECT DISTINCT compares each row with every row already streamed: the cost
+    /// of a chunk and the select history kept by the iterator grow with the number
+    /// of rows streamed before it. Only [DistinctStrategy::Pairwise] is supported.
+    fn run_streaming<'a>(
+        query: &Q,
+        tables: &'a OrderedTables,
+        chunk_num_rows: usize,
+        options: &SqlServerOptions,
+    ) -> Result<SqlResultStreamIter<'a, Self::Result>, FheSqlError> {
+        Self::run_streaming_with_control(
+            query,
+            tables,
+            chunk_num_rows,
+            options,
+            &RunControl::default(),
+        )
+    }
+    /// Same as [run_streaming](FheRunSqlQuery::run_streaming), the progress of each
+    /// chunk is reported to `control`. If `control` is cancelled, the current chunk
+    /// stops at the next step and the iterator returns [FheSqlError::Cancelled].
+    fn run_streaming_with_control<'a>(
+        query: &Q,
+        tables: &'a OrderedTables,
+        chunk_num_rows: usize,
+        options: &SqlServerOptions,
+        control: &RunControl,
+    ) -> Result<SqlResultStreamIter<'a, Self::Result>, FheSqlError>;
+    /// Same as [run_streaming](FheRunSqlQuery::run_streaming) but the masks and
+    /// options shared by all the partial results are only sent once, in a header
+    /// returned before any computation. Each chunk holds the result bytes of a
+    /// range of rows and is serialized and decrypted on its own, using the header.
+    fn run_chunked<'a>(
+        query: &Q,
+        tables: &'a OrderedTables,
+        chunk_num_rows: usize,
+        options: &SqlServerOptions,
+    ) -> Result<(Self::ResultHeader, SqlResultStreamIter<'a, Self::ResultChunk>), FheSqlError>
+    {
+        Self::run_chunked_with_control(
+            query,
+            tables,
+            chunk_num_rows,
+            options,
+            &RunControl::default(),
+        )
+    }
+    /// Same as [run_chunked](FheRunSqlQuery::run_chunked), with the `control` of
+    /// [run_streaming_with_control](FheRunSqlQuery::run_streaming_with_control).
+    fn run_chunked_with_control<'a>(
+        query: &Q,
+        tables: &'a OrderedTables,
+        chunk_num_rows: usize,
+        options: &SqlServerOptions,
+        control: &RunControl,
+    ) -> Result<(Self::ResultHeader, SqlResultStreamIter<'a, Self::ResultChunk>), FheSqlError>;
 }
 
 impl FheRunSqlQuery<ClearSqlQuery> for FheSqlServer {
     type Result = ClearSqlResult;
+    type ResultHeader = ClearSqlResultHeader;
+    type ResultChunk = ClearSqlResultChunk;
 
-    fn run(query: &ClearSqlQuery, tables: &OrderedTables) -> Result<Self::Result, FheSqlError> {
+    fn estimate_gates(
+        query: &ClearSqlQuery,
+        tables: &OrderedTables,
+        options: &SqlServerOptions,
+    ) -> Result<SqlGateEstimate, FheSqlError> {
+        estimate_query_gates(query, tables, options)
+    }
+
+    fn run_with_control(
+        query: &ClearSqlQuery,
+        tables: &OrderedTables,
+        options: &SqlServerOptions,
+        control: &RunControl,
+    ) -> Result<Self::Result, FheSqlError> {
         Ok(ClearSqlResult(SqlServer::<u8, bool>::run(
             Arc::new(query.clone()),
             tables,
+            options,
+            control,
         )?))
     }
+
+    fn run_streaming_with_control<'a>(
+        query: &ClearSqlQuery,
+        tables: &'a OrderedTables,
+        chunk_num_rows: usize,
+        options: &SqlServerOptions,
+        control: &RunControl,
+    ) -> Result<SqlResultStreamIter<'a, Self::Result>, FheSqlError> {
+        let stream = SqlResultStream::<u8, bool>::new(
+            Arc::new(query.clone()),
+            tables,
+            chunk_num_rows,
+            options,
+            control,
+        )?;
+        Ok(Box::new(stream.map(|r| r.map(ClearSqlResult))))
+    }
+
+    fn run_chunked_with_control<'a>(
+        query: &ClearSqlQuery,
+        tables: &'a OrderedTables,
+        chunk_num_rows: usize,
+        options: &SqlServerOptions,
+        control: &RunControl,
+    ) -> Result<(Self::ResultHeader, SqlResultStreamIter<'a, Self::ResultChunk>), FheSqlError> {
+        let stream = SqlResultStream::<u8, bool>::new(
+            Arc::new(query.clone()),
+            tables,
+            chunk_num_rows,
+            options,
+            control,
+        )?;
+        let header = ClearSqlResultHeader(SqlResultHeader::<bool>::from_query_ref(&stream.query_ref));
+        Ok((
+            header,
+            Box::new(stream.map(|r| r.map(|r| ClearSqlResultChunk(r.into_chunk())))),
+        ))
+    }
 }
 
 impl FheRunSqlQuery<FheSqlQuery> for FheSqlServer {
     type Result = FheSqlResult;
+    type ResultHeader = FheSqlResultHeader;
+    type ResultChunk = FheSqlResultChunk;
+
+    fn estimate_gates(
+        query: &FheSqlQuery,
+        tables: &OrderedTables,
+        options: &SqlServerOptions,
+    ) -> Result<SqlGateEstimate, FheSqlError> {
+        estimate_query_gates(query, tables, options)
+    }
 
-    fn run(query: &FheSqlQuery, tables: &OrderedTables) -> Result<Self::Result, FheSqlError> {
+    fn run_with_control(
+        query: &FheSqlQuery,
+        tables: &OrderedTables,
+        options: &SqlServerOptions,
+        control: &RunControl,
+    ) -> Result<Self::Result, FheSqlError> {
         Ok(FheSqlResult(SqlServer::<FheUint8, FheBool>::run(
             Arc::new(query.clone()),
             tables,
+            options,
+            control,
         )?))
     }
+
+    fn run_streaming_with_control<'a>(
+        query: &FheSqlQuery,
+        tables: &'a OrderedTables,
+        chunk_num_rows: usize,
+        options: &SqlServerOptions,
+        control: &RunControl,
+    ) -> Result<SqlResultStreamIter<'a, Self::Result>, FheSqlError> {
+        let stream = SqlResultStream::<FheUint8, FheBool>::new(
+            Arc::new(query.clone()),
+            tables,
+            chunk_num_rows,
+            options,
+            control,
+        )?;
+        Ok(Box::new(stream.map(|r| r.map(FheSqlResult))))
+    }
+
+    fn run_chunked_with_control<'a>(
+        query: &FheSqlQuery,
+        tables: &'a OrderedTables,
+        chunk_num_rows: usize,
+        options: &SqlServerOptions,
+        control: &RunControl,
+    ) -> Result<(Self::ResultHeader, SqlResultStreamIter<'a, Self::ResultChunk>), FheSqlError> {
+        let stream = SqlResultStream::<FheUint8, FheBool>::new(
+            Arc::new(query.clone()),
+            tables,
+            chunk_num_rows,
+            options,
+            control,
+        )?;
+        let header = FheSqlResultHeader(SqlResultHeader::<FheBool>::from_query_ref(&stream.query_ref));
+        Ok((
+            header,
+            Box::new(stream.map(|r| r.map(|r| FheSqlResultChunk(r.into_chunk())))),
+        ))
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -60,6 +336,8 @@ impl FheRunSqlQuery<FheSqlQuery> for FheSqlServer {
 struct SqlServer<U8, B> {
     phantom_t: PhantomData<U8>,
     phantom_b: PhantomData<B>,
+    options: SqlServerOptions,
+    control: RunControl,
 
     #[cfg(feature = "stats")]
     stats: SqlStats,
@@ -69,10 +347,12 @@ struct SqlServer<U8, B> {
 
 impl<U8, B> SqlServer<U8, B> {
     #[inline]
-    fn new() -> Self {
+    fn new(options: &SqlServerOptions, control: &RunControl) -> Self {
         SqlServer {
             phantom_t: Default::default(),
             phantom_b: Default::default(),
+            options: *options,
+            control: control.clone(),
             #[cfg(feature = "stats")]
             stats: SqlStats::new_empty(),
         }
@@ -81,7 +361,12 @@ impl<U8, B> SqlServer<U8, B> {
 
 impl<U8, B> SqlServer<U8, B>
 where
-    B: ThreadSafeUInt + ThreadSafeBool + DefaultInto<B> + ValueFrom<B>,
+    B: ThreadSafeUInt
+        + ThreadSafeBool
+        + DefaultInto<B>
+        + ValueFrom<B>
+        + serde::Serialize
+        + serde::de::DeserializeOwned,
     for<'a> U8: ThreadSafeUInt
         + ValueFrom<&'a B>
         + ValueFrom<u8>
@@ -92,14 +377,20 @@ where
     fn run(
         query_ref: SqlQueryRef<B>,
         tables: &OrderedTables,
+        options: &SqlServerOptions,
+        control: &RunControl,
     ) -> Result<SqlResult<U8, B>, FheSqlError> {
-        if tables.ordered_schemas() != query_ref.ordered_schemas() {
-            return Err(FheSqlError::InvalidQueryError(
-                "The requested query schemas and tables schemas are incompatible".to_string(),
-            ));
+        // The options are set by the client
+        query_ref.options().validate()?;
+        // An older client only sees the tables and columns of its own schemas
+        let restricted = tables.restrict_to(query_ref.ordered_schemas())?;
+        let tables = restricted.as_deref().unwrap_or(tables);
+        admit_query(&query_ref, tables, options)?;
+        if let Some(checkpoint) = control.checkpoint() {
+            checkpoint.open(checkpoint_fingerprint(&query_ref, tables, options)?)?;
         }
 
-        let mut srv = SqlServer::<U8, B>::new();
+        let mut srv = SqlServer::<U8, B>::new(options, control);
 
         #[cfg(feature = "stats")]
         {
@@ -121,7 +412,12 @@ where
             return Ok(SqlResult::<U8, B>::new_empty());
         }
 
-        let select_mask = self.compute_select_mask(query_ref.clone(), tables)?;
+        // Every table is empty (e.g. after a DELETE), there is no row to select
+        let select_mask = if tables.max_num_rows() == 0 {
+            BoolMask::<B>::new_empty()
+        } else {
+            self.compute_select_mask(query_ref.clone(), tables)?
+        };
 
         self.compute_result(query_ref, tables, select_mask)
     }
@@ -141,6 +437,8 @@ where
 
         assert_eq!(select_mask.len(), tables.max_num_rows());
 
+        self.control.step(RunStage::ComputeResult, 0, 1)?;
+
         let (byte_table_mask, byte_select_mask) = rayon::join(
             || Mask::<U8>::value_from(&query_ref.header().table_mask),
             || Mask::<U8>::value_from(&select_mask),
@@ -156,8 +454,10 @@ where
 
                 // Apply Table(t) AND Select(r) to each row of each previously converted table.
                 assert!(clear_byte_rows_list.len() == select_by_table_byte_matrix.num_columns());
-                let enc_masked_byte_rows_list =
-                    clear_byte_rows_list.par_bitand(&select_by_table_byte_matrix);
+                let enc_masked_byte_rows_list = ByteRowsList::<U8>::par_bitand_clear(
+                    &clear_byte_rows_list,
+                    &select_by_table_byte_matrix,
+                );
 
                 // Flatten the list of tables (as list of byte rows) into a single list of byte rows
                 let enc_byte_rows = enc_masked_byte_rows_list.par_flatten_row_by_row(padding);
@@ -173,14 +473,48 @@ where
 
                 // Apply Table(t) mask to each previously converted table.
                 assert!(clear_byte_array_list.len() == byte_table_mask.len());
-                let enc_masked_byte_array_list = clear_byte_array_list.par_bitand(&byte_table_mask);
+                let enc_masked_byte_array_list =
+                    ByteArrayList::<U8>::par_bitand_clear(&clear_byte_array_list, &byte_table_mask);
 
                 // Flatten the list of tables (as list of byte) into a single list of bytes
                 vec![enc_masked_byte_array_list.par_flatten()]
             }
         };
 
-        let result = SqlResult::<U8, B>::from_query_ref(&query_ref, select_mask, enc_byte_arrays);
+        // Window function: one extra encrypted value per row
+        let window_bytes = if query_ref.window().is_empty() {
+            vec![]
+        } else {
+            compute_window_bytes::<U8, B>(&query_ref, tables, &select_mask)
+        };
+
+        // Oblivious compaction of the selected rows into the requested number of rows
+        let compaction = match query_ref.options().max_num_rows() {
+            Some(k) => Some(Compaction::<B>::new(&select_mask, k)?),
+            None => None,
+        };
+
+        let result = match compaction {
+            Some(c) => SqlResult::<U8, B>::from_query_ref(
+                &query_ref,
+                c.select_mask(),
+                c.compact(&enc_byte_arrays),
+                if window_bytes.is_empty() {
+                    window_bytes
+                } else {
+                    c.compact(&window_bytes)
+                },
+            )
+            .with_truncated(c.truncated()),
+            None => SqlResult::<U8, B>::from_query_ref(
+                &query_ref,
+                select_mask,
+                enc_byte_arrays,
+                window_bytes,
+            ),
+        };
+
+        self.control.progress(RunStage::ComputeResult, 1, 1);
 
         #[cfg(feature = "stats")]
         self.stats_close(stats);
@@ -196,31 +530,381 @@ where
         #[cfg(feature = "stats")]
         let stats = PerfStats::new("Compute Select");
 
-        let mut select_mask: BoolMask<B>;
+        let control = self.control.clone();
+        if let Some(checkpoint) = control.checkpoint() {
+            if let Some(select_mask) = checkpoint.load::<BoolMask<B>>(SELECT_MASK_STATE)? {
+                #[cfg(feature = "stats")]
+                self.stats_close(stats);
 
-        if query_ref.is_where_empty() {
-            select_mask = BoolMask::<B>::all(tables.max_num_rows());
-        } else {
-            const CHUNCK_SIZE: usize = 100;
-
-            let mut ident_cmp_array = IdentCompareWithArray::<B>::new_empty(&query_ref);
-            select_mask = ident_cmp_array.compute_select(&query_ref, tables, CHUNCK_SIZE);
+                return Ok(select_mask);
+            }
         }
 
+        let mut select_mask = match control.checkpoint() {
+            Some(checkpoint) => {
+                Self::compute_where_mask_with_checkpoint(&query_ref, tables, checkpoint, &control)?
+            }
+            None => Self::compute_where_mask(&query_ref, tables, &control)?,
+        };
+
         // Last Pass : compute SELECT DISTINCT flag
-        compute_select_distinct(
+        let compute_distinct = match self.options.distinct_strategy() {
+            DistinctStrategy::Pairwise => compute_select_distinct::<B>,
+            DistinctStrategy::SortNetwork => compute_select_distinct_sort::<B>,
+        };
+        compute_distinct(
             &mut select_mask,
             query_ref.distinct(),
             tables,
             &query_ref.header().table_mask,
             &query_ref.header().not_field_mask,
-        );
+            &control,
+        )?;
+
+        if let Some(checkpoint) = control.checkpoint() {
+            checkpoint.save(SELECT_MASK_STATE, &select_mask)?;
+        }
 
         #[cfg(feature = "stats")]
         self.stats_close(stats);
 
         Ok(select_mask)
     }
+
+    /// WHERE clause only, without the SELECT DISTINCT pass. `control` is checked and
+    /// the progress reported after each binary op and each chunk of [CHUNCK_SIZE] rows.
+    fn compute_where_mask(
+        query_ref: &SqlQueryRef<B>,
+        tables: &OrderedTables,
+        control: &RunControl,
+    ) -> Result<BoolMask<B>, FheSqlError> {
+        let num_rows = tables.max_num_rows();
+        if query_ref.is_where_empty() {
+            control.step(RunStage::ComputeSelect, 0, 1)?;
+            control.progress(RunStage::ComputeSelect, 1, 1);
+            return Ok(BoolMask::<B>::all(num_rows));
+        }
+
+        let mut ident_cmp_array = IdentCompareWithArray::<B>::new_empty(query_ref);
+        let num_ops = ident_cmp_array.len();
+        let num_steps = num_ops + num_rows.div_ceil(CHUNCK_SIZE);
+        control.step(RunStage::ComputeSelect, 0, num_steps)?;
+        ident_cmp_array.pre_compute_select(query_ref, tables, CHUNCK_SIZE, control, num_steps)?;
+
+        let mut where_mask = BoolMask::<B>::new_empty();
+        for (chunk_index, start) in (0..num_rows).step_by(CHUNCK_SIZE).enumerate() {
+            control.check_cancelled()?;
+            let end = num_rows.min(start + CHUNCK_SIZE);
+            let chunk = ident_cmp_array.compute_select_rows(query_ref, start..end);
+            where_mask.mask.extend(chunk.mask);
+            control.step(RunStage::ComputeSelect, num_ops + chunk_index + 1, num_steps)?;
+        }
+        Ok(where_mask)
+    }
+
+    /// Same as [compute_where_mask](Self::compute_where_mask), the select masks of
+    /// the binary ops and each chunk of the WHERE clause mask are saved to `checkpoint`
+    /// and loaded instead of being computed again.
+    fn compute_where_mask_with_checkpoint(
+        query_ref: &SqlQueryRef<B>,
+        tables: &OrderedTables,
+        checkpoint: &SqlCheckpoint,
+        control: &RunControl,
+    ) -> Result<BoolMask<B>, FheSqlError> {
+        let num_rows = tables.max_num_rows();
+        if query_ref.is_where_empty() {
+            control.step(RunStage::ComputeSelect, 0, 1)?;
+            control.progress(RunStage::ComputeSelect, 1, 1);
+            return Ok(BoolMask::<B>::all(num_rows));
+        }
+
+        let mut ident_cmp_array = IdentCompareWithArray::<B>::new_empty(query_ref);
+        let num_ops = ident_cmp_array.len();
+        let chunk_num_rows = checkpoint.chunk_num_rows();
+        let num_steps = num_ops + num_rows.div_ceil(chunk_num_rows);
+        control.step(RunStage::ComputeSelect, 0, num_steps)?;
+        match checkpoint.load::<Vec<BoolMask<B>>>(IDENT_COMPARE_STATE)? {
+            Some(select_masks) => {
+                ident_cmp_array.set_select_masks(select_masks);
+                control.progress(RunStage::ComputeSelect, num_ops, num_steps);
+            }
+            None => {
+                ident_cmp_array
+                    .pre_compute_select(query_ref, tables, CHUNCK_SIZE, control, num_steps)?;
+                checkpoint.save(IDENT_COMPARE_STATE, &ident_cmp_array.select_masks())?;
+            }
+        }
+
+        let mut where_mask = BoolMask::<B>::new_empty();
+        for (chunk_index, start) in (0..num_rows).step_by(chunk_num_rows).enumerate() {
+            control.check_cancelled()?;
+            let end = num_rows.min(start + chunk_num_rows);
+            let name = format!("{}_{}_{}", WHERE_MASK_STATE, start, end);
+            let chunk = match checkpoint.load::<BoolMask<B>>(&name)? {
+                Some(chunk) => chunk,
+                None => {
+                    let chunk = ident_cmp_array.compute_select_rows(query_ref, start..end);
+                    checkpoint.save(&name, &chunk)?;
+                    chunk
+                }
+            };
+            where_mask.mask.extend(chunk.mask);
+            control.step(RunStage::ComputeSelect, num_ops + chunk_index + 1, num_steps)?;
+        }
+        Ok(where_mask)
+    }
+}
+
+/// Clear evaluation of the WHERE clause of `query`, one flag per row
+/// (used by the clear table mutations, see [OrderedTables::execute])
+pub(crate) fn clear_where_mask(
+    query: &ClearSqlQuery,
+    tables: &OrderedTables,
+) -> Result<Vec<bool>, FheSqlError> {
+    let query_ref = Arc::new(query.clone());
+    if query_ref.is_empty() {
+        return Ok(vec![false; tables.max_num_rows()]);
+    }
+    let where_mask =
+        SqlServer::<u8, bool>::compute_where_mask(&query_ref, tables, &RunControl::default())?;
+    Ok(where_mask.mask)
+}
+
+/// Row chunk size of the WHERE binary ops computation
+const CHUNCK_SIZE: usize = 100;
+
+/// Checkpoint state: the select mask of each WHERE binary op
+const IDENT_COMPARE_STATE: &str = "ident_compare";
+/// Checkpoint state: a chunk of the WHERE clause mask (suffixed by the row range)
+const WHERE_MASK_STATE: &str = "where_mask";
+/// Checkpoint state: the select mask after the SELECT DISTINCT pass
+const SELECT_MASK_STATE: &str = "select_mask";
+
+/// Identifies a query run: the checkpoint state is only reused by the same
+/// query on the same tables with the same distinct strategy. The fingerprint is
+/// persisted, it uses the same stable hash as [Table](crate::Table) fingerprints.
+fn checkpoint_fingerprint<B>(
+    query: &SqlQuery<B>,
+    tables: &OrderedTables,
+    options: &SqlServerOptions,
+) -> Result<u64, FheSqlError>
+where
+    B: serde::Serialize,
+{
+    let query_bytes =
+        bincode::serialize(query).map_err(|err| FheSqlError::InternalError(err.to_string()))?;
+    let mut hash = fnv1a_hash(FNV1A_OFFSET_BASIS, &query_bytes);
+    tables.iter_tables().for_each(|table| {
+        hash = fnv1a_hash(hash, &table.fingerprint().to_le_bytes());
+    });
+    let is_sort_network = options.distinct_strategy() == DistinctStrategy::SortNetwork;
+    Ok(fnv1a_hash(hash, &[is_sort_network as u8]))
+}
+
+/// Worst case estimate of the number of operations needed to run the query on `tables`
+/// with `options`, computed from the clear shape of the query
+fn estimate_query_gates<B>(
+    query: &SqlQuery<B>,
+    tables: &OrderedTables,
+    options: &SqlServerOptions,
+) -> Result<SqlGateEstimate, FheSqlError> {
+    let restricted = tables.restrict_to(query.ordered_schemas())?;
+    let num_rows = restricted
+        .as_deref()
+        .unwrap_or(tables)
+        .iter_tables()
+        .map(|t| t.num_rows())
+        .collect::<Vec<usize>>();
+    SqlQueryExplain::from_query(query)
+        .estimate_gates_with_strategy(&num_rows, options.distinct_strategy())
+}
+
+/// Admission control: rejects the query if its estimated cost is over the server budget
+fn admit_query<B>(
+    query_ref: &SqlQueryRef<B>,
+    tables: &OrderedTables,
+    options: &SqlServerOptions,
+) -> Result<(), FheSqlError> {
+    let Some(max_gates) = options.max_gates() else {
+        return Ok(());
+    };
+    let estimated_gates = estimate_query_gates(query_ref, tables, options)?.total();
+    if estimated_gates > max_gates {
+        return Err(FheSqlError::CostLimitExceeded {
+            estimated_gates,
+            max_gates,
+        });
+    }
+    Ok(())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// SqlResultStream
+////////////////////////////////////////////////////////////////////////////////
+
+/// Runs a query on consecutive ranges of `chunk_num_rows` rows and yields
+/// one self-contained result per range. Only the byte rows of the current
+/// range are allocated, the select flags of the previous rows are kept
+/// for the SELECT DISTINCT pass.
+struct SqlResultStream<'a, U8, B> {
+    server: SqlServer<U8, B>,
+    query_ref: SqlQueryRef<B>,
+    tables: &'a OrderedTables,
+    /// The tables as seen by an older client (if any)
+    restricted: Option<Arc<OrderedTables>>,
+    chunk_num_rows: usize,
+    /// First row of the next chunk
+    start: usize,
+    /// Final select flags of the previous rows
+    select_history: BoolMask<B>,
+    done: bool,
+}
+
+impl<'a, U8, B> SqlResultStream<'a, U8, B>
+where
+    B: ThreadSafeUInt
+        + ThreadSafeBool
+        + DefaultInto<B>
+        + ValueFrom<B>
+        + serde::Serialize
+        + serde::de::DeserializeOwned,
+    for<'b> U8: ThreadSafeUInt
+        + ValueFrom<&'b B>
+        + ValueFrom<u8>
+        + BitOrAssign
+        + DefaultInto<U8>
+        + ValueFrom<U8>,
+{
+    fn new(
+        query_ref: SqlQueryRef<B>,
+        tables: &'a OrderedTables,
+        chunk_num_rows: usize,
+        options: &SqlServerOptions,
+        control: &RunControl,
+    ) -> Result<Self, FheSqlError> {
+        query_ref.options().validate()?;
+        let restricted = tables.restrict_to(query_ref.ordered_schemas())?;
+        admit_query(&query_ref, restricted.as_deref().unwrap_or(tables), options)?;
+        if chunk_num_rows == 0 {
+            return Err(FheSqlError::InvalidQueryError(
+                "The number of rows of a chunk must be greater than zero".to_string(),
+            ));
+        }
+        // These features need every row of the tables at once
+        if query_ref.num_aggregates() > 0 {
+            return Err(FheSqlError::UnsupportedSqlQuery(
+                "Scalar subqueries are not supported by the streaming executor".to_string(),
+            ));
+        }
+        if !query_ref.window().is_empty() {
+            return Err(FheSqlError::UnsupportedSqlQuery(
+                "Window functions are not supported by the streaming executor".to_string(),
+            ));
+        }
+        if query_ref.options().max_num_rows().is_some() {
+            return Err(FheSqlError::UnsupportedSqlQuery(
+                "A maximum number of result rows is not supported by the streaming executor"
+                    .to_string(),
+            ));
+        }
+        // The chunks are compared with the previous rows one by one
+        if options.distinct_strategy() == DistinctStrategy::SortNetwork {
+            return Err(FheSqlError::UnsupportedSqlQuery(
+                "The sort network DISTINCT strategy is not supported by the streaming executor"
+                    .to_string(),
+            ));
+        }
+
+        Ok(SqlResultStream {
+            server: SqlServer::<U8, B>::new(options, control),
+            query_ref,
+            tables,
+            restricted,
+            chunk_num_rows,
+            start: 0,
+            select_history: BoolMask::<B>::new_empty(),
+            done: false,
+        })
+    }
+
+    fn next_chunk(&mut self, end: usize) -> Result<SqlResult<U8, B>, FheSqlError> {
+        let rows = self.start..end;
+        let tables = self.restricted.as_deref().unwrap_or(self.tables);
+        let chunk_tables = tables.slice_rows(rows.start, rows.end);
+        let table_mask = &self.query_ref.header().table_mask;
+
+        // WHERE clause on the chunk rows
+        let where_mask = SqlServer::<U8, B>::compute_where_mask(
+            &self.query_ref,
+            &chunk_tables,
+            &self.server.control,
+        )?;
+        assert_eq!(where_mask.len(), rows.len());
+        self.select_history.mask.extend(where_mask.mask);
+
+        // SELECT DISTINCT against all the previous rows
+        compute_select_distinct_rows(
+            &mut self.select_history,
+            rows.clone(),
+            self.query_ref.distinct(),
+            tables,
+            table_mask,
+            &self.query_ref.header().not_field_mask,
+            &self.server.control,
+        )?;
+        let mut select_mask = BoolMask::<B> {
+            mask: self.select_history.mask[rows.clone()].to_vec(),
+        };
+        par_unselect_out_of_bounds(&mut select_mask, &chunk_tables, table_mask);
+        self.select_history.mask[rows].clone_from_slice(&select_mask.mask);
+
+        self.server
+            .compute_result(self.query_ref.clone(), &chunk_tables, select_mask)
+    }
+}
+
+impl<'a, U8, B> Iterator for SqlResultStream<'a, U8, B>
+where
+    B: ThreadSafeUInt
+        + ThreadSafeBool
+        + DefaultInto<B>
+        + ValueFrom<B>
+        + serde::Serialize
+        + serde::de::DeserializeOwned,
+    for<'b> U8: ThreadSafeUInt
+        + ValueFrom<&'b B>
+        + ValueFrom<u8>
+        + BitOrAssign
+        + DefaultInto<U8>
+        + ValueFrom<U8>,
+{
+    type Item = Result<SqlResult<U8, B>, FheSqlError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if self.query_ref.is_empty() {
+            self.done = true;
+            return Some(Ok(SqlResult::<U8, B>::new_empty()));
+        }
+        let max_num_rows = self
+            .restricted
+            .as_deref()
+            .unwrap_or(self.tables)
+            .max_num_rows();
+        if self.start >= max_num_rows {
+            self.done = true;
+            return None;
+        }
+        let end = (self.start + self.chunk_num_rows).min(max_num_rows);
+        let chunk = self.next_chunk(end);
+        self.start = end;
+        if chunk.is_err() {
+            self.done = true;
+        }
+        Some(chunk)
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
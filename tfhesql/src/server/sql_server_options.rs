@@ -0,0 +1,55 @@
+////////////////////////////////////////////////////////////////////////////////
+// DistinctStrategy
+////////////////////////////////////////////////////////////////////////////////
+
+/// Algorithm used by the server to compute the SELECT DISTINCT pass
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DistinctStrategy {
+    /// Default: each row is compared with every previous row, O(n²) encrypted
+    /// row comparisons processed row after row.
+    #[default]
+    Pairwise,
+    /// Oblivious bitonic sort of the projected row keys followed by an
+    /// adjacent-duplicate elimination, O(n.log²(n)) encrypted compare-exchanges.
+    SortNetwork,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// SqlServerOptions
+////////////////////////////////////////////////////////////////////////////////
+
+/// Server side execution options, they do not change the query result (except
+/// for the admission control, which rejects the queries over budget)
+/// (see [FheRunSqlQuery::run_with_options](crate::FheRunSqlQuery::run_with_options))
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SqlServerOptions {
+    distinct_strategy: DistinctStrategy,
+    max_gates: Option<usize>,
+}
+
+impl SqlServerOptions {
+    /// Select the SELECT DISTINCT algorithm (default=`DistinctStrategy::Pairwise`)
+    pub fn with_distinct_strategy(mut self, distinct_strategy: DistinctStrategy) -> Self {
+        self.distinct_strategy = distinct_strategy;
+        self
+    }
+
+    /// Rejects the queries whose estimated number of boolean + u8 operations
+    /// on the server tables is greater than `max_gates`, before any computation
+    /// (see [SqlQueryExplain::estimate_gates_with_strategy](crate::SqlQueryExplain::estimate_gates_with_strategy)).
+    ///
+    /// The queries on tables encrypted at rest are estimated with
+    /// [SqlQueryExplain::estimate_gates_on_encrypted_tables](crate::SqlQueryExplain::estimate_gates_on_encrypted_tables).
+    pub fn with_max_gates(mut self, max_gates: usize) -> Self {
+        self.max_gates = Some(max_gates);
+        self
+    }
+
+    pub fn distinct_strategy(&self) -> DistinctStrategy {
+        self.distinct_strategy
+    }
+
+    pub fn max_gates(&self) -> Option<usize> {
+        self.max_gates
+    }
+}
@@ -0,0 +1,485 @@
+use crate::CompactFheSqlQuery;
+use crate::CompressedFheSqlQuery;
+use crate::FheRunSqlQuery;
+use crate::FheSqlError;
+use crate::FheSqlQuery;
+use crate::FheSqlResult;
+use crate::FheSqlServer;
+use crate::OrderedTables;
+use crate::RunControl;
+use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::JoinHandle;
+use tfhe::ServerKey;
+
+use super::server_key_pool::ServerKeyPool;
+use super::sql_server_options::SqlServerOptions;
+
+////////////////////////////////////////////////////////////////////////////////
+// SqlServiceOptions
+////////////////////////////////////////////////////////////////////////////////
+
+/// [FheSqlService] configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SqlServiceOptions {
+    max_concurrent_queries: usize,
+    num_threads_per_client: usize,
+    server_options: SqlServerOptions,
+}
+
+impl Default for SqlServiceOptions {
+    fn default() -> Self {
+        SqlServiceOptions {
+            max_concurrent_queries: 1,
+            num_threads_per_client: 0,
+            server_options: SqlServerOptions::default(),
+        }
+    }
+}
+
+impl SqlServiceOptions {
+    /// Maximum number of queries executed at the same time, the other queries
+    /// wait in the queue (default=1, at least 1)
+    pub fn with_max_concurrent_queries(mut self, max_concurrent_queries: usize) -> Self {
+        self.max_concurrent_queries = max_concurrent_queries.max(1);
+        self
+    }
+
+    /// Number of threads of the pool created for each registered key
+    /// (default=0, the number of logical cpus)
+    pub fn with_num_threads_per_client(mut self, num_threads_per_client: usize) -> Self {
+        self.num_threads_per_client = num_threads_per_client;
+        self
+    }
+
+    /// Options used to run every query
+    pub fn with_server_options(mut self, server_options: SqlServerOptions) -> Self {
+        self.server_options = server_options;
+        self
+    }
+
+    pub fn max_concurrent_queries(&self) -> usize {
+        self.max_concurrent_queries
+    }
+
+    pub fn num_threads_per_client(&self) -> usize {
+        self.num_threads_per_client
+    }
+
+    pub fn server_options(&self) -> &SqlServerOptions {
+        &self.server_options
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// SqlServiceQuery
+////////////////////////////////////////////////////////////////////////////////
+
+/// Any encrypted query accepted by [FheSqlService::submit]
+pub enum SqlServiceQuery {
+    Fhe(FheSqlQuery),
+    Compressed(CompressedFheSqlQuery),
+    Compact(CompactFheSqlQuery),
+}
+
+impl From<FheSqlQuery> for SqlServiceQuery {
+    fn from(value: FheSqlQuery) -> Self {
+        SqlServiceQuery::Fhe(value)
+    }
+}
+
+impl From<CompressedFheSqlQuery> for SqlServiceQuery {
+    fn from(value: CompressedFheSqlQuery) -> Self {
+        SqlServiceQuery::Compressed(value)
+    }
+}
+
+impl From<CompactFheSqlQuery> for SqlServiceQuery {
+    fn from(value: CompactFheSqlQuery) -> Self {
+        SqlServiceQuery::Compact(value)
+    }
+}
+
+impl SqlServiceQuery {
+    fn run(
+        self,
+        tables: &OrderedTables,
+        pool: &ServerKeyPool,
+        options: &SqlServerOptions,
+    ) -> Result<FheSqlResult, FheSqlError> {
+        // The expansion needs the server key of the client as well
+        let query = pool.install(|| match self {
+            SqlServiceQuery::Fhe(q) => q,
+            SqlServiceQuery::Compressed(q) => q.decompress(),
+            SqlServiceQuery::Compact(q) => q.expand(),
+        });
+        FheSqlServer::run_with_pool_and_control(
+            &query,
+            tables,
+            pool,
+            options,
+            &RunControl::default(),
+        )
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// SqlServiceResult
+////////////////////////////////////////////////////////////////////////////////
+
+/// The result of a query submitted to a [FheSqlService], tagged with the
+/// client id and the query id returned by [FheSqlService::submit]
+pub struct SqlServiceResult {
+    client_id: String,
+    query_id: u64,
+    result: Result<FheSqlResult, FheSqlError>,
+}
+
+impl SqlServiceResult {
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    pub fn query_id(&self) -> u64 {
+        self.query_id
+    }
+
+    pub fn result(&self) -> &Result<FheSqlResult, FheSqlError> {
+        &self.result
+    }
+
+    pub fn into_result(self) -> Result<FheSqlResult, FheSqlError> {
+        self.result
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// FheSqlService
+////////////////////////////////////////////////////////////////////////////////
+
+struct SqlServiceJob {
+    client_id: String,
+    query_id: u64,
+    query: SqlServiceQuery,
+}
+
+struct SqlServiceShared {
+    /// The current tables snapshot, replaced as a whole by a reload
+    tables: RwLock<Arc<OrderedTables>>,
+    options: SqlServiceOptions,
+    /// One thread pool per registered client, with the client server key installed
+    key_pools: RwLock<HashMap<String, Arc<ServerKeyPool>>>,
+}
+
+/// A multi-tenant query service: each client registers its own server key,
+/// the queries are queued and executed against the same shared tables, at most
+/// `max_concurrent_queries` at a time, each one with the key of its client.
+///
+/// The tables can be reloaded while the service is running, see
+/// [reload](FheSqlService::reload). A query always runs on the snapshot of the
+/// tables taken when its execution started.
+pub struct FheSqlService {
+    shared: Arc<SqlServiceShared>,
+    queue: Option<Sender<SqlServiceJob>>,
+    results: Mutex<Receiver<SqlServiceResult>>,
+    next_query_id: Mutex<u64>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl FheSqlService {
+    pub fn new(tables: Arc<OrderedTables>, options: SqlServiceOptions) -> Self {
+        let shared = Arc::new(SqlServiceShared {
+            tables: RwLock::new(tables),
+            options,
+            key_pools: RwLock::new(HashMap::new()),
+        });
+
+        let (queue, jobs) = channel::<SqlServiceJob>();
+        let (results_sender, results) = channel::<SqlServiceResult>();
+        let jobs = Arc::new(Mutex::new(jobs));
+
+        let workers = (0..options.max_concurrent_queries())
+            .map(|_| {
+                let shared = shared.clone();
+                let jobs = jobs.clone();
+                let results_sender = results_sender.clone();
+                std::thread::spawn(move || Self::worker(&shared, &jobs, &results_sender))
+            })
+            .collect();
+
+        FheSqlService {
+            shared,
+            queue: Some(queue),
+            results: Mutex::new(results),
+            next_query_id: Mutex::new(0),
+            workers,
+        }
+    }
+
+    /// Returns the current snapshot of the tables
+    pub fn tables(&self) -> Arc<OrderedTables> {
+        self.shared.tables.read().unwrap().clone()
+    }
+
+    /// Replaces the served tables with `tables`. The running queries keep using
+    /// the previous snapshot, the queries started afterwards use the new one.
+    ///
+    /// The reload is refused if the [OrderedSchemas](crate::OrderedSchemas) of
+    /// `tables` are not an append-only evolution of the served ones (see
+    /// [is_evolution_of](crate::OrderedSchemas::is_evolution_of)), since the
+    /// queries of the clients would no longer match, with a
+    /// [SchemaMismatch](FheSqlError::SchemaMismatch) error that lists the tables
+    /// that differ. Set `allow_schema_change` to accept it anyway.
+    pub fn reload(
+        &self,
+        tables: OrderedTables,
+        allow_schema_change: bool,
+    ) -> Result<(), FheSqlError> {
+        let mut current = self.shared.tables.write().unwrap();
+        if !allow_schema_change {
+            let mismatches = tables
+                .ordered_schemas()
+                .evolution_mismatches(current.ordered_schemas());
+            if !mismatches.is_empty() {
+                return Err(FheSqlError::SchemaMismatch(format!(
+                    "The reloaded tables do not match the schemas of the served tables: {}",
+                    mismatches.join(", ")
+                )));
+            }
+        }
+        *current = Arc::new(tables);
+        Ok(())
+    }
+
+    /// Parses the .csv files of `dir` (see [OrderedTables::load_from_directory])
+    /// and [reloads](FheSqlService::reload) the served tables. The queries are not
+    /// blocked while the files are parsed.
+    pub fn reload_from_directory<P: AsRef<std::path::Path>>(
+        &self,
+        dir: P,
+        allow_schema_change: bool,
+    ) -> Result<(), FheSqlError> {
+        let tables = OrderedTables::load_from_directory(dir)?;
+        self.reload(tables, allow_schema_change)
+    }
+
+    pub fn options(&self) -> &SqlServiceOptions {
+        &self.shared.options
+    }
+
+    /// Registers (or replaces) the server key of `client_id`
+    pub fn register_key(
+        &self,
+        client_id: impl Into<String>,
+        server_key: &ServerKey,
+    ) -> Result<(), FheSqlError> {
+        let pool = ServerKeyPool::new(server_key, self.shared.options.num_threads_per_client())?;
+        self.shared
+            .key_pools
+            .write()
+            .unwrap()
+            .insert(client_id.into(), Arc::new(pool));
+        Ok(())
+    }
+
+    /// Removes the server key of `client_id`, the queued queries of this client fail.
+    /// Returns false if the client is unknown.
+    pub fn unregister_key(&self, client_id: &str) -> bool {
+        self.shared
+            .key_pools
+            .write()
+            .unwrap()
+            .remove(client_id)
+            .is_some()
+    }
+
+    pub fn is_registered(&self, client_id: &str) -> bool {
+        self.shared.key_pools.read().unwrap().contains_key(client_id)
+    }
+
+    /// Queues a query of `client_id` and returns its query id. The result is
+    /// received with [recv_result](FheSqlService::recv_result).
+    pub fn submit(
+        &self,
+        client_id: &str,
+        query: impl Into<SqlServiceQuery>,
+    ) -> Result<u64, FheSqlError> {
+        if !self.is_registered(client_id) {
+            return Err(FheSqlError::InvalidQueryError(format!(
+                "Unknown client id '{}'",
+                client_id
+            )));
+        }
+        let query_id = {
+            let mut next_query_id = self.next_query_id.lock().unwrap();
+            *next_query_id += 1;
+            *next_query_id
+        };
+        let job = SqlServiceJob {
+            client_id: client_id.to_string(),
+            query_id,
+            query: query.into(),
+        };
+        let sent = match &self.queue {
+            Some(queue) => queue.send(job).is_ok(),
+            None => false,
+        };
+        if !sent {
+            return Err(FheSqlError::InternalError(
+                "The query service is stopped".to_string(),
+            ));
+        }
+        Ok(query_id)
+    }
+
+    /// Waits for the next finished query, in completion order
+    pub fn recv_result(&self) -> Option<SqlServiceResult> {
+        self.results.lock().unwrap().recv().ok()
+    }
+
+    /// Returns the next finished query if any, without waiting
+    pub fn try_recv_result(&self) -> Option<SqlServiceResult> {
+        self.results.lock().unwrap().try_recv().ok()
+    }
+
+    fn worker(
+        shared: &SqlServiceShared,
+        jobs: &Mutex<Receiver<SqlServiceJob>>,
+        results: &Sender<SqlServiceResult>,
+    ) {
+        loop {
+            // The lock is released as soon as a job is received
+            let job = match jobs.lock().unwrap().recv() {
+                Ok(job) => job,
+                Err(_) => return,
+            };
+            let pool = shared.key_pools.read().unwrap().get(&job.client_id).cloned();
+            // Keeps the snapshot alive until the end of the query, whatever the reloads
+            let tables = shared.tables.read().unwrap().clone();
+            let result = match pool {
+                // A panic must not kill the worker nor lose the result of the query
+                Some(pool) => std::panic::catch_unwind(AssertUnwindSafe(|| {
+                    job.query.run(&tables, &pool, shared.options.server_options())
+                }))
+                .unwrap_or_else(|_| {
+                    Err(FheSqlError::InternalError(format!(
+                        "The query {} of client '{}' panicked",
+                        job.query_id, job.client_id
+                    )))
+                }),
+                None => Err(FheSqlError::InvalidQueryError(format!(
+                    "Unknown client id '{}'",
+                    job.client_id
+                ))),
+            };
+            let service_result = SqlServiceResult {
+                client_id: job.client_id,
+                query_id: job.query_id,
+                result,
+            };
+            if results.send(service_result).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl Drop for FheSqlService {
+    /// Waits for the queued queries
+    fn drop(&mut self) {
+        drop(self.queue.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write_csv(dir: &std::path::Path, name: &str, content: &str) {
+        std::fs::write(dir.join(name), content).unwrap();
+    }
+
+    #[test]
+    fn test_reload() {
+        let dir = std::env::temp_dir().join(format!("tfhesql_reload_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        write_csv(&dir, "Numbers.csv", "id:uint32,name:string\n1,one\n2,two\n");
+
+        let tables = OrderedTables::load_from_directory(&dir).unwrap();
+        let service = FheSqlService::new(Arc::new(tables), SqlServiceOptions::default());
+        let old_snapshot = service.tables();
+        assert_eq!(old_snapshot.max_num_rows(), 2);
+
+        // Same schemas, new rows
+        write_csv(&dir, "Numbers.csv", "id:uint32,name:string\n1,one\n2,two\n3,three\n");
+        service.reload_from_directory(&dir, false).unwrap();
+        assert_eq!(old_snapshot.max_num_rows(), 2);
+        assert_eq!(service.tables().max_num_rows(), 3);
+        assert_eq!(service.tables().ordered_schemas(), old_snapshot.ordered_schemas());
+
+        // New table
+        write_csv(&dir, "Letters.csv", "letter:string\na\n");
+        service.reload_from_directory(&dir, false).unwrap();
+        assert_eq!(service.tables().num_tables(), 2);
+
+        // Removed table
+        std::fs::remove_file(dir.join("Letters.csv")).unwrap();
+        assert_eq!(
+            service.reload_from_directory(&dir, false),
+            Err(FheSqlError::SchemaMismatch(
+                "The reloaded tables do not match the schemas of the served tables: \
+                 table 'Letters' was removed"
+                    .to_string()
+            ))
+        );
+        assert_eq!(service.tables().num_tables(), 2);
+
+        // Other columns
+        write_csv(&dir, "Letters.csv", "letter:string\na\n");
+        write_csv(&dir, "Numbers.csv", "id:uint64,name:string\n1,one\n");
+        assert_eq!(
+            service.reload_from_directory(&dir, false),
+            Err(FheSqlError::SchemaMismatch(
+                "The reloaded tables do not match the schemas of the served tables: \
+                 column 'id' of table 'Numbers' was renamed, moved or retyped"
+                    .to_string()
+            ))
+        );
+        service.reload_from_directory(&dir, true).unwrap();
+        assert_eq!(service.tables().max_num_rows(), 1);
+
+        // Removed column
+        write_csv(&dir, "Numbers.csv", "id:uint64\n1\n2\n");
+        let tables = OrderedTables::load_from_directory(&dir).unwrap();
+        assert_eq!(
+            service.reload(tables, false),
+            Err(FheSqlError::SchemaMismatch(
+                "The reloaded tables do not match the schemas of the served tables: \
+                 column 'name' of table 'Numbers' was removed"
+                    .to_string()
+            ))
+        );
+        assert_eq!(service.tables().max_num_rows(), 1);
+        let same_tables = OrderedTables::load_from_directory(&dir).unwrap();
+        service.reload(same_tables, true).unwrap();
+        assert_eq!(service.tables().max_num_rows(), 2);
+        assert_eq!(service.tables().num_tables(), 2);
+
+        // Missing directory
+        assert!(matches!(
+            service.reload_from_directory(dir.join("missing"), false),
+            Err(FheSqlError::IoError(_))
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,331 @@
+use crate::bitops::*;
+use crate::default_into::ValueFrom;
+use crate::query::sql_query::SqlQueryRef;
+use crate::sql_ast::window::WindowFunc;
+use crate::table::{OrderedTables, Table};
+use crate::types::*;
+use crate::uint::mask::BoolMask;
+use crate::uint::ByteArray;
+use crate::utils::arrow::array_column_cell_cmp;
+#[cfg(feature = "parallel")]
+use rayon::iter::*;
+use std::collections::HashMap;
+
+////////////////////////////////////////////////////////////////////////////////
+// WindowMasks
+////////////////////////////////////////////////////////////////////////////////
+
+/// Encrypted flags derived from the query window masks
+struct WindowMasks<B> {
+    /// Order(c) AND NOT Desc
+    asc: Vec<B>,
+    /// Order(c) AND Desc
+    desc: Vec<B>,
+    /// Order(c)
+    order: Vec<B>,
+    /// Partition(c)
+    partition: Vec<B>,
+    /// NOT { OR Partition(c) }
+    no_partition: B,
+    is_row_number: B,
+}
+
+impl<B> WindowMasks<B>
+where
+    B: ThreadSafeBool,
+{
+    fn new(query_ref: &SqlQueryRef<B>) -> Self {
+        let window = query_ref.window();
+        let not_desc = window.order_desc.ref_not();
+        let order = window.order_ident_mask.mask.clone();
+        let asc = order.iter().map(|o| o.refref_bitand(&not_desc)).collect();
+        let desc = order
+            .iter()
+            .map(|o| o.refref_bitand(&window.order_desc))
+            .collect();
+        let partition = window.partition_ident_mask.mask.clone();
+        let no_partition = par_bitor_vec(partition.clone())
+            .unwrap_or(B::get_false())
+            .ref_not();
+        WindowMasks {
+            asc,
+            desc,
+            order,
+            partition,
+            no_partition,
+            is_row_number: window.func_mask.get(WindowFunc::RowNumber.index()).clone(),
+        }
+    }
+
+    /// Row(s) is counted before Row(r):
+    /// SamePartition(s, r) AND { Before(s, r) OR (IsRowNumber AND Tie(s, r) AND s < r) }
+    fn counted(&self, key: &[u8]) -> B {
+        // key = [Cmp(s, r, c); 0 <= c < num_columns] + [s < r]
+        let n = key.len() - 1;
+        let s_lt_r = key[n] == 1;
+        let (mut before, mut tie, mut same) = (vec![], vec![], vec![&self.no_partition]);
+        key[0..n].iter().enumerate().for_each(|(c, k)| match *k {
+            EQ => {
+                tie.push(&self.order[c]);
+                same.push(&self.partition[c]);
+            }
+            GT => before.push(&self.desc[c]),
+            LT => before.push(&self.asc[c]),
+            _ => unreachable!(),
+        });
+        let tie_and_row_number = if s_lt_r && !tie.is_empty() {
+            Some(par_bitor_vec_ref(tie).unwrap().refref_bitand(&self.is_row_number))
+        } else {
+            None
+        };
+        if let Some(t) = &tie_and_row_number {
+            before.push(t);
+        }
+        match par_bitor_vec_ref(before) {
+            Some(before) => par_bitor_vec_ref(same).unwrap().refref_bitand(&before),
+            None => B::get_false(),
+        }
+    }
+}
+
+const EQ: u8 = 0;
+const GT: u8 = 1;
+const LT: u8 = 2;
+
+fn row_cmp_row_key(table: &Table, s: usize, r: usize) -> Vec<u8> {
+    let mut key = table
+        .batch()
+        .columns()
+        .iter()
+        .map(|column| {
+            let eq_gt_lt = array_column_cell_cmp(column, s, r);
+            if eq_gt_lt[0] {
+                EQ
+            } else if eq_gt_lt[1] {
+                GT
+            } else {
+                LT
+            }
+        })
+        .collect::<Vec<u8>>();
+    key.push((s < r) as u8);
+    key
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// WindowCacheBuilder
+////////////////////////////////////////////////////////////////////////////////
+
+/// Computes the ROW_NUMBER() or RANK() value of each row.
+/// Rank(r) = 1 + { SUM s; Select(s) AND Counted(s, r) }
+struct WindowCacheBuilder<B> {
+    cache_indices: HashMap<(usize, Vec<u8>), usize>,
+    cache_keys: Vec<(usize, Vec<u8>)>,
+    cache_values: Vec<B>,
+    /// table_pair_to_cache_index[t][s * num_rows(t) + r]
+    table_pair_to_cache_index: Vec<Vec<usize>>,
+}
+
+impl<B> WindowCacheBuilder<B>
+where
+    B: ThreadSafeBool,
+{
+    // Serial: we are manipulating clear data
+    fn pre_build(tables: &OrderedTables) -> Self {
+        let mut builder = WindowCacheBuilder::<B> {
+            cache_indices: HashMap::new(),
+            cache_keys: vec![],
+            cache_values: vec![],
+            table_pair_to_cache_index: vec![],
+        };
+        tables
+            .tables()
+            .iter()
+            .enumerate()
+            .for_each(|(table_index, table)| {
+                let n = table.num_rows();
+                let mut indices = Vec::with_capacity(n * n);
+                for s in 0..n {
+                    for r in 0..n {
+                        let key = (table_index, row_cmp_row_key(table, s, r));
+                        let next_index = builder.cache_keys.len();
+                        let index = *builder.cache_indices.entry(key.clone()).or_insert(next_index);
+                        if index == next_index {
+                            builder.cache_keys.push(key);
+                        }
+                        indices.push(index);
+                    }
+                }
+                builder.table_pair_to_cache_index.push(indices);
+            });
+        builder.cache_values = vec![B::get_false(); builder.cache_keys.len()];
+        builder
+    }
+
+    #[cfg(feature = "parallel")]
+    fn compute_values(&mut self, masks: &WindowMasks<B>, table_mask: &BoolMask<B>) {
+        self.cache_values
+            .par_iter_mut()
+            .zip(self.cache_keys.par_iter())
+            .for_each(|(dst, (table_index, key))| {
+                *dst = masks.counted(key).refref_bitand(table_mask.get(*table_index));
+            });
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    fn compute_values(&mut self, masks: &WindowMasks<B>, table_mask: &BoolMask<B>) {
+        self.cache_values
+            .iter_mut()
+            .zip(self.cache_keys.iter())
+            .for_each(|(dst, (table_index, key))| {
+                *dst = masks.counted(key).refref_bitand(table_mask.get(*table_index));
+            });
+    }
+
+    // Counted(s, r) = OR { t; Table(t) AND Counted(t, s, r) }
+    fn counted_at(&self, tables: &OrderedTables, s: usize, r: usize) -> Option<B> {
+        let v = self
+            .table_pair_to_cache_index
+            .iter()
+            .enumerate()
+            .filter_map(|(table_index, indices)| {
+                let n = tables.tables()[table_index].num_rows();
+                if s < n && r < n {
+                    Some(&self.cache_values[indices[s * n + r]])
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<&B>>();
+        par_bitor_vec_ref(v)
+    }
+
+    fn compute_rank_bits(
+        &self,
+        tables: &OrderedTables,
+        select_mask: &BoolMask<B>,
+        r: usize,
+        num_bits: usize,
+    ) -> Vec<B> {
+        // Starts at 1
+        let mut bits = vec![B::get_false(); num_bits];
+        bits[0] = B::get_true();
+        (0..select_mask.len()).for_each(|s| {
+            if let Some(counted) = self.counted_at(tables, s, r) {
+                increment(&mut bits, counted.refref_bitand(select_mask.get(s)));
+            }
+        });
+        bits
+    }
+}
+
+// Bits += x (wrapping)
+fn increment<B>(bits: &mut [B], x: B)
+where
+    B: ThreadSafeBool,
+{
+    let mut carry = x;
+    bits.iter_mut().for_each(|b| {
+        // b XOR carry = (b OR carry) AND NOT (b AND carry)
+        let b_and_carry = b.refref_bitand(&carry);
+        *b = b.refref_bitor(&carry).refref_bitand(&b_and_carry.ref_not());
+        carry = b_and_carry;
+    });
+}
+
+// Little endian bytes
+fn bits_to_bytes<U8, B>(bits: &[B]) -> ByteArray<U8>
+where
+    B: ThreadSafeBool,
+    for<'a> U8: ThreadSafeUInt + ValueFrom<&'a B> + ValueFrom<u8>,
+{
+    let bytes = bits
+        .chunks(8)
+        .map(|chunk| {
+            let v = chunk
+                .iter()
+                .enumerate()
+                .map(|(k, bit)| U8::value_from(bit).ref_bitand(U8::value_from(1 << k)))
+                .collect::<Vec<U8>>();
+            par_bitor_vec(v).unwrap()
+        })
+        .collect::<Vec<U8>>();
+    ByteArray::<U8>::from_bytes(bytes)
+}
+
+fn window_value_num_bits(max_num_rows: usize) -> usize {
+    // Rank(r) is in [1, max_num_rows]
+    ((usize::BITS - max_num_rows.leading_zeros()) as usize).max(1)
+}
+
+#[cfg(feature = "parallel")]
+/// Computes the encrypted window function value of each row
+pub(super) fn compute_window_bytes<U8, B>(
+    query_ref: &SqlQueryRef<B>,
+    tables: &OrderedTables,
+    select_mask: &BoolMask<B>,
+) -> Vec<ByteArray<U8>>
+where
+    B: ThreadSafeBool,
+    for<'a> U8: ThreadSafeUInt + ValueFrom<&'a B> + ValueFrom<u8>,
+{
+    let masks = WindowMasks::<B>::new(query_ref);
+    let mut builder = WindowCacheBuilder::<B>::pre_build(tables);
+    builder.compute_values(&masks, &query_ref.header().table_mask);
+
+    let num_bits = window_value_num_bits(tables.max_num_rows());
+    (0..select_mask.len())
+        .into_par_iter()
+        .map(|r| {
+            let bits = builder.compute_rank_bits(tables, select_mask, r, num_bits);
+            bits_to_bytes(&bits)
+        })
+        .collect()
+}
+
+#[cfg(not(feature = "parallel"))]
+/// Computes the encrypted window function value of each row
+pub(super) fn compute_window_bytes<U8, B>(
+    query_ref: &SqlQueryRef<B>,
+    tables: &OrderedTables,
+    select_mask: &BoolMask<B>,
+) -> Vec<ByteArray<U8>>
+where
+    B: ThreadSafeBool,
+    for<'a> U8: ThreadSafeUInt + ValueFrom<&'a B> + ValueFrom<u8>,
+{
+    let masks = WindowMasks::<B>::new(query_ref);
+    let mut builder = WindowCacheBuilder::<B>::pre_build(tables);
+    builder.compute_values(&masks, &query_ref.header().table_mask);
+
+    let num_bits = window_value_num_bits(tables.max_num_rows());
+    (0..select_mask.len())
+        .map(|r| {
+            let bits = builder.compute_rank_bits(tables, select_mask, r, num_bits);
+            bits_to_bytes(&bits)
+        })
+        .collect()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_increment() {
+        let mut bits = vec![false; 4];
+        for i in 1..16_u8 {
+            increment(&mut bits, true);
+            increment(&mut bits, false);
+            let bytes = bits_to_bytes::<u8, bool>(&bits);
+            assert_eq!(bytes.bytes, vec![i]);
+        }
+        assert_eq!(window_value_num_bits(0), 1);
+        assert_eq!(window_value_num_bits(255), 8);
+        assert_eq!(window_value_num_bits(256), 9);
+    }
+}
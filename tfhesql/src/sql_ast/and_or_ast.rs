@@ -16,6 +16,10 @@ use super::data_ident::DataIdent;
 use super::data_sig::DataSig;
 use super::data_value::DataValue;
 use super::helpers::compute_expr_tree_info;
+use super::helpers::reflexive_binary_op;
+use super::column_arithmetic::AstArithmetic;
+use super::in_set::AstInSet;
+use super::scalar_subquery::AstAggregate;
 
 ////////////////////////////////////////////////////////////////////////////////
 // AstRightValue
@@ -34,27 +38,104 @@ pub enum AstRightValue {
 #[derive(Clone)]
 pub struct AstNumBinaryOp {
     pub is_dummy: bool,
+    pub is_padding: bool,
     pub pos_mask: ClearBoolMask,
     pub op_mask: ClearComparatorMask,
     pub left_ident_mask: ClearBoolMask,
     pub right_ident_mask: ClearBoolMask,
     pub right_value: AstRightValue,
     pub right_minus_sign: bool,
+    pub right_aggregate_mask: ClearBoolMask,
+    pub left_arithmetic_mask: ClearBoolMask,
+    pub left_set_mask: ClearBoolMask,
 }
 
 impl AstNumBinaryOp {
-    fn new(num_cols: usize) -> Self {
+    fn new(
+        num_cols: usize,
+        num_aggregates: usize,
+        num_arithmetics: usize,
+        num_sets: usize,
+    ) -> Self {
         Self {
             is_dummy: true,
+            is_padding: false,
             pos_mask: ClearBoolMask::new_empty(),
             op_mask: ClearComparatorMask::none(),
             left_ident_mask: ClearBoolMask::none(num_cols), //can all be false
             right_ident_mask: ClearBoolMask::none(num_cols),
             right_value: AstRightValue::Number(0),
             right_minus_sign: false,
+            right_aggregate_mask: ClearBoolMask::none(num_aggregates),
+            left_arithmetic_mask: ClearBoolMask::none(num_arithmetics),
+            left_set_mask: ClearBoolMask::none(num_sets),
         }
     }
 
+    /// Column(0) >= 0 OR Column(0) < 0, true for every row of the selected table
+    fn set_tautology(&mut self) {
+        self.is_dummy = false;
+        self.op_mask.set(&BinaryOperator::GtEq);
+        self.op_mask.set(&BinaryOperator::Lt);
+        self.left_ident_mask.set(0);
+        self.right_value = AstRightValue::Number(0);
+    }
+
+    /// Column(0) with an empty comparator mask, false for every row
+    fn set_contradiction(&mut self) {
+        self.is_dummy = false;
+        self.left_ident_mask.set(0);
+        self.right_value = AstRightValue::Number(0);
+    }
+
+    fn set_in_set(
+        &mut self,
+        op: &BinaryOperator,
+        set_index: usize,
+        right: bool,
+    ) -> Result<(), FheSqlError> {
+        // (Set = true) := Set, (Set = false) := NOT Set
+        let is_member = match op {
+            BinaryOperator::Eq => right,
+            BinaryOperator::NotEq => !right,
+            _ => return Err(FheSqlError::unsupported_binary_op(op)),
+        };
+        self.is_dummy = false;
+        self.op_mask.set(if is_member {
+            &BinaryOperator::Eq
+        } else {
+            &BinaryOperator::NotEq
+        });
+        self.left_set_mask.set(set_index);
+        Ok(())
+    }
+
+    fn set_arithmetic(
+        &mut self,
+        op: &BinaryOperator,
+        arithmetic_index: usize,
+        right: &DataValue,
+    ) -> Result<(), FheSqlError> {
+        self.is_dummy = false;
+        self.op_mask.set(op);
+        self.left_arithmetic_mask.set(arithmetic_index);
+        self.set_right_value(right);
+        Ok(())
+    }
+
+    fn set_aggregate(
+        &mut self,
+        op: &BinaryOperator,
+        left: &DataIdent,
+        aggregate_index: usize,
+    ) -> Result<(), FheSqlError> {
+        self.is_dummy = false;
+        self.op_mask.set(op);
+        self.left_ident_mask.set(left.column_index());
+        self.right_aggregate_mask.set(aggregate_index);
+        Ok(())
+    }
+
     fn set(
         &mut self,
         op: &BinaryOperator,
@@ -65,27 +146,7 @@ impl AstNumBinaryOp {
         self.op_mask.set(op);
         self.left_ident_mask.set(left.column_index());
         match right {
-            DataSig::Value(v_right) => match v_right {
-                DataValue::Bool(b) => {
-                    if *b {
-                        self.right_value = AstRightValue::Number(1);
-                    } else {
-                        self.right_value = AstRightValue::Number(0);
-                    }
-                }
-                DataValue::Num(num) => {
-                    self.right_value = AstRightValue::Number(num.abs());
-                    self.right_minus_sign = num.is_strictly_negative();
-                    // -0 is NOT allowed!
-                    assert!(num.abs() != 0 || !self.right_minus_sign);
-                }
-                DataValue::Ascii(str) => {
-                    if self.right_minus_sign {
-                        panic!("compilation error, unexpected minus sign")
-                    }
-                    self.right_value = AstRightValue::Ascii(str.clone())
-                }
-            },
+            DataSig::Value(v_right) => self.set_right_value(v_right),
             DataSig::Ident(i_right) => {
                 self.right_ident_mask.set(i_right.column_index());
                 self.right_minus_sign = i_right.minus_sign();
@@ -93,6 +154,30 @@ impl AstNumBinaryOp {
         }
         Ok(())
     }
+
+    fn set_right_value(&mut self, v_right: &DataValue) {
+        match v_right {
+            DataValue::Bool(b) => {
+                if *b {
+                    self.right_value = AstRightValue::Number(1);
+                } else {
+                    self.right_value = AstRightValue::Number(0);
+                }
+            }
+            DataValue::Num(num) => {
+                self.right_value = AstRightValue::Number(num.abs());
+                self.right_minus_sign = num.is_strictly_negative();
+                // -0 is NOT allowed!
+                assert!(num.abs() != 0 || !self.right_minus_sign);
+            }
+            DataValue::Ascii(str) => {
+                if self.right_minus_sign {
+                    panic!("compilation error, unexpected minus sign")
+                }
+                self.right_value = AstRightValue::Ascii(str.clone())
+            }
+        }
+    }
 }
 
 impl std::fmt::Display for AstNumBinaryOp {
@@ -105,6 +190,58 @@ impl std::fmt::Display for AstNumBinaryOp {
     }
 }
 
+////////////////////////////////////////////////////////////////////////////////
+// QueryPadding
+////////////////////////////////////////////////////////////////////////////////
+
+/// Fixed shape of the WHERE clause AND/OR tree. Every query compiled with the
+/// same padding sends the same number of encrypted comparisons and the same
+/// tree depth to the server, whatever its WHERE clause (or lack of).
+///
+/// Only the tree is padded: the number of scalar subqueries, column arithmetics
+/// and IN sets, the shape of the IN sets, the arithmetic constants and the window
+/// function are still visible to the server
+/// (see [SqlLeakageReport::unpadded_items](crate::SqlLeakageReport::unpadded_items)).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryPadding {
+    tree_levels: u32,
+    num_compare_ops: usize,
+}
+
+impl QueryPadding {
+    /// Pads queries to `tree_levels` levels of AND/OR operators (`2^tree_levels` leaves)
+    /// and `num_compare_ops` encrypted comparisons. `num_compare_ops` must be
+    /// strictly lower than the number of leaves.
+    pub fn new(tree_levels: u32, num_compare_ops: usize) -> Result<Self, FheSqlError> {
+        if tree_levels == 0 || tree_levels > 16 {
+            return Err(FheSqlError::InvalidQueryError(format!(
+                "Invalid query padding tree levels {}, expecting [1, 16]",
+                tree_levels
+            )));
+        }
+        let num_leaves = 1_usize << tree_levels;
+        if num_compare_ops == 0 || num_compare_ops >= num_leaves {
+            return Err(FheSqlError::InvalidQueryError(format!(
+                "Invalid query padding compare ops {}, expecting [1, {}]",
+                num_compare_ops,
+                num_leaves - 1
+            )));
+        }
+        Ok(QueryPadding {
+            tree_levels,
+            num_compare_ops,
+        })
+    }
+
+    pub fn tree_levels(&self) -> u32 {
+        self.tree_levels
+    }
+
+    pub fn num_compare_ops(&self) -> usize {
+        self.num_compare_ops
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // AstTree
 ////////////////////////////////////////////////////////////////////////////////
@@ -113,10 +250,19 @@ pub struct AstTree {
     bool_ops_tree_levels: u32,
     bool_ops_tree: Vec<ClearBitOpMask>,
     num_ops: Vec<AstNumBinaryOp>,
+    aggregates: Vec<AstAggregate>,
+    arithmetics: Vec<AstArithmetic>,
+    sets: Vec<AstInSet>,
 }
 
 impl AstTree {
-    fn with_levels(levels: u8, num_cols: usize) -> Self {
+    fn with_levels(
+        levels: u8,
+        num_cols: usize,
+        aggregates: &[AstAggregate],
+        arithmetics: &[AstArithmetic],
+        sets: &[AstInSet],
+    ) -> Self {
         assert!(num_cols <= 1024);
         // 1 operator and 2 operands minimum = 2 levels (depth=1)
         assert!(levels >= 2);
@@ -128,7 +274,13 @@ impl AstTree {
         AstTree {
             bool_ops_tree_levels,
             bool_ops_tree: vec![ClearBitOpMask::new_noop(); bool_ops_count],
-            num_ops: vec![AstNumBinaryOp::new(num_cols); num_ops_count],
+            num_ops: vec![
+                AstNumBinaryOp::new(num_cols, aggregates.len(), arithmetics.len(), sets.len());
+                num_ops_count
+            ],
+            aggregates: aggregates.to_vec(),
+            arithmetics: arithmetics.to_vec(),
+            sets: sets.to_vec(),
         }
     }
 
@@ -140,6 +292,18 @@ impl AstTree {
         &self.num_ops
     }
 
+    pub fn aggregates(&self) -> &Vec<AstAggregate> {
+        &self.aggregates
+    }
+
+    pub fn arithmetics(&self) -> &Vec<AstArithmetic> {
+        &self.arithmetics
+    }
+
+    pub fn sets(&self) -> &Vec<AstInSet> {
+        &self.sets
+    }
+
     pub fn compute_positions(&mut self) {
         let n_ops = self.num_ops.len();
         let n_dummies = self
@@ -165,16 +329,38 @@ impl AstTree {
         self.test_positions(n_dummies);
     }
 
+    /// Padding ops are sent to the server but flagged as dummies
     pub fn dummy_not_dummy(&self) -> Vec<ClearEqNe> {
         self.num_ops
             .iter()
             .map(|x| EqNe {
-                eq: x.is_dummy,
-                ne: !x.is_dummy,
+                eq: x.is_dummy || x.is_padding,
+                ne: !(x.is_dummy || x.is_padding),
             })
             .collect()
     }
 
+    /// Turns dummy leaves into padding ops until the tree holds
+    /// exactly `num_compare_ops` non-dummy ops
+    fn pad(&mut self, num_compare_ops: usize) -> Result<(), FheSqlError> {
+        let n = self.num_ops.iter().filter(|op| !op.is_dummy).count();
+        if n > num_compare_ops {
+            return Err(FheSqlError::UnsupportedSqlQuery(format!(
+                "WHERE clause requires {} comparisons, query padding allows {}",
+                n, num_compare_ops
+            )));
+        }
+        self.num_ops
+            .iter_mut()
+            .filter(|op| op.is_dummy)
+            .take(num_compare_ops - n)
+            .for_each(|op| {
+                op.is_dummy = false;
+                op.is_padding = true;
+            });
+        Ok(())
+    }
+
     #[cfg(test)]
     fn test_positions(&self, n_dummies: usize) {
         let mut count_dummies = 0;
@@ -276,6 +462,42 @@ impl AstTree {
         leaf.set(op, left, right)
     }
 
+    fn fill_aggregate_leaf(
+        &mut self,
+        depth: u8,
+        pos: usize,
+        op: &BinaryOperator,
+        left: &DataIdent,
+        aggregate_index: usize,
+    ) -> Result<(), FheSqlError> {
+        let leaf = self.leaf_at_mut(depth, pos);
+        leaf.set_aggregate(op, left, aggregate_index)
+    }
+
+    fn fill_arithmetic_leaf(
+        &mut self,
+        depth: u8,
+        pos: usize,
+        op: &BinaryOperator,
+        arithmetic_index: usize,
+        right: &DataValue,
+    ) -> Result<(), FheSqlError> {
+        let leaf = self.leaf_at_mut(depth, pos);
+        leaf.set_arithmetic(op, arithmetic_index, right)
+    }
+
+    fn fill_set_leaf(
+        &mut self,
+        depth: u8,
+        pos: usize,
+        op: &BinaryOperator,
+        set_index: usize,
+        right: bool,
+    ) -> Result<(), FheSqlError> {
+        let leaf = self.leaf_at_mut(depth, pos);
+        leaf.set_in_set(op, set_index, right)
+    }
+
     fn fill_node(&mut self, depth: u8, pos: usize, op: &BinaryOperator) -> Result<(), FheSqlError> {
         let node = self.node_at_mut(depth, pos);
         node.set(op)
@@ -330,10 +552,19 @@ impl AstTreeResult {
 // compute_ast_tree
 ////////////////////////////////////////////////////////////////////////////////
 
+/// The last `aggregates.len() + arithmetics.len() + sets.len()` fields of `schema` are the
+/// scalar subqueries identifiers (see [`super::scalar_subquery::schema_with_scalar_subqueries`])
+/// followed by the column arithmetics identifiers
+/// (see [`super::column_arithmetic::schema_with_column_arithmetics`])
+/// and the IN sets identifiers (see [`super::in_set::schema_with_in_sets`])
 pub fn compute_ast_tree(
     expr: &Expr,
     schema: &Schema,
     max_num_fields: usize,
+    aggregates: &[AstAggregate],
+    arithmetics: &[AstArithmetic],
+    sets: &[AstInSet],
+    padding: Option<&QueryPadding>,
 ) -> Result<AstTreeResult, FheSqlError> {
     fn fill_ast_tree(
         tree: &mut AstTree,
@@ -341,7 +572,10 @@ pub fn compute_ast_tree(
         depth: u8,
         pos: u64,
         schema: &Schema,
+        num_fields: usize,
     ) -> Result<(), FheSqlError> {
+        let num_aggregates = tree.aggregates().len();
+        let num_arithmetics = tree.arithmetics().len();
         //f(expr, depth, pos);
         match expr {
             Expr::BinaryOp { left, op, right } => {
@@ -356,23 +590,111 @@ pub fn compute_ast_tree(
                 assert_eq!(left_is_leaf, right_is_leaf);
 
                 if left_is_leaf && right_is_leaf {
-                    tree.fill_leaf(
-                        depth,
-                        pos as usize,
-                        op,
-                        DataSig::try_from_expr(left, schema)?.get_ident(),
-                        &DataSig::try_from_expr(right, schema)?,
-                    )?;
+                    let left_sig = DataSig::try_from_expr(left, schema)?;
+                    let right_sig = DataSig::try_from_expr(right, schema)?;
+                    let left_ident = left_sig.get_ident();
+
+                    // IN sets: only compared against a boolean
+                    let set_index = |i: &DataIdent| {
+                        if i.column_index() >= num_fields + num_aggregates + num_arithmetics {
+                            Some(i.column_index() - num_fields - num_aggregates - num_arithmetics)
+                        } else {
+                            None
+                        }
+                    };
+                    if let Some(set_index) = set_index(left_ident) {
+                        match &right_sig {
+                            DataSig::Value(DataValue::Bool(b)) => {
+                                tree.fill_set_leaf(depth, pos as usize, op, set_index, *b)?;
+                                return Ok(());
+                            }
+                            _ => return Err(FheSqlError::unsupported_expr(expr)),
+                        }
+                    }
+                    if let DataSig::Ident(i) = &right_sig {
+                        if set_index(i).is_some() {
+                            return Err(FheSqlError::unsupported_expr(expr));
+                        }
+                    }
+
+                    // Column arithmetics: only compared against a value
+                    let arithmetic_index = |i: &DataIdent| {
+                        if i.column_index() >= num_fields + num_aggregates {
+                            Some(i.column_index() - num_fields - num_aggregates)
+                        } else {
+                            None
+                        }
+                    };
+                    if let Some(arithmetic_index) = arithmetic_index(left_ident) {
+                        match &right_sig {
+                            DataSig::Value(v) if !v.is_ascii() => {
+                                tree.fill_arithmetic_leaf(
+                                    depth,
+                                    pos as usize,
+                                    op,
+                                    arithmetic_index,
+                                    v,
+                                )?;
+                                return Ok(());
+                            }
+                            _ => return Err(FheSqlError::unsupported_expr(expr)),
+                        }
+                    }
+                    if let DataSig::Ident(i) = &right_sig {
+                        if arithmetic_index(i).is_some() {
+                            return Err(FheSqlError::unsupported_expr(expr));
+                        }
+                    }
+
+                    let right_aggregate = match &right_sig {
+                        DataSig::Ident(i) if i.column_index() >= num_fields => {
+                            Some(i.column_index() - num_fields)
+                        }
+                        _ => None,
+                    };
+
+                    // Scalar subqueries
+                    if left_ident.column_index() >= num_fields {
+                        // (SELECT ...) > Column := Column < (SELECT ...)
+                        match &right_sig {
+                            DataSig::Ident(i) if right_aggregate.is_none() && !i.minus_sign() => {
+                                tree.fill_aggregate_leaf(
+                                    depth,
+                                    pos as usize,
+                                    &reflexive_binary_op(op),
+                                    i,
+                                    left_ident.column_index() - num_fields,
+                                )?;
+                                return Ok(());
+                            }
+                            _ => return Err(FheSqlError::unsupported_expr(expr)),
+                        }
+                    }
+                    if let Some(aggregate_index) = right_aggregate {
+                        if right_sig.get_ident().minus_sign() {
+                            return Err(FheSqlError::unsupported_expr(expr));
+                        }
+                        tree.fill_aggregate_leaf(
+                            depth,
+                            pos as usize,
+                            op,
+                            left_ident,
+                            aggregate_index,
+                        )?;
+                        return Ok(());
+                    }
+
+                    tree.fill_leaf(depth, pos as usize, op, left_ident, &right_sig)?;
                     return Ok(());
                 }
 
                 tree.fill_node(depth, pos as usize, op)?;
 
-                match fill_ast_tree(tree, left.as_ref(), depth + 1, 2 * pos, schema) {
+                match fill_ast_tree(tree, left.as_ref(), depth + 1, 2 * pos, schema, num_fields) {
                     Ok(_) => (),
                     Err(err) => return Err(err),
                 };
-                match fill_ast_tree(tree, right.as_ref(), depth + 1, 2 * pos + 1, schema) {
+                match fill_ast_tree(tree, right.as_ref(), depth + 1, 2 * pos + 1, schema, num_fields) {
                     Ok(_) => (),
                     Err(err) => return Err(err),
                 }
@@ -390,22 +712,87 @@ pub fn compute_ast_tree(
     if tree_info.levels > u8::MAX as u32 {
         return Err(FheSqlError::unsupported_expr(expr));
     }
+    let padded_levels = padding.map(|p| p.tree_levels() + AstTree::num_ops_tree_levels());
     if tree_info.levels == 1 {
         if expr.is_false_value() {
             return Ok(AstTreeResult::Boolean(false));
         }
         if expr.is_true_value() {
+            // Same shape as any other padded query
+            if padding.is_some() {
+                return compute_constant_ast_tree(
+                    true,
+                    max_num_fields,
+                    aggregates,
+                    arithmetics,
+                    sets,
+                    padding,
+                );
+            }
             return Ok(AstTreeResult::Boolean(true));
         }
         return Err(FheSqlError::unsupported_expr(expr));
     }
 
     assert!(tree_info.levels > 1);
+    let levels = match padded_levels {
+        Some(l) if tree_info.levels > l => {
+            return Err(FheSqlError::UnsupportedSqlQuery(format!(
+                "WHERE clause requires {} tree levels, query padding allows {}",
+                tree_info.levels - AstTree::num_ops_tree_levels(),
+                l - AstTree::num_ops_tree_levels()
+            )))
+        }
+        Some(l) => l,
+        None => tree_info.levels,
+    };
+    assert!(schema.fields().len() >= aggregates.len() + arithmetics.len() + sets.len());
+    let num_fields = schema.fields().len() - aggregates.len() - arithmetics.len() - sets.len();
     let mut tree = AstTree::with_levels(
-        tree_info.levels as u8,
+        levels as u8,
         max_num_fields,
+        aggregates,
+        arithmetics,
+        sets,
     );
-    fill_ast_tree(&mut tree, expr, 0, 0, schema)?;
+    fill_ast_tree(&mut tree, expr, 0, 0, schema, num_fields)?;
+    if let Some(p) = padding {
+        tree.pad(p.num_compare_ops())?;
+    }
+    tree.compute_positions();
+
+    Ok(AstTreeResult::Tree(tree))
+}
+
+/// Builds a tree made of a single always `value` comparison (plus padding if any),
+/// so that a constant WHERE clause costs the server as much as a real one.
+pub fn compute_constant_ast_tree(
+    value: bool,
+    max_num_fields: usize,
+    aggregates: &[AstAggregate],
+    arithmetics: &[AstArithmetic],
+    sets: &[AstInSet],
+    padding: Option<&QueryPadding>,
+) -> Result<AstTreeResult, FheSqlError> {
+    let levels = match padding {
+        Some(p) => p.tree_levels() + AstTree::num_ops_tree_levels(),
+        None => AstTree::num_ops_tree_levels(),
+    };
+    let mut tree = AstTree::with_levels(
+        levels as u8,
+        max_num_fields,
+        aggregates,
+        arithmetics,
+        sets,
+    );
+    if value {
+        tree.num_ops[0].set_tautology();
+    } else {
+        tree.num_ops[0].set_contradiction();
+    }
+    if let Some(p) = padding {
+        tree.pad(p.num_compare_ops())?;
+    }
     tree.compute_positions();
 
     Ok(AstTreeResult::Tree(tree))
@@ -422,7 +809,7 @@ mod test {
 
     use crate::sql_ast::and_or_ast::{compute_ast_tree, AstTreeResult};
     use crate::sql_ast::to_parenthesized_string::ToParenthesizedString;
-    use crate::sql_ast::{and_or_ast::AstTree, CompileWhereStatement};
+    use crate::sql_ast::{and_or_ast::AstTree, coercion::CoercionMode, CompileWhereStatement};
 
     fn get_schema() -> Schema {
         Schema::new(vec![
@@ -522,12 +909,12 @@ mod test {
                 let compiled_where_expr = statements
                     .first()
                     .unwrap()
-                    .compile_where(&schema)
+                    .compile_where(&schema, CoercionMode::default())
                     .unwrap()
                     .unwrap();
                 assert_eq!(compiled_where_expr.to_parenthesized_string(), *e);
 
-                match compute_ast_tree(&compiled_where_expr, &schema, schema.fields().len())
+                match compute_ast_tree(&compiled_where_expr, &schema, schema.fields().len(), &[], &[], &[], None)
                     .unwrap()
                 {
                     AstTreeResult::Boolean(b) => println!("{}", b),
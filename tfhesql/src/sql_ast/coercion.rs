@@ -0,0 +1,446 @@
+use arrow_schema::Schema;
+use sqlparser::ast::{BinaryOperator, Expr, Ident, UnaryOperator, Value};
+
+use crate::{error::FheSqlError, uint::signed_u64::SignedU64};
+
+use super::{data_type::DataType, data_value::DataValue, SqlExprDataType};
+
+////////////////////////////////////////////////////////////////////////////////
+// CoercionMode
+////////////////////////////////////////////////////////////////////////////////
+
+/// SQL type-coercion semantics used by the client to compile the WHERE clause
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum CoercionMode {
+    /// MySQL semantics: operands are casted to numbers when types differ
+    /// (0 if a string cannot be parsed, 0/1 for booleans) and any
+    /// expression can be used as a condition.
+    #[default]
+    MySql,
+    /// Only operands of the same type (integer, string or boolean) can be
+    /// compared and conditions must be boolean expressions.
+    Strict,
+    /// Like [CoercionMode::Strict], but a string literal compared to an
+    /// integer or a boolean operand is casted to the operand type, the same
+    /// way PostgreSQL types an unknown literal. A failed cast is an error.
+    PostgreSql,
+}
+
+impl CoercionMode {
+    #[inline]
+    pub fn is_strict(&self) -> bool {
+        !matches!(self, CoercionMode::MySql)
+    }
+}
+
+impl std::fmt::Display for CoercionMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CoercionMode::MySql => f.write_str("MySQL"),
+            CoercionMode::Strict => f.write_str("strict"),
+            CoercionMode::PostgreSql => f.write_str("PostgreSQL"),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Checks the operand types of the WHERE clause and folds the literal casts
+/// (`CAST('12' AS INT)`, `'12'::INT` or `INT '12'`) according to `mode`.
+pub(super) fn coerce_where_expr_in_place(
+    where_expr: &mut Expr,
+    schema: &Schema,
+    mode: CoercionMode,
+) -> Result<(), FheSqlError> {
+    let data_type = recursive_coerce(where_expr, schema, mode)?;
+    if mode.is_strict() && !data_type.is_bool() {
+        return Err(not_a_condition(where_expr, mode));
+    }
+    Ok(())
+}
+
+/// Checks that the literal `value` of an IN set can be compared with `column`
+/// according to `mode` and returns it, casted to the column type in PostgreSQL
+/// mode. Any other expression is returned unchanged.
+pub(super) fn coerce_set_value(
+    column: &Ident,
+    column_type: DataType,
+    value: &Expr,
+    mode: CoercionMode,
+) -> Result<Expr, FheSqlError> {
+    let mut value = value.clone();
+    let value_type = match try_get_literal(&value) {
+        Ok(Some(v)) => v.data_type(),
+        _ => return Ok(value),
+    };
+    let mut column = Expr::Identifier(column.clone());
+    coerce_comparison(&mut column, column_type, &mut value, value_type, mode)?;
+    Ok(value)
+}
+
+/// A boolean column used as an integer operand (aggregate or arithmetic)
+pub(super) fn not_an_integer_column(
+    column: &Ident,
+    the_expr: &dyn std::fmt::Display,
+    mode: CoercionMode,
+) -> FheSqlError {
+    FheSqlError::SyntaxError(format!(
+        "Column '{}' is not an integer in '{}' ({} type coercion)",
+        column, the_expr, mode
+    ))
+}
+
+fn recursive_coerce(
+    the_expr: &mut Expr,
+    schema: &Schema,
+    mode: CoercionMode,
+) -> Result<DataType, FheSqlError> {
+    match the_expr {
+        Expr::Cast {
+            expr,
+            data_type,
+            format: None,
+        } => {
+            recursive_coerce(expr, schema, mode)?;
+            let value = match try_get_literal(expr)? {
+                Some(value) => cast_literal(&value, data_type, mode)?,
+                None => return Err(FheSqlError::unsupported_expr(the_expr)),
+            };
+            let value_type = value.data_type();
+            *the_expr = Expr::Value(Value::from(&value));
+            Ok(value_type)
+        }
+        Expr::TypedString { data_type, value } => {
+            let value = cast_literal(&DataValue::Ascii(value.clone()), data_type, mode)?;
+            let value_type = value.data_type();
+            *the_expr = Expr::Value(Value::from(&value));
+            Ok(value_type)
+        }
+        Expr::Nested(expr) => recursive_coerce(expr, schema, mode),
+        Expr::UnaryOp { op, expr } => {
+            let expr_type = recursive_coerce(expr, schema, mode)?;
+            if mode.is_strict() {
+                match op {
+                    UnaryOperator::Not if !expr_type.is_bool() => {
+                        return Err(not_a_condition(expr, mode))
+                    }
+                    UnaryOperator::Plus | UnaryOperator::Minus if !expr_type.is_integer() => {
+                        return Err(FheSqlError::SyntaxError(format!(
+                            "Operator '{}' expects an integer operand, got '{}' ({} type coercion)",
+                            op, expr, mode
+                        )))
+                    }
+                    _ => (),
+                }
+            }
+            the_expr.data_type(schema)
+        }
+        Expr::BinaryOp { left, op, right } => match op {
+            BinaryOperator::And | BinaryOperator::Or | BinaryOperator::Xor => {
+                let left_type = recursive_coerce(left, schema, mode)?;
+                let right_type = recursive_coerce(right, schema, mode)?;
+                if mode.is_strict() {
+                    if !left_type.is_bool() {
+                        return Err(not_a_condition(left, mode));
+                    }
+                    if !right_type.is_bool() {
+                        return Err(not_a_condition(right, mode));
+                    }
+                }
+                Ok(DataType::Boolean)
+            }
+            BinaryOperator::Gt
+            | BinaryOperator::Lt
+            | BinaryOperator::GtEq
+            | BinaryOperator::LtEq
+            | BinaryOperator::Eq
+            | BinaryOperator::NotEq => {
+                let left_type = recursive_coerce(left, schema, mode)?;
+                let right_type = recursive_coerce(right, schema, mode)?;
+                coerce_comparison(left, left_type, right, right_type, mode)?;
+                Ok(DataType::Boolean)
+            }
+            // Column arithmetics by a constant, folded by the rewriter
+            BinaryOperator::PGBitwiseShiftLeft
+            | BinaryOperator::PGBitwiseShiftRight
+            | BinaryOperator::Plus
+            | BinaryOperator::Minus
+            | BinaryOperator::Multiply
+            | BinaryOperator::Divide => {
+                let left_type = recursive_coerce(left, schema, mode)?;
+                let right_type = recursive_coerce(right, schema, mode)?;
+                if mode.is_strict() && !(left_type.is_integer() && right_type.is_integer()) {
+                    return Err(FheSqlError::SyntaxError(format!(
+                        "Operator '{}' expects integer operands ({} type coercion)",
+                        op, mode
+                    )));
+                }
+                Ok(DataType::AnyInt)
+            }
+            _ => the_expr.data_type(schema),
+        },
+        Expr::InList { expr, list, .. } => {
+            let expr_type = recursive_coerce(expr, schema, mode)?;
+            for item in list.iter_mut() {
+                let item_type = recursive_coerce(item, schema, mode)?;
+                coerce_comparison(expr, expr_type, item, item_type, mode)?;
+            }
+            Ok(DataType::Boolean)
+        }
+        Expr::Between {
+            expr, low, high, ..
+        } => {
+            let expr_type = recursive_coerce(expr, schema, mode)?;
+            let low_type = recursive_coerce(low, schema, mode)?;
+            let high_type = recursive_coerce(high, schema, mode)?;
+            coerce_comparison(expr, expr_type, low, low_type, mode)?;
+            coerce_comparison(expr, expr_type, high, high_type, mode)?;
+            Ok(DataType::Boolean)
+        }
+        _ => the_expr.data_type(schema),
+    }
+}
+
+/// Integers, strings and booleans can only be compared to operands of the
+/// same family. PostgreSQL mode casts a string literal to the other operand type.
+fn coerce_comparison(
+    left: &mut Expr,
+    left_type: DataType,
+    right: &mut Expr,
+    right_type: DataType,
+    mode: CoercionMode,
+) -> Result<(), FheSqlError> {
+    if !mode.is_strict() || is_same_type_family(&left_type, &right_type) {
+        return Ok(());
+    }
+    if mode == CoercionMode::PostgreSql
+        && (try_cast_string_literal(right, &left_type)? || try_cast_string_literal(left, &right_type)?)
+    {
+        return Ok(());
+    }
+    Err(FheSqlError::SyntaxError(format!(
+        "Cannot compare '{}' ({}) with '{}' ({}) using {} type coercion",
+        left,
+        type_family_name(&left_type),
+        right,
+        type_family_name(&right_type),
+        mode
+    )))
+}
+
+/// Replaces a string literal by its `to_type` value, returns `false` if `the_expr` is
+/// not a string literal or `to_type` is not an integer or a boolean type.
+fn try_cast_string_literal(the_expr: &mut Expr, to_type: &DataType) -> Result<bool, FheSqlError> {
+    let s = match the_expr {
+        Expr::Value(Value::SingleQuotedString(s)) | Expr::Value(Value::DoubleQuotedString(s)) => s,
+        _ => return Ok(false),
+    };
+    let value = if to_type.is_integer() {
+        DataValue::Num(parse_integer_literal(s)?)
+    } else if to_type.is_bool() {
+        DataValue::Bool(parse_bool_literal(s)?)
+    } else {
+        return Ok(false);
+    };
+    *the_expr = Expr::Value(Value::from(&value));
+    Ok(true)
+}
+
+/// Returns the literal value of `the_expr` (a value or a signed number)
+fn try_get_literal(the_expr: &Expr) -> Result<Option<DataValue>, FheSqlError> {
+    match the_expr {
+        Expr::Value(value) => Ok(Some(DataValue::try_from(value)?)),
+        Expr::UnaryOp { op, expr } => match (op, expr.as_ref()) {
+            (UnaryOperator::Minus, Expr::Value(value @ Value::Number(..))) => {
+                Ok(Some(DataValue::try_from(value)?.minus()))
+            }
+            (UnaryOperator::Plus, Expr::Value(value @ Value::Number(..))) => {
+                Ok(Some(DataValue::try_from(value)?))
+            }
+            _ => Ok(None),
+        },
+        _ => Ok(None),
+    }
+}
+
+/// CAST(value AS to_type)
+fn cast_literal(
+    value: &DataValue,
+    to_type: &sqlparser::ast::DataType,
+    mode: CoercionMode,
+) -> Result<DataValue, FheSqlError> {
+    use sqlparser::ast::DataType as SqlDataType;
+    match to_type {
+        SqlDataType::TinyInt(_)
+        | SqlDataType::UnsignedTinyInt(_)
+        | SqlDataType::SmallInt(_)
+        | SqlDataType::UnsignedSmallInt(_)
+        | SqlDataType::MediumInt(_)
+        | SqlDataType::UnsignedMediumInt(_)
+        | SqlDataType::Int(_)
+        | SqlDataType::UnsignedInt(_)
+        | SqlDataType::Integer(_)
+        | SqlDataType::UnsignedInteger(_)
+        | SqlDataType::BigInt(_)
+        | SqlDataType::UnsignedBigInt(_)
+        | SqlDataType::Int2(_)
+        | SqlDataType::UnsignedInt2(_)
+        | SqlDataType::Int4(_)
+        | SqlDataType::UnsignedInt4(_)
+        | SqlDataType::Int8(_)
+        | SqlDataType::UnsignedInt8(_)
+        | SqlDataType::Int64 => match value {
+            DataValue::Ascii(s) if mode.is_strict() => Ok(DataValue::Num(parse_integer_literal(s)?)),
+            _ => Ok(value.cast_to_num()),
+        },
+        SqlDataType::Bool | SqlDataType::Boolean => match value {
+            DataValue::Ascii(s) if mode.is_strict() => Ok(DataValue::Bool(parse_bool_literal(s)?)),
+            _ => Ok(value.cast_to_bool()),
+        },
+        SqlDataType::Text
+        | SqlDataType::String(_)
+        | SqlDataType::Varchar(_)
+        | SqlDataType::CharVarying(_)
+        | SqlDataType::CharacterVarying(_)
+        | SqlDataType::Char(_)
+        | SqlDataType::Character(_) => match value {
+            DataValue::Bool(b) if mode.is_strict() => Ok(DataValue::Ascii(b.to_string())),
+            DataValue::Bool(b) => Ok(DataValue::Ascii((*b as u8).to_string())),
+            _ => Ok(DataValue::Ascii(value.to_string())),
+        },
+        _ => Err(FheSqlError::UnsupportedSqlQuery(format!(
+            "Unsupported cast type '{}'",
+            to_type
+        ))),
+    }
+}
+
+/// '[+|-]digits', surrounding whitespaces are ignored
+fn parse_integer_literal(s: &str) -> Result<SignedU64, FheSqlError> {
+    let t = s.trim();
+    let digits = t.strip_prefix(['+', '-']).unwrap_or(t);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(FheSqlError::parse_int_error(s));
+    }
+    SignedU64::try_from(t)
+}
+
+/// PostgreSQL boolean literals, case insensitive, surrounding whitespaces are ignored
+fn parse_bool_literal(s: &str) -> Result<bool, FheSqlError> {
+    match s.trim().to_ascii_lowercase().as_str() {
+        "t" | "true" | "y" | "yes" | "on" | "1" => Ok(true),
+        "f" | "false" | "n" | "no" | "off" | "0" => Ok(false),
+        _ => Err(FheSqlError::SyntaxError(format!(
+            "Unable to parse boolean argument '{}'",
+            s
+        ))),
+    }
+}
+
+#[inline]
+fn is_same_type_family(a: &DataType, b: &DataType) -> bool {
+    (a.is_integer() && b.is_integer())
+        || (a.is_ascii() && b.is_ascii())
+        || (a.is_bool() && b.is_bool())
+}
+
+#[inline]
+fn type_family_name(data_type: &DataType) -> &'static str {
+    if data_type.is_bool() {
+        "boolean"
+    } else if data_type.is_ascii() {
+        "string"
+    } else {
+        "integer"
+    }
+}
+
+fn not_a_condition(the_expr: &Expr, mode: CoercionMode) -> FheSqlError {
+    FheSqlError::SyntaxError(format!(
+        "Expression '{}' is not a boolean condition ({} type coercion)",
+        the_expr, mode
+    ))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sql_ast::to_parenthesized_string::ToParenthesizedString;
+    use crate::sql_ast::CompileWhereStatement;
+    use arrow_schema::Field;
+    use sqlparser::dialect::GenericDialect;
+    use sqlparser::parser::Parser;
+
+    fn get_schema() -> Schema {
+        Schema::new(vec![
+            Field::new("a_s", arrow_schema::DataType::Utf8, false),
+            Field::new("a_b", arrow_schema::DataType::Boolean, false),
+            Field::new("a_i", arrow_schema::DataType::Int32, false),
+            Field::new("a_u", arrow_schema::DataType::UInt8, false),
+        ])
+    }
+
+    fn compile(w: &str, mode: CoercionMode) -> Result<String, FheSqlError> {
+        let sql = format!("SELECT * FROM t WHERE {}", w);
+        let statements = Parser::parse_sql(&GenericDialect {}, &sql).unwrap();
+        let where_expr = statements[0]
+            .compile_where(&get_schema(), mode)?
+            .unwrap();
+        Ok(where_expr.to_parenthesized_string())
+    }
+
+    #[test]
+    fn test_mysql() {
+        let mode = CoercionMode::MySql;
+        assert_eq!(compile("a_i = '12'", mode).unwrap(), "(a_i = 12)");
+        assert_eq!(compile("a_i = 'abc'", mode).unwrap(), "(a_i = 0)");
+        assert_eq!(compile("a_i", mode).unwrap(), "(a_i <> 0)");
+        assert_eq!(compile("a_i = CAST('x' AS INT)", mode).unwrap(), "(a_i = 0)");
+        assert_eq!(compile("a_s = CAST(true AS TEXT)", mode).unwrap(), "(a_s = '1')");
+    }
+
+    #[test]
+    fn test_strict() {
+        let mode = CoercionMode::Strict;
+        assert_eq!(compile("a_i = 12 AND a_s = 'x'", mode).unwrap(), "((a_i = 12) AND (a_s = 'x'))");
+        assert_eq!(compile("a_b AND NOT a_b = false", mode).unwrap(), "(a_b = true)");
+        assert_eq!(compile("a_i = CAST('12' AS INT)", mode).unwrap(), "(a_i = 12)");
+        assert_eq!(compile("a_i > -CAST(' -3 ' AS INTEGER)", mode).unwrap(), "(a_i > 3)");
+        assert_eq!(compile("a_s = CAST(true AS VARCHAR)", mode).unwrap(), "(a_s = 'true')");
+        assert!(compile("a_i = '12'", mode).is_err());
+        assert!(compile("a_s = 12", mode).is_err());
+        assert!(compile("a_b = 1", mode).is_err());
+        assert!(compile("a_i IN (1, 'b')", mode).is_err());
+        assert!(compile("a_i BETWEEN '1' AND 3", mode).is_err());
+        assert!(compile("a_i", mode).is_err());
+        assert!(compile("a_b AND a_u", mode).is_err());
+        assert!(compile("NOT a_s", mode).is_err());
+        assert!(compile("-a_s > 1", mode).is_err());
+        assert!(compile("a_i = CAST('1.5' AS INT)", mode).is_err());
+        assert!(compile("a_i = CAST(a_u AS INT)", mode).is_err());
+    }
+
+    #[test]
+    fn test_postgresql() {
+        let mode = CoercionMode::PostgreSql;
+        assert_eq!(compile("a_i = '12'", mode).unwrap(), "(a_i = 12)");
+        assert_eq!(compile("'-7' < a_i", mode).unwrap(), "(-7 < a_i)");
+        assert_eq!(compile("a_b = 'yes'", mode).unwrap(), "(a_b = true)");
+        assert_eq!(compile("a_u = '12'::INT", mode).unwrap(), "(a_u = 12)");
+        assert_eq!(compile("a_u = INT '12'", mode).unwrap(), "(a_u = 12)");
+        assert_eq!(
+            compile("a_i IN ('1', '2')", mode).unwrap(),
+            "((a_i = 1) OR (a_i = 2))"
+        );
+        assert!(compile("a_i = 'abc'", mode).is_err());
+        assert!(compile("a_b = 'maybe'", mode).is_err());
+        assert!(compile("a_s = 12", mode).is_err());
+        assert!(compile("a_b = 1", mode).is_err());
+        assert!(compile("a_i", mode).is_err());
+    }
+
+}
@@ -0,0 +1,447 @@
+use std::ops::ControlFlow;
+
+use arrow_schema::{Field, Schema};
+use sqlparser::ast::{
+    BinaryOperator, Expr, Ident, SetExpr, Statement, UnaryOperator, Value, VisitMut, VisitorMut,
+};
+
+use crate::error::FheSqlError;
+use crate::uint::mask::ClearBoolMask;
+use crate::OrderedSchemas;
+
+use super::coercion::{not_an_integer_column, CoercionMode};
+use super::column_ident::ColumnIdent;
+use super::parser::get_statement_from;
+
+////////////////////////////////////////////////////////////////////////////////
+// ArithmeticOp
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithmeticOp {
+    Plus,
+    Minus,
+    Multiply,
+    Divide,
+    Modulo,
+    BitwiseAnd,
+    BitwiseOr,
+    BitwiseXor,
+}
+
+impl ArithmeticOp {
+    pub const LEN: usize = 8;
+    pub const ALL: [ArithmeticOp; Self::LEN] = [
+        ArithmeticOp::Plus,
+        ArithmeticOp::Minus,
+        ArithmeticOp::Multiply,
+        ArithmeticOp::Divide,
+        ArithmeticOp::Modulo,
+        ArithmeticOp::BitwiseAnd,
+        ArithmeticOp::BitwiseOr,
+        ArithmeticOp::BitwiseXor,
+    ];
+
+    #[inline]
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    /// `column op constant` cannot be folded into the compared value,
+    /// the constant is sent in clear to the server
+    #[inline]
+    pub fn needs_clear_constant(&self) -> bool {
+        matches!(
+            self,
+            ArithmeticOp::Modulo
+                | ArithmeticOp::BitwiseAnd
+                | ArithmeticOp::BitwiseOr
+                | ArithmeticOp::BitwiseXor
+        )
+    }
+
+    fn try_from_binary_op(op: &BinaryOperator) -> Option<Self> {
+        match op {
+            BinaryOperator::Plus => Some(ArithmeticOp::Plus),
+            BinaryOperator::Minus => Some(ArithmeticOp::Minus),
+            BinaryOperator::Multiply => Some(ArithmeticOp::Multiply),
+            BinaryOperator::Divide => Some(ArithmeticOp::Divide),
+            BinaryOperator::Modulo => Some(ArithmeticOp::Modulo),
+            BinaryOperator::BitwiseAnd => Some(ArithmeticOp::BitwiseAnd),
+            BinaryOperator::BitwiseOr => Some(ArithmeticOp::BitwiseOr),
+            BinaryOperator::BitwiseXor | BinaryOperator::PGBitwiseXor => {
+                Some(ArithmeticOp::BitwiseXor)
+            }
+            _ => None,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// AstArithmetic
+////////////////////////////////////////////////////////////////////////////////
+
+/// Clear masks describing `column op column` or `column op constant`
+#[derive(Clone, Debug)]
+pub struct AstArithmetic {
+    pub left_ident_mask: ClearBoolMask,
+    /// `max_num_fields` columns followed by the query arithmetic constants
+    pub right_ident_mask: ClearBoolMask,
+    pub op_mask: ClearBoolMask,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// ColumnArithmetic
+////////////////////////////////////////////////////////////////////////////////
+
+/// A `column op column` or `column op constant` expression found in a WHERE clause,
+/// `op` is `%`, `&`, `|` or `^` in the latter case.
+/// In the WHERE clause, the expression is replaced by an identifier named
+/// after the expression text.
+#[derive(Clone, Debug)]
+pub struct ColumnArithmetic {
+    name: String,
+    arithmetic: AstArithmetic,
+}
+
+impl ColumnArithmetic {
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    pub fn arithmetic(&self) -> &AstArithmetic {
+        &self.arithmetic
+    }
+
+    fn try_from_expr(
+        expr: &Expr,
+        ordered_schemas: &OrderedSchemas,
+        table_index: usize,
+        constants: &mut Vec<i64>,
+        mode: CoercionMode,
+    ) -> Result<Option<Self>, FheSqlError> {
+        let (left, op, right) = match expr {
+            Expr::BinaryOp { left, op, right } => match left.as_ref() {
+                Expr::Identifier(l) => match ArithmeticOp::try_from_binary_op(op) {
+                    Some(op) => (l, op, right.as_ref()),
+                    None => return Ok(None),
+                },
+                _ => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+
+        let schema = ordered_schemas.schema(table_index);
+        let column_index = |ident: &Ident| {
+            let column = ColumnIdent::try_from_ident(ident, schema)?;
+            if column.data_type().is_ascii() {
+                return Err(FheSqlError::UnsupportedSqlQuery(format!(
+                    "Arithmetic over a string column in '{}'",
+                    expr
+                )));
+            }
+            if mode.is_strict() && column.data_type().is_bool() {
+                return Err(not_an_integer_column(ident, expr, mode));
+            }
+            Ok(column.index() as usize)
+        };
+
+        let max_num_fields = ordered_schemas.max_num_fields();
+        let right_index = match right {
+            Expr::Identifier(r) => column_index(r)?,
+            _ => match try_get_constant(right)? {
+                Some(_) if !op.needs_clear_constant() => {
+                    // Folded into the encrypted compared value by the rewriter
+                    column_index(left)?;
+                    return Ok(None);
+                }
+                Some(c) => {
+                    let c_index = match constants.iter().position(|x| *x == c) {
+                        Some(i) => i,
+                        None => {
+                            constants.push(c);
+                            constants.len() - 1
+                        }
+                    };
+                    max_num_fields + c_index
+                }
+                None => return Ok(None),
+            },
+        };
+
+        let mut left_ident_mask = ClearBoolMask::none(max_num_fields);
+        left_ident_mask.set(column_index(left)?);
+        // Resized once all the constants are known
+        let mut right_ident_mask = ClearBoolMask::none(right_index + 1);
+        right_ident_mask.set(right_index);
+        let mut op_mask = ClearBoolMask::none(ArithmeticOp::LEN);
+        op_mask.set(op.index());
+
+        Ok(Some(ColumnArithmetic {
+            name: expr.to_string(),
+            arithmetic: AstArithmetic {
+                left_ident_mask,
+                right_ident_mask,
+                op_mask,
+            },
+        }))
+    }
+}
+
+/// `123` or `-123`
+fn try_get_constant(expr: &Expr) -> Result<Option<i64>, FheSqlError> {
+    let (num, negative) = match expr {
+        Expr::Value(Value::Number(num, _)) => (num, false),
+        Expr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr,
+        } => match expr.as_ref() {
+            Expr::Value(Value::Number(num, _)) => (num, true),
+            _ => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    let num = if negative {
+        format!("-{}", num)
+    } else {
+        num.clone()
+    };
+    match num.parse::<i64>() {
+        Ok(c) => Ok(Some(c)),
+        Err(_) => Err(FheSqlError::parse_int_error(&num)),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// ColumnArithmetics
+////////////////////////////////////////////////////////////////////////////////
+
+/// The column arithmetics of a WHERE clause and the constants they use.
+/// The constants are sent in clear to the server.
+#[derive(Clone, Debug, Default)]
+pub struct ColumnArithmetics {
+    arithmetics: Vec<ColumnArithmetic>,
+    constants: Vec<i64>,
+}
+
+impl ColumnArithmetics {
+    #[inline]
+    pub fn arithmetics(&self) -> &[ColumnArithmetic] {
+        &self.arithmetics
+    }
+
+    #[inline]
+    pub fn constants(&self) -> &[i64] {
+        &self.constants
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// ExtractColumnArithmetics
+////////////////////////////////////////////////////////////////////////////////
+
+pub trait ExtractColumnArithmetics {
+    /// Replaces each `column op column` expression of the WHERE clause by an
+    /// identifier and returns the list of extracted expressions.
+    fn extract_column_arithmetics(
+        &mut self,
+        ordered_schemas: &OrderedSchemas,
+        mode: CoercionMode,
+    ) -> Result<ColumnArithmetics, FheSqlError>;
+}
+
+impl ExtractColumnArithmetics for Statement {
+    fn extract_column_arithmetics(
+        &mut self,
+        ordered_schemas: &OrderedSchemas,
+        mode: CoercionMode,
+    ) -> Result<ColumnArithmetics, FheSqlError> {
+        let from = get_statement_from(self)?;
+        let table_index = match ordered_schemas
+            .compute_table_mask::<bool>(&from.0)
+            .index_of_first_set()
+        {
+            Some(idx) => idx,
+            None => return Err(FheSqlError::syntax_error("No table selected")),
+        };
+
+        let selection = match self {
+            Statement::Query(query) => match query.body.as_mut() {
+                SetExpr::Select(s) => &mut s.selection,
+                _ => return Ok(ColumnArithmetics::default()),
+            },
+            _ => return Ok(ColumnArithmetics::default()),
+        };
+        let where_expr = match selection {
+            Some(w) => w,
+            None => return Ok(ColumnArithmetics::default()),
+        };
+
+        struct V<'a> {
+            ordered_schemas: &'a OrderedSchemas,
+            table_index: usize,
+            mode: CoercionMode,
+            arithmetics: Vec<ColumnArithmetic>,
+            constants: Vec<i64>,
+        }
+
+        impl<'a> VisitorMut for V<'a> {
+            type Break = FheSqlError;
+
+            fn pre_visit_expr(&mut self, expr: &mut Expr) -> ControlFlow<Self::Break> {
+                let arithmetic = match ColumnArithmetic::try_from_expr(
+                    expr,
+                    self.ordered_schemas,
+                    self.table_index,
+                    &mut self.constants,
+                    self.mode,
+                ) {
+                    Ok(Some(a)) => a,
+                    Ok(None) => return ControlFlow::Continue(()),
+                    Err(err) => return ControlFlow::Break(err),
+                };
+                *expr = Expr::Identifier(Ident::new(arithmetic.name()));
+                // The same expression may appear multiple times
+                if !self.arithmetics.iter().any(|a| a.name() == arithmetic.name()) {
+                    self.arithmetics.push(arithmetic);
+                }
+                ControlFlow::Continue(())
+            }
+        }
+
+        let mut v = V {
+            ordered_schemas,
+            table_index,
+            mode,
+            arithmetics: vec![],
+            constants: vec![],
+        };
+        if let ControlFlow::Break(err) = where_expr.visit(&mut v) {
+            return Err(err);
+        }
+
+        let right_len = ordered_schemas.max_num_fields() + v.constants.len();
+        v.arithmetics.iter_mut().for_each(|a| {
+            a.arithmetic.right_ident_mask.mask.resize(right_len, false);
+        });
+        Ok(ColumnArithmetics {
+            arithmetics: v.arithmetics,
+            constants: v.constants,
+        })
+    }
+}
+
+/// Appends one signed integer column per expression to `schema`.
+/// The extra columns are used to type-check the expressions identifiers.
+pub fn schema_with_column_arithmetics(schema: &Schema, arithmetics: &[ColumnArithmetic]) -> Schema {
+    if arithmetics.is_empty() {
+        return schema.clone();
+    }
+    let mut fields: Vec<Field> = schema.fields().iter().map(|f| f.as_ref().clone()).collect();
+    arithmetics.iter().for_each(|a| {
+        fields.push(Field::new(a.name(), arrow_schema::DataType::Int64, false));
+    });
+    Schema::new(fields)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sql_ast::to_parenthesized_string::ToParenthesizedString;
+    use crate::sql_ast::{coercion::CoercionMode, CompileWhereStatement};
+    use crate::test::simple_batch::RecordBatchBuilder;
+    use crate::{OrderedTables, Table};
+    use arrow_array::types::{Int32Type, UInt8Type};
+    use sqlparser::{dialect::GenericDialect, parser::Parser};
+
+    fn ordered_schemas() -> OrderedSchemas {
+        let mut rb = RecordBatchBuilder::new();
+        rb.push_with_name::<Int32Type>("Price", vec![1, 2]);
+        rb.push_with_name::<UInt8Type>("Quantity", vec![3, 4]);
+        rb.push_str_with_name("Name", vec!["a", "b"]);
+        let t = Table::new("Products", rb.finish());
+        OrderedTables::new(vec![t])
+            .unwrap()
+            .ordered_schemas()
+            .clone()
+    }
+
+    #[test]
+    fn test_extract() {
+        let ordered_schemas = ordered_schemas();
+        let dialect = GenericDialect {};
+        let sql = "SELECT * FROM Products WHERE Price * Quantity > 1000 OR (Price - Quantity) < 3 AND 10 < Price * Quantity";
+        let mut statements = Parser::parse_sql(&dialect, sql).unwrap();
+        let column_arithmetics = statements[0]
+            .extract_column_arithmetics(&ordered_schemas, CoercionMode::default())
+            .unwrap();
+        let arithmetics = column_arithmetics.arithmetics();
+        assert_eq!(arithmetics.len(), 2);
+        assert!(column_arithmetics.constants().is_empty());
+        assert_eq!(arithmetics[0].name(), "Price * Quantity");
+        let a = arithmetics[1].arithmetic();
+        assert_eq!(a.left_ident_mask.index_of_first_set(), Some(0));
+        assert_eq!(a.right_ident_mask.index_of_first_set(), Some(1));
+        assert_eq!(a.op_mask.index_of_first_set(), Some(ArithmeticOp::Minus.index()));
+
+        let schema = schema_with_column_arithmetics(ordered_schemas.schema(0), arithmetics);
+        let where_expr = statements[0].compile_where(&schema, CoercionMode::default()).unwrap().unwrap();
+        assert_eq!(
+            where_expr.to_parenthesized_string(),
+            "((Price * Quantity > 1000) OR ((Price - Quantity < 3) AND (10 < Price * Quantity)))"
+        );
+    }
+
+    #[test]
+    fn test_extract_constants() {
+        let ordered_schemas = ordered_schemas();
+        let max_num_fields = ordered_schemas.max_num_fields();
+        let dialect = GenericDialect {};
+        let sql = "SELECT * FROM Products WHERE Price & 4 <> 0 AND Quantity % 10 = 3 OR Price ^ -1 > Quantity | 4 AND Price + 7 > 2";
+        let mut statements = Parser::parse_sql(&dialect, sql).unwrap();
+        let column_arithmetics = statements[0]
+            .extract_column_arithmetics(&ordered_schemas, CoercionMode::default())
+            .unwrap();
+        assert_eq!(column_arithmetics.constants(), &[4, 10, -1]);
+        let arithmetics = column_arithmetics.arithmetics();
+        assert_eq!(arithmetics.len(), 4);
+        assert_eq!(arithmetics[1].name(), "Quantity % 10");
+        let expected = [
+            (0, max_num_fields, ArithmeticOp::BitwiseAnd),
+            (1, max_num_fields + 1, ArithmeticOp::Modulo),
+            (0, max_num_fields + 2, ArithmeticOp::BitwiseXor),
+            (1, max_num_fields, ArithmeticOp::BitwiseOr),
+        ];
+        arithmetics.iter().zip(expected).for_each(|(a, (l, r, op))| {
+            let a = a.arithmetic();
+            assert_eq!(a.right_ident_mask.len(), max_num_fields + 3);
+            assert_eq!(a.left_ident_mask.index_of_first_set(), Some(l));
+            assert_eq!(a.right_ident_mask.index_of_first_set(), Some(r));
+            assert_eq!(a.op_mask.index_of_first_set(), Some(op.index()));
+        });
+        // `Price + 7` is left to the rewriter
+        assert!(statements[0].to_string().ends_with("Price + 7 > 2"));
+    }
+
+    #[test]
+    fn test_extract_errors() {
+        let ordered_schemas = ordered_schemas();
+        let dialect = GenericDialect {};
+        let sqls = [
+            "SELECT * FROM Products WHERE Price * Name > 1",
+            "SELECT * FROM Products WHERE Price + Unknown > 1",
+        ];
+        sqls.iter().for_each(|sql| {
+            let mut statements = Parser::parse_sql(&dialect, sql).unwrap();
+            assert!(statements[0]
+                .extract_column_arithmetics(&ordered_schemas, CoercionMode::default())
+                .is_err());
+        });
+    }
+}
@@ -63,6 +63,11 @@ where
         TriangularMatrix::<Self>::from_vec(elements, matrix.dim())
     }
 
+    /// and_value = OperatorMask[k; Op(k) AND value]
+    pub fn and_value(&self, value: &B) -> Self {
+        ComparatorMask(self.0.refref_bitand(value))
+    }
+
     /// Cost:
     /// -----
     /// - 2 x Bit Not
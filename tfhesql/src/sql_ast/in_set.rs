@@ -0,0 +1,457 @@
+use std::ops::ControlFlow;
+
+use arrow_schema::{Field, Schema};
+use sqlparser::ast::{
+    Expr, Ident, SetExpr, Statement, UnaryOperator, Value, VisitMut, VisitorMut,
+};
+
+use crate::ascii::ascii_to_le_u64x4;
+use crate::error::FheSqlError;
+use crate::uint::mask::ClearBoolMask;
+use crate::uint::signed_u64::SignedU64;
+use crate::OrderedSchemas;
+
+use super::coercion::{coerce_set_value, CoercionMode};
+use super::column_ident::ColumnIdent;
+use super::data_value::DataValue;
+use super::parser::get_statement_from;
+
+/// Single column IN lists shorter than this are expanded into an OR of
+/// equalities (see `rewrite_in_list`)
+pub const IN_SET_MIN_LEN: usize = 4;
+
+////////////////////////////////////////////////////////////////////////////////
+// InSetValue
+////////////////////////////////////////////////////////////////////////////////
+
+/// 256 bits encoding of a set element or of a table cell.
+/// Numbers are stored as `[abs, 0, 0, 0]`, strings as 32 ascii bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InSetValue {
+    pub le_u64x4: [u64; 4],
+    pub negative: bool,
+}
+
+impl InSetValue {
+    pub const BITS: usize = 256;
+
+    #[inline]
+    pub fn from_i128(value: i128) -> Self {
+        InSetValue {
+            le_u64x4: [value.unsigned_abs() as u64, 0, 0, 0],
+            negative: value < 0,
+        }
+    }
+
+    #[inline]
+    pub fn from_ascii(value: &str) -> Self {
+        InSetValue {
+            le_u64x4: ascii_to_le_u64x4(value),
+            negative: false,
+        }
+    }
+
+    #[inline]
+    pub fn bit(&self, index: usize) -> bool {
+        (self.le_u64x4[index / 64] >> (index % 64)) & 1 == 1
+    }
+
+    #[inline]
+    pub fn byte(&self, index: usize) -> u8 {
+        (self.le_u64x4[index / 8] >> (8 * (index % 8))) as u8
+    }
+}
+
+impl From<&SignedU64> for InSetValue {
+    fn from(value: &SignedU64) -> Self {
+        InSetValue {
+            le_u64x4: [value.abs(), 0, 0, 0],
+            negative: value.is_strictly_negative(),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// AstInSet
+////////////////////////////////////////////////////////////////////////////////
+
+/// Clear description of `(c1, c2, ...) IN ((v11, v12, ...), (v21, v22, ...), ...)`
+#[derive(Clone, Debug)]
+pub struct AstInSet {
+    /// One column mask per tuple position
+    pub ident_masks: Vec<ClearBoolMask>,
+    /// One value per tuple position
+    pub elements: Vec<Vec<InSetValue>>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// InSet
+////////////////////////////////////////////////////////////////////////////////
+
+/// A `[NOT] IN` list found in a WHERE clause. In the WHERE clause, the list is
+/// replaced by a boolean identifier named after the (non negated) expression text.
+#[derive(Clone, Debug)]
+pub struct InSet {
+    name: String,
+    set: AstInSet,
+}
+
+impl InSet {
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    pub fn set(&self) -> &AstInSet {
+        &self.set
+    }
+
+    /// Returns `None` if the list must be expanded into an OR of equalities
+    fn try_from_expr(
+        expr: &Expr,
+        ordered_schemas: &OrderedSchemas,
+        table_index: usize,
+        mode: CoercionMode,
+    ) -> Result<Option<(Self, bool)>, FheSqlError> {
+        let (columns, list, negated) = match expr {
+            Expr::InList {
+                expr: in_expr,
+                list,
+                negated,
+            } => (in_expr.as_ref(), list, *negated),
+            _ => return Ok(None),
+        };
+        if list.is_empty() {
+            return Ok(None);
+        }
+
+        let schema = ordered_schemas.schema(table_index);
+        let idents = match columns {
+            Expr::Identifier(ident) => {
+                if list.len() < IN_SET_MIN_LEN {
+                    return Ok(None);
+                }
+                vec![ident]
+            }
+            Expr::Tuple(exprs) => {
+                let idents = exprs
+                    .iter()
+                    .map(|e| match e {
+                        Expr::Identifier(ident) => Ok(ident),
+                        _ => Err(FheSqlError::unsupported_expr(expr)),
+                    })
+                    .collect::<Result<Vec<&Ident>, FheSqlError>>()?;
+                if idents.is_empty() {
+                    return Err(FheSqlError::unsupported_expr(expr));
+                }
+                idents
+            }
+            _ => return Ok(None),
+        };
+        let is_tuple = matches!(columns, Expr::Tuple(_));
+
+        // Scalar subqueries or unknown identifiers
+        let columns = match idents
+            .iter()
+            .map(|ident| ColumnIdent::try_from_ident(ident, schema))
+            .collect::<Result<Vec<ColumnIdent>, FheSqlError>>()
+        {
+            Ok(columns) => columns,
+            Err(err) if is_tuple => return Err(err),
+            Err(_) => return Ok(None),
+        };
+
+        let mut elements = Vec::<Vec<InSetValue>>::with_capacity(list.len());
+        for item in list {
+            let values = match (item, is_tuple) {
+                (Expr::Tuple(values), true) => values.iter().collect::<Vec<&Expr>>(),
+                (_, false) => vec![item],
+                _ => return Err(FheSqlError::unsupported_expr(expr)),
+            };
+            if values.len() != columns.len() {
+                return Err(FheSqlError::unsupported_expr(expr));
+            }
+            let element = values
+                .iter()
+                .zip(idents.iter().zip(columns.iter()))
+                .map(|(v, (ident, c))| {
+                    if mode.is_strict() {
+                        let v = coerce_set_value(ident, c.data_type(), v, mode)?;
+                        try_get_set_value(&v, c)
+                    } else {
+                        try_get_set_value(v, c)
+                    }
+                })
+                .collect::<Result<Option<Vec<InSetValue>>, FheSqlError>>()?;
+            match element {
+                Some(e) => elements.push(e),
+                None if is_tuple => return Err(FheSqlError::unsupported_expr(expr)),
+                None => return Ok(None),
+            }
+        }
+
+        let max_num_fields = ordered_schemas.max_num_fields();
+        let ident_masks = columns
+            .iter()
+            .map(|c| {
+                let mut mask = ClearBoolMask::none(max_num_fields);
+                mask.set(c.index() as usize);
+                mask
+            })
+            .collect::<Vec<ClearBoolMask>>();
+
+        let name = format!(
+            "({}) IN ({})",
+            idents
+                .iter()
+                .map(|i| i.to_string())
+                .collect::<Vec<String>>()
+                .join(", "),
+            list.iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+
+        Ok(Some((
+            InSet {
+                name,
+                set: AstInSet {
+                    ident_masks,
+                    elements,
+                },
+            },
+            negated,
+        )))
+    }
+}
+
+/// `'abc'`, `123`, `-123` or `true` compatible with the column type,
+/// `None` otherwise
+fn try_get_set_value(
+    expr: &Expr,
+    column: &ColumnIdent,
+) -> Result<Option<InSetValue>, FheSqlError> {
+    let value = match expr {
+        Expr::Value(v) => match DataValue::try_from(v) {
+            Ok(v) => v,
+            Err(_) => return Ok(None),
+        },
+        Expr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr,
+        } => match expr.as_ref() {
+            Expr::Value(Value::Number(num, _)) => {
+                DataValue::Num(SignedU64::try_from(format!("-{}", num).as_str())?)
+            }
+            _ => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    let is_ascii = column.data_type().is_ascii();
+    match value {
+        DataValue::Ascii(s) if is_ascii && s.len() <= 32 => Ok(Some(InSetValue::from_ascii(&s))),
+        DataValue::Num(n) if !is_ascii => Ok(Some(InSetValue::from(&n))),
+        DataValue::Bool(b) if !is_ascii => Ok(Some(InSetValue::from_i128(b as i128))),
+        _ => Ok(None),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// ExtractInSets
+////////////////////////////////////////////////////////////////////////////////
+
+pub trait ExtractInSets {
+    /// Replaces each large or tuple `[NOT] IN` list of the WHERE clause by a
+    /// boolean identifier and returns the list of extracted sets. The values
+    /// are type-checked against their column according to `mode`.
+    fn extract_in_sets(
+        &mut self,
+        ordered_schemas: &OrderedSchemas,
+        mode: CoercionMode,
+    ) -> Result<Vec<InSet>, FheSqlError>;
+}
+
+impl ExtractInSets for Statement {
+    fn extract_in_sets(
+        &mut self,
+        ordered_schemas: &OrderedSchemas,
+        mode: CoercionMode,
+    ) -> Result<Vec<InSet>, FheSqlError> {
+        let from = get_statement_from(self)?;
+        let table_index = match ordered_schemas
+            .compute_table_mask::<bool>(&from.0)
+            .index_of_first_set()
+        {
+            Some(idx) => idx,
+            None => return Err(FheSqlError::syntax_error("No table selected")),
+        };
+
+        let selection = match self {
+            Statement::Query(query) => match query.body.as_mut() {
+                SetExpr::Select(s) => &mut s.selection,
+                _ => return Ok(vec![]),
+            },
+            _ => return Ok(vec![]),
+        };
+        let where_expr = match selection {
+            Some(w) => w,
+            None => return Ok(vec![]),
+        };
+
+        struct V<'a> {
+            ordered_schemas: &'a OrderedSchemas,
+            table_index: usize,
+            mode: CoercionMode,
+            sets: Vec<InSet>,
+        }
+
+        impl<'a> VisitorMut for V<'a> {
+            type Break = FheSqlError;
+
+            fn pre_visit_expr(&mut self, expr: &mut Expr) -> ControlFlow<Self::Break> {
+                let (set, negated) = match InSet::try_from_expr(
+                    expr,
+                    self.ordered_schemas,
+                    self.table_index,
+                    self.mode,
+                ) {
+                    Ok(Some(s)) => s,
+                    Ok(None) => return ControlFlow::Continue(()),
+                    Err(err) => return ControlFlow::Break(err),
+                };
+                let ident = Expr::Identifier(Ident::new(set.name()));
+                *expr = if negated {
+                    Expr::UnaryOp {
+                        op: UnaryOperator::Not,
+                        expr: Box::new(ident),
+                    }
+                } else {
+                    ident
+                };
+                // The same list may appear multiple times
+                if !self.sets.iter().any(|s| s.name() == set.name()) {
+                    self.sets.push(set);
+                }
+                ControlFlow::Continue(())
+            }
+        }
+
+        let mut v = V {
+            ordered_schemas,
+            table_index,
+            mode,
+            sets: vec![],
+        };
+        if let ControlFlow::Break(err) = where_expr.visit(&mut v) {
+            return Err(err);
+        }
+        Ok(v.sets)
+    }
+}
+
+/// Appends one boolean column per set to `schema`.
+/// The extra columns are used to type-check the sets identifiers.
+pub fn schema_with_in_sets(schema: &Schema, sets: &[InSet]) -> Schema {
+    if sets.is_empty() {
+        return schema.clone();
+    }
+    let mut fields: Vec<Field> = schema.fields().iter().map(|f| f.as_ref().clone()).collect();
+    sets.iter().for_each(|s| {
+        fields.push(Field::new(s.name(), arrow_schema::DataType::Boolean, false));
+    });
+    Schema::new(fields)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sql_ast::to_parenthesized_string::ToParenthesizedString;
+    use crate::sql_ast::{coercion::CoercionMode, CompileWhereStatement};
+    use crate::test::simple_batch::RecordBatchBuilder;
+    use crate::{OrderedTables, Table};
+    use arrow_array::types::{Int32Type, UInt8Type};
+    use sqlparser::{dialect::GenericDialect, parser::Parser};
+
+    fn ordered_schemas() -> OrderedSchemas {
+        let mut rb = RecordBatchBuilder::new();
+        rb.push_with_name::<Int32Type>("Price", vec![1, 2]);
+        rb.push_with_name::<UInt8Type>("Quantity", vec![3, 4]);
+        rb.push_str_with_name("Name", vec!["a", "b"]);
+        let t = Table::new("Products", rb.finish());
+        OrderedTables::new(vec![t])
+            .unwrap()
+            .ordered_schemas()
+            .clone()
+    }
+
+    #[test]
+    fn test_extract() {
+        let ordered_schemas = ordered_schemas();
+        let dialect = GenericDialect {};
+        let sql = "SELECT * FROM Products WHERE Price IN (1, -2, 3, 4) AND Name NOT IN ('a', 'b', 'c', 'd') OR (Price, Quantity) IN ((1, 3), (2, true)) OR Quantity IN (1, 2)";
+        let mut statements = Parser::parse_sql(&dialect, sql).unwrap();
+        let sets = statements[0].extract_in_sets(&ordered_schemas, CoercionMode::default()).unwrap();
+        assert_eq!(sets.len(), 3);
+        assert_eq!(sets[0].name(), "(Price) IN (1, -2, 3, 4)");
+        assert_eq!(sets[2].name(), "(Price, Quantity) IN ((1, 3), (2, true))");
+
+        let s = sets[0].set();
+        assert_eq!(s.ident_masks.len(), 1);
+        assert_eq!(s.ident_masks[0].index_of_first_set(), Some(0));
+        assert_eq!(s.elements[1], vec![InSetValue::from_i128(-2)]);
+
+        let s = sets[1].set();
+        assert_eq!(s.ident_masks[0].index_of_first_set(), Some(2));
+        assert_eq!(s.elements[3], vec![InSetValue::from_ascii("d")]);
+
+        let s = sets[2].set();
+        assert_eq!(s.ident_masks.len(), 2);
+        assert_eq!(s.ident_masks[1].index_of_first_set(), Some(1));
+        assert_eq!(
+            s.elements[1],
+            vec![InSetValue::from_i128(2), InSetValue::from_i128(1)]
+        );
+
+        let schema = schema_with_in_sets(ordered_schemas.schema(0), &sets);
+        let where_expr = statements[0].compile_where(&schema, CoercionMode::default()).unwrap().unwrap();
+        assert_eq!(
+            where_expr.to_parenthesized_string(),
+            "(((((Price) IN (1, -2, 3, 4) = true) AND ((Name) IN ('a', 'b', 'c', 'd') = false)) OR ((Price, Quantity) IN ((1, 3), (2, true)) = true)) OR ((Quantity = 1) OR (Quantity = 2)))"
+        );
+    }
+
+    #[test]
+    fn test_extract_errors() {
+        let ordered_schemas = ordered_schemas();
+        let dialect = GenericDialect {};
+        let sqls = [
+            "SELECT * FROM Products WHERE (Price, Quantity) IN ((1, 2), (3))",
+            "SELECT * FROM Products WHERE (Price, Name) IN ((1, 2))",
+            "SELECT * FROM Products WHERE (Price, Unknown) IN ((1, 2))",
+        ];
+        sqls.iter().for_each(|sql| {
+            let mut statements = Parser::parse_sql(&dialect, sql).unwrap();
+            assert!(statements[0].extract_in_sets(&ordered_schemas, CoercionMode::default()).is_err());
+        });
+    }
+
+    #[test]
+    fn test_value_bits() {
+        let v = InSetValue::from_ascii("ab");
+        assert_eq!(v.byte(0), b'a');
+        assert_eq!(v.byte(1), b'b');
+        assert_eq!(v.byte(2), 0);
+        assert!(v.bit(0));
+        assert!(!v.bit(1));
+        let v = InSetValue::from_i128(-(1 << 8));
+        assert!(v.negative);
+        assert_eq!(v.byte(1), 1);
+        assert!(v.bit(8));
+    }
+}
@@ -4,6 +4,7 @@ use sqlparser::ast::{BinaryOperator, Expr, SetExpr, Statement, UnaryOperator};
 use crate::error::FheSqlError;
 
 use self::{
+    coercion::{coerce_where_expr_in_place, CoercionMode},
     data_ident::DataIdent, data_type::DataType, data_value::DataValue,
     num_op_rewriter::rewrite_where_expr_in_place, range_optimizer::RangeOptimizer,
     where_validator::validate_where_expr_tree,
@@ -11,18 +12,23 @@ use self::{
 
 pub mod and_or_ast;
 pub mod bitop_mask;
+pub mod coercion;
+pub mod column_arithmetic;
 mod column_ident;
 mod data_ident;
 mod data_sig;
 mod data_type;
 mod data_value;
 mod helpers;
+pub mod in_set;
 mod num_op_rewriter;
 pub mod parser;
 mod range_optimizer;
+pub mod scalar_subquery;
 mod tests;
-mod to_parenthesized_string;
+pub(crate) mod to_parenthesized_string;
 mod where_validator;
+pub mod window;
 
 mod comparator_mask;
 pub use comparator_mask::ComparatorMask;
@@ -59,7 +65,10 @@ impl SqlExprGetChildren for Expr {
             Expr::Nested(expr) => {
                 Ok(vec![expr.as_ref()])
             }
-            Expr::Value(_) => Ok(vec![]),
+            Expr::Cast { expr, .. } => {
+                Ok(vec![expr.as_ref()])
+            }
+            Expr::Value(_) | Expr::TypedString { .. } => Ok(vec![]),
             _ => Err(FheSqlError::unsupported_expr(self)),
         }
     }
@@ -86,7 +95,10 @@ impl SqlExprGetChildren for Expr {
             Expr::Nested(expr) => {
                 Ok(vec![expr.as_mut()])
             }
-            Expr::Value(_) => Ok(vec![]),
+            Expr::Cast { expr, .. } => {
+                Ok(vec![expr.as_mut()])
+            }
+            Expr::Value(_) | Expr::TypedString { .. } => Ok(vec![]),
             _ => Err(FheSqlError::unsupported_expr(self)),
         }
     }
@@ -158,7 +170,11 @@ pub trait CompileWhereStatement
 where
     Self: CloneWhereStatement,
 {
-    fn compile_where(&self, schema: &Schema) -> Result<Option<Box<Expr>>, FheSqlError> {
+    fn compile_where(
+        &self,
+        schema: &Schema,
+        mode: CoercionMode,
+    ) -> Result<Option<Box<Expr>>, FheSqlError> {
         let mut where_expr = match self.clone_where() {
             Some(w) => w,
             None => return Ok(None),
@@ -167,6 +183,9 @@ where
         // First step : remove parentheses
         where_expr.as_mut().remove_nested_in_place()?;
 
+        // Check operand types, fold literal casts
+        coerce_where_expr_in_place(&mut where_expr, schema, mode)?;
+
         // Second step : rewrite where statement (remove minus, plus etc.)
         rewrite_where_expr_in_place(&mut where_expr, schema)?;
 
@@ -498,6 +498,264 @@ fn rewrite_between(expr: &Expr, negated: bool, low: &Expr, high: &Expr) -> Box<E
     }
 }
 
+fn is_shift_expr(the_expr: &Expr) -> bool {
+    matches!(
+        the_expr,
+        Expr::BinaryOp {
+            op: BinaryOperator::PGBitwiseShiftLeft | BinaryOperator::PGBitwiseShiftRight,
+            ..
+        }
+    )
+}
+
+fn try_get_i128(the_expr: &Expr) -> Result<Option<i128>, FheSqlError> {
+    match the_expr {
+        Expr::Value(Value::Number(num, _)) => match num.parse::<i128>() {
+            Ok(n) => Ok(Some(n)),
+            Err(_) => Err(FheSqlError::parse_int_error(num)),
+        },
+        _ => Ok(None),
+    }
+}
+
+fn is_folded_arithmetic_expr(the_expr: &Expr) -> bool {
+    matches!(
+        the_expr,
+        Expr::BinaryOp {
+            op: BinaryOperator::Plus
+                | BinaryOperator::Minus
+                | BinaryOperator::Multiply
+                | BinaryOperator::Divide,
+            ..
+        }
+    )
+}
+
+/// `123` or `-123`
+fn try_get_signed_i128(the_expr: &Expr) -> Result<Option<i128>, FheSqlError> {
+    match the_expr {
+        Expr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr,
+        } => Ok(try_get_i128(expr)?.map(|n| -n)),
+        _ => try_get_i128(the_expr),
+    }
+}
+
+/// X cmp num, where num may be out of the [i64::MIN, u64::MAX] range
+fn make_a_cmp_to_i128(a: &Expr, op: BinaryOperator, num: i128) -> Box<Expr> {
+    let always = if num > u64::MAX as i128 {
+        // X < num
+        Some(matches!(
+            op,
+            BinaryOperator::Lt | BinaryOperator::LtEq | BinaryOperator::NotEq
+        ))
+    } else if num < i64::MIN as i128 {
+        // X > num
+        Some(matches!(
+            op,
+            BinaryOperator::Gt | BinaryOperator::GtEq | BinaryOperator::NotEq
+        ))
+    } else {
+        None
+    };
+    match always {
+        Some(b) => Box::new(Expr::Value(Value::Boolean(b))),
+        None => make_binary_op(a, op, &Expr::Value(Value::Number(num.to_string(), false))),
+    }
+}
+
+/// Shift by a constant compared to a constant
+/// - X >> k cmp V : X / 2^k (floor) cmp V
+/// - X << k cmp V : X * 2^k cmp V
+fn rewrite_shift_op_num(
+    left: &Expr,
+    op: &BinaryOperator,
+    right: &Expr,
+    negated: bool,
+) -> Result<Option<Box<Expr>>, FheSqlError> {
+    let (shift_expr, cmp_op, num_expr) = if is_shift_expr(left) {
+        (left, op.clone(), right)
+    } else if is_shift_expr(right) {
+        (right, reflexive_binary_op(op), left)
+    } else {
+        return Ok(None);
+    };
+    let (x, shift_op, k) = match shift_expr {
+        Expr::BinaryOp { left, op, right } => (left.as_ref(), op, right.as_ref()),
+        _ => unreachable!(),
+    };
+    if !x.is_identifier() {
+        return Err(FheSqlError::unsupported_expr(shift_expr));
+    }
+    let k = match try_get_i128(k)? {
+        Some(k) if (0..64).contains(&k) => k as u32,
+        _ => return Err(FheSqlError::unsupported_expr(shift_expr)),
+    };
+    let v = match try_get_i128(num_expr)? {
+        Some(v) => v,
+        None => return Err(FheSqlError::unsupported_expr(num_expr)),
+    };
+    let cmp_op = if negated {
+        not_binary_op(&cmp_op)
+    } else {
+        cmp_op
+    };
+
+    let n: i128 = 1 << k;
+    let cmp = |op: BinaryOperator, num: i128| make_a_cmp_to_i128(x, op, num);
+    let the_expr = match shift_op {
+        BinaryOperator::PGBitwiseShiftRight => {
+            // X >> k = V := lo <= X <= hi
+            let lo = v.saturating_mul(n);
+            let hi = lo.saturating_add(n - 1);
+            match cmp_op {
+                BinaryOperator::Eq => {
+                    make_a_and_b(cmp(BinaryOperator::GtEq, lo), cmp(BinaryOperator::LtEq, hi))
+                }
+                BinaryOperator::NotEq => {
+                    make_a_or_b(cmp(BinaryOperator::Lt, lo), cmp(BinaryOperator::Gt, hi))
+                }
+                BinaryOperator::Gt => cmp(BinaryOperator::Gt, hi),
+                BinaryOperator::GtEq => cmp(BinaryOperator::GtEq, lo),
+                BinaryOperator::Lt => cmp(BinaryOperator::Lt, lo),
+                BinaryOperator::LtEq => cmp(BinaryOperator::LtEq, hi),
+                _ => return Err(FheSqlError::unsupported_binary_op(&cmp_op)),
+            }
+        }
+        _ => {
+            // X << k = V := X = V / 2^k
+            let floor = v.div_euclid(n);
+            let ceil = -(-v).div_euclid(n);
+            let exact = v.rem_euclid(n) == 0;
+            match cmp_op {
+                BinaryOperator::Eq if exact => cmp(BinaryOperator::Eq, floor),
+                BinaryOperator::Eq => Box::new(Expr::Value(Value::Boolean(false))),
+                BinaryOperator::NotEq if exact => cmp(BinaryOperator::NotEq, floor),
+                BinaryOperator::NotEq => Box::new(Expr::Value(Value::Boolean(true))),
+                BinaryOperator::Gt => cmp(BinaryOperator::Gt, floor),
+                BinaryOperator::GtEq => cmp(BinaryOperator::GtEq, ceil),
+                BinaryOperator::Lt => cmp(BinaryOperator::Lt, ceil),
+                BinaryOperator::LtEq => cmp(BinaryOperator::LtEq, floor),
+                _ => return Err(FheSqlError::unsupported_binary_op(&cmp_op)),
+            }
+        }
+    };
+    Ok(Some(the_expr))
+}
+
+/// Arithmetic by a constant compared to a constant, the constant is folded
+/// into the compared value so that it is never sent in clear
+/// - X + c cmp V : X cmp V - c
+/// - X - c cmp V : X cmp V + c
+/// - X * c cmp V : X cmp V / c (rounded, cmp reversed if c < 0)
+/// - X / c cmp V : X cmp V * c (cmp reversed if c < 0), FALSE if c = 0
+fn rewrite_arithmetic_op_num(
+    left: &Expr,
+    op: &BinaryOperator,
+    right: &Expr,
+    negated: bool,
+) -> Result<Option<Box<Expr>>, FheSqlError> {
+    let (arithmetic_expr, cmp_op, num_expr) = if is_folded_arithmetic_expr(left) {
+        (left, op.clone(), right)
+    } else if is_folded_arithmetic_expr(right) {
+        (right, reflexive_binary_op(op), left)
+    } else {
+        return Ok(None);
+    };
+    let (x, arithmetic_op, c) = match arithmetic_expr {
+        Expr::BinaryOp { left, op, right } => (left.as_ref(), op, right.as_ref()),
+        _ => unreachable!(),
+    };
+    if !x.is_identifier() {
+        return Err(FheSqlError::unsupported_expr(arithmetic_expr));
+    }
+    let c = match try_get_signed_i128(c)? {
+        Some(c) => c,
+        None => return Err(FheSqlError::unsupported_expr(arithmetic_expr)),
+    };
+    let v = match try_get_i128(num_expr)? {
+        Some(v) => v,
+        None => return Err(FheSqlError::unsupported_expr(num_expr)),
+    };
+    let cmp_op = if negated {
+        not_binary_op(&cmp_op)
+    } else {
+        cmp_op
+    };
+
+    let cmp = |op: BinaryOperator, num: i128| make_a_cmp_to_i128(x, op, num);
+    // X * c cmp V with c < 0 := X * (-c) reflexive(cmp) -V
+    let (cmp_op, c, v) = match arithmetic_op {
+        BinaryOperator::Multiply | BinaryOperator::Divide if c < 0 => {
+            (reflexive_binary_op(&cmp_op), -c, v.saturating_neg())
+        }
+        _ => (cmp_op, c, v),
+    };
+    let the_expr = match arithmetic_op {
+        BinaryOperator::Plus => cmp(cmp_op, v.saturating_sub(c)),
+        BinaryOperator::Minus => cmp(cmp_op, v.saturating_add(c)),
+        // Division by zero is NULL, never selected
+        BinaryOperator::Divide if c == 0 => Box::new(Expr::Value(Value::Boolean(false))),
+        BinaryOperator::Divide => cmp(cmp_op, v.saturating_mul(c)),
+        _ if c == 0 => {
+            // X * 0 cmp V := 0 cmp V
+            let b = match cmp_op {
+                BinaryOperator::Eq => v == 0,
+                BinaryOperator::NotEq => v != 0,
+                BinaryOperator::Gt => 0 > v,
+                BinaryOperator::GtEq => 0 >= v,
+                BinaryOperator::Lt => 0 < v,
+                BinaryOperator::LtEq => 0 <= v,
+                _ => return Err(FheSqlError::unsupported_binary_op(&cmp_op)),
+            };
+            Box::new(Expr::Value(Value::Boolean(b)))
+        }
+        _ => {
+            // X * c = V := X = V / c
+            let floor = v.div_euclid(c);
+            let ceil = -(-v).div_euclid(c);
+            let exact = v.rem_euclid(c) == 0;
+            match cmp_op {
+                BinaryOperator::Eq if exact => cmp(BinaryOperator::Eq, floor),
+                BinaryOperator::Eq => Box::new(Expr::Value(Value::Boolean(false))),
+                BinaryOperator::NotEq if exact => cmp(BinaryOperator::NotEq, floor),
+                BinaryOperator::NotEq => Box::new(Expr::Value(Value::Boolean(true))),
+                BinaryOperator::Gt => cmp(BinaryOperator::Gt, floor),
+                BinaryOperator::GtEq => cmp(BinaryOperator::GtEq, ceil),
+                BinaryOperator::Lt => cmp(BinaryOperator::Lt, ceil),
+                BinaryOperator::LtEq => cmp(BinaryOperator::LtEq, floor),
+                _ => return Err(FheSqlError::unsupported_binary_op(&cmp_op)),
+            }
+        }
+    };
+    Ok(Some(the_expr))
+}
+
+/// Returns the bool value of a 'BoolColumn = true' leaf (operands in any order)
+fn try_get_bool_leaf_value<'a>(
+    left: &'a mut Expr,
+    right: &'a mut Expr,
+    schema: &Schema,
+) -> Option<&'a mut bool> {
+    let is_bool_ident = |e: &Expr| match e {
+        Expr::Identifier(ident) => DataIdent::try_from_ident(ident, schema)
+            .map(|data_ident| data_ident.is_bool())
+            .unwrap_or(false),
+        _ => false,
+    };
+    if is_bool_ident(left) {
+        if let Expr::Value(Value::Boolean(b)) = right {
+            return Some(b);
+        }
+    } else if is_bool_ident(right) {
+        if let Expr::Value(Value::Boolean(b)) = left {
+            return Some(b);
+        }
+    }
+    None
+}
+
 fn recursive_to_bool(
     the_expr: &mut Box<Expr>,
     schema: &Schema,
@@ -577,6 +835,14 @@ fn recursive_to_bool(
                 //  - SomeStringColumn <> 'some string'
                 match op {
                     BinaryOperator::Eq | BinaryOperator::NotEq => {
+                        // 'BoolColumn = true' is already a leaf (the NumRewriter would
+                        // cast 'true' to 1 and expand the leaf again, endlessly)
+                        if let Some(b) = try_get_bool_leaf_value(left, right, schema) {
+                            let is_eq = matches!(op, BinaryOperator::Eq);
+                            *b = *b == (is_eq != the_negated);
+                            *op = BinaryOperator::Eq;
+                            return Ok(());
+                        }
                         if left.is_utf8_identifier(schema) && right.is_string_value() {
                             if the_negated {
                                 *the_expr = make_binary_op(&left, not_binary_op(op), &right);
@@ -597,6 +863,20 @@ fn recursive_to_bool(
                 num_rw.rewrite(left)?;
                 num_rw.rewrite(right)?;
 
+                // X >> 2 > 1 := X >= 8
+                if let Some(shift_expr) = rewrite_shift_op_num(left, op, right, the_negated)? {
+                    *the_expr = shift_expr;
+                    return recursive_to_bool(the_expr, schema, false);
+                }
+
+                // X + 3 > 70 := X > 67
+                if let Some(arithmetic_expr) =
+                    rewrite_arithmetic_op_num(left, op, right, the_negated)?
+                {
+                    *the_expr = arithmetic_expr;
+                    return recursive_to_bool(the_expr, schema, false);
+                }
+
                 if left == right {
                     match op {
                         BinaryOperator::Gt | BinaryOperator::Lt | BinaryOperator::NotEq => {
@@ -650,6 +930,18 @@ fn recursive_to_bool(
 
                 Ok(())
             }
+            BinaryOperator::Plus
+            | BinaryOperator::Minus
+            | BinaryOperator::Multiply
+            | BinaryOperator::Divide => {
+                // toBool(X + c) := (X + c != 0)
+                *the_expr = make_binary_op(
+                    the_expr,
+                    BinaryOperator::NotEq,
+                    &Expr::Value(Value::Number("0".to_string(), false)),
+                );
+                recursive_to_bool(the_expr, schema, the_negated)
+            }
             BinaryOperator::Xor => {
                 todo!()
             }
@@ -865,6 +1157,16 @@ mod test {
             "(NOT (NOT (a_s = b_s)))",
             "(NOT(NOT(e_i >= -9223372036854775808)))",
             "(NOT(e_i >= -9223372036854775808))",
+            "(a_i >> 2 = 1)",
+            "(a_i >> 2 <> -1)",
+            "(1 < a_i >> 2)",
+            "(NOT (a_u << 3 > 20))",
+            "(a_u << 2 = 7)",
+            "(a_u << 2 = 8)",
+            "(e_u >> 63 > 1)",
+            "(e_u >> 63 > 2)",
+            "(a_b AND b_b OR a_i = 2)",
+            "(NOT (a_b AND (b_b OR NOT c_b)))",
         ];
         let expected_result = [
             "(a_b > a_u)",
@@ -918,6 +1220,16 @@ mod test {
             "(a_s = b_s)",
             "(e_i >= -9223372036854775808)",
             "(e_i < -9223372036854775808)",
+            "((a_i >= 4) AND (a_i <= 7))",
+            "((a_i < -4) OR (a_i > -1))",
+            "(a_i > 7)",
+            "(a_u <= 2)",
+            "false",
+            "(a_u = 2)",
+            "(e_u > 18446744073709551615)",
+            "false",
+            "(((a_b = true) AND (b_b = true)) OR (a_i = 2))",
+            "((a_b = false) OR ((b_b = false) AND (c_b = true)))",
         ];
         where_clauses
             .iter()
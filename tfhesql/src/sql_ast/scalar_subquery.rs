@@ -0,0 +1,351 @@
+use std::ops::ControlFlow;
+
+use arrow_schema::{Field, Schema};
+use sqlparser::ast::{
+    Expr, FunctionArg, FunctionArgExpr, Ident, Query, SelectItem, SetExpr, Statement, VisitMut,
+    VisitorMut,
+};
+
+use crate::error::FheSqlError;
+use crate::uint::mask::ClearBoolMask;
+use crate::OrderedSchemas;
+
+use super::coercion::{not_an_integer_column, CoercionMode};
+use super::column_ident::ColumnIdent;
+use super::parser::get_statement_from;
+
+////////////////////////////////////////////////////////////////////////////////
+// AggregateFunc
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateFunc {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+impl AggregateFunc {
+    pub const LEN: usize = 5;
+    pub const ALL: [AggregateFunc; Self::LEN] = [
+        AggregateFunc::Count,
+        AggregateFunc::Sum,
+        AggregateFunc::Avg,
+        AggregateFunc::Min,
+        AggregateFunc::Max,
+    ];
+
+    #[inline]
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    fn try_from_name(name: &str) -> Option<Self> {
+        if name.eq_ignore_ascii_case("COUNT") {
+            Some(AggregateFunc::Count)
+        } else if name.eq_ignore_ascii_case("SUM") {
+            Some(AggregateFunc::Sum)
+        } else if name.eq_ignore_ascii_case("AVG") {
+            Some(AggregateFunc::Avg)
+        } else if name.eq_ignore_ascii_case("MIN") {
+            Some(AggregateFunc::Min)
+        } else if name.eq_ignore_ascii_case("MAX") {
+            Some(AggregateFunc::Max)
+        } else {
+            None
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// AstAggregate
+////////////////////////////////////////////////////////////////////////////////
+
+/// Clear masks describing `AGG(column) FROM table`
+#[derive(Clone, Debug)]
+pub struct AstAggregate {
+    pub table_mask: ClearBoolMask,
+    pub ident_mask: ClearBoolMask,
+    pub func_mask: ClearBoolMask,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// ScalarSubquery
+////////////////////////////////////////////////////////////////////////////////
+
+/// A `(SELECT AGG(column) FROM table)` expression found in a WHERE clause.
+/// In the WHERE clause, the subquery is replaced by an identifier named after
+/// the subquery text.
+#[derive(Clone, Debug)]
+pub struct ScalarSubquery {
+    name: String,
+    aggregate: AstAggregate,
+}
+
+impl ScalarSubquery {
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    pub fn aggregate(&self) -> &AstAggregate {
+        &self.aggregate
+    }
+
+    fn try_from_query(
+        query: &Query,
+        ordered_schemas: &OrderedSchemas,
+        mode: CoercionMode,
+    ) -> Result<Self, FheSqlError> {
+        let unsupported = |msg: &str| {
+            FheSqlError::UnsupportedSqlQuery(format!("{} in subquery '{}'", msg, query))
+        };
+
+        if query.with.is_some()
+            || !query.order_by.is_empty()
+            || query.limit.is_some()
+            || query.offset.is_some()
+            || query.fetch.is_some()
+        {
+            return Err(unsupported("Unsupported clause"));
+        }
+        let select = match query.body.as_ref() {
+            SetExpr::Select(s) => s.as_ref(),
+            _ => return Err(unsupported("Unsupported body")),
+        };
+        if select.selection.is_some() {
+            return Err(unsupported("WHERE clause not supported"));
+        }
+        if select.having.is_some() || select.distinct.is_some() {
+            return Err(unsupported("Unsupported clause"));
+        }
+        if select.projection.len() != 1 {
+            return Err(unsupported("A single aggregate is expected"));
+        }
+
+        let statement = Statement::Query(Box::new(query.clone()));
+        let from = get_statement_from(&statement)?;
+        let table_mask: ClearBoolMask = ordered_schemas.compute_table_mask(&from.0);
+        let table_index = match table_mask.index_of_first_set() {
+            Some(idx) => idx,
+            None => return Err(FheSqlError::UnknownColumnName(from.to_string())),
+        };
+        let table_schema = ordered_schemas.schema(table_index);
+
+        let func = match &select.projection[0] {
+            SelectItem::UnnamedExpr(Expr::Function(func)) => func,
+            _ => return Err(unsupported("An aggregate function is expected")),
+        };
+        if func.filter.is_some() || func.over.is_some() || func.distinct || func.args.len() != 1 {
+            return Err(unsupported("Unsupported aggregate function"));
+        }
+        let agg_func = match AggregateFunc::try_from_name(&func.name.to_string()) {
+            Some(f) => f,
+            None => return Err(unsupported("Unsupported aggregate function")),
+        };
+
+        let column_index = match &func.args[0] {
+            FunctionArg::Unnamed(FunctionArgExpr::Wildcard) => {
+                if agg_func != AggregateFunc::Count || table_schema.fields().is_empty() {
+                    return Err(unsupported("Unsupported aggregate argument"));
+                }
+                // COUNT(*) == COUNT(first column), columns are never null
+                0
+            }
+            FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Identifier(ident))) => {
+                let column = ColumnIdent::try_from_ident(ident, table_schema)?;
+                if agg_func != AggregateFunc::Count && column.data_type().is_ascii() {
+                    return Err(unsupported("Aggregate over a string column"));
+                }
+                if agg_func != AggregateFunc::Count
+                    && mode.is_strict()
+                    && column.data_type().is_bool()
+                {
+                    return Err(not_an_integer_column(ident, query, mode));
+                }
+                column.index() as usize
+            }
+            _ => return Err(unsupported("Unsupported aggregate argument")),
+        };
+
+        let mut ident_mask = ClearBoolMask::none(ordered_schemas.max_num_fields());
+        ident_mask.set(column_index);
+        let mut func_mask = ClearBoolMask::none(AggregateFunc::LEN);
+        func_mask.set(agg_func.index());
+
+        Ok(ScalarSubquery {
+            name: format!("({})", query),
+            aggregate: AstAggregate {
+                table_mask,
+                ident_mask,
+                func_mask,
+            },
+        })
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// ExtractScalarSubqueries
+////////////////////////////////////////////////////////////////////////////////
+
+pub trait ExtractScalarSubqueries {
+    /// Replaces each scalar subquery of the WHERE clause by an identifier and
+    /// returns the list of extracted subqueries. The aggregated columns are
+    /// type-checked according to `mode`.
+    fn extract_scalar_subqueries(
+        &mut self,
+        ordered_schemas: &OrderedSchemas,
+        mode: CoercionMode,
+    ) -> Result<Vec<ScalarSubquery>, FheSqlError>;
+}
+
+impl ExtractScalarSubqueries for Statement {
+    fn extract_scalar_subqueries(
+        &mut self,
+        ordered_schemas: &OrderedSchemas,
+        mode: CoercionMode,
+    ) -> Result<Vec<ScalarSubquery>, FheSqlError> {
+        let selection = match self {
+            Statement::Query(query) => match query.body.as_mut() {
+                SetExpr::Select(s) => &mut s.selection,
+                _ => return Ok(vec![]),
+            },
+            _ => return Ok(vec![]),
+        };
+        let where_expr = match selection {
+            Some(w) => w,
+            None => return Ok(vec![]),
+        };
+
+        struct V<'a> {
+            ordered_schemas: &'a OrderedSchemas,
+            mode: CoercionMode,
+            subqueries: Vec<ScalarSubquery>,
+        }
+
+        impl<'a> VisitorMut for V<'a> {
+            type Break = FheSqlError;
+
+            fn pre_visit_expr(&mut self, expr: &mut Expr) -> ControlFlow<Self::Break> {
+                let subquery = match expr {
+                    Expr::Subquery(query) => match ScalarSubquery::try_from_query(
+                        query,
+                        self.ordered_schemas,
+                        self.mode,
+                    ) {
+                        Ok(s) => s,
+                        Err(err) => return ControlFlow::Break(err),
+                    },
+                    Expr::Exists { .. } | Expr::InSubquery { .. } => {
+                        return ControlFlow::Break(FheSqlError::unsupported_expr(expr))
+                    }
+                    _ => return ControlFlow::Continue(()),
+                };
+                *expr = Expr::Identifier(Ident::new(subquery.name()));
+                // The same subquery may appear multiple times
+                if !self.subqueries.iter().any(|s| s.name() == subquery.name()) {
+                    self.subqueries.push(subquery);
+                }
+                ControlFlow::Continue(())
+            }
+        }
+
+        let mut v = V {
+            ordered_schemas,
+            mode,
+            subqueries: vec![],
+        };
+        match where_expr.visit(&mut v) {
+            ControlFlow::Continue(_) => Ok(v.subqueries),
+            ControlFlow::Break(err) => Err(err),
+        }
+    }
+}
+
+/// Appends one signed integer column per subquery to `schema`.
+/// The extra columns are used to type-check the subqueries identifiers.
+pub fn schema_with_scalar_subqueries(schema: &Schema, subqueries: &[ScalarSubquery]) -> Schema {
+    if subqueries.is_empty() {
+        return schema.clone();
+    }
+    let mut fields: Vec<Field> = schema.fields().iter().map(|f| f.as_ref().clone()).collect();
+    subqueries.iter().for_each(|s| {
+        fields.push(Field::new(s.name(), arrow_schema::DataType::Int64, false));
+    });
+    Schema::new(fields)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sql_ast::to_parenthesized_string::ToParenthesizedString;
+    use crate::sql_ast::{coercion::CoercionMode, CompileWhereStatement};
+    use crate::test::simple_batch::RecordBatchBuilder;
+    use crate::{OrderedTables, Table};
+    use arrow_array::types::{Int32Type, UInt8Type};
+    use sqlparser::{dialect::GenericDialect, parser::Parser};
+
+    fn ordered_schemas() -> OrderedSchemas {
+        let mut rb = RecordBatchBuilder::new();
+        rb.push_with_name::<Int32Type>("Price", vec![1, 2]);
+        rb.push_str_with_name("Name", vec!["a", "b"]);
+        let t1 = Table::new("Products", rb.finish());
+        let mut rb = RecordBatchBuilder::new();
+        rb.push_with_name::<UInt8Type>("Quantity", vec![3]);
+        let t2 = Table::new("Orders", rb.finish());
+        OrderedTables::new(vec![t1, t2])
+            .unwrap()
+            .ordered_schemas()
+            .clone()
+    }
+
+    #[test]
+    fn test_extract() {
+        let ordered_schemas = ordered_schemas();
+        let dialect = GenericDialect {};
+        let sql = "SELECT * FROM Products WHERE Price > (SELECT AVG(Quantity) FROM Orders) OR Price = (SELECT MAX(Quantity) FROM Orders) OR (SELECT AVG(Quantity) FROM Orders) < Price";
+        let mut statements = Parser::parse_sql(&dialect, sql).unwrap();
+        let subqueries = statements[0]
+            .extract_scalar_subqueries(&ordered_schemas, CoercionMode::default())
+            .unwrap();
+        assert_eq!(subqueries.len(), 2);
+        assert_eq!(subqueries[0].name(), "(SELECT AVG(Quantity) FROM Orders)");
+        let agg = subqueries[0].aggregate();
+        assert_eq!(agg.table_mask.index_of_first_set(), Some(0));
+        assert_eq!(agg.ident_mask.index_of_first_set(), Some(0));
+        assert_eq!(agg.func_mask.index_of_first_set(), Some(AggregateFunc::Avg.index()));
+
+        let schema = schema_with_scalar_subqueries(ordered_schemas.schema(1), &subqueries);
+        let where_expr = statements[0].compile_where(&schema, CoercionMode::default()).unwrap().unwrap();
+        assert_eq!(
+            where_expr.to_parenthesized_string(),
+            "(((Price > (SELECT AVG(Quantity) FROM Orders)) OR (Price = (SELECT MAX(Quantity) FROM Orders))) OR ((SELECT AVG(Quantity) FROM Orders) < Price))"
+        );
+    }
+
+    #[test]
+    fn test_extract_errors() {
+        let ordered_schemas = ordered_schemas();
+        let dialect = GenericDialect {};
+        let sqls = [
+            "SELECT * FROM Products WHERE Price > (SELECT AVG(Name) FROM Products)",
+            "SELECT * FROM Products WHERE Price > (SELECT Price FROM Products)",
+            "SELECT * FROM Products WHERE Price > (SELECT AVG(Price) FROM Products WHERE Price > 1)",
+            "SELECT * FROM Products WHERE Price > (SELECT SUM(*) FROM Products)",
+            "SELECT * FROM Products WHERE Price IN (SELECT Price FROM Products)",
+        ];
+        sqls.iter().for_each(|sql| {
+            let mut statements = Parser::parse_sql(&dialect, sql).unwrap();
+            assert!(statements[0]
+                .extract_scalar_subqueries(&ordered_schemas, CoercionMode::default())
+                .is_err());
+        });
+    }
+}
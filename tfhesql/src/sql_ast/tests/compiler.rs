@@ -118,7 +118,7 @@ mod test {
                 let statements = Parser::parse_sql(&dialect, &sql).unwrap();
                 assert!(!statements.is_empty());
                 
-                let compiled_where_expr = statements.first().unwrap().compile_where(&schema).unwrap().unwrap();
+                let compiled_where_expr = statements.first().unwrap().compile_where(&schema, CoercionMode::default()).unwrap().unwrap();
                 assert_eq!(compiled_where_expr.to_parenthesized_string(), *e);
             });
     }
@@ -2,7 +2,7 @@ use sqlparser::ast::{BinaryOperator, Expr, UnaryOperator};
 
 use crate::error::FheSqlError;
 
-pub(super) trait ToParenthesizedString {
+pub(crate) trait ToParenthesizedString {
     fn try_to_parenthesized_string(&self) -> Result<String, FheSqlError>;
     fn to_parenthesized_string(&self) -> String {
         self.try_to_parenthesized_string().unwrap()
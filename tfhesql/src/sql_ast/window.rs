@@ -0,0 +1,249 @@
+use sqlparser::ast::{Expr, Function, SelectItem, SetExpr, Statement, WindowType};
+
+use crate::error::FheSqlError;
+use crate::uint::mask::ClearBoolMask;
+use crate::OrderedSchemas;
+
+use super::column_ident::ColumnIdent;
+use super::parser::get_statement_from;
+
+////////////////////////////////////////////////////////////////////////////////
+// WindowFunc
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowFunc {
+    RowNumber,
+    Rank,
+}
+
+impl WindowFunc {
+    pub const LEN: usize = 2;
+
+    #[inline]
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    fn try_from_name(name: &str) -> Option<Self> {
+        if name.eq_ignore_ascii_case("ROW_NUMBER") {
+            Some(WindowFunc::RowNumber)
+        } else if name.eq_ignore_ascii_case("RANK") {
+            Some(WindowFunc::Rank)
+        } else {
+            None
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// AstWindow
+////////////////////////////////////////////////////////////////////////////////
+
+/// `ROW_NUMBER() | RANK() OVER ([PARTITION BY column] ORDER BY column [ASC|DESC])`
+#[derive(Clone, Debug)]
+pub struct AstWindow {
+    /// Output column name (the alias if any)
+    pub name: String,
+    pub func_mask: ClearBoolMask,
+    pub order_ident_mask: ClearBoolMask,
+    pub order_desc: bool,
+    /// All false if there is no PARTITION BY clause
+    pub partition_ident_mask: ClearBoolMask,
+}
+
+impl AstWindow {
+    fn try_from_function(
+        func: &Function,
+        name: String,
+        ordered_schemas: &OrderedSchemas,
+        table_index: usize,
+    ) -> Result<Self, FheSqlError> {
+        let unsupported = |msg: &str| {
+            FheSqlError::UnsupportedSqlQuery(format!("{} in window function '{}'", msg, func))
+        };
+
+        let window_func = match WindowFunc::try_from_name(&func.name.to_string()) {
+            Some(f) => f,
+            None => return Err(unsupported("Unsupported function")),
+        };
+        if !func.args.is_empty() || func.filter.is_some() || func.distinct || !func.order_by.is_empty() {
+            return Err(unsupported("Unsupported arguments"));
+        }
+        let spec = match &func.over {
+            Some(WindowType::WindowSpec(spec)) => spec,
+            _ => return Err(unsupported("An OVER clause is expected")),
+        };
+        if spec.window_frame.is_some() {
+            return Err(unsupported("Window frames are not supported"));
+        }
+        if spec.order_by.len() != 1 {
+            return Err(unsupported("A single ORDER BY column is expected"));
+        }
+        if spec.partition_by.len() > 1 {
+            return Err(unsupported("At most one PARTITION BY column is expected"));
+        }
+
+        let schema = ordered_schemas.schema(table_index);
+        let column_index = |expr: &Expr| match expr {
+            Expr::Identifier(ident) => {
+                ColumnIdent::try_from_ident(ident, schema).map(|c| c.index() as usize)
+            }
+            _ => Err(unsupported("A column name is expected")),
+        };
+
+        let mut func_mask = ClearBoolMask::none(WindowFunc::LEN);
+        func_mask.set(window_func.index());
+
+        let mut order_ident_mask = ClearBoolMask::none(ordered_schemas.max_num_fields());
+        order_ident_mask.set(column_index(&spec.order_by[0].expr)?);
+
+        let mut partition_ident_mask = ClearBoolMask::none(ordered_schemas.max_num_fields());
+        if let Some(expr) = spec.partition_by.first() {
+            partition_ident_mask.set(column_index(expr)?);
+        }
+
+        Ok(AstWindow {
+            name,
+            func_mask,
+            order_ident_mask,
+            order_desc: spec.order_by[0].asc == Some(false),
+            partition_ident_mask,
+        })
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// ExtractWindowFunction
+////////////////////////////////////////////////////////////////////////////////
+
+pub trait ExtractWindowFunction {
+    /// Removes the window function (if any) from the projection and returns it.
+    fn extract_window_function(
+        &mut self,
+        ordered_schemas: &OrderedSchemas,
+    ) -> Result<Option<AstWindow>, FheSqlError>;
+}
+
+impl ExtractWindowFunction for Statement {
+    fn extract_window_function(
+        &mut self,
+        ordered_schemas: &OrderedSchemas,
+    ) -> Result<Option<AstWindow>, FheSqlError> {
+        let is_window_item = |item: &SelectItem| match item {
+            SelectItem::UnnamedExpr(Expr::Function(f)) => f.over.is_some(),
+            SelectItem::ExprWithAlias {
+                expr: Expr::Function(f),
+                ..
+            } => f.over.is_some(),
+            _ => false,
+        };
+
+        let num_windows = match &*self {
+            Statement::Query(query) => match query.body.as_ref() {
+                SetExpr::Select(s) => s.projection.iter().filter(|i| is_window_item(i)).count(),
+                _ => 0,
+            },
+            _ => 0,
+        };
+        if num_windows == 0 {
+            return Ok(None);
+        }
+        if num_windows > 1 {
+            return Err(FheSqlError::UnsupportedSqlQuery(
+                "At most one window function is supported".to_string(),
+            ));
+        }
+
+        let from = get_statement_from(self)?;
+        let table_index = match ordered_schemas
+            .compute_table_mask::<bool>(&from.0)
+            .index_of_first_set()
+        {
+            Some(idx) => idx,
+            None => return Err(FheSqlError::syntax_error("No table selected")),
+        };
+
+        let projection = match self {
+            Statement::Query(query) => match query.body.as_mut() {
+                SetExpr::Select(s) => &mut s.projection,
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+        let position = projection.iter().position(is_window_item).unwrap();
+        let item = projection.remove(position);
+        let window = match &item {
+            SelectItem::UnnamedExpr(Expr::Function(f)) => {
+                AstWindow::try_from_function(f, f.to_string(), ordered_schemas, table_index)?
+            }
+            SelectItem::ExprWithAlias {
+                expr: Expr::Function(f),
+                alias,
+            } => AstWindow::try_from_function(f, alias.value.clone(), ordered_schemas, table_index)?,
+            _ => unreachable!(),
+        };
+        Ok(Some(window))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::simple_batch::RecordBatchBuilder;
+    use crate::{OrderedTables, Table};
+    use arrow_array::types::Int32Type;
+    use sqlparser::{dialect::GenericDialect, parser::Parser};
+
+    fn ordered_schemas() -> OrderedSchemas {
+        let mut rb = RecordBatchBuilder::new();
+        rb.push_with_name::<Int32Type>("Price", vec![1, 2]);
+        rb.push_str_with_name("Name", vec!["a", "b"]);
+        let t = Table::new("Products", rb.finish());
+        OrderedTables::new(vec![t])
+            .unwrap()
+            .ordered_schemas()
+            .clone()
+    }
+
+    #[test]
+    fn test_extract() {
+        let ordered_schemas = ordered_schemas();
+        let dialect = GenericDialect {};
+        let sql = "SELECT Name, RANK() OVER (PARTITION BY Name ORDER BY Price DESC) AS r FROM Products";
+        let mut statements = Parser::parse_sql(&dialect, sql).unwrap();
+        let window = statements[0]
+            .extract_window_function(&ordered_schemas)
+            .unwrap()
+            .unwrap();
+        assert_eq!(statements[0].to_string(), "SELECT Name FROM Products");
+        assert_eq!(window.name, "r");
+        assert_eq!(window.func_mask.index_of_first_set(), Some(WindowFunc::Rank.index()));
+        assert_eq!(window.order_ident_mask.index_of_first_set(), Some(0));
+        assert!(window.order_desc);
+        assert_eq!(window.partition_ident_mask.index_of_first_set(), Some(1));
+    }
+
+    #[test]
+    fn test_extract_errors() {
+        let ordered_schemas = ordered_schemas();
+        let dialect = GenericDialect {};
+        let sqls = [
+            "SELECT ROW_NUMBER() OVER (ORDER BY Price), RANK() OVER (ORDER BY Price) FROM Products",
+            "SELECT ROW_NUMBER() OVER (ORDER BY Price, Name) FROM Products",
+            "SELECT ROW_NUMBER() OVER (PARTITION BY Price, Name ORDER BY Price) FROM Products",
+            "SELECT ROW_NUMBER() OVER (ORDER BY Unknown) FROM Products",
+            "SELECT SUM(Price) OVER (ORDER BY Price) FROM Products",
+        ];
+        sqls.iter().for_each(|sql| {
+            let mut statements = Parser::parse_sql(&dialect, sql).unwrap();
+            assert!(statements[0]
+                .extract_window_function(&ordered_schemas)
+                .is_err());
+        });
+    }
+}
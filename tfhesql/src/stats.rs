@@ -13,6 +13,7 @@ static U8_OR_COUNT: AtomicUsize = AtomicUsize::new(0);
 static U8_NOT_COUNT: AtomicUsize = AtomicUsize::new(0);
 
 static U8_IF_THEN_ELSE_COUNT: AtomicUsize = AtomicUsize::new(0);
+static U8_CMP_COUNT: AtomicUsize = AtomicUsize::new(0);
 
 pub fn inc_bool_and() {
     BOOL_AND_COUNT.fetch_add(1, Ordering::Relaxed);
@@ -36,6 +37,9 @@ pub fn inc_u8_not() {
 pub fn inc_u8_if_then_else() {
     U8_IF_THEN_ELSE_COUNT.fetch_add(1, Ordering::Relaxed);
 }
+pub fn inc_u8_cmp() {
+    U8_CMP_COUNT.fetch_add(1, Ordering::Relaxed);
+}
 
 #[derive(Debug, Clone)]
 pub struct PerfStats {
@@ -50,6 +54,7 @@ pub struct PerfStats {
     u8_or: usize,
     u8_not: usize,
     u8_if_then_else: usize,
+    u8_cmp: usize,
 }
 
 impl Default for PerfStats {
@@ -66,6 +71,7 @@ impl Default for PerfStats {
             u8_or: Default::default(),
             u8_not: Default::default(),
             u8_if_then_else: Default::default(),
+            u8_cmp: Default::default(),
         }
     }
 }
@@ -85,6 +91,7 @@ impl PerfStats {
         self.u8_or = s.u8_or - self.u8_or;
         self.u8_not = s.u8_not - self.u8_not;
         self.u8_if_then_else = s.u8_if_then_else - self.u8_if_then_else;
+        self.u8_cmp = s.u8_cmp - self.u8_cmp;
     }
 
     pub fn new(name: &str) -> PerfStats {
@@ -95,6 +102,7 @@ impl PerfStats {
         let u8_or = U8_OR_COUNT.load(std::sync::atomic::Ordering::Relaxed);
         let u8_not = U8_NOT_COUNT.load(std::sync::atomic::Ordering::Relaxed);
         let u8_if_then_else = U8_IF_THEN_ELSE_COUNT.load(std::sync::atomic::Ordering::Relaxed);
+        let u8_cmp = U8_CMP_COUNT.load(std::sync::atomic::Ordering::Relaxed);
         PerfStats {
             closed: false,
             name: name.to_string(),
@@ -107,6 +115,7 @@ impl PerfStats {
             u8_or,
             u8_not,
             u8_if_then_else,
+            u8_cmp,
         }
     }
 
@@ -143,6 +152,7 @@ impl PerfStats {
             prefix, self.bool_not, self.u8_not
         );
         println!("{}IF : {:?}", prefix, self.u8_if_then_else);
+        println!("{}CMP: u8:{:?}", prefix, self.u8_cmp);
     }
     pub fn print_vec(vec: &[PerfStats]) {
         if vec.is_empty() {
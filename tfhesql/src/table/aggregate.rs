@@ -0,0 +1,121 @@
+use super::Table;
+use crate::sql_ast::scalar_subquery::AggregateFunc;
+use arrow_array::cast::*;
+use arrow_array::types::*;
+use arrow_array::*;
+
+////////////////////////////////////////////////////////////////////////////////
+// AggregateValue
+////////////////////////////////////////////////////////////////////////////////
+
+/// Exact rational value `num / den` with `den > 0`
+/// (AVG is not always an integer)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct AggregateValue {
+    num: i128,
+    den: i128,
+}
+
+impl From<i128> for AggregateValue {
+    fn from(value: i128) -> Self {
+        AggregateValue { num: value, den: 1 }
+    }
+}
+
+impl AggregateValue {
+    /// Returns [left == self, left > self, left < self]
+    #[inline]
+    pub(crate) fn cmp_left(&self, left: i128) -> [bool; 3] {
+        let left = left * self.den;
+        [left == self.num, left > self.num, left < self.num]
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Table
+////////////////////////////////////////////////////////////////////////////////
+
+impl Table {
+    #[inline]
+    pub(crate) fn is_ascii_column(&self, column_index: usize) -> bool {
+        self.batch.schema().field(column_index).data_type() == &arrow_schema::DataType::Utf8
+    }
+
+    /// Numeric value of a table cell, strings are casted using the MySQL rules.
+    pub(crate) fn cell_as_i128(&self, column_index: usize, row_index: usize) -> i128 {
+        let column = self.batch.column(column_index);
+        match column.data_type() {
+            arrow_schema::DataType::Boolean => as_boolean_array(column).value(row_index) as i128,
+            arrow_schema::DataType::Int8 => as_primitive_array::<Int8Type>(column).value(row_index) as i128,
+            arrow_schema::DataType::Int16 => as_primitive_array::<Int16Type>(column).value(row_index) as i128,
+            arrow_schema::DataType::Int32 => as_primitive_array::<Int32Type>(column).value(row_index) as i128,
+            arrow_schema::DataType::Int64 => as_primitive_array::<Int64Type>(column).value(row_index) as i128,
+            arrow_schema::DataType::UInt8 => as_primitive_array::<UInt8Type>(column).value(row_index) as i128,
+            arrow_schema::DataType::UInt16 => as_primitive_array::<UInt16Type>(column).value(row_index) as i128,
+            arrow_schema::DataType::UInt32 => as_primitive_array::<UInt32Type>(column).value(row_index) as i128,
+            arrow_schema::DataType::UInt64 => as_primitive_array::<UInt64Type>(column).value(row_index) as i128,
+            arrow_schema::DataType::Utf8 => as_string_array(column)
+                .value(row_index)
+                .parse::<i128>()
+                .unwrap_or(0),
+            _ => panic!("called cell_as_i128 with wrong parameters"),
+        }
+    }
+
+    /// String value of an ascii table cell
+    #[inline]
+    pub(crate) fn cell_as_ascii(&self, column_index: usize, row_index: usize) -> &str {
+        as_string_array(self.batch.column(column_index)).value(row_index)
+    }
+
+    /// Computes `AGG(column)`. Returns `None` if the aggregate is NULL (empty table)
+    pub(crate) fn aggregate(&self, column_index: usize, func: AggregateFunc) -> Option<AggregateValue> {
+        let n = self.num_rows();
+        if func == AggregateFunc::Count {
+            return Some(AggregateValue::from(n as i128));
+        }
+        if n == 0 {
+            return None;
+        }
+        let values = (0..n).map(|row_index| self.cell_as_i128(column_index, row_index));
+        match func {
+            AggregateFunc::Count => unreachable!(),
+            AggregateFunc::Sum => Some(AggregateValue::from(values.sum::<i128>())),
+            AggregateFunc::Avg => Some(AggregateValue {
+                num: values.sum::<i128>(),
+                den: n as i128,
+            }),
+            AggregateFunc::Min => values.min().map(AggregateValue::from),
+            AggregateFunc::Max => values.max().map(AggregateValue::from),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::simple_batch::RecordBatchBuilder;
+
+    #[test]
+    fn test_aggregate() {
+        let mut rb = RecordBatchBuilder::new();
+        rb.push_with_name::<Int16Type>("a", vec![-3, 1, 4]);
+        rb.push_str_with_name("b", vec!["12", "x", "-2"]);
+        let t = Table::new("t", rb.finish());
+
+        assert_eq!(t.aggregate(0, AggregateFunc::Count), Some(AggregateValue::from(3)));
+        assert_eq!(t.aggregate(0, AggregateFunc::Sum), Some(AggregateValue::from(2)));
+        assert_eq!(t.aggregate(0, AggregateFunc::Min), Some(AggregateValue::from(-3)));
+        assert_eq!(t.aggregate(0, AggregateFunc::Max), Some(AggregateValue::from(4)));
+        assert_eq!(t.aggregate(1, AggregateFunc::Sum), Some(AggregateValue::from(10)));
+
+        // AVG = 2/3
+        let avg = t.aggregate(0, AggregateFunc::Avg).unwrap();
+        assert_eq!(avg.cmp_left(0), [false, false, true]);
+        assert_eq!(avg.cmp_left(1), [false, true, false]);
+    }
+}
@@ -1,4 +1,5 @@
 use rayon::iter::*;
+use std::borrow::Cow;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::usize;
@@ -19,7 +20,7 @@ use crate::{default_into::*, FheSqlError};
 // ByteRows
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
 pub struct ByteRows<U8> {
     rows: Vec<ByteArray<U8>>,
 }
@@ -193,8 +194,6 @@ pub struct ByteRowsList<U8> {
     list: Vec<ByteRows<U8>>,
 }
 
-pub type ClearByteRowsList = ByteRowsList<u8>;
-
 ////////////////////////////////////////////////////////////////////////////////
 
 impl<U8> ByteRowsList<U8> {
@@ -298,23 +297,28 @@ where
 // ClearByteRowsList
 ////////////////////////////////////////////////////////////////////////////////
 
-impl ClearByteRowsList {
-    pub fn par_bitand<U8>(self, select_x_table_mask_matrix: &ByteMaskMatrix<U8>) -> ByteRowsList<U8>
-    where
-        U8: Send
-            + Sync
-            + RefBitAnd<Output = U8>
-            + RefBitOr<Output = U8>
-            + Clone
-            + ValueFrom<u8>
-            + DefaultInto<U8>,
-    {
-        assert!(self.list.len() == select_x_table_mask_matrix.num_columns());
-        let mut obt = ByteRowsList::<U8>::alloc(self.len());
+impl<U8> ByteRowsList<U8>
+where
+    U8: Send
+        + Sync
+        + RefBitAnd<Output = U8>
+        + RefBitOr<Output = U8>
+        + Clone
+        + ValueFrom<u8>
+        + DefaultInto<U8>,
+{
+    /// The clear tables are borrowed, they are usually the precomputed encodings
+    /// shared by every query.
+    pub(crate) fn par_bitand_clear(
+        clear_tables: &[Cow<'_, ClearByteRows>],
+        select_x_table_mask_matrix: &ByteMaskMatrix<U8>,
+    ) -> ByteRowsList<U8> {
+        assert!(clear_tables.len() == select_x_table_mask_matrix.num_columns());
+        let mut obt = ByteRowsList::<U8>::alloc(clear_tables.len());
         obt.list
             .par_iter_mut()
             .zip(
-                self.list
+                clear_tables
                     .par_iter()
                     .zip(select_x_table_mask_matrix.columns.par_iter()),
             )
@@ -324,7 +328,9 @@ impl ClearByteRowsList {
 
         obt
     }
+}
 
+impl ByteRowsList<u8> {
     // pub(crate) fn num_ors<U8>(&self) -> usize
     // where
     //     U8: Send
@@ -0,0 +1,419 @@
+use std::ops::ControlFlow;
+
+use arrow_array::ArrayRef;
+use rayon::iter::*;
+use tfhe::{ClientKey, FheBool, FheUint8};
+
+use crate::ascii::ascii_to_le_u8x32;
+use crate::encrypt::traits::EncryptRef;
+use crate::error::FheSqlError;
+use crate::uint::ByteArray;
+
+use super::row_visitor::TableVisitor;
+use super::{OrderedSchemas, OrderedTables, Table};
+
+////////////////////////////////////////////////////////////////////////////////
+// CellKind
+////////////////////////////////////////////////////////////////////////////////
+
+/// How the cells of a column are compared
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Deserialize, serde::Serialize)]
+pub(crate) enum CellKind {
+    /// Booleans and unsigned integers
+    Unsigned,
+    /// Signed integers
+    Signed,
+    /// Strings: comparison of the ascii bytes
+    Ascii,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// EncryptedCell
+////////////////////////////////////////////////////////////////////////////////
+
+/// Number of bytes of the absolute value of an integer cell
+pub(crate) const CELL_INTEGER_BYTES: usize = 8;
+/// Number of ascii bytes of a string cell
+pub(crate) const CELL_ASCII_BYTES: usize = 32;
+
+#[derive(Clone, PartialEq, Eq, Debug, serde::Deserialize, serde::Serialize)]
+pub(crate) struct EncryptedCell<U8, B> {
+    /// Absolute value, in little endian order. A string cell holds the
+    /// integer it parses to (zero if it is not an integer).
+    pub(crate) le_bytes: Vec<U8>,
+    pub(crate) is_strictly_negative: B,
+    /// String cells only: the ascii bytes in char order, zero padded
+    pub(crate) ascii: Vec<U8>,
+}
+
+type ClearEncryptedCell = EncryptedCell<u8, bool>;
+
+impl ClearEncryptedCell {
+    fn from_integer(abs: u64, is_strictly_negative: bool) -> Self {
+        EncryptedCell {
+            le_bytes: abs.to_le_bytes().to_vec(),
+            is_strictly_negative,
+            ascii: vec![],
+        }
+    }
+
+    fn from_str(value: &str) -> Self {
+        // Same rule as the clear column to column comparison
+        let num = value.parse::<i128>().unwrap_or(0);
+        let abs = num.unsigned_abs().min(u64::MAX as u128) as u64;
+        EncryptedCell {
+            ascii: ascii_to_le_u8x32(value).to_vec(),
+            ..Self::from_integer(abs, num < 0)
+        }
+    }
+}
+
+impl<U8, B> EncryptedCell<U8, B> {
+    fn map<V8, VB>(&self, f8: &impl Fn(&U8) -> V8, fb: &impl Fn(&B) -> VB) -> EncryptedCell<V8, VB> {
+        EncryptedCell {
+            le_bytes: self.le_bytes.iter().map(f8).collect(),
+            is_strictly_negative: fb(&self.is_strictly_negative),
+            ascii: self.ascii.iter().map(f8).collect(),
+        }
+    }
+}
+
+/// Collects the cells of a row, one for each column
+#[derive(Default)]
+struct RowCells(Vec<ClearEncryptedCell>);
+
+impl TableVisitor for RowCells {
+    type Break = ();
+
+    fn visit_bool(&mut self, _: &ArrayRef, _: usize, _: usize, value: bool) -> ControlFlow<()> {
+        self.0.push(ClearEncryptedCell::from_integer(value as u64, false));
+        ControlFlow::Continue(())
+    }
+    fn visit_i8(&mut self, _: &ArrayRef, _: usize, _: usize, value: i8) -> ControlFlow<()> {
+        self.0.push(ClearEncryptedCell::from_integer(value.unsigned_abs() as u64, value < 0));
+        ControlFlow::Continue(())
+    }
+    fn visit_i16(&mut self, _: &ArrayRef, _: usize, _: usize, value: i16) -> ControlFlow<()> {
+        self.0.push(ClearEncryptedCell::from_integer(value.unsigned_abs() as u64, value < 0));
+        ControlFlow::Continue(())
+    }
+    fn visit_i32(&mut self, _: &ArrayRef, _: usize, _: usize, value: i32) -> ControlFlow<()> {
+        self.0.push(ClearEncryptedCell::from_integer(value.unsigned_abs() as u64, value < 0));
+        ControlFlow::Continue(())
+    }
+    fn visit_i64(&mut self, _: &ArrayRef, _: usize, _: usize, value: i64) -> ControlFlow<()> {
+        self.0.push(ClearEncryptedCell::from_integer(value.unsigned_abs(), value < 0));
+        ControlFlow::Continue(())
+    }
+    fn visit_u8(&mut self, _: &ArrayRef, _: usize, _: usize, value: u8) -> ControlFlow<()> {
+        self.0.push(ClearEncryptedCell::from_integer(value as u64, false));
+        ControlFlow::Continue(())
+    }
+    fn visit_u16(&mut self, _: &ArrayRef, _: usize, _: usize, value: u16) -> ControlFlow<()> {
+        self.0.push(ClearEncryptedCell::from_integer(value as u64, false));
+        ControlFlow::Continue(())
+    }
+    fn visit_u32(&mut self, _: &ArrayRef, _: usize, _: usize, value: u32) -> ControlFlow<()> {
+        self.0.push(ClearEncryptedCell::from_integer(value as u64, false));
+        ControlFlow::Continue(())
+    }
+    fn visit_u64(&mut self, _: &ArrayRef, _: usize, _: usize, value: u64) -> ControlFlow<()> {
+        self.0.push(ClearEncryptedCell::from_integer(value, false));
+        ControlFlow::Continue(())
+    }
+    fn visit_str(&mut self, _: &ArrayRef, _: usize, _: usize, value: &str) -> ControlFlow<()> {
+        self.0.push(ClearEncryptedCell::from_str(value));
+        ControlFlow::Continue(())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// EncryptedTable
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, PartialEq, Eq, Debug, serde::Deserialize, serde::Serialize)]
+pub(crate) struct EncryptedTable<U8, B> {
+    name: String,
+    kinds: Vec<CellKind>,
+    /// One cell per column, row after row
+    cells: Vec<Vec<EncryptedCell<U8, B>>>,
+    /// Uncompressed byte encoding of each row (EOF marker included),
+    /// zero padded to the width of the largest row of the table
+    byte_rows: Vec<ByteArray<U8>>,
+}
+
+impl EncryptedTable<u8, bool> {
+    fn from_table(table: &Table) -> Self {
+        let kinds = table
+            .iter_columns()
+            .map(|column| match column.data_type() {
+                arrow_schema::DataType::Utf8 => CellKind::Ascii,
+                arrow_schema::DataType::Int8
+                | arrow_schema::DataType::Int16
+                | arrow_schema::DataType::Int32
+                | arrow_schema::DataType::Int64 => CellKind::Signed,
+                _ => CellKind::Unsigned,
+            })
+            .collect::<Vec<CellKind>>();
+
+        let cells = (0..table.num_rows())
+            .into_par_iter()
+            .map(|row_index| {
+                let mut row = RowCells::default();
+                let _ = table.visit_row(&mut row, row_index);
+                assert_eq!(row.0.len(), kinds.len());
+                row.0
+            })
+            .collect::<Vec<Vec<ClearEncryptedCell>>>();
+
+        let mut byte_rows = table.to_bytes_rows(false).into_owned().into_byte_array_vec();
+        let width = byte_rows.iter().map(|r| r.len()).max().unwrap_or(0);
+        byte_rows.iter_mut().for_each(|r| r.bytes.resize(width, 0));
+
+        EncryptedTable {
+            name: table.name().clone(),
+            kinds,
+            cells,
+            byte_rows,
+        }
+    }
+}
+
+impl<U8, B> EncryptedTable<U8, B> {
+    #[inline]
+    pub(crate) fn num_rows(&self) -> usize {
+        self.cells.len()
+    }
+    #[inline]
+    pub(crate) fn num_columns(&self) -> usize {
+        self.kinds.len()
+    }
+    #[inline]
+    pub(crate) fn kind(&self, column_index: usize) -> CellKind {
+        self.kinds[column_index]
+    }
+    #[inline]
+    pub(crate) fn cell(&self, row_index: usize, column_index: usize) -> &EncryptedCell<U8, B> {
+        &self.cells[row_index][column_index]
+    }
+    #[inline]
+    pub(crate) fn byte_rows(&self) -> &Vec<ByteArray<U8>> {
+        &self.byte_rows
+    }
+}
+
+impl<U8, B> EncryptedTable<U8, B>
+where
+    U8: Send + Sync,
+    B: Send + Sync,
+{
+    fn map<V8, VB>(
+        &self,
+        f8: &(impl Fn(&U8) -> V8 + Sync),
+        fb: &(impl Fn(&B) -> VB + Sync),
+    ) -> EncryptedTable<V8, VB>
+    where
+        V8: Send,
+        VB: Send,
+    {
+        let (cells, byte_rows) = rayon::join(
+            || {
+                self.cells
+                    .par_iter()
+                    .map(|row| row.iter().map(|cell| cell.map(f8, fb)).collect())
+                    .collect()
+            },
+            || {
+                self.byte_rows
+                    .par_iter()
+                    .map(|row| ByteArray::from_bytes(row.bytes.par_iter().map(f8).collect()))
+                    .collect()
+            },
+        );
+        EncryptedTable {
+            name: self.name.clone(),
+            kinds: self.kinds.clone(),
+            cells,
+            byte_rows,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// EncryptedOrderedTables
+////////////////////////////////////////////////////////////////////////////////
+
+/// [OrderedTables] whose cells are encrypted under the data owner's [ClientKey].
+/// A query on these tables is run with
+/// [FheRunSqlQueryOnEncryptedTables](crate::FheRunSqlQueryOnEncryptedTables) and must
+/// be encrypted with the same key: the server never sees the cells, only the
+/// schemas and the number of rows of each table.
+///
+/// The structure is serializable, it can be stored encrypted on the server.
+///
+/// The server cannot compress encrypted cells: the queries must be built with
+/// `SqlResultOptions::default().with_compress(false)`, a query with the default
+/// options (compressed results) is rejected with [FheSqlError::UnsupportedSqlQuery](crate::FheSqlError::UnsupportedSqlQuery).
+///
+/// # Example
+///
+/// ```no_run
+/// # use tfhesql::*;
+/// # use tfhe::prelude::FheTryEncrypt;
+/// # fn example(tables: &OrderedTables, ck: &tfhe::ClientKey) {
+/// let enc_tables = FheOrderedTables::try_encrypt(tables, ck).unwrap();
+///
+/// let sql_client = FheSqlClient::new(tables.ordered_schemas().clone()).unwrap();
+/// let options = SqlResultOptions::default().with_compress(false);
+/// let enc_sql_query = sql_client
+///     .encrypt_sql("SELECT * FROM Customers", ck, options)
+///     .unwrap();
+/// let enc_result = FheSqlServer::run_on_encrypted_tables(&enc_sql_query, &enc_tables);
+/// # }
+/// ```
+#[derive(Clone, PartialEq, Eq, Debug, serde::Deserialize, serde::Serialize)]
+pub struct EncryptedOrderedTables<U8, B> {
+    ordered_schemas: OrderedSchemas,
+    tables: Vec<EncryptedTable<U8, B>>,
+}
+
+/// Clear version of [FheOrderedTables], runs the encrypted tables code path
+/// on clear values.
+pub type ClearEncryptedOrderedTables = EncryptedOrderedTables<u8, bool>;
+pub type FheOrderedTables = EncryptedOrderedTables<FheUint8, FheBool>;
+
+impl From<&OrderedTables> for ClearEncryptedOrderedTables {
+    fn from(value: &OrderedTables) -> Self {
+        EncryptedOrderedTables {
+            ordered_schemas: value.ordered_schemas().clone(),
+            tables: value
+                .tables()
+                .par_iter()
+                .map(EncryptedTable::from_table)
+                .collect(),
+        }
+    }
+}
+
+impl<U8, B> EncryptedOrderedTables<U8, B> {
+    /// Returns an immutable reference to the fixed-order list of all the tables schemas.
+    #[inline]
+    pub fn ordered_schemas(&self) -> &OrderedSchemas {
+        &self.ordered_schemas
+    }
+
+    #[inline]
+    pub fn num_tables(&self) -> usize {
+        self.tables.len()
+    }
+
+    #[inline]
+    pub(crate) fn tables(&self) -> &Vec<EncryptedTable<U8, B>> {
+        &self.tables
+    }
+
+    /// The number of rows of each table
+    pub(crate) fn tables_num_rows(&self) -> Vec<usize> {
+        self.tables.iter().map(|t| t.num_rows()).collect()
+    }
+
+    pub(crate) fn max_num_rows(&self) -> usize {
+        self.tables.iter().map(|t| t.num_rows()).max().unwrap_or(0)
+    }
+}
+
+impl<U8, B> EncryptedOrderedTables<U8, B>
+where
+    U8: Send + Sync,
+    B: Send + Sync,
+{
+    fn map<V8, VB>(
+        &self,
+        f8: impl Fn(&U8) -> V8 + Sync,
+        fb: impl Fn(&B) -> VB + Sync,
+    ) -> EncryptedOrderedTables<V8, VB>
+    where
+        V8: Send,
+        VB: Send,
+    {
+        EncryptedOrderedTables {
+            ordered_schemas: self.ordered_schemas.clone(),
+            tables: self.tables.iter().map(|t| t.map(&f8, &fb)).collect(),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// THFE public traits impl
+////////////////////////////////////////////////////////////////////////////////
+
+impl tfhe::prelude::FheTryEncrypt<&OrderedTables, ClientKey> for FheOrderedTables {
+    type Error = FheSqlError;
+
+    /// Implements the [FheTryEncrypt] trait
+    fn try_encrypt(value: &OrderedTables, key: &ClientKey) -> Result<Self, Self::Error> {
+        let clear = ClearEncryptedOrderedTables::from(value);
+        Ok(clear.map(
+            |x| FheUint8::encrypt_ref(x, key),
+            |x| FheBool::encrypt_ref(x, key),
+        ))
+    }
+}
+
+impl tfhe::prelude::FheDecrypt<ClearEncryptedOrderedTables> for FheOrderedTables {
+    /// Implements the [FheDecrypt] trait
+    fn decrypt(&self, key: &ClientKey) -> ClearEncryptedOrderedTables {
+        self.map(
+            |x| tfhe::prelude::FheDecrypt::<u8>::decrypt(x, key),
+            |x| tfhe::prelude::FheDecrypt::<bool>::decrypt(x, key),
+        )
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::simple_batch::{simple_batch_0, simple_batch_1};
+
+    #[test]
+    fn test_clear_encrypted_tables() {
+        let tables = OrderedTables::new(vec![
+            Table::new("table0", simple_batch_0()),
+            Table::new("table1", simple_batch_1()),
+        ])
+        .unwrap();
+        let enc_tables = ClearEncryptedOrderedTables::from(&tables);
+
+        assert_eq!(enc_tables.ordered_schemas(), tables.ordered_schemas());
+        assert_eq!(enc_tables.num_tables(), 2);
+        enc_tables
+            .tables()
+            .iter()
+            .zip(tables.tables())
+            .for_each(|(enc_table, table)| {
+                assert_eq!(&enc_table.name, table.name());
+                assert_eq!(enc_table.num_rows(), table.num_rows());
+                assert_eq!(enc_table.num_columns(), table.num_columns());
+                // Rows are padded to the same width, the EOF marker is kept
+                let width = enc_table.byte_rows()[0].len();
+                enc_table.byte_rows().iter().for_each(|r| {
+                    assert_eq!(r.len(), width);
+                    assert!(r.bytes.contains(&u8::MAX));
+                });
+                (0..table.num_rows()).for_each(|row_index| {
+                    (0..table.num_columns()).for_each(|column_index| {
+                        let cell = enc_table.cell(row_index, column_index);
+                        assert_eq!(cell.le_bytes.len(), CELL_INTEGER_BYTES);
+                        match enc_table.kind(column_index) {
+                            CellKind::Ascii => assert_eq!(cell.ascii.len(), CELL_ASCII_BYTES),
+                            _ => assert!(cell.ascii.is_empty()),
+                        }
+                    })
+                });
+            });
+    }
+}
@@ -2,6 +2,11 @@ mod schema;
 mod row_visitor;
 mod block_iter;
 mod type_cache;
+mod precomputed;
+mod mutation;
+mod encrypted;
+
+pub(crate) mod aggregate;
 
 pub mod byte_rows;
 pub mod cmp;
@@ -15,18 +20,27 @@ use rayon::iter::*;
 use crate::{
     csv,
     error::FheSqlError,
-    uint::{ClearByteArray, ClearByteArrayList},
+    uint::ClearByteArray,
     utils::{
         arrow::{
             array_column_cell_eq, arrow_shema_data_type_width, write_column_le_bytes,
             write_row_le_bytes,
         },
+        fnv1a_hash,
         path::{absolute_path, csv_sorted_list_in_dir},
+        FNV1A_OFFSET_BASIS,
     },
 };
 
-use byte_rows::{ClearByteRows, ClearByteRowsList};
+use byte_rows::ClearByteRows;
+use precomputed::{PrecomputedTables, TableEncodings};
+use type_cache::TableValueKeys;
 pub use schema::OrderedSchemas;
+pub use encrypted::{ClearEncryptedOrderedTables, EncryptedOrderedTables, FheOrderedTables};
+pub(crate) use encrypted::{CellKind, EncryptedCell, EncryptedTable};
+pub(crate) use encrypted::{CELL_ASCII_BYTES, CELL_INTEGER_BYTES};
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex, OnceLock};
 
 ////////////////////////////////////////////////////////////////////////////////
 // Table
@@ -38,6 +52,12 @@ pub use schema::OrderedSchemas;
 pub struct Table {
     name: String,
     batch: RecordBatch,
+    /// Byte encodings loaded by [OrderedTables::load_precomputed]
+    encodings: Option<Arc<TableEncodings>>,
+    /// Number of modifications since the table was created
+    version: u64,
+    /// Hash of the table content, computed on first use
+    fingerprint: OnceLock<u64>,
 }
 
 impl Table {
@@ -70,6 +90,9 @@ impl Table {
         Table {
             name: name.to_string(),
             batch,
+            encodings: None,
+            version: 0,
+            fingerprint: OnceLock::new(),
         }
     }
 
@@ -119,6 +142,13 @@ impl Table {
         self.name = name.to_string();
     }
 
+    /// Returns the number of times the table has been modified
+    /// (see [OrderedTables::execute])
+    #[inline]
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
     /// Returns the table's [RecordBatch] that stores the table dataset
     #[inline]
     pub(crate) fn batch(&self) -> &RecordBatch {
@@ -158,7 +188,37 @@ impl Table {
 }
 
 impl Table {
-    pub(crate) fn to_bytes_rows(&self, compress: bool) -> ClearByteRows {
+    /// Borrows the precomputed encoding if any, otherwise computes it
+    pub(crate) fn to_bytes_rows(&self, compress: bool) -> Cow<'_, ClearByteRows> {
+        match &self.encodings {
+            Some(encodings) => Cow::Borrowed(encodings.byte_rows(compress)),
+            None => Cow::Owned(self.compute_bytes_rows(compress)),
+        }
+    }
+
+    pub(crate) fn to_byte_array_in_row_order(&self, compress: bool) -> Cow<'_, ClearByteArray> {
+        match &self.encodings {
+            Some(encodings) => Cow::Borrowed(encodings.row_order(compress)),
+            None => Cow::Owned(self.compute_byte_array_in_row_order(compress)),
+        }
+    }
+
+    pub(crate) fn to_byte_array_in_column_order(
+        &self,
+        compress: bool,
+    ) -> Cow<'_, ClearByteArray> {
+        match &self.encodings {
+            Some(encodings) => Cow::Borrowed(encodings.column_order(compress)),
+            None => Cow::Owned(self.compute_byte_array_in_column_order(compress)),
+        }
+    }
+
+    /// The distinct values of each column, loaded by [OrderedTables::load_precomputed]
+    pub(crate) fn value_keys(&self) -> Option<&TableValueKeys> {
+        self.encodings.as_ref().map(|encodings| encodings.value_keys())
+    }
+
+    pub(super) fn compute_bytes_rows(&self, compress: bool) -> ClearByteRows {
         let mut clear_byte_array_vec: Vec<ClearByteArray> =
             vec![ClearByteArray::default(); self.num_rows()];
         clear_byte_array_vec
@@ -180,7 +240,7 @@ impl Table {
         ClearByteRows::from_byte_array_vec(clear_byte_array_vec)
     }
 
-    pub(crate) fn to_byte_array_in_row_order(&self, compress: bool) -> ClearByteArray {
+    pub(super) fn compute_byte_array_in_row_order(&self, compress: bool) -> ClearByteArray {
         let mut buffer = ClearByteArray::default();
         self.write_rows_le_bytes(&mut buffer);
 
@@ -193,7 +253,7 @@ impl Table {
         buffer
     }
 
-    pub(crate) fn to_byte_array_in_column_order(&self, compress: bool) -> ClearByteArray {
+    pub(super) fn compute_byte_array_in_column_order(&self, compress: bool) -> ClearByteArray {
         let mut buffer = ClearByteArray::default();
         self.write_columns_le_bytes(&mut buffer);
 
@@ -231,6 +291,17 @@ impl Table {
     }
 
     #[inline]
+    /// Hash of the uncompressed column order encoding, it identifies the table
+    /// content in the precomputed files and in the checkpoints. The hash (64 bits
+    /// FNV-1a) is stable across builds.
+    pub(crate) fn fingerprint(&self) -> u64 {
+        *self.fingerprint.get_or_init(|| {
+            let mut buffer = ClearByteArray::default();
+            self.write_columns_le_bytes(&mut buffer);
+            fnv1a_hash(FNV1A_OFFSET_BASIS, &buffer.bytes)
+        })
+    }
+
     pub(super) fn write_columns_le_bytes(&self, buffer: &mut ClearByteArray) {
         buffer.write_header(self.num_rows(), self.num_columns());
         (0..self.batch.num_columns())
@@ -305,8 +376,16 @@ impl Table {
 pub struct OrderedTables {
     pub(super) tables: Vec<Table>,
     pub(super) ordered_schemas: OrderedSchemas,
+    pub(super) version: u64,
+    /// The tables as seen by the clients built with older schemas, one entry
+    /// per schemas (see [restrict_to](OrderedTables::restrict_to))
+    pub(super) restricted: Mutex<Vec<Arc<OrderedTables>>>,
 }
 
+/// Maximum number of older client schemas whose projected tables are kept,
+/// the oldest entry is dropped first.
+const MAX_RESTRICTED_SCHEMAS: usize = 8;
+
 impl OrderedTables {
     /// Creates a new OrderedTables structure from a vector of [`Table`]. 
     ///
@@ -345,7 +424,12 @@ impl OrderedTables {
             assert_eq!(t.name(), ordered_schemas.name(i));
         });
 
-        Ok(OrderedTables { tables, ordered_schemas })
+        Ok(OrderedTables {
+            tables,
+            ordered_schemas,
+            version: 0,
+            restricted: Mutex::default(),
+        })
     }
 
     /// Creates a new OrderedTables structure by parsing all the .csv files located in the specified directory.
@@ -397,47 +481,171 @@ impl OrderedTables {
     pub fn par_iter_tables(&self) -> rayon::slice::Iter<Table> {
         self.tables.par_iter()
     }
+
+    /// Returns the tables as seen by a client built with `ordered_schemas`. If the tables
+    /// schemas are an append-only evolution of `ordered_schemas` (see
+    /// [OrderedSchemas::is_evolution_of]), the new tables and the new columns are left out,
+    /// as if the client masks were padded with false, and the result keeps the layout
+    /// expected by the client.
+    ///
+    /// The projected tables are computed on the first query of each older schemas and
+    /// cached until the tables are modified. If the tables are precomputed (see
+    /// [load_precomputed](OrderedTables::load_precomputed)), the encodings of the
+    /// projected tables are computed once as well.
+    ///
+    /// Returns `None` if the tables schemas are already `ordered_schemas`.
+    pub(crate) fn restrict_to(
+        &self,
+        ordered_schemas: &OrderedSchemas,
+    ) -> Result<Option<Arc<OrderedTables>>, FheSqlError> {
+        if &self.ordered_schemas == ordered_schemas {
+            return Ok(None);
+        }
+        if !self.ordered_schemas.is_evolution_of(ordered_schemas) {
+            return Err(FheSqlError::InvalidQueryError(
+                "The requested query schemas and tables schemas are incompatible".to_string(),
+            ));
+        }
+        if let Some(restricted) = self.find_restricted(ordered_schemas) {
+            return Ok(Some(restricted));
+        }
+        // The projection runs without holding the lock, a concurrent query
+        // with the same schemas may compute it too, only one is kept.
+        let restricted = Arc::new(self.project(ordered_schemas)?);
+        let mut cache = self.restricted.lock().unwrap();
+        if let Some(cached) = cache.iter().find(|t| &t.ordered_schemas == ordered_schemas) {
+            return Ok(Some(cached.clone()));
+        }
+        if cache.len() == MAX_RESTRICTED_SCHEMAS {
+            cache.remove(0);
+        }
+        cache.push(restricted.clone());
+        Ok(Some(restricted))
+    }
+
+    fn find_restricted(&self, ordered_schemas: &OrderedSchemas) -> Option<Arc<OrderedTables>> {
+        self.restricted
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|t| &t.ordered_schemas == ordered_schemas)
+            .cloned()
+    }
+
+    /// Drops the cached projections of [restrict_to](OrderedTables::restrict_to)
+    pub(super) fn clear_restricted(&mut self) {
+        self.restricted.get_mut().unwrap().clear();
+    }
+
+    /// Projects the tables on `ordered_schemas`, an older version of the tables schemas
+    fn project(&self, ordered_schemas: &OrderedSchemas) -> Result<OrderedTables, FheSqlError> {
+        let tables = (0..ordered_schemas.len())
+            .map(|i| {
+                let name = ordered_schemas.name(i);
+                let table = self.tables.iter().find(|t| &t.name == name).unwrap();
+                let indices = (0..ordered_schemas.schema(i).fields().len()).collect::<Vec<usize>>();
+                let batch = table
+                    .batch
+                    .project(&indices)
+                    .map_err(|err| FheSqlError::ArrowError(err.to_string()))?;
+                let mut projected = Table {
+                    name: name.clone(),
+                    batch,
+                    encodings: None,
+                    version: table.version,
+                    fingerprint: OnceLock::new(),
+                };
+                if table.encodings.is_some() {
+                    projected.encodings = Some(Arc::new(TableEncodings::compute(&projected)));
+                }
+                Ok(projected)
+            })
+            .collect::<Result<Vec<Table>, FheSqlError>>()?;
+        Ok(OrderedTables {
+            tables,
+            ordered_schemas: ordered_schemas.clone(),
+            version: self.version,
+            restricted: Mutex::default(),
+        })
+    }
 }
 
 impl OrderedTables {
-    pub(crate) fn to_byte_rows_list(&self, compress: bool) -> ClearByteRowsList {
-        let compressed_tables = self
-            .tables
+    /// Offline step: computes the byte encodings of every table, compressed and
+    /// uncompressed, and writes them to `dir` in a versioned binary format.
+    /// See [load_precomputed](OrderedTables::load_precomputed).
+    ///
+    /// The distinct values of each column are persisted as well, the type caches
+    /// of the WHERE clause are then filled without visiting the batches.
+    pub fn precompute<P: AsRef<std::path::Path>>(&self, dir: P) -> Result<(), FheSqlError> {
+        let precomputed = PrecomputedTables {
+            ordered_schemas: self.ordered_schemas.clone(),
+            num_rows: self.tables.iter().map(|t| t.num_rows()).collect(),
+            fingerprints: self.tables.par_iter().map(|t| t.fingerprint()).collect(),
+            encodings: self.tables.par_iter().map(TableEncodings::compute).collect(),
+        };
+        precomputed.write(dir.as_ref())
+    }
+
+    /// Loads the encodings written by [precompute](OrderedTables::precompute), the server
+    /// then uses them instead of encoding and compressing the tables for each query.
+    /// Fails if the file was written by another version or for other tables, the
+    /// content of each table is checked with its fingerprint.
+    pub fn load_precomputed<P: AsRef<std::path::Path>>(
+        &mut self,
+        dir: P,
+    ) -> Result<(), FheSqlError> {
+        let precomputed = PrecomputedTables::read(dir.as_ref())?;
+        let num_rows: Vec<usize> = self.tables.iter().map(|t| t.num_rows()).collect();
+        let fingerprints: Vec<u64> = self.tables.par_iter().map(|t| t.fingerprint()).collect();
+        if precomputed.ordered_schemas != self.ordered_schemas
+            || precomputed.num_rows != num_rows
+            || precomputed.fingerprints != fingerprints
+        {
+            return Err(FheSqlError::IoError(format!(
+                "The precomputed tables in {} do not match the tables",
+                dir.as_ref().display()
+            )));
+        }
+        self.tables
+            .iter_mut()
+            .zip(precomputed.encodings)
+            .for_each(|(table, encodings)| table.encodings = Some(Arc::new(encodings)));
+        self.clear_restricted();
+        Ok(())
+    }
+
+    /// Returns true if the table encodings have been loaded with
+    /// [load_precomputed](OrderedTables::load_precomputed)
+    pub fn is_precomputed(&self) -> bool {
+        self.tables.iter().all(|t| t.encodings.is_some())
+    }
+}
+
+impl OrderedTables {
+    pub(crate) fn to_byte_rows_list(&self, compress: bool) -> Vec<Cow<'_, ClearByteRows>> {
+        self.tables
             .par_iter()
             .map(|table| table.to_bytes_rows(compress))
-            .collect::<Vec<ClearByteRows>>();
-        ClearByteRowsList::from_byte_rows(compressed_tables)
+            .collect()
     }
 
     pub(crate) fn to_byte_array_list(
         &self,
         in_row_order: bool,
         compress: bool,
-    ) -> ClearByteArrayList {
-        let compressed_tables = if in_row_order {
-            self
-                .tables
+    ) -> Vec<Cow<'_, ClearByteArray>> {
+        if in_row_order {
+            self.tables
                 .par_iter()
                 .map(|table| table.to_byte_array_in_row_order(compress))
-                .collect::<Vec<ClearByteArray>>()
+                .collect()
         } else {
-            self
-                .tables
+            self.tables
                 .par_iter()
                 .map(|table| table.to_byte_array_in_column_order(compress))
-                .collect::<Vec<ClearByteArray>>()
-        };
-        ClearByteArrayList {
-            list: compressed_tables,
-        }
-    }
-
-    pub(crate) fn min_num_rows(&self) -> usize {
-        let mut min_num = usize::MAX;
-        for i in 0..self.num_tables() {
-            min_num = min_num.min(self.tables[i].num_rows());
+                .collect()
         }
-        min_num
     }
 
     pub(crate) fn max_num_rows(&self) -> usize {
@@ -448,26 +656,25 @@ impl OrderedTables {
         max_num
     }
 
-    pub(crate) fn encode_predicate_rows_gteq(&self, num_rows: usize) -> u64 {
-        assert!(self.num_tables() <= 64);
-        let mut code_u64 = 0_u64;
-        for i in 0..self.num_tables() {
-            if self.tables[i].num_rows() >= num_rows {
-                code_u64 |= (1 << i) as u64;
-            }
-        }
-        code_u64
-    }
-
-    pub(crate) fn rows_between(&self, min_rows: usize, max_rows: usize) -> Vec<usize> {
-        let mut overflow_vec = vec![];
-        for i in 0..self.num_tables() {
-            let n = self.tables[i].num_rows();
-            if min_rows <= n && n < max_rows {
-                overflow_vec.push(i);
-            }
+    /// Returns the rows [start, end) of every table, the tables with less
+    /// than `start` rows become empty. The ordered schemas are preserved.
+    pub(crate) fn slice_rows(&self, start: usize, end: usize) -> OrderedTables {
+        assert!(start <= end);
+        let tables = self
+            .tables
+            .iter()
+            .map(|table| {
+                let n = table.num_rows();
+                let offset = start.min(n);
+                Table::new(&table.name, table.batch.slice(offset, end.min(n) - offset))
+            })
+            .collect();
+        OrderedTables {
+            tables,
+            ordered_schemas: self.ordered_schemas.clone(),
+            version: self.version,
+            restricted: Mutex::default(),
         }
-        overflow_vec
     }
 }
 
@@ -483,6 +690,10 @@ mod test {
             table_customers,
         }, test_util::tfhesql_test_db_file, uint::mask::{ClearBoolMask, ClearByteMask, ClearByteMaskMatrix}, OrderedTables, Table
     };
+    use crate::uint::ByteArrayList;
+    use super::byte_rows::ByteRowsList;
+    use super::type_cache::TableValueKeys;
+    use std::borrow::Cow;
 
     #[test]
     fn test_load_csv() {
@@ -519,7 +730,8 @@ mod test {
         let byte_rows_list = ordered_tables.to_byte_rows_list(compress);
 
         assert!(byte_rows_list.len() == select_by_table_byte_matrix.num_columns());
-        let masked_byte_rows_list = byte_rows_list.par_bitand(&select_by_table_byte_matrix);
+        let masked_byte_rows_list =
+            ByteRowsList::par_bitand_clear(&byte_rows_list, &select_by_table_byte_matrix);
 
         let byte_rows = masked_byte_rows_list.par_flatten_row_by_row(padding);
 
@@ -559,11 +771,12 @@ mod test {
         let aa = byte_array_list.clone();
         // Apply Table(t) mask to each previously converted table.
         assert!(byte_array_list.len() == byte_table_mask.len());
-        let masked_byte_array_list = byte_array_list.par_bitand(&byte_table_mask);
+        let masked_byte_array_list =
+            ByteArrayList::par_bitand_clear(&byte_array_list, &byte_table_mask);
 
         // Flatten the list of tables (as list of byte) into a single list of bytes
         let byte_array = masked_byte_array_list.par_flatten();
-        assert_eq!(&byte_array, &aa.list[1]);
+        assert_eq!(&byte_array, aa[1].as_ref());
 
         let rb = byte_array
             .extract_record_batch(
@@ -590,7 +803,7 @@ mod test {
         tables.iter().for_each(|t| {
             let compress = false;
             let in_row_order = false;
-            let a = t.to_byte_array_in_column_order(compress);
+            let a = t.to_byte_array_in_column_order(compress).into_owned();
 
             let select_mask = ClearBoolMask::all(t.num_rows());
             let field_mask = ClearBoolMask::all(t.num_columns());
@@ -610,7 +823,7 @@ mod test {
         tables.iter().for_each(|t| {
             let compress = true;
             let in_row_order = false;
-            let a = t.to_byte_array_in_column_order(compress);
+            let a = t.to_byte_array_in_column_order(compress).into_owned();
 
             let select_mask = ClearBoolMask::all(t.num_rows());
             let field_mask = ClearBoolMask::all(t.num_columns());
@@ -640,7 +853,7 @@ mod test {
         tables.iter().for_each(|t| {
             let compress = false;
             let in_row_order = true;
-            let a = t.to_byte_array_in_row_order(compress);
+            let a = t.to_byte_array_in_row_order(compress).into_owned();
 
             let select_mask = ClearBoolMask::all(t.num_rows());
             let field_mask = ClearBoolMask::all(t.num_columns());
@@ -660,7 +873,7 @@ mod test {
         tables.iter().for_each(|t| {
             let compress = true;
             let in_row_order = true;
-            let a = t.to_byte_array_in_row_order(compress);
+            let a = t.to_byte_array_in_row_order(compress).into_owned();
 
             let select_mask = ClearBoolMask::all(t.num_rows());
             let field_mask = ClearBoolMask::all(t.num_columns());
@@ -677,8 +890,64 @@ mod test {
             assert_eq!(&rb, t.batch());
         });
     }
-}
 
+    #[test]
+    fn test_precompute() {
+        let dir = std::env::temp_dir().join(format!("tfhesql_precompute_{}", std::process::id()));
+        let new_tables = || {
+            OrderedTables::new(vec![
+                Table::new("table1", simple_batch_1()),
+                Table::new("table2", simple_batch_2()),
+            ])
+            .unwrap()
+        };
+
+        let tables = new_tables();
+        tables.precompute(&dir).unwrap();
+
+        let mut precomputed_tables = new_tables();
+        assert!(!precomputed_tables.is_precomputed());
+        precomputed_tables.load_precomputed(&dir).unwrap();
+        assert!(precomputed_tables.is_precomputed());
+
+        // The encodings are borrowed from the loaded file
+        assert!(precomputed_tables
+            .to_byte_rows_list(true)
+            .iter()
+            .all(|byte_rows| matches!(byte_rows, Cow::Borrowed(_))));
+        assert!(tables
+            .to_byte_rows_list(true)
+            .iter()
+            .all(|byte_rows| matches!(byte_rows, Cow::Owned(_))));
+
+        for (precomputed_table, table) in precomputed_tables.tables.iter().zip(tables.tables.iter())
+        {
+            assert_eq!(
+                precomputed_table.value_keys(),
+                Some(&TableValueKeys::compute(table))
+            );
+            assert_eq!(table.value_keys(), None);
+        }
 
+        for compress in [false, true] {
+            assert_eq!(
+                precomputed_tables.to_byte_rows_list(compress),
+                tables.to_byte_rows_list(compress)
+            );
+            for in_row_order in [false, true] {
+                assert_eq!(
+                    precomputed_tables.to_byte_array_list(in_row_order, compress),
+                    tables.to_byte_array_list(in_row_order, compress)
+                );
+            }
+        }
 
+        // Other tables
+        let mut other_tables =
+            OrderedTables::new(vec![Table::new("table3", simple_batch_3())]).unwrap();
+        assert!(other_tables.load_precomputed(&dir).is_err());
+        assert!(!other_tables.is_precomputed());
 
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,542 @@
+use std::sync::{Arc, OnceLock};
+
+use arrow_array::{Array, ArrayRef, BooleanArray, RecordBatch, Scalar, StringArray};
+use arrow_cast::cast::{cast_with_options, CastOptions};
+use arrow_schema::DataType;
+use sqlparser::ast::{
+    Assignment, Expr, FromTable, ObjectName, SetExpr, Statement, TableFactor, TableWithJoins,
+    UnaryOperator, Value,
+};
+use sqlparser::{dialect::GenericDialect, parser::Parser};
+
+use crate::error::FheSqlError;
+use crate::server::clear_where_mask;
+use crate::FheSqlClient;
+use crate::SqlResultOptions;
+
+use super::{OrderedTables, Table};
+
+////////////////////////////////////////////////////////////////////////////////
+// Table mutations
+////////////////////////////////////////////////////////////////////////////////
+
+impl OrderedTables {
+    /// Increased by one each time a table is modified. A client can compare
+    /// the version of the tables it queried with the current one to detect
+    /// stale results.
+    #[inline]
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Appends the rows of `batch` to the table named `table_name`.
+    /// `batch` must have the same schema as the table. Returns the number of inserted rows.
+    pub fn insert_batch(
+        &mut self,
+        table_name: &str,
+        batch: RecordBatch,
+    ) -> Result<usize, FheSqlError> {
+        let table_index = self.table_index(table_name)?;
+        let table = &self.tables[table_index];
+        if batch.schema().fields() != table.schema_ref().fields() {
+            return Err(FheSqlError::InvalidQueryError(format!(
+                "The inserted rows schema does not match the schema of table '{}'",
+                table_name
+            )));
+        }
+        let num_rows = batch.num_rows();
+        let new_batch =
+            arrow_select::concat::concat_batches(table.schema_ref(), [table.batch(), &batch])
+                .map_err(|err| FheSqlError::ArrowError(err.to_string()))?;
+        self.replace_batch(table_index, new_batch);
+        Ok(num_rows)
+    }
+
+    /// Removes the rows of the table named `table_name` flagged in `rows`.
+    /// Returns the number of deleted rows.
+    pub fn delete_rows(&mut self, table_name: &str, rows: &[bool]) -> Result<usize, FheSqlError> {
+        let table_index = self.table_index(table_name)?;
+        let table = &self.tables[table_index];
+        check_rows_len(table, rows)?;
+        let keep = BooleanArray::from(rows.iter().map(|r| !r).collect::<Vec<bool>>());
+        let new_batch = arrow_select::filter::filter_record_batch(table.batch(), &keep)
+            .map_err(|err| FheSqlError::ArrowError(err.to_string()))?;
+        let num_rows = table.num_rows() - new_batch.num_rows();
+        self.replace_batch(table_index, new_batch);
+        Ok(num_rows)
+    }
+
+    /// Sets the columns listed in `assignments` (column name, clear value) in the rows of
+    /// the table named `table_name` flagged in `rows`. Returns the number of updated rows.
+    pub fn update_rows(
+        &mut self,
+        table_name: &str,
+        rows: &[bool],
+        assignments: &[(&str, &str)],
+    ) -> Result<usize, FheSqlError> {
+        let table_index = self.table_index(table_name)?;
+        let table = &self.tables[table_index];
+        check_rows_len(table, rows)?;
+        let mask = BooleanArray::from(rows.to_vec());
+        let mut columns = table.batch().columns().to_vec();
+        for (column_name, value) in assignments {
+            let column_index = match table.schema_ref().index_of(column_name) {
+                Ok(i) => i,
+                Err(_) => return Err(FheSqlError::UnknownColumnName(column_name.to_string())),
+            };
+            let data_type = table.schema_ref().field(column_index).data_type();
+            let value = Scalar::new(values_to_array(&[value.to_string()], data_type)?);
+            columns[column_index] = arrow_select::zip::zip(&mask, &value, &columns[column_index])
+                .map_err(|err| FheSqlError::ArrowError(err.to_string()))?;
+        }
+        let new_batch = RecordBatch::try_new(table.schema_ref().clone(), columns)
+            .map_err(|err| FheSqlError::ArrowError(err.to_string()))?;
+        let num_rows = rows.iter().filter(|r| **r).count();
+        self.replace_batch(table_index, new_batch);
+        Ok(num_rows)
+    }
+
+    /// Executes a clear SQL `INSERT`, `UPDATE` or `DELETE` statement on the tables.
+    /// The `WHERE` clause supports the same expressions as a `SELECT` query.
+    /// Returns the number of inserted, updated or deleted rows.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::sync::Arc;
+    /// # use arrow_array::Int32Array;
+    /// # use arrow_schema::{DataType, Field, Schema};
+    /// # use tfhesql::{OrderedTables, Table};
+    ///
+    /// let schema = Schema::new(vec![Field::new("id", DataType::Int32, false)]);
+    /// let table = Table::try_new("table1", Arc::new(schema), vec![Arc::new(Int32Array::from(vec![1, 2]))]).unwrap();
+    /// let mut tables = OrderedTables::new(vec![table]).unwrap();
+    ///
+    /// assert_eq!(tables.execute("INSERT INTO table1 VALUES (3), (4)").unwrap(), 2);
+    /// assert_eq!(tables.execute("DELETE FROM table1 WHERE id > 2").unwrap(), 2);
+    /// assert_eq!(tables.version(), 2);
+    /// ```
+    pub fn execute(&mut self, sql: &str) -> Result<usize, FheSqlError> {
+        let dialect = GenericDialect {};
+        let mut statements = Parser::parse_sql(&dialect, sql)
+            .map_err(|err| FheSqlError::SyntaxError(err.to_string()))?;
+        if statements.len() != 1 {
+            return Err(FheSqlError::UnsupportedSqlStatement(sql.to_string()));
+        }
+        match statements.remove(0) {
+            Statement::Insert {
+                table_name,
+                columns,
+                source: Some(source),
+                ..
+            } => {
+                let rows = match source.body.as_ref() {
+                    SetExpr::Values(values) => &values.rows,
+                    _ => return Err(FheSqlError::UnsupportedSqlStatement(sql.to_string())),
+                };
+                self.execute_insert(&table_name, &columns, rows)
+            }
+            Statement::Update {
+                table,
+                assignments,
+                from: None,
+                selection,
+                returning: None,
+            } => {
+                let table_name = table_with_joins_name(&table)?;
+                let rows = self.where_rows(&table_name, selection.as_ref())?;
+                let assignments = assignments
+                    .iter()
+                    .map(assignment_to_string)
+                    .collect::<Result<Vec<(String, String)>, FheSqlError>>()?;
+                let assignments = assignments
+                    .iter()
+                    .map(|(c, v)| (c.as_str(), v.as_str()))
+                    .collect::<Vec<(&str, &str)>>();
+                self.update_rows(&table_name, &rows, &assignments)
+            }
+            Statement::Delete {
+                tables,
+                from: FromTable::WithFromKeyword(from),
+                using: None,
+                selection,
+                returning: None,
+                order_by,
+                limit: None,
+            } if tables.is_empty() && from.len() == 1 && order_by.is_empty() => {
+                let table_name = table_with_joins_name(&from[0])?;
+                let rows = self.where_rows(&table_name, selection.as_ref())?;
+                self.delete_rows(&table_name, &rows)
+            }
+            statement => Err(FheSqlError::UnsupportedSqlStatement(statement.to_string())),
+        }
+    }
+
+    fn execute_insert(
+        &mut self,
+        table_name: &ObjectName,
+        columns: &[sqlparser::ast::Ident],
+        rows: &[Vec<Expr>],
+    ) -> Result<usize, FheSqlError> {
+        let table_name = table_name.to_string();
+        let schema = self.tables[self.table_index(&table_name)?]
+            .schema_ref()
+            .clone();
+
+        // Position of each table column in the VALUES tuples
+        let positions = if columns.is_empty() {
+            (0..schema.fields().len()).collect::<Vec<usize>>()
+        } else {
+            if columns.len() != schema.fields().len() {
+                return Err(FheSqlError::InvalidQueryError(format!(
+                    "INSERT must set all the columns of table '{}'",
+                    table_name
+                )));
+            }
+            let mut positions = vec![];
+            for field in schema.fields() {
+                match columns.iter().position(|c| &c.value == field.name()) {
+                    Some(p) => positions.push(p),
+                    None => return Err(FheSqlError::UnknownColumnName(field.name().clone())),
+                }
+            }
+            positions
+        };
+
+        let mut values = vec![vec![]; schema.fields().len()];
+        for row in rows {
+            if row.len() != schema.fields().len() {
+                return Err(FheSqlError::InvalidQueryError(format!(
+                    "Expecting {} values, got {}",
+                    schema.fields().len(),
+                    row.len()
+                )));
+            }
+            for (column_index, position) in positions.iter().enumerate() {
+                values[column_index].push(literal_to_string(&row[*position])?);
+            }
+        }
+
+        let columns = schema
+            .fields()
+            .iter()
+            .zip(values)
+            .map(|(field, column_values)| values_to_array(&column_values, field.data_type()))
+            .collect::<Result<Vec<ArrayRef>, FheSqlError>>()?;
+        let batch = RecordBatch::try_new(schema, columns)
+            .map_err(|err| FheSqlError::ArrowError(err.to_string()))?;
+        self.insert_batch(&table_name, batch)
+    }
+
+    /// Evaluates the `WHERE` clause on the rows of the table named `table_name`
+    fn where_rows(
+        &self,
+        table_name: &str,
+        selection: Option<&Expr>,
+    ) -> Result<Vec<bool>, FheSqlError> {
+        let num_rows = self.tables[self.table_index(table_name)?].num_rows();
+        let selection = match selection {
+            Some(selection) => selection,
+            None => return Ok(vec![true; num_rows]),
+        };
+        let sql_client = FheSqlClient::new(self.ordered_schemas.clone())?;
+        let query = sql_client.clear_sql(
+            &format!("SELECT * FROM {} WHERE {}", table_name, selection),
+            SqlResultOptions::default(),
+        )?;
+        let mut rows = clear_where_mask(&query, self)?;
+        rows.truncate(num_rows);
+        Ok(rows)
+    }
+
+    fn table_index(&self, table_name: &str) -> Result<usize, FheSqlError> {
+        match self.tables.iter().position(|t| t.name() == table_name) {
+            Some(i) => Ok(i),
+            None => Err(FheSqlError::InvalidQueryError(format!(
+                "Unknown table '{}'",
+                table_name
+            ))),
+        }
+    }
+
+    /// The schema order is unchanged, only the precomputed encodings
+    /// of the modified table are dropped.
+    fn replace_batch(&mut self, table_index: usize, batch: RecordBatch) {
+        let table = &mut self.tables[table_index];
+        table.batch = batch;
+        table.encodings = None;
+        table.fingerprint = OnceLock::new();
+        table.version += 1;
+        self.version += 1;
+        self.clear_restricted();
+    }
+}
+
+fn check_rows_len(table: &Table, rows: &[bool]) -> Result<(), FheSqlError> {
+    if rows.len() != table.num_rows() {
+        return Err(FheSqlError::InvalidQueryError(format!(
+            "Expecting {} row flags for table '{}', got {}",
+            table.num_rows(),
+            table.name(),
+            rows.len()
+        )));
+    }
+    Ok(())
+}
+
+fn table_with_joins_name(table: &TableWithJoins) -> Result<String, FheSqlError> {
+    match &table.relation {
+        TableFactor::Table { name, .. } if table.joins.is_empty() => Ok(name.to_string()),
+        _ => Err(FheSqlError::UnsupportedSqlQuery(table.to_string())),
+    }
+}
+
+fn assignment_to_string(assignment: &Assignment) -> Result<(String, String), FheSqlError> {
+    let column_name = match assignment.id.last() {
+        Some(ident) => ident.value.clone(),
+        None => return Err(FheSqlError::unsupported_expr(&assignment.value)),
+    };
+    Ok((column_name, literal_to_string(&assignment.value)?))
+}
+
+/// Clear value of a literal, parsed later according to the column type
+fn literal_to_string(expr: &Expr) -> Result<String, FheSqlError> {
+    match expr {
+        Expr::Value(Value::Number(n, _)) => Ok(n.clone()),
+        Expr::Value(Value::SingleQuotedString(s)) => Ok(s.clone()),
+        Expr::Value(Value::Boolean(b)) => Ok(b.to_string()),
+        Expr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr,
+        } => match expr.as_ref() {
+            Expr::Value(Value::Number(n, _)) => Ok(format!("-{}", n)),
+            _ => Err(FheSqlError::unsupported_expr(expr)),
+        },
+        Expr::UnaryOp {
+            op: UnaryOperator::Plus,
+            expr,
+        } => literal_to_string(expr),
+        Expr::Nested(expr) => literal_to_string(expr),
+        _ => Err(FheSqlError::unsupported_expr(expr)),
+    }
+}
+
+fn values_to_array(values: &[String], data_type: &DataType) -> Result<ArrayRef, FheSqlError> {
+    let strings: ArrayRef = Arc::new(StringArray::from(values.to_vec()));
+    let options = CastOptions {
+        safe: false,
+        ..Default::default()
+    };
+    let array = cast_with_options(&strings, data_type, &options)
+        .map_err(|err| FheSqlError::ArrowError(err.to_string()))?;
+    assert_eq!(array.len(), values.len());
+    Ok(array)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use crate::test::simple_batch::{simple_batch_1, simple_batch_2};
+    use crate::{
+        FheRunSqlQuery, FheSqlClient, FheSqlServer, OrderedTables, SqlResultFormat,
+        SqlResultOptions, Table,
+    };
+
+    fn new_tables() -> OrderedTables {
+        OrderedTables::new(vec![
+            Table::new("table1", simple_batch_1()),
+            Table::new("table2", simple_batch_2()),
+        ])
+        .unwrap()
+    }
+
+    fn select_csv(tables: &OrderedTables, sql: &str) -> String {
+        let sql_client = FheSqlClient::new(tables.ordered_schemas().clone()).unwrap();
+        let query = sql_client
+            .clear_sql(sql, SqlResultOptions::default())
+            .unwrap();
+        FheSqlServer::run(&query, tables)
+            .unwrap()
+            .into_csv()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_insert_update_delete() {
+        let mut tables = new_tables();
+        let ordered_schemas = tables.ordered_schemas().clone();
+        assert_eq!(tables.version(), 0);
+
+        let n = tables
+            .execute("INSERT INTO table2 (Name, ProductID, Type, Style, Category) VALUES ('gh', -4, 8, 12, 16)")
+            .unwrap();
+        assert_eq!(n, 1);
+        assert_eq!(
+            select_csv(&tables, "SELECT ProductID, Name FROM table2"),
+            "ProductID:int8,Name:string\n5,ab\n2,cd\n3,ef\n-4,gh\n"
+        );
+
+        let n = tables
+            .execute("UPDATE table2 SET Name = 'zz', Type = 0 WHERE ProductID < 4")
+            .unwrap();
+        assert_eq!(n, 3);
+        assert_eq!(
+            select_csv(&tables, "SELECT ProductID, Type, Name FROM table2"),
+            "ProductID:int8,Type:int16,Name:string\n5,5,ab\n2,0,zz\n3,0,zz\n-4,0,zz\n"
+        );
+
+        let n = tables.execute("DELETE FROM table2 WHERE Type = 0").unwrap();
+        assert_eq!(n, 3);
+        assert_eq!(
+            select_csv(&tables, "SELECT ProductID, Name FROM table2"),
+            "ProductID:int8,Name:string\n5,ab\n"
+        );
+
+        let n = tables.execute("DELETE FROM table2").unwrap();
+        assert_eq!(n, 1);
+        assert_eq!(tables.tables()[1].num_rows(), 0);
+
+        // The schemas and the table order are unchanged
+        assert_eq!(tables.ordered_schemas(), &ordered_schemas);
+        assert_eq!(tables.version(), 4);
+        assert_eq!(tables.tables()[0].version(), 0);
+        assert_eq!(tables.tables()[1].version(), 4);
+    }
+
+    #[test]
+    fn test_select_empty_tables() {
+        let mut tables = new_tables();
+        tables.execute("DELETE FROM table1").unwrap();
+        tables.execute("DELETE FROM table2").unwrap();
+        assert_eq!(tables.max_num_rows(), 0);
+
+        // Every query returns an empty result
+        assert_eq!(
+            select_csv(&tables, "SELECT ProductID, Name FROM table2"),
+            "ProductID:int8,Name:string\n"
+        );
+        assert_eq!(
+            select_csv(&tables, "SELECT DISTINCT Name FROM table2 WHERE Type > 5"),
+            "Name:string\n"
+        );
+        assert_eq!(
+            select_csv(&tables, "SELECT CustomerID FROM table1 WHERE CustomerID = 21"),
+            "CustomerID:int8\n"
+        );
+
+        assert_eq!(
+            select_csv(
+                &tables,
+                "SELECT ProductID, ROW_NUMBER() OVER (ORDER BY Type DESC) AS rn FROM table2"
+            ),
+            "ProductID:int8,rn:uint64\n"
+        );
+        assert_eq!(
+            select_csv(
+                &tables,
+                "SELECT Name FROM table2 WHERE Type > (SELECT COUNT(*) FROM table1)"
+            ),
+            "Name:string\n"
+        );
+
+        let sql_client = FheSqlClient::new(tables.ordered_schemas().clone()).unwrap();
+        let formats = [
+            SqlResultFormat::RowBytes(true),
+            SqlResultFormat::TableBytesInRowOrder,
+            SqlResultFormat::TableBytesInColumnOrder,
+        ];
+        for format in formats {
+            for compress in [false, true] {
+                let options = SqlResultOptions::default()
+                    .with_format(format)
+                    .with_compress(compress);
+                let query = sql_client
+                    .clear_sql("SELECT DISTINCT * FROM table2 WHERE Type > 5", options)
+                    .unwrap();
+                let rb = FheSqlServer::run(&query, &tables)
+                    .unwrap()
+                    .into_record_batch()
+                    .unwrap();
+                assert_eq!(rb.num_rows(), 0);
+                assert_eq!(rb.num_columns(), 5);
+            }
+        }
+        let options = SqlResultOptions::default().with_max_num_rows(2);
+        let query = sql_client
+            .clear_sql("SELECT Name FROM table2", options)
+            .unwrap();
+        assert_eq!(
+            FheSqlServer::run(&query, &tables).unwrap().into_csv().unwrap(),
+            "Name:string\n"
+        );
+        let query = sql_client
+            .clear_sql("SELECT Name FROM table2", SqlResultOptions::default())
+            .unwrap();
+        for chunk in FheSqlServer::run_streaming(&query, &tables, 1, &Default::default()).unwrap() {
+            assert_eq!(chunk.unwrap().into_record_batch().unwrap().num_rows(), 0);
+        }
+    }
+
+    #[test]
+    fn test_mutation_errors() {
+        let mut tables = new_tables();
+        assert!(tables.execute("INSERT INTO table3 VALUES (1)").is_err());
+        assert!(tables.execute("INSERT INTO table2 VALUES (1, 2)").is_err());
+        assert!(tables
+            .execute("INSERT INTO table2 VALUES (1000, 1, 1, 1, 'a')")
+            .is_err());
+        assert!(tables.execute("UPDATE table2 SET Foo = 1").is_err());
+        assert!(tables.execute("DROP TABLE table2").is_err());
+        assert!(tables.execute("DELETE FROM table2 WHERE").is_err());
+        assert_eq!(tables.version(), 0);
+    }
+
+    #[test]
+    fn test_mutation_invalidates_precomputed() {
+        let dir = std::env::temp_dir().join(format!("tfhesql_mutation_{}", std::process::id()));
+        let mut tables = new_tables();
+        tables.precompute(&dir).unwrap();
+        tables.load_precomputed(&dir).unwrap();
+
+        tables.delete_rows("table2", &[true, false, false]).unwrap();
+        assert!(tables.tables()[0].encodings.is_some());
+        assert!(tables.tables()[1].encodings.is_none());
+        assert_eq!(
+            tables.to_byte_rows_list(true),
+            new_tables_without_first_row().to_byte_rows_list(true)
+        );
+
+        // The precomputed file no longer matches
+        assert!(tables.load_precomputed(&dir).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_update_invalidates_precomputed() {
+        let dir = std::env::temp_dir().join(format!("tfhesql_update_{}", std::process::id()));
+        let mut tables = new_tables();
+        tables.precompute(&dir).unwrap();
+
+        // Same number of rows, other cell values
+        tables
+            .update_rows("table2", &[true, false, false], &[("Type", "99")])
+            .unwrap();
+        assert!(tables.load_precomputed(&dir).is_err());
+        assert!(!tables.is_precomputed());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn new_tables_without_first_row() -> OrderedTables {
+        let batch_2 = simple_batch_2();
+        OrderedTables::new(vec![
+            Table::new("table1", simple_batch_1()),
+            Table::new("table2", batch_2.slice(1, batch_2.num_rows() - 1)),
+        ])
+        .unwrap()
+    }
+}
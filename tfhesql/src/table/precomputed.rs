@@ -0,0 +1,128 @@
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::error::FheSqlError;
+use crate::uint::ClearByteArray;
+
+use super::byte_rows::ClearByteRows;
+use super::type_cache::TableValueKeys;
+use super::{OrderedSchemas, Table};
+
+////////////////////////////////////////////////////////////////////////////////
+// TableEncodings
+////////////////////////////////////////////////////////////////////////////////
+
+/// Format version of the precomputed file, bumped each time the byte
+/// encoding of a table or the layout of [TableEncodings] changes.
+pub(super) const PRECOMPUTED_VERSION: u32 = 3;
+pub(super) const PRECOMPUTED_FILE: &str = "tables.precomputed";
+
+/// The clear byte encodings of a table, as sent back by the server.
+/// Each encoding is stored uncompressed (index 0) and compressed (index 1).
+/// The distinct values of each column fill the type caches of the WHERE clause.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub(crate) struct TableEncodings {
+    byte_rows: [ClearByteRows; 2],
+    row_order: [ClearByteArray; 2],
+    column_order: [ClearByteArray; 2],
+    value_keys: TableValueKeys,
+}
+
+impl TableEncodings {
+    pub(super) fn compute(table: &Table) -> Self {
+        let ((byte_rows, value_keys), (row_order, column_order)) = rayon::join(
+            || {
+                rayon::join(
+                    || [false, true].map(|compress| table.compute_bytes_rows(compress)),
+                    || TableValueKeys::compute(table),
+                )
+            },
+            || {
+                rayon::join(
+                    || {
+                        [false, true]
+                            .map(|compress| table.compute_byte_array_in_row_order(compress))
+                    },
+                    || {
+                        [false, true]
+                            .map(|compress| table.compute_byte_array_in_column_order(compress))
+                    },
+                )
+            },
+        );
+        TableEncodings {
+            byte_rows,
+            row_order,
+            column_order,
+            value_keys,
+        }
+    }
+
+    #[inline]
+    pub(super) fn byte_rows(&self, compress: bool) -> &ClearByteRows {
+        &self.byte_rows[compress as usize]
+    }
+
+    #[inline]
+    pub(super) fn row_order(&self, compress: bool) -> &ClearByteArray {
+        &self.row_order[compress as usize]
+    }
+
+    #[inline]
+    pub(super) fn column_order(&self, compress: bool) -> &ClearByteArray {
+        &self.column_order[compress as usize]
+    }
+
+    #[inline]
+    pub(super) fn value_keys(&self) -> &TableValueKeys {
+        &self.value_keys
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// PrecomputedTables
+////////////////////////////////////////////////////////////////////////////////
+
+/// Content of the precomputed file, preceded by [PRECOMPUTED_VERSION].
+/// The schemas, the row counts and the content fingerprints identify the tables
+/// the encodings belong to.
+#[derive(serde::Deserialize, serde::Serialize)]
+pub(super) struct PrecomputedTables {
+    pub(super) ordered_schemas: OrderedSchemas,
+    pub(super) num_rows: Vec<usize>,
+    pub(super) fingerprints: Vec<u64>,
+    pub(super) encodings: Vec<TableEncodings>,
+}
+
+impl PrecomputedTables {
+    pub(super) fn write(&self, dir: &Path) -> Result<(), FheSqlError> {
+        std::fs::create_dir_all(dir)?;
+        let map_err = |err: bincode::Error| FheSqlError::IoError(err.to_string());
+        let path = dir.join(PRECOMPUTED_FILE);
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut writer = BufWriter::new(std::fs::File::create(&tmp_path)?);
+            bincode::serialize_into(&mut writer, &PRECOMPUTED_VERSION).map_err(map_err)?;
+            bincode::serialize_into(&mut writer, self).map_err(map_err)?;
+            writer.flush()?;
+        }
+        std::fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    pub(super) fn read(dir: &Path) -> Result<Self, FheSqlError> {
+        let map_err = |err: bincode::Error| FheSqlError::IoError(err.to_string());
+        let path = dir.join(PRECOMPUTED_FILE);
+        let mut reader = BufReader::new(std::fs::File::open(&path)?);
+        let version: u32 = bincode::deserialize_from(&mut reader).map_err(map_err)?;
+        if version != PRECOMPUTED_VERSION {
+            return Err(FheSqlError::IoError(format!(
+                "Unsupported precomputed tables version {} in {} (expected {})",
+                version,
+                path.display(),
+                PRECOMPUTED_VERSION
+            )));
+        }
+        bincode::deserialize_from(&mut reader).map_err(map_err)
+    }
+}
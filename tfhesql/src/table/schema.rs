@@ -246,6 +246,47 @@ impl OrderedSchemas {
         Ok(m)
     }
 
+    /// Returns `true` if `self` is an append-only evolution of `older`: every table of
+    /// `older` is still present with the same name, and its columns are the first
+    /// columns of the new table. The evolution can add new tables and new columns at
+    /// the end of the existing tables, it cannot rename, remove, reorder or retype them.
+    ///
+    /// The server accepts the queries of the clients built with `older`, see
+    /// [FheRunSqlQuery](crate::FheRunSqlQuery).
+    pub fn is_evolution_of(&self, older: &OrderedSchemas) -> bool {
+        self.evolution_mismatches(older).is_empty()
+    }
+
+    /// Describes each table of `older` that breaks the evolution (see
+    /// [is_evolution_of](OrderedSchemas::is_evolution_of)), in the order of `older`
+    pub(crate) fn evolution_mismatches(&self, older: &OrderedSchemas) -> Vec<String> {
+        older
+            .ordered_schemas
+            .iter()
+            .filter_map(|older_named| {
+                let Some(named) = self.ordered_schemas.iter().find(|n| n.name == older_named.name)
+                else {
+                    return Some(format!("table '{}' was removed", older_named.name));
+                };
+                let fields = named.schema.fields();
+                let older_fields = older_named.schema.fields();
+                match older_fields.iter().zip(fields.iter()).find(|(a, b)| a != b) {
+                    Some((a, _)) => Some(format!(
+                        "column '{}' of table '{}' was renamed, moved or retyped",
+                        a.name(),
+                        older_named.name
+                    )),
+                    None if older_fields.len() > fields.len() => Some(format!(
+                        "column '{}' of table '{}' was removed",
+                        older_fields[fields.len()].name(),
+                        older_named.name
+                    )),
+                    None => None,
+                }
+            })
+            .collect()
+    }
+
     pub(crate) fn compute_table_mask<T>(&self, idents: &[sqlparser::ast::Ident]) -> Mask<T>
     where
         T: UIntType + Clone,
@@ -263,6 +304,8 @@ impl OrderedSchemas {
 #[cfg(test)]
 mod test {
     use crate::test_util::tfhesql_test_db_dir;
+    use arrow_schema::{DataType, Field, Schema};
+    use std::sync::Arc;
 
     use super::OrderedSchemas;
 
@@ -279,4 +322,36 @@ mod test {
 
         std::fs::remove_file("./test/schemas.json").unwrap();
     }
+
+    #[test]
+    fn test_evolution() {
+        let schemas = |tables: Vec<(&str, Vec<&str>)>| {
+            let named_schemas = tables
+                .into_iter()
+                .map(|(name, fields)| {
+                    let fields = fields
+                        .into_iter()
+                        .map(|f| Field::new(f, DataType::UInt32, false))
+                        .collect::<Vec<Field>>();
+                    (Arc::new(Schema::new(fields)), name.to_string())
+                })
+                .collect();
+            OrderedSchemas::from_schemas(named_schemas).unwrap()
+        };
+
+        let os = schemas(vec![("B", vec!["id", "x"])]);
+        assert!(os.is_evolution_of(&os));
+
+        // New column at the end, new table before the existing one
+        let new_os = schemas(vec![("A", vec!["id"]), ("B", vec!["id", "x", "y"])]);
+        assert!(new_os.is_evolution_of(&os));
+        assert!(!os.is_evolution_of(&new_os));
+
+        // New column in the middle
+        assert!(!schemas(vec![("B", vec!["id", "y", "x"])]).is_evolution_of(&os));
+        // Removed column
+        assert!(!schemas(vec![("B", vec!["id"])]).is_evolution_of(&os));
+        // Renamed table
+        assert!(!schemas(vec![("C", vec!["id", "x"])]).is_evolution_of(&os));
+    }
 }
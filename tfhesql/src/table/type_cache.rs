@@ -215,12 +215,48 @@ where
     /// - key = Tables[k][i]
     /// - value = Default
     /// - Cache(Tables[k][i]) = Default
+    ///
+    /// Precomputed tables are filled from their [TableValueKeys], in the same
+    /// order as a visit of the batches.
     #[inline]
     pub fn default_into_from_ordered_tables(&mut self, tables: &OrderedTables) {
         assert!(!self.value_cache_dropped);
+        if tables.is_precomputed() {
+            tables
+                .tables()
+                .iter()
+                .for_each(|table| self.default_into_from_value_keys(table.value_keys().unwrap()));
+            return;
+        }
         let mut c = Fill::<T> { cache: self };
         c.fill_default_into(tables);
     }
+
+    fn default_into_from_value_keys(&mut self, value_keys: &TableValueKeys) {
+        macro_rules! fill {
+            ($keys:tt, $ty:ty) => {
+                value_keys.$keys.iter().for_each(|(value, column_index)| {
+                    self.value_cache
+                        .insert_key::<$ty>(value.clone(), (), &T::default_into());
+                    self.column_value_cache.insert_key::<$ty>(
+                        value.clone(),
+                        *column_index,
+                        &T::default_into(),
+                    );
+                });
+            };
+        }
+        fill!(bool_keys, bool);
+        fill!(i8_keys, i8);
+        fill!(u8_keys, u8);
+        fill!(i16_keys, i16);
+        fill!(u16_keys, u16);
+        fill!(i32_keys, i32);
+        fill!(u32_keys, u32);
+        fill!(i64_keys, i64);
+        fill!(u64_keys, u64);
+        fill!(str_keys, String);
+    }
 }
 
 impl<T> TypedTableValueCache<T>
@@ -321,6 +357,47 @@ where
     }
 }
 
+////////////////////////////////////////////////////////////////////////////////
+// TableValueKeys
+////////////////////////////////////////////////////////////////////////////////
+
+/// The distinct (value, column index) pairs of a table, in visit order.
+/// Persisted with the precomputed encodings so that the type caches are filled
+/// without visiting every cell of the table.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub(crate) struct TableValueKeys {
+    bool_keys: Vec<(bool, usize)>,
+    i8_keys: Vec<(i8, usize)>,
+    u8_keys: Vec<(u8, usize)>,
+    i16_keys: Vec<(i16, usize)>,
+    u16_keys: Vec<(u16, usize)>,
+    i32_keys: Vec<(i32, usize)>,
+    u32_keys: Vec<(u32, usize)>,
+    i64_keys: Vec<(i64, usize)>,
+    u64_keys: Vec<(u64, usize)>,
+    str_keys: Vec<(String, usize)>,
+}
+
+impl TableValueKeys {
+    pub(super) fn compute(table: &Table) -> Self {
+        let mut cache = TypedTableValueCache::<bool>::default();
+        let _ = table.visit(&mut Fill::<bool> { cache: &mut cache });
+        let column_value_cache = cache.column_value_cache;
+        TableValueKeys {
+            bool_keys: column_value_cache.bool_cache.into_keys(),
+            i8_keys: column_value_cache.i8_cache.into_keys(),
+            u8_keys: column_value_cache.u8_cache.into_keys(),
+            i16_keys: column_value_cache.i16_cache.into_keys(),
+            u16_keys: column_value_cache.u16_cache.into_keys(),
+            i32_keys: column_value_cache.i32_cache.into_keys(),
+            u32_keys: column_value_cache.u32_cache.into_keys(),
+            i64_keys: column_value_cache.i64_cache.into_keys(),
+            u64_keys: column_value_cache.u64_cache.into_keys(),
+            str_keys: column_value_cache.str_cache.into_keys(),
+        }
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Fill
 ////////////////////////////////////////////////////////////////////////////////
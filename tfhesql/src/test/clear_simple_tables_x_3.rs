@@ -7,8 +7,9 @@ use crate::{
     test::simple_batch::{
         simple_batch_1, simple_batch_2, simple_batch_3, simple_batch_4, simple_batch_5,
     },
-    ClearSqlResult, FheRunSqlQuery, FheSqlClient, FheSqlServer, OrderedTables, SqlResultOptions,
-    Table,
+    ClearSqlResult, ClearSqlResultChunk, ClearSqlResultHeader, CoercionMode, DistinctStrategy,
+    FheRunSqlQuery, FheSqlClient, FheSqlServer, OrderedTables, QueryPadding, RunControl,
+    RunProgress, RunStage, SqlCheckpoint, SqlResultOptions, SqlServerOptions, Table,
 };
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -259,3 +260,1232 @@ fn test7() {
 
     assert_eq!(&rb, &expected_batch);
 }
+
+#[test]
+fn test_scalar_subquery() {
+    // table3: ProductID = [50, 100, 100], Type = [50, 500, 600]
+    // AVG(Type) = 383.33
+    let sqls = [
+        "SELECT ProductID FROM table3 WHERE Type > (SELECT AVG(Type) FROM table3)",
+        "SELECT ProductID FROM table3 WHERE (SELECT AVG(Type) FROM table3) < Type",
+        "SELECT ProductID FROM table3 WHERE ProductID = (SELECT MAX(ProductID) FROM table3)",
+        "SELECT ProductID FROM table3 WHERE ProductID > (SELECT COUNT(*) FROM table2) AND Type >= (SELECT SUM(Type) FROM table2) AND Type != 50",
+    ];
+
+    // Two lines : 100, 100
+    let expected_batch = RecordBatch::try_new(
+        Arc::new(Schema::new(vec![Field::new(
+            "ProductID",
+            DataType::Int16,
+            false,
+        )])),
+        vec![Arc::new(Int16Array::from(vec![100, 100]))],
+    )
+    .unwrap();
+
+    sqls.iter().for_each(|sql| {
+        run_test(sql, &expected_batch);
+    });
+}
+
+#[test]
+fn test_window_function() {
+    // table3: ProductID = [50, 100, 100], Type = [50, 500, 600]
+    let sqls = [
+        (
+            "SELECT ProductID, ROW_NUMBER() OVER (ORDER BY Type DESC) AS rn FROM table3",
+            "rn",
+            vec![3, 2, 1],
+        ),
+        (
+            "SELECT ProductID, RANK() OVER (ORDER BY ProductID) AS rn FROM table3",
+            "rn",
+            vec![1, 2, 2],
+        ),
+        (
+            "SELECT ProductID, ROW_NUMBER() OVER (PARTITION BY ProductID ORDER BY Type) AS rn FROM table3",
+            "rn",
+            vec![1, 1, 2],
+        ),
+    ];
+
+    sqls.iter().for_each(|(sql, name, values)| {
+        let expected_batch = RecordBatch::try_new(
+            Arc::new(Schema::new(vec![
+                Field::new("ProductID", DataType::Int16, false),
+                Field::new(*name, DataType::UInt64, false),
+            ])),
+            vec![
+                Arc::new(Int16Array::from(vec![50, 100, 100])),
+                Arc::new(UInt64Array::from(values.clone())),
+            ],
+        )
+        .unwrap();
+        run_test(sql, &expected_batch);
+    });
+
+    // Filtered rows are not counted
+    let sql = "SELECT RANK() OVER (ORDER BY Type) AS r FROM table3 WHERE Type > 50";
+    let expected_batch = RecordBatch::try_new(
+        Arc::new(Schema::new(vec![Field::new("r", DataType::UInt64, false)])),
+        vec![Arc::new(UInt64Array::from(vec![1, 2]))],
+    )
+    .unwrap();
+    run_test(sql, &expected_batch);
+}
+
+#[test]
+fn test_column_arithmetic() {
+    // table3: ProductID = [50, 100, 100], Type = [50, 500, 600]
+    let sqls = [
+        "SELECT ProductID FROM table3 WHERE ProductID * Type > 10000",
+        "SELECT ProductID FROM table3 WHERE Type - ProductID >= 400",
+        "SELECT ProductID FROM table3 WHERE ProductID - Type < -100",
+        "SELECT ProductID FROM table3 WHERE ProductID / Type < 1",
+        "SELECT ProductID FROM table3 WHERE Type / ProductID != 1 AND Type + ProductID > 0",
+        "SELECT ProductID FROM table3 WHERE ProductID / Type < 1 AND ProductID / Type > 0",
+        // Constants folded into the compared value
+        "SELECT ProductID FROM table3 WHERE Type + 3 > 70",
+        "SELECT ProductID FROM table3 WHERE 450 < Type - 3",
+        "SELECT ProductID FROM table3 WHERE ProductID * 3 = 300",
+        "SELECT ProductID FROM table3 WHERE ProductID * -2 <= -101",
+        "SELECT ProductID FROM table3 WHERE NOT Type / 7 < 10",
+        "SELECT ProductID FROM table3 WHERE Type / -100 < -1 AND ProductID + -1 <> 49",
+        "SELECT ProductID FROM table3 WHERE ProductID - 50 AND ProductID / 0 = 1 OR ProductID / 3 > 33",
+    ];
+
+    // Two lines : 100, 100
+    let expected_batch = RecordBatch::try_new(
+        Arc::new(Schema::new(vec![Field::new(
+            "ProductID",
+            DataType::Int16,
+            false,
+        )])),
+        vec![Arc::new(Int16Array::from(vec![100, 100]))],
+    )
+    .unwrap();
+
+    sqls.iter().for_each(|sql| {
+        run_test(sql, &expected_batch);
+    });
+}
+
+#[test]
+fn test_bitwise_and_modulo() {
+    // table3: ProductID = [50, 100, 100], Type = [50, 500, 600]
+    let sqls = [
+        "SELECT ProductID FROM table3 WHERE Type % 100 = 0",
+        "SELECT ProductID FROM table3 WHERE ProductID % 7 = 2",
+        "SELECT ProductID FROM table3 WHERE Type & 448 <> 0",
+        "SELECT ProductID FROM table3 WHERE Type | 1 > 100",
+        "SELECT ProductID FROM table3 WHERE Type ^ ProductID <> 0",
+        "SELECT ProductID FROM table3 WHERE Type >> 8 >= 1",
+        "SELECT ProductID FROM table3 WHERE ProductID << 1 = 200",
+    ];
+
+    // Two lines : 100, 100
+    let expected_batch = RecordBatch::try_new(
+        Arc::new(Schema::new(vec![Field::new(
+            "ProductID",
+            DataType::Int16,
+            false,
+        )])),
+        vec![Arc::new(Int16Array::from(vec![100, 100]))],
+    )
+    .unwrap();
+
+    sqls.iter().for_each(|sql| {
+        run_test(sql, &expected_batch);
+    });
+}
+
+#[test]
+fn test_in_set() {
+    // table3: ProductID = [50, 100, 100], Type = [50, 500, 600]
+    let sqls = [
+        "SELECT ProductID FROM table3 WHERE Type IN (500, 600, 7, -3)",
+        "SELECT ProductID FROM table3 WHERE Type NOT IN (50, 1, 2, 3)",
+        "SELECT ProductID FROM table3 WHERE NOT Type IN (50, 1, 2, 3) AND ProductID > 0",
+        "SELECT ProductID FROM table3 WHERE (ProductID, Type) IN ((100, 500), (100, 600))",
+        "SELECT ProductID FROM table3 WHERE (Type, ProductID) NOT IN ((50, 50), (500, 50))",
+        "SELECT ProductID FROM table3 WHERE (Type) IN ((500), (600)) OR Type IN (1, 2, 3, 4)",
+    ];
+
+    // Two lines : 100, 100
+    let expected_batch = RecordBatch::try_new(
+        Arc::new(Schema::new(vec![Field::new(
+            "ProductID",
+            DataType::Int16,
+            false,
+        )])),
+        vec![Arc::new(Int16Array::from(vec![100, 100]))],
+    )
+    .unwrap();
+
+    sqls.iter().for_each(|sql| {
+        run_test(sql, &expected_batch);
+    });
+}
+
+#[test]
+fn test_in_set_str() {
+    let t1 = Table::new("table_1", simple_batch_5());
+    let tables: OrderedTables = OrderedTables::new(vec![t1]).unwrap();
+    let public_ordered_schemas = tables.ordered_schemas();
+
+    let sqls = [
+        "SELECT some_int FROM table_1 WHERE some_str IN ('other test', 'some other line', 'a', 'b')",
+        "SELECT some_int FROM table_1 WHERE (some_bool, some_str) IN ((false, 'other test'), (false, 'some other line'))",
+        "SELECT some_int FROM table_1 WHERE (some_str, some_bool) NOT IN (('first line', true), ('other', false))",
+    ];
+
+    // Two lines : 123, 3
+    let expected_batch = RecordBatch::try_new(
+        Arc::new(Schema::new(vec![Field::new(
+            "some_int",
+            DataType::UInt32,
+            false,
+        )])),
+        vec![Arc::new(UInt32Array::from(vec![123, 3]))],
+    )
+    .unwrap();
+
+    let sql_client = FheSqlClient::new(public_ordered_schemas.clone()).unwrap();
+    let options = SqlResultOptions::default().with_compress(true);
+
+    sqls.iter().for_each(|sql| {
+        let clear_sql_query = sql_client.clear_sql(sql, options).unwrap();
+        let sql_result = FheSqlServer::run(&clear_sql_query, &tables).unwrap();
+        let rb = sql_result.clone().into_record_batch().unwrap();
+        assert_eq!(&rb, &expected_batch);
+    });
+}
+
+#[test]
+fn test_coercion_mode() {
+    let t3 = Table::new("table3", simple_batch_3());
+    let tables: OrderedTables = OrderedTables::new(vec![t3]).unwrap();
+    let public_ordered_schemas = tables.ordered_schemas();
+    let options = SqlResultOptions::default().with_compress(true);
+
+    // One line : 100
+    let expected_batch = RecordBatch::try_new(
+        Arc::new(Schema::new(vec![Field::new(
+            "ProductID",
+            DataType::Int16,
+            false,
+        )])),
+        vec![Arc::new(Int16Array::from(vec![100]))],
+    )
+    .unwrap();
+
+    let strict_client = FheSqlClient::new(public_ordered_schemas.clone())
+        .unwrap()
+        .with_coercion_mode(CoercionMode::Strict);
+    let pg_client = FheSqlClient::new(public_ordered_schemas.clone())
+        .unwrap()
+        .with_coercion_mode(CoercionMode::PostgreSql);
+
+    let run = |sql_client: &FheSqlClient, sql: &str| {
+        let clear_sql_query = sql_client.clear_sql(sql, options).unwrap();
+        let sql_result = FheSqlServer::run(&clear_sql_query, &tables).unwrap();
+        let rb = sql_result.clone().into_record_batch().unwrap();
+        assert_eq!(&rb, &expected_batch);
+    };
+
+    run(&strict_client, "SELECT ProductID FROM table3 WHERE Type = CAST('500' AS INT)");
+    run(&pg_client, "SELECT ProductID FROM table3 WHERE Type = '500'");
+    run(&pg_client, "SELECT ProductID FROM table3 WHERE Type IN ('500', '501')");
+
+    // A failed cast is an error instead of a comparison with 0
+    let sql = "SELECT ProductID FROM table3 WHERE Type = '500x'";
+    assert!(strict_client.clear_sql(sql, options).is_err());
+    assert!(pg_client.clear_sql(sql, options).is_err());
+    assert!(FheSqlClient::new(public_ordered_schemas.clone())
+        .unwrap()
+        .clear_sql(sql, options)
+        .is_ok());
+}
+
+#[test]
+fn test_coercion_mode_extracted_operands() {
+    let t5 = Table::new("table5", simple_batch_5());
+    let tables: OrderedTables = OrderedTables::new(vec![t5]).unwrap();
+    let public_ordered_schemas = tables.ordered_schemas();
+    let options = SqlResultOptions::default();
+
+    let mysql_client = FheSqlClient::new(public_ordered_schemas.clone()).unwrap();
+    let strict_client = FheSqlClient::new(public_ordered_schemas.clone())
+        .unwrap()
+        .with_coercion_mode(CoercionMode::Strict);
+    let pg_client = FheSqlClient::new(public_ordered_schemas.clone())
+        .unwrap()
+        .with_coercion_mode(CoercionMode::PostgreSql);
+
+    // IN sets, column arithmetics and scalar subqueries are type-checked
+    // before being replaced by identifiers
+    let sqls = [
+        "SELECT some_int FROM table5 WHERE some_int IN (0, 1, 2, true)",
+        "SELECT some_int FROM table5 WHERE some_bool IN (true, false, 1, 0)",
+        "SELECT some_int FROM table5 WHERE (some_int, some_bool) IN ((3, 1), (0, 0))",
+        "SELECT some_int FROM table5 WHERE some_int + some_bool > 1",
+        "SELECT some_int FROM table5 WHERE some_bool * 2 = 2",
+        "SELECT some_int FROM table5 WHERE some_int > (SELECT SUM(some_bool) FROM table5)",
+    ];
+    for sql in sqls {
+        assert!(mysql_client.clear_sql(sql, options).is_ok(), "{}", sql);
+        assert!(strict_client.clear_sql(sql, options).is_err(), "{}", sql);
+        assert!(pg_client.clear_sql(sql, options).is_err(), "{}", sql);
+    }
+    let sql = "SELECT some_int FROM table5 WHERE some_int > (SELECT COUNT(some_bool) FROM table5)";
+    assert!(strict_client.clear_sql(sql, options).is_ok());
+
+    // PostgreSQL mode casts the string literals of the sets to the column type
+    let run = |sql_client: &FheSqlClient, sql: &str| {
+        let clear_sql_query = sql_client.clear_sql(sql, options).unwrap();
+        FheSqlServer::run(&clear_sql_query, &tables)
+            .unwrap()
+            .into_csv()
+            .unwrap()
+    };
+    let expected = run(
+        &strict_client,
+        "SELECT some_int FROM table5 WHERE (some_int, some_bool) IN ((3, true), (0, false))",
+    );
+    assert_eq!(expected, "some_int:uint32\n3\n");
+    let sql = "SELECT some_int FROM table5 WHERE (some_int, some_bool) IN (('3', 'yes'), ('0', 'no'))";
+    assert_eq!(run(&pg_client, sql), expected);
+    assert!(strict_client.clear_sql(sql, options).is_err());
+}
+
+#[test]
+fn test_query_padding() {
+    let t1 = Table::new("table1", simple_batch_1());
+    let t2 = Table::new("table2", simple_batch_2());
+    let t3 = Table::new("table3", simple_batch_3());
+    let t4 = Table::new("table4", simple_batch_4());
+    let t5 = Table::new("table5", simple_batch_5());
+    let tables: OrderedTables = OrderedTables::new(vec![t1, t2, t3, t4, t5]).unwrap();
+    let public_ordered_schemas = tables.ordered_schemas();
+    let options = SqlResultOptions::default();
+
+    let sql_client = FheSqlClient::new(public_ordered_schemas.clone()).unwrap();
+    let padded_client = FheSqlClient::new(public_ordered_schemas.clone())
+        .unwrap()
+        .with_query_padding(QueryPadding::new(3, 6).unwrap());
+
+    let sqls = [
+        "SELECT ProductID FROM table3",
+        "SELECT DISTINCT ProductID FROM table3",
+        "SELECT ProductID FROM table3 WHERE Type = 500",
+        "SELECT ProductID FROM table2 WHERE Type > 5 AND Style < 11 OR Category = 13",
+        "SELECT * FROM table1 WHERE CustomerID > 21 AND PostalCode < -6",
+        "SELECT * FROM table4",
+        "SELECT * FROM table5",
+        "SELECT * FROM table5 WHERE some_str = 'first line'",
+        "SELECT * FROM table5 WHERE some_bool = true OR some_int > 100",
+    ];
+
+    for sql in sqls {
+        let clear_sql_query = sql_client.clear_sql(sql, options).unwrap();
+        let expected_batch = FheSqlServer::run(&clear_sql_query, &tables)
+            .unwrap()
+            .into_record_batch()
+            .unwrap();
+
+        let padded_sql_query = padded_client.clear_sql(sql, options).unwrap();
+        // Same shape whatever the WHERE clause
+        assert_eq!(padded_sql_query.num_binary_ops(), 6);
+        assert_eq!(padded_sql_query.where_tree().max_num_ops(), 8);
+
+        let rb = FheSqlServer::run(&padded_sql_query, &tables)
+            .unwrap()
+            .into_record_batch()
+            .unwrap();
+        assert_eq!(rb, expected_batch, "{}", sql);
+    }
+
+    // Larger than the padded shape
+    let sql = "SELECT ProductID FROM table2 WHERE Type = 1 OR Type = 2 OR Type = 3 OR Type = 4 OR Type = 5 OR Type = 6 OR Type = 7";
+    assert!(padded_client.clear_sql(sql, options).is_err());
+    assert!(QueryPadding::new(2, 4).is_err());
+    assert!(QueryPadding::new(0, 1).is_err());
+}
+
+#[test]
+fn test_constant_time_false_where() {
+    let t1 = Table::new("table1", simple_batch_1());
+    let t2 = Table::new("table2", simple_batch_2());
+    let t3 = Table::new("table3", simple_batch_3());
+    let tables: OrderedTables = OrderedTables::new(vec![t1, t2, t3]).unwrap();
+    let public_ordered_schemas = tables.ordered_schemas();
+    let options = SqlResultOptions::default();
+
+    let sql_client = FheSqlClient::new(public_ordered_schemas.clone())
+        .unwrap()
+        .with_constant_time_false_where(true);
+    let padded_client = FheSqlClient::new(public_ordered_schemas.clone())
+        .unwrap()
+        .with_constant_time_false_where(true)
+        .with_query_padding(QueryPadding::new(2, 3).unwrap());
+
+    let never = "SELECT ProductID FROM table3 WHERE Type > 5 AND 1 = 2";
+    let sometimes = "SELECT ProductID FROM table3 WHERE Type = 5000";
+
+    for client in [&sql_client, &padded_client] {
+        let a = client.leakage_report(never, options).unwrap();
+        let b = client.leakage_report(sometimes, options).unwrap();
+        assert_eq!(a, b);
+
+        let clear_sql_query = client.clear_sql(never, options).unwrap();
+        let rb = FheSqlServer::run(&clear_sql_query, &tables)
+            .unwrap()
+            .into_record_batch()
+            .unwrap();
+        assert_eq!(rb.num_rows(), 0);
+        assert_eq!(rb.schema().field(0).name(), "ProductID");
+    }
+
+    let sql = "SELECT DISTINCT * FROM table1 WHERE CustomerID > 21 AND NOT (CustomerID > 20)";
+    let clear_sql_query = sql_client.clear_sql(sql, options).unwrap();
+    assert!(!clear_sql_query.is_empty());
+    let rb = FheSqlServer::run(&clear_sql_query, &tables)
+        .unwrap()
+        .into_record_batch()
+        .unwrap();
+    assert_eq!(rb.num_rows(), 0);
+}
+
+#[test]
+fn test_max_num_rows() {
+    let t1 = Table::new("table1", simple_batch_1());
+    let t2 = Table::new("table2", simple_batch_2());
+    let t3 = Table::new("table3", simple_batch_3());
+    let tables: OrderedTables = OrderedTables::new(vec![t1, t2, t3]).unwrap();
+    let sql_client = FheSqlClient::new(tables.ordered_schemas().clone()).unwrap();
+
+    let run = |sql: &str, options: SqlResultOptions| {
+        let clear_sql_query = sql_client.clear_sql(sql, options).unwrap();
+        FheSqlServer::run(&clear_sql_query, &tables).unwrap()
+    };
+
+    let sqls = [
+        "SELECT * FROM table2 WHERE Type > 5 AND Name <> 'zz'",
+        "SELECT DISTINCT ProductID FROM table3",
+        "SELECT ProductID, ROW_NUMBER() OVER (ORDER BY Type DESC) AS rn FROM table3 WHERE Type > 70 OR ProductID = 50",
+    ];
+    for sql in sqls {
+        let expected = run(sql, SqlResultOptions::default())
+            .into_record_batch()
+            .unwrap();
+        for compress in [true, false] {
+            for padding in [true, false] {
+                let options = SqlResultOptions::default()
+                    .with_compress(compress)
+                    .with_format(crate::SqlResultFormat::RowBytes(padding))
+                    .with_max_num_rows(expected.num_rows().max(1));
+                let sql_result = run(sql, options);
+                assert!(!sql_result.is_truncated());
+                assert_eq!(sql_result.into_record_batch().unwrap(), expected);
+
+                let options = options.with_max_num_rows(expected.num_rows() + 3);
+                let sql_result = run(sql, options);
+                assert!(!sql_result.is_truncated());
+                assert_eq!(sql_result.into_record_batch().unwrap(), expected);
+            }
+        }
+
+        // Keeps the first matching rows
+        let options = SqlResultOptions::default().with_max_num_rows(1);
+        let sql_result = run(sql, options);
+        assert!(sql_result.is_truncated());
+        assert_eq!(
+            sql_result.into_record_batch().unwrap(),
+            expected.slice(0, 1)
+        );
+    }
+
+    let sql = "SELECT * FROM table2";
+    let options = SqlResultOptions::best().with_max_num_rows(2);
+    assert!(sql_client.clear_sql(sql, options).is_err());
+    let options = SqlResultOptions::default().with_max_num_rows(0);
+    assert!(sql_client.clear_sql(sql, options).is_err());
+
+    // The server checks the options again
+    let clear_sql_query = sql_client
+        .clear_sql(sql, SqlResultOptions::default().with_max_num_rows(2))
+        .unwrap();
+    let json = serde_json::to_string(&clear_sql_query).unwrap();
+    assert!(json.contains("\"max_num_rows\":2"));
+    let invalid_jsons = [
+        json.replace("\"max_num_rows\":2", "\"max_num_rows\":0"),
+        json.replace("{\"RowBytes\":true}", "\"TableBytesInColumnOrder\""),
+    ];
+    for invalid_json in invalid_jsons {
+        assert_ne!(invalid_json, json);
+        let invalid_query: crate::ClearSqlQuery = serde_json::from_str(&invalid_json).unwrap();
+        assert!(matches!(
+            FheSqlServer::run(&invalid_query, &tables),
+            Err(crate::FheSqlError::InvalidQueryError(_))
+        ));
+        let server_options = SqlServerOptions::default();
+        assert!(FheSqlServer::run_streaming(&invalid_query, &tables, 2, &server_options).is_err());
+    }
+}
+
+#[test]
+fn test_distinct_sort_network() {
+    let t1 = Table::new("table1", simple_batch_1());
+    let t2 = Table::new("table2", simple_batch_2());
+    let t3 = Table::new("table3", simple_batch_3());
+    let t5 = Table::new("table5", simple_batch_5());
+    let tables: OrderedTables = OrderedTables::new(vec![t1, t2, t3, t5]).unwrap();
+    let sql_client = FheSqlClient::new(tables.ordered_schemas().clone()).unwrap();
+    let options = SqlResultOptions::default();
+    let sort_network =
+        SqlServerOptions::default().with_distinct_strategy(DistinctStrategy::SortNetwork);
+
+    let sqls = [
+        "SELECT DISTINCT ProductID FROM table3",
+        "SELECT DISTINCT ProductID FROM table3 WHERE Type > 70",
+        "SELECT DISTINCT * FROM table3",
+        "SELECT ProductID FROM table3",
+        "SELECT DISTINCT Type FROM table2",
+        "SELECT DISTINCT some_str FROM table5",
+        "SELECT DISTINCT some_int, some_str FROM table5",
+        "SELECT DISTINCT some_str, some_bool FROM table5 WHERE some_int < 100",
+        "SELECT DISTINCT some_bool FROM table5 WHERE some_int > 0",
+        "SELECT DISTINCT * FROM table1",
+        "SELECT DISTINCT PostalCode FROM table1",
+    ];
+    for sql in sqls {
+        let clear_sql_query = sql_client.clear_sql(sql, options).unwrap();
+        let expected = FheSqlServer::run(&clear_sql_query, &tables)
+            .unwrap()
+            .into_record_batch()
+            .unwrap();
+        let rb = FheSqlServer::run_with_options(&clear_sql_query, &tables, &sort_network)
+            .unwrap()
+            .into_record_batch()
+            .unwrap();
+        assert_eq!(rb, expected, "{}", sql);
+    }
+}
+
+#[test]
+fn test_distinct_sort_network_random() {
+    use crate::test::simple_batch::RecordBatchBuilder;
+    use arrow_array::types::{Int16Type, UInt8Type};
+    use rand::Rng;
+
+    let mut rng = rand::thread_rng();
+    let mut a = RecordBatchBuilder::new();
+    a.push_with_name::<UInt8Type>("a", (0..37).map(|_| rng.gen_range(0..3)).collect());
+    a.push_with_name::<Int16Type>("b", (0..37).map(|_| rng.gen_range(-2..2)).collect());
+    let mut b = RecordBatchBuilder::new();
+    b.push_with_name::<Int16Type>("b", (0..21).map(|_| rng.gen_range(0..4)).collect());
+
+    let tables = OrderedTables::new(vec![
+        Table::new("ta", a.finish()),
+        Table::new("tb", b.finish()),
+    ])
+    .unwrap();
+    let sql_client = FheSqlClient::new(tables.ordered_schemas().clone()).unwrap();
+    let options = SqlResultOptions::default();
+    let sort_network =
+        SqlServerOptions::default().with_distinct_strategy(DistinctStrategy::SortNetwork);
+
+    let sqls = [
+        "SELECT DISTINCT * FROM ta",
+        "SELECT DISTINCT a FROM ta WHERE b >= 0",
+        "SELECT DISTINCT b FROM ta WHERE a <> 1",
+        "SELECT DISTINCT b FROM tb",
+        "SELECT DISTINCT * FROM tb WHERE b < 3",
+    ];
+    for sql in sqls {
+        let clear_sql_query = sql_client.clear_sql(sql, options).unwrap();
+        let expected = FheSqlServer::run(&clear_sql_query, &tables)
+            .unwrap()
+            .into_record_batch()
+            .unwrap();
+        let rb = FheSqlServer::run_with_options(&clear_sql_query, &tables, &sort_network)
+            .unwrap()
+            .into_record_batch()
+            .unwrap();
+        assert_eq!(rb, expected, "{}", sql);
+    }
+}
+
+#[test]
+fn test_many_tables() {
+    use crate::test::simple_batch::RecordBatchBuilder;
+    use arrow_array::types::Int32Type;
+
+    // Table t_i has 1 + (i % 7) rows, with values [i % 3, ...]
+    let num_tables = 300;
+    let tables = (0..num_tables)
+        .map(|i| {
+            let mut rb = RecordBatchBuilder::new();
+            let num_rows = 1 + (i % 7);
+            rb.push_with_name::<Int32Type>(
+                "v",
+                (0..num_rows).map(|r| ((i + r / 2) % 3) as i32).collect(),
+            );
+            if i % 2 == 0 {
+                rb.push_with_name::<Int32Type>("w", (0..num_rows).map(|r| r as i32).collect());
+            }
+            Table::new(&format!("t_{}", i), rb.finish())
+        })
+        .collect::<Vec<Table>>();
+    let tables = OrderedTables::new(tables).unwrap();
+    assert_eq!(tables.num_tables(), num_tables);
+
+    let sql_client = FheSqlClient::new(tables.ordered_schemas().clone()).unwrap();
+    let options = SqlResultOptions::default();
+    let sort_network =
+        SqlServerOptions::default().with_distinct_strategy(DistinctStrategy::SortNetwork);
+
+    let run = |sql: &str, server_options: &SqlServerOptions| {
+        let clear_sql_query = sql_client.clear_sql(sql, options).unwrap();
+        FheSqlServer::run_with_options(&clear_sql_query, &tables, server_options)
+            .unwrap()
+            .into_record_batch()
+            .unwrap()
+    };
+
+    // t_250: 6 rows, v = [1, 1, 2, 2, 0, 0]
+    let rb = run("SELECT v FROM t_250 WHERE v >= 1", &SqlServerOptions::default());
+    let expected = RecordBatch::try_new(
+        Arc::new(Schema::new(vec![Field::new("v", DataType::Int32, false)])),
+        vec![Arc::new(Int32Array::from(vec![1, 1, 2, 2]))],
+    )
+    .unwrap();
+    assert_eq!(rb, expected);
+
+    // t_299: 6 rows, v = [2, 2, 0, 0, 1, 1]
+    let expected = RecordBatch::try_new(
+        Arc::new(Schema::new(vec![Field::new("v", DataType::Int32, false)])),
+        vec![Arc::new(Int32Array::from(vec![2, 0, 1]))],
+    )
+    .unwrap();
+    for server_options in [&SqlServerOptions::default(), &sort_network] {
+        let rb = run("SELECT DISTINCT v FROM t_299", server_options);
+        assert_eq!(rb, expected);
+    }
+
+    // t_0: 1 row, the 6 following rows are out of bounds
+    let rb = run("SELECT * FROM t_0", &SqlServerOptions::default());
+    assert_eq!(rb.num_rows(), 1);
+}
+
+#[test]
+fn test_run_streaming() {
+    use crate::csv::record_batch_to_csv_string;
+
+    let t1 = Table::new("table1", simple_batch_1());
+    let t2 = Table::new("table2", simple_batch_2());
+    let t3 = Table::new("table3", simple_batch_3());
+    let t5 = Table::new("table5", simple_batch_5());
+    let tables: OrderedTables = OrderedTables::new(vec![t1, t2, t3, t5]).unwrap();
+    let sql_client = FheSqlClient::new(tables.ordered_schemas().clone()).unwrap();
+    let server_options = SqlServerOptions::default();
+
+    let sqls = [
+        "SELECT * FROM table5",
+        "SELECT DISTINCT some_str FROM table5",
+        "SELECT DISTINCT some_bool, some_str FROM table5 WHERE some_int < 100",
+        "SELECT ProductID FROM table3 WHERE Type > 70",
+        "SELECT DISTINCT ProductID FROM table3",
+        "SELECT * FROM table2 WHERE Name IN ('ab', 'ef')",
+        "SELECT ProductID FROM table3 WHERE Type + ProductID > 200",
+        "SELECT * FROM table1 WHERE 1 = 2",
+    ];
+    let formats = [
+        crate::SqlResultFormat::RowBytes(true),
+        crate::SqlResultFormat::RowBytes(false),
+        crate::SqlResultFormat::TableBytesInRowOrder,
+        crate::SqlResultFormat::TableBytesInColumnOrder,
+    ];
+    for sql in sqls {
+        for format in formats {
+            let options = SqlResultOptions::default().with_format(format);
+            let clear_sql_query = sql_client.clear_sql(sql, options).unwrap();
+            let expected = FheSqlServer::run(&clear_sql_query, &tables)
+                .unwrap()
+                .into_record_batch()
+                .unwrap();
+            for chunk_num_rows in [1, 2, 3, 10] {
+                let batches = FheSqlServer::run_streaming(
+                    &clear_sql_query,
+                    &tables,
+                    chunk_num_rows,
+                    &server_options,
+                )
+                .unwrap()
+                .map(|chunk| chunk.unwrap().into_record_batch().unwrap())
+                .collect::<Vec<RecordBatch>>();
+                let body = batches
+                    .iter()
+                    .map(|rb| {
+                        assert_eq!(rb.schema(), expected.schema());
+                        let csv = record_batch_to_csv_string(rb).unwrap();
+                        csv.split_once('\n').map(|(_, body)| body.to_string()).unwrap()
+                    })
+                    .collect::<String>();
+                let expected_csv = record_batch_to_csv_string(&expected).unwrap();
+                assert_eq!(
+                    body,
+                    expected_csv.split_once('\n').unwrap().1,
+                    "{} {}",
+                    sql,
+                    chunk_num_rows
+                );
+            }
+        }
+    }
+
+    // Needs every row at once
+    let options = SqlResultOptions::default();
+    let sql = "SELECT ProductID FROM table3 WHERE Type > (SELECT AVG(Type) FROM table3)";
+    let clear_sql_query = sql_client.clear_sql(sql, options).unwrap();
+    assert!(FheSqlServer::run_streaming(&clear_sql_query, &tables, 2, &server_options).is_err());
+
+    // The DISTINCT pass of the chunks is always pairwise
+    let sql = "SELECT DISTINCT Type FROM table3";
+    let clear_sql_query = sql_client.clear_sql(sql, options).unwrap();
+    let sort_options =
+        SqlServerOptions::default().with_distinct_strategy(DistinctStrategy::SortNetwork);
+    assert!(matches!(
+        FheSqlServer::run_streaming(&clear_sql_query, &tables, 2, &sort_options),
+        Err(crate::FheSqlError::UnsupportedSqlQuery(_))
+    ));
+}
+
+#[test]
+fn test_run_chunked() {
+    use crate::csv::record_batch_to_csv_string;
+
+    let t2 = Table::new("table2", simple_batch_2());
+    let t3 = Table::new("table3", simple_batch_3());
+    let t5 = Table::new("table5", simple_batch_5());
+    let tables: OrderedTables = OrderedTables::new(vec![t2, t3, t5]).unwrap();
+    let sql_client = FheSqlClient::new(tables.ordered_schemas().clone()).unwrap();
+    let server_options = SqlServerOptions::default();
+
+    let sqls = [
+        "SELECT DISTINCT some_bool, some_str FROM table5",
+        "SELECT ProductID, Name FROM table2 WHERE Type > 70",
+        "SELECT * FROM table3 WHERE 1 = 2",
+    ];
+    let formats = [
+        crate::SqlResultFormat::RowBytes(true),
+        crate::SqlResultFormat::TableBytesInRowOrder,
+        crate::SqlResultFormat::TableBytesInColumnOrder,
+    ];
+    for sql in sqls {
+        for format in formats {
+            let options = SqlResultOptions::default().with_format(format);
+            let clear_sql_query = sql_client.clear_sql(sql, options).unwrap();
+            let expected = FheSqlServer::run(&clear_sql_query, &tables)
+                .unwrap()
+                .into_csv()
+                .unwrap();
+
+            let (header, chunks) =
+                FheSqlServer::run_chunked(&clear_sql_query, &tables, 2, &server_options).unwrap();
+
+            // The header and every chunk are sent separately
+            let header: ClearSqlResultHeader =
+                bincode::deserialize(&bincode::serialize(&header).unwrap()).unwrap();
+            let body = chunks
+                .map(|chunk| {
+                    let bytes = bincode::serialize(&chunk.unwrap()).unwrap();
+                    let chunk: ClearSqlResultChunk = bincode::deserialize(&bytes).unwrap();
+                    let csv = record_batch_to_csv_string(
+                        &header.chunk_into_record_batch(chunk).unwrap(),
+                    )
+                    .unwrap();
+                    csv.split_once('\n').map(|(_, body)| body.to_string()).unwrap()
+                })
+                .collect::<String>();
+            assert_eq!(body, expected.split_once('\n').unwrap().1, "{}", sql);
+        }
+    }
+}
+
+#[test]
+fn test_run_control() {
+    use std::sync::Mutex;
+
+    let t3 = Table::new("table3", simple_batch_3());
+    let tables: OrderedTables = OrderedTables::new(vec![t3]).unwrap();
+    let sql_client = FheSqlClient::new(tables.ordered_schemas().clone()).unwrap();
+    let sql = "SELECT DISTINCT ProductID FROM table3 WHERE Type > 5";
+    let clear_sql_query = sql_client
+        .clear_sql(sql, SqlResultOptions::default())
+        .unwrap();
+    let num_rows = tables.max_num_rows();
+
+    for strategy in [DistinctStrategy::Pairwise, DistinctStrategy::SortNetwork] {
+        let server_options = SqlServerOptions::default().with_distinct_strategy(strategy);
+
+        // Progress
+        let steps = Arc::new(Mutex::new(Vec::<RunProgress>::new()));
+        let control_steps = steps.clone();
+        let control =
+            RunControl::new().with_progress(move |p| control_steps.lock().unwrap().push(*p));
+        let result =
+            FheSqlServer::run_with_control(&clear_sql_query, &tables, &server_options, &control)
+                .unwrap();
+        assert_eq!(
+            result.into_csv().unwrap(),
+            FheSqlServer::run_with_options(&clear_sql_query, &tables, &server_options)
+                .unwrap()
+                .into_csv()
+                .unwrap()
+        );
+        let steps = steps.lock().unwrap();
+        let stages = steps.iter().map(|p| p.stage()).collect::<Vec<RunStage>>();
+        // Start, binary op, row chunk
+        let mut expected_stages = vec![RunStage::ComputeSelect; 3];
+        expected_stages.extend(
+            steps
+                .iter()
+                .filter(|p| p.stage() == RunStage::SelectDistinct)
+                .map(|p| p.stage()),
+        );
+        expected_stages.extend([RunStage::ComputeResult; 2]);
+        assert_eq!(stages, expected_stages);
+        let distinct_steps = steps
+            .iter()
+            .filter(|p| p.stage() == RunStage::SelectDistinct)
+            .collect::<Vec<_>>();
+        let last = distinct_steps.last().unwrap();
+        assert_eq!(last.done(), last.total());
+        if strategy == DistinctStrategy::Pairwise {
+            assert_eq!(last.total(), num_rows - 1);
+        }
+        assert!(steps.iter().all(|p| p.done() <= p.total()));
+
+        // Cancellation during the DISTINCT pass
+        let handle = RunControl::new();
+        let cancel_handle = handle.clone();
+        let control = handle.with_progress(move |p| {
+            if p.stage() == RunStage::SelectDistinct && p.done() == 1 {
+                cancel_handle.cancel();
+            }
+        });
+        let result =
+            FheSqlServer::run_with_control(&clear_sql_query, &tables, &server_options, &control);
+        assert_eq!(result.err(), Some(crate::FheSqlError::Cancelled));
+        assert!(control.is_cancelled());
+    }
+}
+
+#[test]
+fn test_run_control_compute_select() {
+    use std::sync::Mutex;
+
+    let t3 = Table::new("table3", simple_batch_3());
+    let tables: OrderedTables = OrderedTables::new(vec![t3]).unwrap();
+    let sql_client = FheSqlClient::new(tables.ordered_schemas().clone()).unwrap();
+    let sql = "SELECT DISTINCT ProductID FROM table3 WHERE Type > 5 AND ProductID < 1000";
+    let clear_sql_query = sql_client
+        .clear_sql(sql, SqlResultOptions::default())
+        .unwrap();
+    let server_options = SqlServerOptions::default();
+
+    // Cancellation after the first binary op of the WHERE clause
+    let cancel_control = || {
+        let steps = Arc::new(Mutex::new(Vec::<RunProgress>::new()));
+        let control_steps = steps.clone();
+        let handle = RunControl::new();
+        let cancel_handle = handle.clone();
+        let control = handle.with_progress(move |p| {
+            control_steps.lock().unwrap().push(*p);
+            if p.stage() == RunStage::ComputeSelect && p.done() == 1 {
+                cancel_handle.cancel();
+            }
+        });
+        (control, steps)
+    };
+
+    let (control, steps) = cancel_control();
+    let result =
+        FheSqlServer::run_with_control(&clear_sql_query, &tables, &server_options, &control);
+    assert_eq!(result.err(), Some(crate::FheSqlError::Cancelled));
+    let steps = steps.lock().unwrap();
+    assert!(steps.iter().all(|p| p.stage() == RunStage::ComputeSelect));
+    assert!(steps.iter().all(|p| p.done() < p.total()));
+
+    // Streaming: the first chunk is cancelled, then the iterator ends
+    let (control, steps) = cancel_control();
+    let mut stream = FheSqlServer::run_streaming_with_control(
+        &clear_sql_query,
+        &tables,
+        2,
+        &server_options,
+        &control,
+    )
+    .unwrap();
+    assert_eq!(stream.next().unwrap().err(), Some(crate::FheSqlError::Cancelled));
+    assert!(stream.next().is_none());
+    assert!(steps
+        .lock()
+        .unwrap()
+        .iter()
+        .all(|p| p.stage() == RunStage::ComputeSelect));
+}
+
+#[test]
+fn test_admission_control() {
+    let t2 = Table::new("table2", simple_batch_2());
+    let t3 = Table::new("table3", simple_batch_3());
+    let tables: OrderedTables = OrderedTables::new(vec![t2, t3]).unwrap();
+    let sql_client = FheSqlClient::new(tables.ordered_schemas().clone()).unwrap();
+    let options = SqlResultOptions::default();
+
+    let sql = "SELECT ProductID FROM table3 WHERE Type > 70 AND Type <= 550";
+    let clear_sql_query = sql_client.clear_sql(sql, options).unwrap();
+    let estimate =
+        FheSqlServer::estimate_gates(&clear_sql_query, &tables, &SqlServerOptions::default())
+            .unwrap();
+    assert_eq!(
+        estimate,
+        sql_client
+            .explain(sql, options)
+            .unwrap()
+            .estimate_gates(&[3, 3])
+            .unwrap()
+    );
+    let estimated_gates = estimate.total();
+
+    // Within budget
+    let server_options = SqlServerOptions::default().with_max_gates(estimated_gates);
+    assert!(FheSqlServer::run_with_options(&clear_sql_query, &tables, &server_options).is_ok());
+
+    // Over budget
+    let max_gates = estimated_gates - 1;
+    let server_options = SqlServerOptions::default().with_max_gates(max_gates);
+    let is_rejected = |err: Option<crate::FheSqlError>| {
+        err == Some(crate::FheSqlError::CostLimitExceeded {
+            estimated_gates,
+            max_gates,
+        })
+    };
+    assert!(is_rejected(
+        FheSqlServer::run_with_options(&clear_sql_query, &tables, &server_options).err()
+    ));
+    assert!(is_rejected(
+        FheSqlServer::run_streaming(&clear_sql_query, &tables, 2, &server_options).err()
+    ));
+
+    // An empty query costs nothing
+    let clear_sql_query = sql_client
+        .clear_sql("SELECT ProductID FROM table3 WHERE 1 = 2", options)
+        .unwrap();
+    let server_options = SqlServerOptions::default().with_max_gates(0);
+    assert!(FheSqlServer::run_with_options(&clear_sql_query, &tables, &server_options).is_ok());
+
+    // IN sets, scalar subqueries, arithmetics and window functions are charged
+    // on top of the WHERE comparisons of the first query
+    let server_options = SqlServerOptions::default().with_max_gates(estimated_gates);
+    let sqls = [
+        "SELECT ProductID FROM table3 WHERE Type IN (70, 550, 7) AND Type <= 550",
+        "SELECT ProductID FROM table3 WHERE Type > (SELECT AVG(Type) FROM table3) AND Type <= 550",
+        "SELECT ProductID FROM table3 WHERE Type + ProductID > 70 AND Type <= 550",
+        "SELECT ProductID, ROW_NUMBER() OVER (ORDER BY ProductID) AS rn FROM table3 WHERE Type > 70 AND Type <= 550",
+    ];
+    for sql in sqls {
+        let clear_sql_query = sql_client.clear_sql(sql, options).unwrap();
+        let estimate =
+            FheSqlServer::estimate_gates(&clear_sql_query, &tables, &server_options).unwrap();
+        assert_eq!(
+            estimate,
+            sql_client
+                .explain(sql, options)
+                .unwrap()
+                .estimate_gates(&[3, 3])
+                .unwrap(),
+            "{}",
+            sql
+        );
+        assert!(
+            matches!(
+                FheSqlServer::run_with_options(&clear_sql_query, &tables, &server_options),
+                Err(crate::FheSqlError::CostLimitExceeded { .. })
+            ),
+            "{}",
+            sql
+        );
+    }
+
+    // The estimate follows the server DISTINCT strategy
+    let sql = "SELECT DISTINCT ProductID FROM table3 WHERE Type > 70";
+    let clear_sql_query = sql_client.clear_sql(sql, options).unwrap();
+    let explain = sql_client.explain(sql, options).unwrap();
+    for distinct_strategy in [DistinctStrategy::Pairwise, DistinctStrategy::SortNetwork] {
+        let server_options = SqlServerOptions::default().with_distinct_strategy(distinct_strategy);
+        assert_eq!(
+            FheSqlServer::estimate_gates(&clear_sql_query, &tables, &server_options).unwrap(),
+            explain
+                .estimate_gates_with_strategy(&[3, 3], distinct_strategy)
+                .unwrap()
+        );
+    }
+}
+
+#[test]
+fn test_checkpoint_resume() {
+    use std::sync::Mutex;
+
+    let t3 = Table::new("table3", simple_batch_3());
+    let tables: OrderedTables = OrderedTables::new(vec![t3]).unwrap();
+    let sql_client = FheSqlClient::new(tables.ordered_schemas().clone()).unwrap();
+    let sql = "SELECT DISTINCT ProductID FROM table3 WHERE Type > 5 AND ProductID < 1000";
+    let clear_sql_query = sql_client
+        .clear_sql(sql, SqlResultOptions::default())
+        .unwrap();
+    let server_options = SqlServerOptions::default();
+    let expected_csv = FheSqlServer::run(&clear_sql_query, &tables)
+        .unwrap()
+        .into_csv()
+        .unwrap();
+
+    let dir = std::env::temp_dir().join(format!("tfhesql_resume_{}", std::process::id()));
+    let checkpoint = SqlCheckpoint::new(&dir).with_chunk_num_rows(1);
+    checkpoint.clear().unwrap();
+
+    // Cancellation during the DISTINCT pass, after row 1
+    let handle = RunControl::new().with_checkpoint(checkpoint.clone());
+    let cancel_handle = handle.clone();
+    let control = handle.with_progress(move |p| {
+        if p.stage() == RunStage::SelectDistinct && p.done() == 1 {
+            cancel_handle.cancel();
+        }
+    });
+    let result =
+        FheSqlServer::run_with_control(&clear_sql_query, &tables, &server_options, &control);
+    assert_eq!(result.err(), Some(crate::FheSqlError::Cancelled));
+
+    // Resume: the WHERE clause is loaded, the DISTINCT pass restarts at row 2
+    let run_resume = || {
+        let steps = Arc::new(Mutex::new(Vec::<RunProgress>::new()));
+        let control_steps = steps.clone();
+        let control = RunControl::new()
+            .with_checkpoint(checkpoint.clone())
+            .with_progress(move |p| control_steps.lock().unwrap().push(*p));
+        let result =
+            FheSqlServer::run_with_control(&clear_sql_query, &tables, &server_options, &control)
+                .unwrap();
+        assert_eq!(result.into_csv().unwrap(), expected_csv);
+        let steps = steps.lock().unwrap().clone();
+        steps
+    };
+    let steps = run_resume();
+    let first_distinct = steps
+        .iter()
+        .find(|p| p.stage() == RunStage::SelectDistinct)
+        .unwrap();
+    assert_eq!(first_distinct.done(), 1);
+
+    // The final select mask is loaded
+    let steps = run_resume();
+    assert!(steps.iter().all(|p| p.stage() == RunStage::ComputeResult));
+
+    // Another query discards the checkpoint
+    let other_sql_query = sql_client
+        .clear_sql(
+            "SELECT DISTINCT ProductID FROM table3 WHERE Type > 60",
+            SqlResultOptions::default(),
+        )
+        .unwrap();
+    let control = RunControl::new().with_checkpoint(checkpoint.clone());
+    assert_eq!(
+        FheSqlServer::run_with_control(&other_sql_query, &tables, &server_options, &control)
+            .unwrap()
+            .into_csv()
+            .unwrap(),
+        FheSqlServer::run(&other_sql_query, &tables)
+            .unwrap()
+            .into_csv()
+            .unwrap()
+    );
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_precomputed_where() {
+    let new_tables = || {
+        OrderedTables::new(vec![
+            Table::new("table1", simple_batch_1()),
+            Table::new("table2", simple_batch_2()),
+            Table::new("table3", simple_batch_3()),
+        ])
+        .unwrap()
+    };
+    let tables = new_tables();
+    let dir = std::env::temp_dir().join(format!("tfhesql_precomputed_where_{}", std::process::id()));
+    tables.precompute(&dir).unwrap();
+    let mut precomputed_tables = new_tables();
+    precomputed_tables.load_precomputed(&dir).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // The type caches of the WHERE clause are filled from the precomputed values
+    let sql_client = FheSqlClient::new(tables.ordered_schemas().clone()).unwrap();
+    let sqls = [
+        "SELECT * FROM table1 WHERE CustomerID > 2",
+        "SELECT * FROM table2 WHERE Name = 'cd' OR Type > Category",
+        "SELECT DISTINCT ProductID FROM table3 WHERE Type > 60 AND ProductID <> 100",
+    ];
+    for sql in sqls {
+        let clear_sql_query = sql_client
+            .clear_sql(sql, SqlResultOptions::default())
+            .unwrap();
+        assert_eq!(
+            FheSqlServer::run(&clear_sql_query, &precomputed_tables)
+                .unwrap()
+                .into_csv()
+                .unwrap(),
+            FheSqlServer::run(&clear_sql_query, &tables)
+                .unwrap()
+                .into_csv()
+                .unwrap(),
+            "{}",
+            sql
+        );
+        assert_eq!(
+            FheSqlServer::estimate_gates(&clear_sql_query, &precomputed_tables, &Default::default())
+                .unwrap(),
+            FheSqlServer::estimate_gates(&clear_sql_query, &tables, &Default::default()).unwrap()
+        );
+    }
+}
+
+#[test]
+fn test_schema_evolution() {
+    let old_tables = OrderedTables::new(vec![
+        Table::new("table2", simple_batch_2()),
+        Table::new("table3", simple_batch_3()),
+    ])
+    .unwrap();
+    let old_client = FheSqlClient::new(old_tables.ordered_schemas().clone()).unwrap();
+
+    // New table before the old ones, new column at the end of table2
+    let batch_2 = simple_batch_2();
+    let mut fields = batch_2.schema().fields().to_vec();
+    fields.push(Arc::new(Field::new("Stock", DataType::Int64, false)));
+    let mut columns = batch_2.columns().to_vec();
+    columns.push(Arc::new(Int64Array::from(vec![1000, 2000, 3000])));
+    let new_batch_2 = RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).unwrap();
+    let mut tables = OrderedTables::new(vec![
+        Table::new("table1", simple_batch_1()),
+        Table::new("table2", new_batch_2),
+        Table::new("table3", simple_batch_3()),
+    ])
+    .unwrap();
+    assert!(tables.ordered_schemas().is_evolution_of(old_tables.ordered_schemas()));
+
+    let sqls = [
+        "SELECT * FROM table2",
+        "SELECT DISTINCT Name FROM table2 WHERE Type > Category",
+        "SELECT * FROM table3 WHERE ProductID = 100",
+        "SELECT ProductID FROM table3 WHERE Type > (SELECT AVG(Type) FROM table3)",
+    ];
+    let formats = [
+        crate::SqlResultFormat::RowBytes(true),
+        crate::SqlResultFormat::TableBytesInRowOrder,
+        crate::SqlResultFormat::TableBytesInColumnOrder,
+    ];
+    for sql in sqls {
+        for format in formats {
+            for compress in [false, true] {
+                let options = SqlResultOptions::default()
+                    .with_format(format)
+                    .with_compress(compress);
+                let old_sql_query = old_client.clear_sql(sql, options).unwrap();
+                let expected_csv = FheSqlServer::run(&old_sql_query, &old_tables)
+                    .unwrap()
+                    .into_csv()
+                    .unwrap();
+                let csv = FheSqlServer::run(&old_sql_query, &tables)
+                    .unwrap()
+                    .into_csv()
+                    .unwrap();
+                assert_eq!(csv, expected_csv, "{}", sql);
+                assert_eq!(
+                    FheSqlServer::estimate_gates(&old_sql_query, &tables, &Default::default())
+                        .unwrap(),
+                    FheSqlServer::estimate_gates(&old_sql_query, &old_tables, &Default::default())
+                        .unwrap()
+                );
+            }
+        }
+    }
+
+    // The projected tables are cached per client schemas
+    let restricted = tables.restrict_to(old_tables.ordered_schemas()).unwrap().unwrap();
+    assert!(Arc::ptr_eq(
+        &restricted,
+        &tables.restrict_to(old_tables.ordered_schemas()).unwrap().unwrap()
+    ));
+    assert!(!restricted.is_precomputed());
+
+    // ... with their own encodings when the tables are precomputed
+    let dir = std::env::temp_dir().join(format!("tfhesql_evolution_{}", std::process::id()));
+    tables.precompute(&dir).unwrap();
+    tables.load_precomputed(&dir).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    let restricted = tables.restrict_to(old_tables.ordered_schemas()).unwrap().unwrap();
+    assert!(restricted.is_precomputed());
+    for compress in [false, true] {
+        let options = SqlResultOptions::default()
+            .with_format(crate::SqlResultFormat::TableBytesInColumnOrder)
+            .with_compress(compress);
+        let old_sql_query = old_client.clear_sql("SELECT * FROM table2", options).unwrap();
+        assert_eq!(
+            FheSqlServer::run(&old_sql_query, &tables).unwrap().into_csv().unwrap(),
+            FheSqlServer::run(&old_sql_query, &old_tables).unwrap().into_csv().unwrap()
+        );
+    }
+
+    // A modification drops the cache
+    tables.execute("DELETE FROM table1 WHERE CustomerID = 21").unwrap();
+    let modified = tables.restrict_to(old_tables.ordered_schemas()).unwrap().unwrap();
+    assert!(!Arc::ptr_eq(&restricted, &modified));
+
+    let server_options = SqlServerOptions::default();
+    let options = SqlResultOptions::default();
+    let old_sql_query = old_client.clear_sql("SELECT * FROM table2", options).unwrap();
+    let num_chunks = FheSqlServer::run_streaming(&old_sql_query, &tables, 2, &server_options)
+        .unwrap()
+        .map(|chunk| {
+            let rb = chunk.unwrap().into_record_batch().unwrap();
+            assert_eq!(rb.schema(), old_tables.ordered_schemas().schema(0).clone());
+        })
+        .count();
+    assert_eq!(num_chunks, 2);
+
+    // A new client sees the new column
+    let new_client = FheSqlClient::new(tables.ordered_schemas().clone()).unwrap();
+    let new_sql_query = new_client
+        .clear_sql("SELECT Stock FROM table2 WHERE ProductID = 2", options)
+        .unwrap();
+    let rb = FheSqlServer::run(&new_sql_query, &tables)
+        .unwrap()
+        .into_record_batch()
+        .unwrap();
+    assert_eq!(rb.column(0).as_ref(), &Int64Array::from(vec![2000]));
+    assert!(FheSqlServer::run(&new_sql_query, &old_tables).is_err());
+
+    // Not an append-only evolution: the Type column of table3 is removed
+    let mut rb = crate::test::simple_batch::RecordBatchBuilder::new();
+    rb.push_with_name::<arrow_array::types::Int16Type>("ProductID", vec![50, 100, 100]);
+    let other_tables = OrderedTables::new(vec![
+        Table::new("table2", simple_batch_2()),
+        Table::new("table3", rb.finish()),
+    ])
+    .unwrap();
+    assert!(FheSqlServer::run(&old_sql_query, &other_tables).is_err());
+}
@@ -1,9 +1,15 @@
-use super::{simple_batch::RecordBatchBuilder, sql_client_customers};
+use super::{
+    simple_batch::RecordBatchBuilder, sql_client_customers, sql_client_customers_with_bounds,
+};
 use crate::{
-    test::sql_client_customers_categories, test_util::try_load_or_gen_test_keys, FheRunSqlQuery, FheSqlServer, SqlResultOptions
+    test::sql_client_customers_categories, test_util::try_load_or_gen_test_keys, FheRunSqlQuery, FheSqlServer, SqlResultOptions,
+    FheSqlService, ServerKeyPool, SqlServiceOptions, ClearSqlQuery, FheSqlQuery, FheSqlError,
+    RunControl, SqlServerOptions,
 };
 use crate::test_util::broadcast_set_server_key;
 use arrow_array::types::UInt32Type;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use tfhe::set_server_key;
 
 #[test]
@@ -141,3 +147,191 @@ fn test_customers_4() {
     #[cfg(feature = "stats")]
     enc_sql_result.print_stats();
 }
+
+#[test]
+fn test_customers_run_with_key() {
+    let (_, sk) = try_load_or_gen_test_keys(false);
+
+    let (sql_client, tables) = sql_client_customers_with_bounds(0, 30);
+
+    let sql = "SELECT CustomerID,City FROM Customers WHERE Country='France' OR Country='Germany'";
+    let options = SqlResultOptions::default();
+    let clear_sql_query = sql_client.clear_sql(sql, options).unwrap();
+    let expected_rb = FheSqlServer::run(&clear_sql_query, &tables)
+        .unwrap()
+        .into_record_batch()
+        .unwrap();
+
+    // The key is only installed on the client pool (trivial encryption),
+    // not on the calling thread nor on the global rayon pool.
+    let client_sk = sk.clone();
+    let client_pool = rayon::ThreadPoolBuilder::new()
+        .start_handler(move |_| set_server_key(client_sk.clone()))
+        .build()
+        .unwrap();
+    let enc_sql_query =
+        client_pool.install(|| sql_client.trivial_encrypt_sql(sql, options).unwrap());
+
+    let enc_sql_result = FheSqlServer::run_with_key(&enc_sql_query, &tables, &sk).unwrap();
+    let rb = enc_sql_result
+        .try_decrypt_trivial_record_batch()
+        .unwrap();
+    assert_eq!(rb, expected_rb);
+
+    // The same pool is reused by consecutive queries
+    let server_pool = ServerKeyPool::new(&sk, 2).unwrap();
+    for _ in 0..2 {
+        let enc_sql_result =
+            FheSqlServer::run_with_pool(&enc_sql_query, &tables, &server_pool).unwrap();
+        let rb = enc_sql_result
+            .try_decrypt_trivial_record_batch()
+            .unwrap();
+        assert_eq!(rb, expected_rb);
+    }
+
+    // ... with the server options and the run control
+    let progress = Arc::new(AtomicUsize::new(0));
+    let control_progress = progress.clone();
+    let control = RunControl::new().with_progress(move |_| {
+        control_progress.fetch_add(1, Ordering::Relaxed);
+    });
+    let enc_sql_result = FheSqlServer::run_with_pool_and_control(
+        &enc_sql_query,
+        &tables,
+        &server_pool,
+        &SqlServerOptions::default(),
+        &control,
+    )
+    .unwrap();
+    assert_eq!(enc_sql_result.try_decrypt_trivial_record_batch().unwrap(), expected_rb);
+    assert!(progress.load(Ordering::Relaxed) > 0);
+
+    let result = FheSqlServer::run_with_pool_and_control(
+        &enc_sql_query,
+        &tables,
+        &server_pool,
+        &SqlServerOptions::default().with_max_gates(1),
+        &RunControl::default(),
+    );
+    assert!(matches!(result, Err(FheSqlError::CostLimitExceeded { .. })));
+}
+
+#[test]
+fn test_customers_service() {
+    let (_, sk) = try_load_or_gen_test_keys(false);
+    // Client side trivial encryption
+    broadcast_set_server_key(&sk);
+    set_server_key(sk.clone());
+
+    let (sql_client, tables) = sql_client_customers_with_bounds(0, 20);
+    let options = SqlResultOptions::default();
+    let sqls = [
+        "SELECT CustomerID FROM Customers WHERE Country='France'",
+        "SELECT City FROM Customers WHERE CustomerID < 4",
+        "SELECT DISTINCT Country FROM Customers",
+    ];
+    let expected_csvs = sqls
+        .iter()
+        .map(|sql| {
+            let clear_sql_query = sql_client.clear_sql(sql, options).unwrap();
+            FheSqlServer::run(&clear_sql_query, &tables)
+                .unwrap()
+                .into_csv()
+                .unwrap()
+        })
+        .collect::<Vec<String>>();
+
+    let service = FheSqlService::new(
+        Arc::new(tables),
+        SqlServiceOptions::default().with_max_concurrent_queries(2),
+    );
+    service.register_key("alice", &sk).unwrap();
+    service.register_key("bob", &sk).unwrap();
+
+    let mut submitted = vec![];
+    for client_id in ["alice", "bob"] {
+        for (i, sql) in sqls.iter().enumerate() {
+            let enc_sql_query = sql_client.trivial_encrypt_sql(sql, options).unwrap();
+            let query_id = service.submit(client_id, enc_sql_query).unwrap();
+            submitted.push((query_id, client_id, i));
+        }
+    }
+    let enc_sql_query = sql_client.trivial_encrypt_sql(sqls[0], options).unwrap();
+    assert!(service.submit("carol", enc_sql_query).is_err());
+
+    for _ in 0..submitted.len() {
+        let service_result = service.recv_result().unwrap();
+        let (_, client_id, i) = submitted
+            .iter()
+            .find(|(query_id, _, _)| *query_id == service_result.query_id())
+            .unwrap();
+        assert_eq!(service_result.client_id(), *client_id);
+        let csv = service_result
+            .into_result()
+            .unwrap()
+            .try_decrypt_trivial_csv()
+            .unwrap();
+        assert_eq!(csv, expected_csvs[*i]);
+    }
+    assert!(service.try_recv_result().is_none());
+
+    // The server options of the service apply to every query
+    let (_, tables) = sql_client_customers_with_bounds(0, 20);
+    let limited_service = FheSqlService::new(
+        Arc::new(tables),
+        SqlServiceOptions::default()
+            .with_server_options(SqlServerOptions::default().with_max_gates(1)),
+    );
+    limited_service.register_key("alice", &sk).unwrap();
+    let enc_sql_query = sql_client.trivial_encrypt_sql(sqls[0], options).unwrap();
+    limited_service.submit("alice", enc_sql_query).unwrap();
+    assert!(matches!(
+        limited_service.recv_result().unwrap().into_result(),
+        Err(FheSqlError::CostLimitExceeded { .. })
+    ));
+}
+
+
+#[test]
+fn test_customers_service_panic() {
+    use crate::encrypt::traits::TrivialEncryptRef;
+
+    let (_, sk) = try_load_or_gen_test_keys(false);
+    broadcast_set_server_key(&sk);
+    set_server_key(sk.clone());
+
+    let (sql_client, tables) = sql_client_customers_with_bounds(0, 20);
+    let options = SqlResultOptions::default();
+    let sql = "SELECT CustomerID FROM Customers WHERE Country='France'";
+
+    // A query with an inconsistent table mask makes the server panic
+    let json = serde_json::to_string(&sql_client.clear_sql(sql, options).unwrap()).unwrap();
+    let bad_json = json.replacen(
+        "\"table_mask\":{\"mask\":[true]}",
+        "\"table_mask\":{\"mask\":[true,true]}",
+        1,
+    );
+    assert_ne!(bad_json, json);
+    let bad_query: ClearSqlQuery = serde_json::from_str(&bad_json).unwrap();
+
+    let service = FheSqlService::new(Arc::new(tables), SqlServiceOptions::default());
+    service.register_key("alice", &sk).unwrap();
+    let bad_query_id = service
+        .submit("alice", FheSqlQuery::encrypt_trivial_ref(&bad_query))
+        .unwrap();
+    let service_result = service.recv_result().unwrap();
+    assert_eq!(service_result.query_id(), bad_query_id);
+    assert_eq!(service_result.client_id(), "alice");
+    assert!(matches!(
+        service_result.result(),
+        Err(FheSqlError::InternalError(_))
+    ));
+
+    // The worker is still running
+    let query_id = service
+        .submit("alice", sql_client.trivial_encrypt_sql(sql, options).unwrap())
+        .unwrap();
+    let service_result = service.recv_result().unwrap();
+    assert_eq!(service_result.query_id(), query_id);
+    assert!(service_result.result().is_ok());
+}
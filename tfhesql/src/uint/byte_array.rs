@@ -13,6 +13,7 @@ use arrow_array::RecordBatch;
 use arrow_schema::Schema;
 use arrow_schema::SchemaRef;
 use rayon::iter::*;
+use std::borrow::Cow;
 use std::mem::take;
 use std::sync::Arc;
 
@@ -240,6 +241,9 @@ impl ClearByteArray {
         let mut rows = vec![];
 
         let raw_bytes_rows = &byte_array.bytes.as_slice()[offset + 8..];
+        if num_rows == 0 {
+            return rows;
+        }
         let mut i = 0;
         let mut j = 0;
         loop {
@@ -323,7 +327,7 @@ impl ClearByteArray {
     }
 
     pub fn into_bool_vec(self, select_mask: &ClearBoolMask) -> Vec<bool> {
-        assert_eq!(self.len(), select_mask.len());
+        assert!(self.len() <= select_mask.len());
         self.bytes
             .iter()
             .enumerate()
@@ -434,8 +438,6 @@ pub struct ByteArrayList<U8> {
 
 derive1_encrypt_decrypt! { ByteArrayList<U8> {list: Vec<ByteArray<U8>>} }
 
-pub type ClearByteArrayList = ByteArrayList<u8>;
-
 ////////////////////////////////////////////////////////////////////////////////
 
 impl<U8> ByteArrayList<U8> {
@@ -510,23 +512,27 @@ where
 // ClearByteArrayList
 ////////////////////////////////////////////////////////////////////////////////
 
-impl ClearByteArrayList {
-    pub fn par_bitand<U8>(self, mask: &ByteMask<U8>) -> ByteArrayList<U8>
-    where
-        U8: Send
-            + Sync
-            + RefBitAnd<Output = U8>
-            + RefBitOr<Output = U8>
-            + Clone
-            + ValueFrom<u8>
-            + DefaultInto<U8>,
-        //        U8: Send + Sync + RefBitAnd<Output = U8> + Clone + ValueFrom<u8>,
-    {
-        assert!(self.list.len() == mask.len());
+impl<U8> ByteArrayList<U8>
+where
+    U8: Send
+        + Sync
+        + RefBitAnd<Output = U8>
+        + RefBitOr<Output = U8>
+        + Clone
+        + ValueFrom<u8>
+        + DefaultInto<U8>,
+{
+    /// The clear arrays are borrowed, they are usually the precomputed encodings
+    /// shared by every query.
+    pub(crate) fn par_bitand_clear(
+        clear_arrays: &[Cow<'_, ClearByteArray>],
+        mask: &ByteMask<U8>,
+    ) -> ByteArrayList<U8> {
+        assert!(clear_arrays.len() == mask.len());
         let mut bal = ByteArrayList::<U8>::alloc(mask.len());
         bal.list
             .par_iter_mut()
-            .zip(self.list.par_iter().zip(mask.mask.par_iter()))
+            .zip(clear_arrays.par_iter().zip(mask.mask.par_iter()))
             .for_each(|(dst, (clear_array, m))| {
                 *dst = clear_array.par_bitand(m);
             });
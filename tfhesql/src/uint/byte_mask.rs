@@ -264,15 +264,6 @@ where
             *m = T::get_zero();
         });
     }
-    #[inline]
-    pub fn max_u64(&self) -> u64 {
-        let mut the_u64 = 0_u64;
-        for i in 0..self.len() {
-            the_u64 |= (1 << i) as u64;
-        }
-        the_u64
-    }
-
     pub fn extract(&self, indices: &[usize]) -> Vec<&T> {
         indices.iter().map(|i| &self.mask[*i]).collect()
     }
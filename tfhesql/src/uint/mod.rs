@@ -4,8 +4,8 @@ mod block_pow2_array;
 mod byte_array;
 
 pub use byte_array::ByteArray;
+pub use byte_array::ByteArrayList;
 pub use byte_array::ClearByteArray;
-pub use byte_array::ClearByteArrayList;
 
 mod byte_mask;
 pub mod mask {
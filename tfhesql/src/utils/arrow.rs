@@ -77,6 +77,59 @@ pub fn array_column_cell_eq(column: &ArrayRef, row_index1: usize, row_index2: us
     }
 }
 
+/// Returns [EQ, GT, LT] of Cell(row_index1) compared to Cell(row_index2)
+pub fn array_column_cell_cmp(column: &ArrayRef, row_index1: usize, row_index2: usize) -> [bool; 3] {
+    let data_type = column.data_type();
+    let ord = match *data_type {
+        arrow_schema::DataType::Boolean => {
+            let a: &BooleanArray = as_boolean_array(&column);
+            a.value(row_index1).cmp(&a.value(row_index2))
+        }
+        arrow_schema::DataType::Int8 => {
+            let a: &Int8Array = as_primitive_array(&column);
+            a.value(row_index1).cmp(&a.value(row_index2))
+        }
+        arrow_schema::DataType::Int16 => {
+            let a: &Int16Array = as_primitive_array(&column);
+            a.value(row_index1).cmp(&a.value(row_index2))
+        }
+        arrow_schema::DataType::Int32 => {
+            let a: &Int32Array = as_primitive_array(&column);
+            a.value(row_index1).cmp(&a.value(row_index2))
+        }
+        arrow_schema::DataType::Int64 => {
+            let a: &Int64Array = as_primitive_array(&column);
+            a.value(row_index1).cmp(&a.value(row_index2))
+        }
+        arrow_schema::DataType::UInt8 => {
+            let a: &UInt8Array = as_primitive_array(&column);
+            a.value(row_index1).cmp(&a.value(row_index2))
+        }
+        arrow_schema::DataType::UInt16 => {
+            let a: &UInt16Array = as_primitive_array(&column);
+            a.value(row_index1).cmp(&a.value(row_index2))
+        }
+        arrow_schema::DataType::UInt32 => {
+            let a: &UInt32Array = as_primitive_array(&column);
+            a.value(row_index1).cmp(&a.value(row_index2))
+        }
+        arrow_schema::DataType::UInt64 => {
+            let a: &UInt64Array = as_primitive_array(&column);
+            a.value(row_index1).cmp(&a.value(row_index2))
+        }
+        arrow_schema::DataType::Utf8 => {
+            let a: &StringArray = as_string_array(&column);
+            a.value(row_index1).cmp(a.value(row_index2))
+        }
+        _ => todo!(),
+    };
+    [
+        ord == std::cmp::Ordering::Equal,
+        ord == std::cmp::Ordering::Greater,
+        ord == std::cmp::Ordering::Less,
+    ]
+}
+
 pub fn write_row_le_bytes(column: &ArrayRef, row_index: usize, buffer: &mut ClearByteArray) {
     // WARNING!!
     // u8_index can overflow!
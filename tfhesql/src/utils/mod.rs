@@ -6,3 +6,14 @@ pub mod arrow;
 pub const fn is_little_endian() -> bool {
     u16::from_ne_bytes([1, 0]) == 1
 }
+
+/// Initial value of [fnv1a_hash]
+pub const FNV1A_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+
+/// 64 bits FNV-1a hash of `bytes` starting from `hash`. Unlike the std
+/// hashers, the result is stable across builds and can be persisted.
+pub fn fnv1a_hash(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ (*byte as u64)).wrapping_mul(0x100000001b3)
+    })
+}